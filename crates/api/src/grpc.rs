@@ -0,0 +1,248 @@
+use chrono::NaiveDate;
+use cloud_cost_core::{
+    AccountError, AccountSummary, CostProvider, CurrencySubtotal, Report, month_to_date, today_in,
+    validate_window,
+};
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use crate::{AppState, authorize, cached_report};
+
+pub mod proto {
+    tonic::include_proto!("cloudcost.v1");
+}
+
+use proto::cost_report_server::{CostReport, CostReportServer};
+
+pub struct CostReportService {
+    state: Arc<AppState>,
+}
+
+pub fn service(state: Arc<AppState>) -> CostReportServer<CostReportService> {
+    CostReportServer::new(CostReportService { state })
+}
+
+impl CostReportService {
+    // Same auth rules as REST; gRPC metadata carries the API Gateway headers
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let headers = request.metadata().clone().into_headers();
+        authorize(self.state.auth, &headers)
+            .map_err(|_| Status::unauthenticated("missing x-amzn-iam-arn"))
+    }
+}
+
+#[tonic::async_trait]
+impl CostReport for CostReportService {
+    async fn get_report(
+        &self,
+        request: Request<proto::GetReportRequest>,
+    ) -> Result<Response<proto::Report>, Status> {
+        self.authorize(&request)?;
+
+        match cached_report(&self.state, false).await.0 {
+            Ok(report) => Ok(Response::new(report.into())),
+            Err(err) => {
+                tracing::error!(error = %err, "report failed");
+                Err(Status::internal("report failed"))
+            }
+        }
+    }
+
+    async fn get_account_summary(
+        &self,
+        request: Request<proto::GetAccountSummaryRequest>,
+    ) -> Result<Response<proto::AccountSummary>, Status> {
+        self.authorize(&request)?;
+
+        let req = request.into_inner();
+        if !self.state.accounts.contains(&req.account_ref) {
+            return Err(Status::not_found(format!(
+                "Unknown account reference: {}",
+                req.account_ref
+            )));
+        }
+        let (start, end_exclusive) = if req.start.is_empty() && req.end_exclusive.is_empty() {
+            month_to_date(today_in(self.state.timezone))
+        } else {
+            (parse_date(&req.start)?, parse_date(&req.end_exclusive)?)
+        };
+        validate_window(start, end_exclusive)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        match self
+            .state
+            .provider
+            .fetch_account_summary(&req.account_ref, start, end_exclusive)
+            .await
+        {
+            Ok(summary) => Ok(Response::new(summary.into())),
+            Err(err) => {
+                tracing::error!(error = %err, account_ref = %req.account_ref, "account summary failed");
+                Err(Status::internal("account summary failed"))
+            }
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, Status> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        Status::invalid_argument(format!("Invalid date {value:?}, expected YYYY-MM-DD"))
+    })
+}
+
+impl From<AccountSummary> for proto::AccountSummary {
+    fn from(s: AccountSummary) -> Self {
+        Self {
+            account_ref: s.account_ref,
+            account_id: s.account_id,
+            account_name: s.account_name,
+            total: s.total,
+            services: s.services.into_iter().collect(),
+            currency: s.currency,
+        }
+    }
+}
+
+impl From<CurrencySubtotal> for proto::CurrencySubtotal {
+    fn from(c: CurrencySubtotal) -> Self {
+        Self {
+            currency: c.currency,
+            total: c.total,
+            account_refs: c.account_refs,
+        }
+    }
+}
+
+impl From<AccountError> for proto::AccountError {
+    fn from(e: AccountError) -> Self {
+        Self {
+            account_ref: e.account_ref,
+            error: e.error,
+        }
+    }
+}
+
+impl From<Report> for proto::Report {
+    fn from(r: Report) -> Self {
+        Self {
+            month_start: r.month_start.to_string(),
+            month_end_exclusive: r.month_end_exclusive.to_string(),
+            prev_start: r.prev_start.to_string(),
+            prev_end_exclusive: r.prev_end_exclusive.to_string(),
+            summaries: r.summaries.into_iter().map(Into::into).collect(),
+            total_all: r.total_all,
+            currency: r.currency,
+            currency_subtotals: r.currency_subtotals.into_iter().map(Into::into).collect(),
+            services_total: r.services_total.into_iter().collect(),
+            top_services: r
+                .top_services
+                .into_iter()
+                .map(|(service, amount)| proto::ServiceCost { service, amount })
+                .collect(),
+            prev_total: r.prev_total,
+            delta: r.delta,
+            delta_pct: r.delta_pct,
+            new_services: r.new_services,
+            removed_services: r.removed_services,
+            days_elapsed: r.days_elapsed,
+            low_confidence: r.low_confidence,
+            generated_at: r.generated_at.to_rfc3339(),
+            tool_version: r.tool_version.to_string(),
+            errors: r.errors.into_iter().map(Into::into).collect(),
+            warnings: r.warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Result, anyhow};
+    use cloud_cost_core::generate_report;
+    use std::collections::HashMap;
+
+    /// Bills every account $10 of EC2, in EUR for `eu` and USD otherwise, except that every
+    /// call for `broken` fails.
+    struct TestProvider;
+
+    #[tonic::async_trait]
+    impl CostProvider for TestProvider {
+        async fn fetch_account_summary(
+            &self,
+            account_ref: &str,
+            start: NaiveDate,
+            end_exclusive: NaiveDate,
+        ) -> Result<AccountSummary> {
+            let services = self
+                .service_costs(account_ref, start, end_exclusive)
+                .await?;
+            Ok(AccountSummary {
+                currency: Some(if account_ref == "eu" { "EUR" } else { "USD" }.to_string()),
+                ..AccountSummary::for_test(account_ref, services)
+            })
+        }
+
+        async fn service_costs(
+            &self,
+            account_ref: &str,
+            _start: NaiveDate,
+            _end_exclusive: NaiveDate,
+        ) -> Result<HashMap<String, f64>> {
+            if account_ref == "broken" {
+                return Err(anyhow!("AccessDenied for {account_ref}"));
+            }
+            Ok(HashMap::from([("EC2".to_string(), 10.0)]))
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_report_carries_its_errors() {
+        let accounts = ["prod".to_string(), "broken".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 8, 15).unwrap();
+        let report = generate_report(&TestProvider, &accounts, today)
+            .await
+            .unwrap();
+
+        let report = proto::Report::from(report);
+        assert_eq!(report.summaries.len(), 1);
+        assert_eq!(report.total_all, Some(10.0));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].account_ref, "broken");
+        assert!(report.errors[0].error.contains("AccessDenied"));
+    }
+
+    #[tokio::test]
+    async fn mixed_currencies_carry_their_subtotals() {
+        let accounts = ["prod".to_string(), "eu".to_string(), "staging".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 8, 15).unwrap();
+        let report = generate_report(&TestProvider, &accounts, today)
+            .await
+            .unwrap();
+
+        let report = proto::Report::from(report);
+        assert_eq!(report.total_all, None);
+        assert_eq!(report.currency, None);
+        let subtotals: Vec<_> = report
+            .currency_subtotals
+            .iter()
+            .map(|c| (c.currency.as_str(), c.total, c.account_refs.len()))
+            .collect();
+        assert_eq!(subtotals, [("EUR", 10.0, 1), ("USD", 20.0, 2)]);
+        let eu = report.summaries.iter().find(|s| s.account_ref == "eu");
+        assert_eq!(eu.unwrap().currency.as_deref(), Some("EUR"));
+    }
+
+    #[tokio::test]
+    async fn single_currency_is_reported() {
+        let accounts = ["prod".to_string(), "staging".to_string()];
+        let today = NaiveDate::from_ymd_opt(2024, 8, 15).unwrap();
+        let report = generate_report(&TestProvider, &accounts, today)
+            .await
+            .unwrap();
+
+        let report = proto::Report::from(report);
+        assert_eq!(report.total_all, Some(20.0));
+        assert_eq!(report.currency.as_deref(), Some("USD"));
+        assert!(report.currency_subtotals.is_empty());
+    }
+}
@@ -1,57 +1,182 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use axum::response::Response;
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{Query, State},
     http::{HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     routing::get,
 };
-use chrono::Utc;
+use chrono::NaiveDate;
 use clap::{Parser, ValueEnum};
-use cloud_cost_aws::{AssumeRoleConfig, AwsCostProvider, StaticCredentials};
-use cloud_cost_core::generate_report;
+use cloud_cost_aws::accounts::{AccountSources, NO_ACCOUNTS, build_provider, mfa_serial_number};
+use cloud_cost_aws::{
+    AppName, AwsCostProvider, HttpSettings, MAX_TREND_MONTHS, PRIMARY_METRIC, Partition,
+    parse_app_name,
+};
+use cloud_cost_core::render::{self, MoneyFormat, TextOptions};
+use cloud_cost_core::{
+    Report, ReportOptions, Tz, generate_report_with_options, parse_timezone, today_in,
+};
 use http::header::{
-    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE, VARY,
 };
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, watch};
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+#[cfg(feature = "grpc")]
+mod grpc;
+
 #[derive(Parser, Debug)]
 #[command(name = "cloud-cost-api")]
 #[command(about = "REST API for multi-account AWS cost summary", long_about = None)]
 struct Args {
     /// Bind address (host:port)
-    #[arg(long, default_value = "127.0.0.1:8080")]
+    #[arg(long, env = "CCM_BIND", default_value = "127.0.0.1:8080")]
     bind: String,
 
     /// Override AWS region (Cost Explorer is us-east-1 by default)
-    #[arg(long, default_value = "us-east-1")]
+    #[arg(long, env = "CCM_REGION", default_value = "us-east-1")]
     region: String,
 
+    /// App id added to the user agent of every AWS call, for attribution in CloudTrail
+    /// (default cloud-cost-manager-<version>)
+    #[arg(long, value_parser = parse_app_name, env = "CCM_APP_ID")]
+    app_id: Option<AppName>,
+
+    /// IANA timezone in which "today" is taken for report windows; UTC by default
+    #[arg(long, value_parser = parse_timezone, env = "CCM_TIMEZONE")]
+    timezone: Option<Tz>,
+
+    /// With profiles, use each profile's own region (falls back to --region)
+    #[arg(long, env = "CCM_REGION_FROM_PROFILE")]
+    region_from_profile: bool,
+
+    /// AWS partition of the accounts: aws, aws-cn (China) or aws-us-gov (GovCloud)
+    #[arg(long, env = "CCM_PARTITION", default_value = "aws")]
+    partition: Partition,
+
+    /// Custom Cost Explorer endpoint URL: https, or http to localhost for a mock server
+    #[arg(long, visible_alias = "endpoint-url", env = "CCM_CE_ENDPOINT_URL")]
+    ce_endpoint_url: Option<String>,
+
     /// Comma-separated list of AWS shared config profiles
-    #[arg(long, value_delimiter = ',')]
+    #[arg(long, env = "CCM_PROFILES", value_delimiter = ',')]
     profiles: Vec<String>,
 
-    /// Load AWS credentials from a JSON file (overrides profiles)
-    #[arg(long)]
-    accounts_file: Option<PathBuf>,
+    /// Read several linked accounts through one profile: a JSON list of
+    /// {"profile", "linked_account_ids"} entries, one account per id; repeatable
+    /// (comma-separated in the env var)
+    #[arg(long, env = "CCM_PROFILE_ACCOUNTS_FILE", value_delimiter = ',', conflicts_with_all = ["accounts_file", "accounts_from_ssm", "accounts_from_secret", "assume_roles_file"])]
+    profile_accounts_file: Vec<PathBuf>,
+
+    /// Load AWS credentials from a JSON file (overrides profiles); repeatable (comma-separated
+    /// in the env var), entries are merged in order
+    #[arg(long, env = "CCM_ACCOUNTS_FILE", value_delimiter = ',')]
+    accounts_file: Vec<PathBuf>,
 
-    /// Load role ARNs from a JSON file (overrides profiles/accounts)
-    #[arg(long)]
-    assume_roles_file: Option<PathBuf>,
+    /// Load the accounts JSON from this SSM parameter (SecureString supported)
+    #[arg(long, env = "CCM_ACCOUNTS_FROM_SSM", conflicts_with = "accounts_file")]
+    accounts_from_ssm: Option<String>,
+
+    /// Load the accounts JSON from this Secrets Manager secret
+    #[arg(long, env = "CCM_ACCOUNTS_FROM_SECRET", conflicts_with_all = ["accounts_file", "accounts_from_ssm"])]
+    accounts_from_secret: Option<String>,
+
+    /// Profile used to read --accounts-from-ssm/--accounts-from-secret (defaults to the default chain)
+    #[arg(long, env = "CCM_BOOTSTRAP_PROFILE")]
+    bootstrap_profile: Option<String>,
+
+    /// Load role ARNs from a JSON file (overrides profiles/accounts); repeatable
+    /// (comma-separated in the env var), entries are merged in order
+    #[arg(long, env = "CCM_ASSUME_ROLES_FILE", value_delimiter = ',', conflicts_with_all = ["accounts_file", "accounts_from_ssm", "accounts_from_secret"])]
+    assume_roles_file: Vec<PathBuf>,
 
     /// Base profile for STS AssumeRole calls
-    #[arg(long)]
+    #[arg(long, env = "CCM_BASE_PROFILE")]
     base_profile: Option<String>,
 
     /// Authentication mode
-    #[arg(long, value_enum, default_value_t = AuthMode::None)]
+    #[arg(long, env = "CCM_AUTH", value_enum, default_value_t = AuthMode::None)]
     auth: AuthMode,
+
+    /// gRPC bind address (host:port); gRPC is disabled when unset
+    #[cfg(feature = "grpc")]
+    #[arg(long, env = "CCM_GRPC_BIND")]
+    grpc_bind: Option<String>,
+
+    /// Idle connections kept per AWS endpoint in the shared HTTP pool
+    #[arg(long, env = "CCM_HTTP_POOL_SIZE", default_value_t = 32)]
+    http_pool_size: usize,
+
+    /// Seconds an idle pooled connection is kept open
+    #[arg(long, env = "CCM_HTTP_POOL_IDLE_SECS", default_value_t = 90)]
+    http_pool_idle_secs: u64,
+
+    /// Connect timeout for AWS API calls, in seconds
+    #[arg(long, env = "CCM_HTTP_CONNECT_TIMEOUT_SECS", default_value_t = 5)]
+    http_connect_timeout_secs: u64,
+
+    /// Overall timeout for each AWS API call (including retries), in seconds
+    #[arg(long, env = "CCM_HTTP_TIMEOUT_SECS", default_value_t = 60)]
+    http_timeout_secs: u64,
+
+    /// Overall report deadline in seconds; slower accounts are listed as errors
+    #[arg(long, env = "CCM_REPORT_DEADLINE_SECS")]
+    report_deadline_secs: Option<u64>,
+
+    /// Most provider calls in flight at once per report
+    #[arg(long, env = "CCM_MAX_CONCURRENCY")]
+    max_concurrency: Option<usize>,
+
+    /// Page size for paginated AWS calls that accept one (capped at each API's maximum)
+    #[arg(long, env = "CCM_PAGE_SIZE")]
+    page_size: Option<u32>,
+
+    /// Fail the whole report when any account fails instead of returning a partial report
+    #[arg(long, env = "CCM_FAIL_ON_ERROR")]
+    fail_on_error: bool,
+
+    /// Fail the report when the services do not add up to the totals instead of listing it
+    /// in `warnings`
+    #[arg(long, env = "CCM_STRICT")]
+    strict: bool,
+
+    /// Compare against the average of the same window over the last N periods (one extra
+    /// Cost Explorer query per account per period)
+    #[arg(long, env = "CCM_BASELINE_PERIODS", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=12))]
+    baseline_periods: u32,
+
+    /// Leave accounts that spent less than this many dollars out of the account list
+    #[arg(long, env = "CCM_MIN_ACCOUNT_SPEND")]
+    min_account_spend: Option<f64>,
+
+    /// Still count accounts below --min-account-spend in the totals
+    #[arg(long, env = "CCM_COUNT_SMALL_ACCOUNTS", requires = "min_account_spend")]
+    count_small_accounts: bool,
+
+    /// Also project the month from complete days only (one daily query per account)
+    #[arg(long, env = "CCM_PRORATE")]
+    prorate: bool,
+
+    /// Average the prorated projection with Cost Explorer's forecast (one forecast query
+    /// per account)
+    #[arg(long, env = "CCM_BLEND_FORECAST", requires = "prorate")]
+    blend_forecast: bool,
+
+    /// Serve the last computed report and recompute it in the background every this many
+    /// seconds instead of on each request (`?fresh=true` forces a recompute)
+    #[arg(long, env = "CCM_CACHE_REFRESH_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    cache_refresh_secs: Option<u64>,
 }
 
 #[derive(Clone, Copy, Debug, ValueEnum)]
@@ -60,24 +185,125 @@ enum AuthMode {
     Iam,
 }
 
-#[derive(Clone)]
+/// Representations `/report/aws` can produce, chosen from the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReportFormat {
+    Json,
+    Csv,
+    Text,
+    Markdown,
+    Html,
+}
+
 struct AppState {
     provider: AwsCostProvider,
     accounts: Vec<String>,
     auth: AuthMode,
+    options: ReportOptions,
+    /// Timezone of "today" for the month-to-date window
+    timezone: Option<Tz>,
+    inflight: InFlightReports,
+    /// Set with --cache-refresh-secs
+    cache: Option<ReportCache>,
 }
 
-#[derive(Debug, Deserialize)]
-struct AccountsFileEntry {
-    access_key_id: String,
-    secret_access_key: String,
+/// Last successfully computed report, refreshed on a schedule by `refresh_cache` and served
+/// instead of recomputing on every request.
+#[derive(Default)]
+struct ReportCache(Mutex<Option<Report>>);
+
+type ReportOutcome = Result<Report, String>;
+
+/// Coalesces concurrent report requests for the same window into one computation. The
+/// computation runs in its own task, so one client disconnecting does not restart it for
+/// the others; once every request waiting on it has gone away it is cancelled, which stops
+/// its Cost Explorer calls.
+#[derive(Default)]
+struct InFlightReports {
+    pending: Arc<Mutex<HashMap<NaiveDate, Arc<PendingReport>>>>,
 }
 
-#[derive(Debug, Deserialize)]
-struct AssumeRoleEntry {
-    account_ref: String,
-    role_arn: String,
-    external_id: Option<String>,
+struct PendingReport {
+    /// `None` until the computation finishes
+    outcome: watch::Receiver<Option<ReportOutcome>>,
+    cancel: CancellationToken,
+    /// Requests still waiting; the report is abandoned once this reaches zero
+    waiters: AtomicUsize,
+}
+
+/// One request's interest in a pending report; the last one dropped cancels it.
+struct Waiter(Arc<PendingReport>);
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.0.waiters.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.cancel.cancel();
+        }
+    }
+}
+
+impl InFlightReports {
+    async fn run<F, Fut>(&self, key: NaiveDate, compute: F) -> ReportOutcome
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ReportOutcome> + Send + 'static,
+    {
+        let waiter = {
+            let mut pending = self.pending.lock().await;
+            // A report nobody waits on any more is finished or being cancelled; start over
+            let joined = pending.get(&key).filter(|report| {
+                report
+                    .waiters
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                        (n > 0).then_some(n + 1)
+                    })
+                    .is_ok()
+            });
+            match joined {
+                Some(report) => Waiter(report.clone()),
+                None => {
+                    let report = self.start(key, compute);
+                    pending.insert(key, report.clone());
+                    Waiter(report)
+                }
+            }
+        };
+
+        let mut outcome = waiter.0.outcome.clone();
+        let finished = outcome.wait_for(Option::is_some).await;
+        match finished.as_deref() {
+            Ok(Some(outcome)) => outcome.clone(),
+            _ => Err("report computation ended without a result".to_string()),
+        }
+    }
+
+    fn start<F, Fut>(&self, key: NaiveDate, compute: F) -> Arc<PendingReport>
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ReportOutcome> + Send + 'static,
+    {
+        let (sender, outcome) = watch::channel(None);
+        let cancel = CancellationToken::new();
+        let report = Arc::new(PendingReport {
+            outcome,
+            cancel: cancel.clone(),
+            waiters: AtomicUsize::new(1),
+        });
+        let computation = compute(cancel);
+        let (pending, started) = (self.pending.clone(), report.clone());
+        tokio::spawn(async move {
+            sender.send_replace(Some(computation.await));
+            // Later requests should fetch fresh data rather than reuse this result.
+            let mut pending = pending.lock().await;
+            if pending
+                .get(&key)
+                .is_some_and(|current| Arc::ptr_eq(current, &started))
+            {
+                pending.remove(&key);
+            }
+        });
+        report
+    }
 }
 
 #[tokio::main]
@@ -88,69 +314,93 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
-    let (provider, accounts) = if let Some(path) = args.assume_roles_file.clone() {
-        let contents = std::fs::read_to_string(&path)?;
-        let entries: Vec<AssumeRoleEntry> = serde_json::from_str(&contents)?;
-        let mut roles = HashMap::new();
-        let mut account_refs = Vec::with_capacity(entries.len());
-        for entry in entries {
-            account_refs.push(entry.account_ref.clone());
-            roles.insert(
-                entry.account_ref,
-                AssumeRoleConfig {
-                    role_arn: entry.role_arn,
-                    external_id: entry.external_id,
-                },
-            );
-        }
-        (
-            AwsCostProvider::with_assume_roles(args.region, args.base_profile, roles),
-            account_refs,
-        )
-    } else if let Some(path) = args.accounts_file.clone() {
-        let contents = std::fs::read_to_string(&path)?;
-        let entries: Vec<AccountsFileEntry> = serde_json::from_str(&contents)?;
-        let mut creds_map = HashMap::new();
-        let mut labels = Vec::with_capacity(entries.len());
-        for (idx, entry) in entries.into_iter().enumerate() {
-            let label = format!("credential-{}", idx + 1);
-            labels.push(label.clone());
-            creds_map.insert(
-                label,
-                StaticCredentials {
-                    access_key_id: entry.access_key_id,
-                    secret_access_key: entry.secret_access_key,
-                    session_token: None,
-                },
-            );
-        }
-        (
-            AwsCostProvider::with_static_credentials(args.region, creds_map),
-            labels,
-        )
-    } else {
-        let profiles = if args.profiles.is_empty() {
-            vec!["default".to_string()]
-        } else {
-            args.profiles
-        };
-        (AwsCostProvider::new(args.region), profiles)
-    };
+    let (mut provider, accounts) = build_provider(&AccountSources {
+        assume_roles_files: &args.assume_roles_file,
+        base_profile: args.base_profile.as_deref(),
+        files: &args.accounts_file,
+        ssm_parameter: args.accounts_from_ssm.as_deref(),
+        secret: args.accounts_from_secret.as_deref(),
+        region: &args.region,
+        bootstrap_profile: args.bootstrap_profile.as_deref(),
+        profiles: &args.profiles,
+        profile_accounts_files: &args.profile_accounts_file,
+    })
+    .await?;
+    // The server outlives any MFA code it could be given at start-up
+    if let Some(serial) = mfa_serial_number(&provider, &accounts) {
+        bail!(
+            "Roles that need MFA ({serial}) are not supported by the server; \
+             remove serial_number from --assume-roles-file"
+        );
+    }
+
+    if accounts.is_empty() {
+        bail!(NO_ACCOUNTS);
+    }
+
+    provider.region_from_profile = args.region_from_profile;
+    if let Some(app_id) = &args.app_id {
+        provider.app_name = app_id.clone();
+    }
+    if let Some(page_size) = args.page_size {
+        provider.set_page_size(page_size)?;
+    }
+    provider.apply_partition(args.partition, args.ce_endpoint_url.clone())?;
+    provider.apply_http_settings(&HttpSettings {
+        pool_max_idle_per_host: args.http_pool_size,
+        pool_idle_timeout: Duration::from_secs(args.http_pool_idle_secs),
+        connect_timeout: Duration::from_secs(args.http_connect_timeout_secs),
+        operation_timeout: Duration::from_secs(args.http_timeout_secs),
+    });
 
     let state = Arc::new(AppState {
         provider,
         accounts,
         auth: args.auth,
+        timezone: args.timezone,
+        options: ReportOptions {
+            deadline: args.report_deadline_secs.map(Duration::from_secs),
+            max_concurrency: args.max_concurrency,
+            fail_on_error: args.fail_on_error,
+            strict: args.strict,
+            baseline_periods: args.baseline_periods as usize,
+            min_account_spend: args.min_account_spend,
+            count_small_accounts: args.count_small_accounts,
+            prorate: args.prorate,
+            blend_forecast: args.blend_forecast,
+            ..ReportOptions::default()
+        },
+        inflight: InFlightReports::default(),
+        cache: args.cache_refresh_secs.map(|_| ReportCache::default()),
     });
+    if let Some(secs) = args.cache_refresh_secs {
+        tokio::spawn(refresh_cache(state.clone(), Duration::from_secs(secs)));
+    }
 
     let app = Router::new()
         .route("/health", get(health).options(options_handler))
         .route("/report/aws", get(report_aws).options(options_handler))
-        .with_state(state);
+        .route("/trend/aws", get(trend_aws).options(options_handler))
+        .with_state(state.clone());
 
     let addr: SocketAddr = args.bind.parse()?;
     tracing::info!("listening on {}", addr);
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    let rest = axum::serve(tokio::net::TcpListener::bind(addr).await?, app);
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_bind) = &args.grpc_bind {
+        let grpc_addr: SocketAddr = grpc_bind.parse()?;
+        tracing::info!("gRPC listening on {}", grpc_addr);
+        let grpc = tonic::transport::Server::builder()
+            .add_service(grpc::service(state))
+            .serve(grpc_addr);
+        tokio::try_join!(async { rest.await.map_err(anyhow::Error::from) }, async {
+            grpc.await.map_err(anyhow::Error::from)
+        },)?;
+        return Ok(());
+    }
+
+    rest.await?;
     Ok(())
 }
 
@@ -158,14 +408,38 @@ async fn health() -> impl IntoResponse {
     with_cors(StatusCode::OK.into_response())
 }
 
-async fn report_aws(State(state): State<Arc<AppState>>, headers: HeaderMap) -> impl IntoResponse {
+#[derive(Debug, Deserialize)]
+struct ReportParams {
+    /// Recompute instead of serving the cached report
+    #[serde(default)]
+    fresh: bool,
+}
+
+async fn report_aws(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ReportParams>,
+) -> impl IntoResponse {
     if let Err(status) = authorize(state.auth, &headers) {
         return with_cors(status.into_response());
     }
 
-    let today = Utc::now().date_naive();
-    match generate_report(&state.provider, &state.accounts, today).await {
-        Ok(report) => with_cors(Json(report).into_response()),
+    let Some(format) = negotiate_format(&headers) else {
+        return with_cors(StatusCode::NOT_ACCEPTABLE.into_response());
+    };
+
+    let (outcome, hit) = cached_report(&state, params.fresh).await;
+    match outcome {
+        Ok(report) => {
+            let mut res = render_report(&report, format);
+            if state.cache.is_some() {
+                res.headers_mut().insert(
+                    "x-cache",
+                    HeaderValue::from_static(if hit { "HIT" } else { "MISS" }),
+                );
+            }
+            with_cors(res)
+        }
         Err(err) => {
             tracing::error!(error = %err, "report failed");
             with_cors(StatusCode::INTERNAL_SERVER_ERROR.into_response())
@@ -173,6 +447,183 @@ async fn report_aws(State(state): State<Arc<AppState>>, headers: HeaderMap) -> i
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct TrendParams {
+    /// Months to include, counting the current one; 1 to `MAX_TREND_MONTHS`
+    months: Option<u32>,
+}
+
+/// Per-service spend for each of the last `months` (default 6) months, oldest first, as
+/// `[{"month": "YYYY-MM-01", "services": {...}}]`.
+async fn trend_aws(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<TrendParams>,
+) -> impl IntoResponse {
+    if let Err(status) = authorize(state.auth, &headers) {
+        return with_cors(status.into_response());
+    }
+    let months = params.months.unwrap_or(6);
+    if !(1..=MAX_TREND_MONTHS).contains(&months) {
+        return with_cors(
+            (
+                StatusCode::BAD_REQUEST,
+                format!("months must be between 1 and {MAX_TREND_MONTHS}"),
+            )
+                .into_response(),
+        );
+    }
+
+    let today = today_in(state.timezone);
+    match state
+        .provider
+        .service_trend(&state.accounts, today, months)
+        .await
+    {
+        Ok((trend, _)) => {
+            let months: Vec<serde_json::Value> = trend
+                .iter()
+                .map(
+                    |(month, services)| serde_json::json!({ "month": month, "services": services }),
+                )
+                .collect();
+            with_cors(Json(months).into_response())
+        }
+        Err(err) => {
+            tracing::error!(error = %format!("{err:#}"), "trend failed");
+            with_cors(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+fn render_report(report: &Report, format: ReportFormat) -> Response {
+    let mut res = match format {
+        ReportFormat::Json => match render::json(report, MoneyFormat::Dollars) {
+            Ok(body) => ([(CONTENT_TYPE, "application/json")], body).into_response(),
+            Err(err) => {
+                tracing::error!(error = %err, "report serialization failed");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
+        ReportFormat::Csv => (
+            [(CONTENT_TYPE, "text/csv; charset=utf-8")],
+            render::csv(report, MoneyFormat::Dollars),
+        )
+            .into_response(),
+        ReportFormat::Text => (
+            [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+            render::text(
+                report,
+                &TextOptions {
+                    primary_metric: PRIMARY_METRIC.to_string(),
+                    rank_accounts: false,
+                    rank_limit: None,
+                    min_delta_abs: None,
+                    min_delta_pct: None,
+                },
+            ),
+        )
+            .into_response(),
+        ReportFormat::Markdown => (
+            [(CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            render::markdown(report),
+        )
+            .into_response(),
+        ReportFormat::Html => (
+            [(CONTENT_TYPE, "text/html; charset=utf-8")],
+            render::html(report),
+        )
+            .into_response(),
+    };
+    res.headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept"));
+    res
+}
+
+/// Picks the highest-quality supported media range; JSON when `Accept` is absent or `*/*`.
+fn negotiate_format(headers: &HeaderMap) -> Option<ReportFormat> {
+    let Some(accept) = headers.get(ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return Some(ReportFormat::Json);
+    };
+    if accept.trim().is_empty() {
+        return Some(ReportFormat::Json);
+    }
+
+    let mut ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media = parts.next().unwrap_or_default();
+            let quality = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (media, quality)
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    ranges
+        .into_iter()
+        .find_map(|(media, _)| match media.to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Some(ReportFormat::Json),
+            "text/csv" => Some(ReportFormat::Csv),
+            "text/plain" | "text/*" => Some(ReportFormat::Text),
+            "text/markdown" => Some(ReportFormat::Markdown),
+            "text/html" => Some(ReportFormat::Html),
+            _ => None,
+        })
+}
+
+async fn current_report(state: &Arc<AppState>) -> ReportOutcome {
+    let today = today_in(state.timezone);
+    let outcome = state
+        .inflight
+        .run(today, |cancel| {
+            let state = state.clone();
+            async move {
+                let options = ReportOptions {
+                    cancel: Some(cancel),
+                    ..state.options.clone()
+                };
+                generate_report_with_options(&state.provider, &state.accounts, today, &options)
+                    .await
+                    .map_err(|err| format!("{err:#}"))
+            }
+        })
+        .await;
+    if let (Some(cache), Ok(report)) = (&state.cache, &outcome) {
+        *cache.0.lock().await = Some(report.clone());
+    }
+    outcome
+}
+
+/// The cached report, unless `fresh` is asked for or nothing is cached yet; the flag says
+/// whether it came from the cache.
+async fn cached_report(state: &Arc<AppState>, fresh: bool) -> (ReportOutcome, bool) {
+    if !fresh
+        && let Some(cache) = &state.cache
+        && let Some(report) = cache.0.lock().await.clone()
+    {
+        return (Ok(report), true);
+    }
+    (current_report(state).await, false)
+}
+
+/// Recomputes the cached report every `every`, starting immediately. A failed refresh keeps
+/// serving the previous report.
+async fn refresh_cache(state: Arc<AppState>, every: Duration) {
+    let mut ticks = tokio::time::interval(every);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        if let Err(err) = current_report(&state).await {
+            tracing::warn!(error = %err, "scheduled report refresh failed; keeping the cached report");
+        }
+    }
+}
+
 fn authorize(mode: AuthMode, headers: &HeaderMap) -> Result<(), StatusCode> {
     match mode {
         AuthMode::None => Ok(()),
@@ -204,3 +655,131 @@ fn with_cors(mut res: Response) -> Response {
 async fn options_handler() -> impl IntoResponse {
     with_cors(StatusCode::NO_CONTENT.into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_computation() {
+        let inflight = Arc::new(InFlightReports::default());
+        let computations = Arc::new(AtomicUsize::new(0));
+        let (release, released) = watch::channel(false);
+        let day = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+
+        let requests: Vec<_> = (0..8)
+            .map(|_| {
+                let (inflight, computations) = (inflight.clone(), computations.clone());
+                let released = released.clone();
+                tokio::spawn(async move {
+                    inflight
+                        .run(day, move |_cancel| async move {
+                            computations.fetch_add(1, Ordering::SeqCst);
+                            let mut released = released;
+                            let _ = released.wait_for(|go| *go).await;
+                            Err("provider failed".to_string())
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        // Hold the computation until every request has joined it
+        loop {
+            let waiting = inflight
+                .pending
+                .lock()
+                .await
+                .get(&day)
+                .map_or(0, |report| report.waiters.load(Ordering::SeqCst));
+            if waiting == 8 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        release.send_replace(true);
+
+        for request in requests {
+            assert_eq!(request.await.unwrap().unwrap_err(), "provider failed");
+        }
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn finished_report_is_not_reused() {
+        let inflight = InFlightReports::default();
+        let computations = Arc::new(AtomicUsize::new(0));
+        let day = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+
+        for _ in 0..2 {
+            let computations = computations.clone();
+            let outcome = inflight
+                .run(day, move |_cancel| async move {
+                    computations.fetch_add(1, Ordering::SeqCst);
+                    Err("done".to_string())
+                })
+                .await;
+            assert!(outcome.is_err());
+            // The spawned task removes the finished entry after publishing its outcome
+            while inflight.pending.lock().await.contains_key(&day) {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(computations.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn report_is_cancelled_once_every_waiting_request_is_gone() {
+        let inflight = Arc::new(InFlightReports::default());
+        let (stopped, mut stopped_rx) = watch::channel(false);
+        let day = NaiveDate::from_ymd_opt(2024, 8, 14).unwrap();
+        let waiting = || async {
+            inflight
+                .pending
+                .lock()
+                .await
+                .get(&day)
+                .map_or(0, |report| report.waiters.load(Ordering::SeqCst))
+        };
+
+        let requests: Vec<_> = (0..2)
+            .map(|_| {
+                let (inflight, stopped) = (inflight.clone(), stopped.clone());
+                tokio::spawn(async move {
+                    inflight
+                        .run(day, move |cancel| async move {
+                            cancel.cancelled().await;
+                            stopped.send_replace(true);
+                            Err("cancelled".to_string())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        while waiting().await < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // One client going away leaves the computation running for the other
+        requests[0].abort();
+        while waiting().await > 1 {
+            tokio::task::yield_now().await;
+        }
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!*stopped_rx.borrow());
+
+        requests[1].abort();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            stopped_rx.wait_for(|stopped| *stopped),
+        )
+        .await
+        .expect("the abandoned computation was cancelled")
+        .unwrap();
+        while inflight.pending.lock().await.contains_key(&day) {
+            tokio::task::yield_now().await;
+        }
+    }
+}
@@ -0,0 +1,559 @@
+//! Account lists shared by the CLI and the API: the entries of `--accounts-file`,
+//! `--assume-roles-file` and `--profile-accounts-file`, where they are read from, and the
+//! provider built on them.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    AssumeRoleConfig, AwsCostProvider, StaticCredentials, load_secret_string, load_ssm_parameter,
+    parse_granularity,
+};
+
+/// Error for a configuration that names no account at all.
+pub const NO_ACCOUNTS: &str = "No accounts configured; pass --profiles, --assume-roles-file, or \
+                               an accounts list via --accounts-file, --accounts-from-ssm or \
+                               --accounts-from-secret";
+
+#[derive(Debug, Deserialize)]
+pub struct AccountsFileEntry {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Scope this entry to one linked account of an org-wide credential
+    pub linked_account_id: Option<String>,
+    /// Overrides the default granularity for this account
+    pub granularity: Option<String>,
+}
+
+impl AccountsFileEntry {
+    /// Identifies an entry for duplicate detection; the secret is left out of error messages.
+    pub fn key(&self) -> String {
+        match &self.linked_account_id {
+            Some(linked_id) => format!("{} (linked account {linked_id})", self.access_key_id),
+            None => self.access_key_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssumeRoleEntry {
+    pub account_ref: String,
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub via_role_arns: Vec<String>,
+    pub serial_number: Option<String>,
+    pub granularity: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileAccountsEntry {
+    pub profile: String,
+    /// Linked accounts read with this profile's credentials, one account each
+    pub linked_account_ids: Vec<String>,
+}
+
+/// Where the accounts come from. `build_provider` uses the assume-roles files when given,
+/// then a list of `AccountsFileEntry` (the first of files, SSM parameter or secret set),
+/// then the profiles.
+#[derive(Debug, Default)]
+pub struct AccountSources<'a> {
+    /// `--assume-roles-file`, read and concatenated in order
+    pub assume_roles_files: &'a [PathBuf],
+    /// Profile the roles are assumed from
+    pub base_profile: Option<&'a str>,
+    /// `--accounts-file`, read and concatenated in order
+    pub files: &'a [PathBuf],
+    /// Name of an SSM parameter holding the JSON list
+    pub ssm_parameter: Option<&'a str>,
+    /// Name of a Secrets Manager secret holding the JSON list
+    pub secret: Option<&'a str>,
+    /// Region and profile used to read the parameter or secret
+    pub region: &'a str,
+    pub bootstrap_profile: Option<&'a str>,
+    /// `--profiles`, plus the accounts of `--profile-accounts-file`
+    pub profiles: &'a [String],
+    pub profile_accounts_files: &'a [PathBuf],
+}
+
+/// The provider for `sources` and the accounts to report on, in order. Credentials from an
+/// accounts list are named `credential-1`, `credential-2`, …; with no source at all the
+/// `default` profile is used. Roles with a `serial_number` still need
+/// `AwsCostProvider::mfa_token_code`, which is up to the caller.
+pub async fn build_provider(
+    sources: &AccountSources<'_>,
+) -> Result<(AwsCostProvider, Vec<String>)> {
+    if !sources.assume_roles_files.is_empty() {
+        let entries = read_assume_roles(sources.assume_roles_files)?;
+        let mut roles = HashMap::new();
+        let mut account_granularity = HashMap::new();
+        let mut account_refs = Vec::with_capacity(entries.len());
+        for entry in entries {
+            account_refs.push(entry.account_ref.clone());
+            if let Some(granularity) = &entry.granularity {
+                account_granularity
+                    .insert(entry.account_ref.clone(), parse_granularity(granularity)?);
+            }
+            roles.insert(
+                entry.account_ref,
+                AssumeRoleConfig {
+                    role_arn: entry.role_arn,
+                    external_id: entry.external_id,
+                    via_role_arns: entry.via_role_arns,
+                    serial_number: entry.serial_number,
+                },
+            );
+        }
+        let mut provider = AwsCostProvider::with_assume_roles(
+            sources.region,
+            sources.base_profile.map(String::from),
+            roles,
+        );
+        provider.account_granularity = account_granularity;
+        return Ok((provider, account_refs));
+    }
+
+    if let Some(entries) = load_account_entries(sources).await? {
+        let mut creds_map = HashMap::new();
+        let mut linked_accounts = HashMap::new();
+        let mut account_granularity = HashMap::new();
+        let mut labels = Vec::with_capacity(entries.len());
+        for (idx, entry) in entries.into_iter().enumerate() {
+            let label = format!("credential-{}", idx + 1);
+            labels.push(label.clone());
+            if let Some(linked_id) = entry.linked_account_id {
+                linked_accounts.insert(label.clone(), linked_id);
+            }
+            if let Some(granularity) = &entry.granularity {
+                account_granularity.insert(label.clone(), parse_granularity(granularity)?);
+            }
+            creds_map.insert(
+                label,
+                StaticCredentials {
+                    access_key_id: entry.access_key_id,
+                    secret_access_key: entry.secret_access_key,
+                    session_token: None,
+                },
+            );
+        }
+        let mut provider = AwsCostProvider::with_static_credentials(sources.region, creds_map);
+        provider.linked_accounts = linked_accounts;
+        provider.account_granularity = account_granularity;
+        return Ok((provider, labels));
+    }
+
+    let mut provider = AwsCostProvider::new(sources.region);
+    let mut profiles = sources.profiles.to_vec();
+    profiles.extend(add_profile_accounts(
+        &mut provider,
+        sources.profile_accounts_files,
+    )?);
+    if profiles.is_empty() {
+        profiles.push("default".to_string());
+    }
+    Ok((provider, profiles))
+}
+
+/// Serial number of the first role in `accounts` that needs an MFA code.
+pub fn mfa_serial_number(provider: &AwsCostProvider, accounts: &[String]) -> Option<String> {
+    let roles = provider.assume_roles.as_ref()?;
+    accounts
+        .iter()
+        .find_map(|account_ref| roles.get(account_ref)?.serial_number.clone())
+}
+
+/// Accounts from every file in order, or from SSM / Secrets Manager; `None` when no source
+/// is set. The same credential (and linked account) listed twice is rejected.
+pub async fn load_account_entries(
+    sources: &AccountSources<'_>,
+) -> Result<Option<Vec<AccountsFileEntry>>> {
+    let profile = sources.bootstrap_profile;
+    if !sources.files.is_empty() {
+        let entries = read_json_entries::<AccountsFileEntry>(sources.files)?;
+        reject_duplicates(
+            "credential",
+            entries
+                .iter()
+                .map(|(path, entry)| (path.display(), entry.key())),
+        )?;
+        return Ok(Some(entries.into_iter().map(|(_, entry)| entry).collect()));
+    }
+    let (source, contents) = if let Some(name) = sources.ssm_parameter {
+        (
+            format!("SSM parameter {name}"),
+            load_ssm_parameter(sources.region, profile, name).await?,
+        )
+    } else if let Some(name) = sources.secret {
+        (
+            format!("secret {name}"),
+            load_secret_string(sources.region, profile, name).await?,
+        )
+    } else {
+        return Ok(None);
+    };
+    parse_account_entries(&source, &contents).map(Some)
+}
+
+/// Parses the JSON list held in `source` (an SSM parameter or a secret), rejecting the
+/// same credential listed twice.
+fn parse_account_entries(source: &str, contents: &str) -> Result<Vec<AccountsFileEntry>> {
+    let entries: Vec<AccountsFileEntry> =
+        serde_json::from_str(contents).with_context(|| format!("Invalid JSON in {source}"))?;
+    reject_duplicates(
+        "credential",
+        entries.iter().map(|entry| (source, entry.key())),
+    )?;
+    Ok(entries)
+}
+
+/// Entries of every `--assume-roles-file` in order; an `account_ref` listed twice is rejected.
+pub fn read_assume_roles(paths: &[PathBuf]) -> Result<Vec<AssumeRoleEntry>> {
+    let entries = read_json_entries::<AssumeRoleEntry>(paths)?;
+    reject_duplicates(
+        "account_ref",
+        entries
+            .iter()
+            .map(|(path, entry)| (path.display(), entry.account_ref.clone())),
+    )?;
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Expands `--profile-accounts-file` entries into one account per linked account id, named
+/// `<profile>/<id>`, and points each at its profile's credentials with a LINKED_ACCOUNT filter.
+pub fn add_profile_accounts(
+    provider: &mut AwsCostProvider,
+    paths: &[PathBuf],
+) -> Result<Vec<String>> {
+    let entries = read_json_entries::<ProfileAccountsEntry>(paths)?;
+    let refs: Vec<(&Path, String, String, String)> = entries
+        .iter()
+        .flat_map(|(path, entry)| {
+            entry.linked_account_ids.iter().map(|linked_id| {
+                (
+                    *path,
+                    format!("{}/{linked_id}", entry.profile),
+                    entry.profile.clone(),
+                    linked_id.clone(),
+                )
+            })
+        })
+        .collect();
+    reject_duplicates(
+        "profile account",
+        refs.iter()
+            .map(|(path, account_ref, ..)| (path.display(), account_ref.clone())),
+    )?;
+    let mut accounts = Vec::with_capacity(refs.len());
+    for (_, account_ref, profile, linked_id) in refs {
+        provider
+            .account_profiles
+            .insert(account_ref.clone(), profile);
+        provider
+            .linked_accounts
+            .insert(account_ref.clone(), linked_id);
+        accounts.push(account_ref);
+    }
+    Ok(accounts)
+}
+
+/// Reads a JSON array from each file and concatenates the entries in the order given, each
+/// paired with the file it came from.
+fn read_json_entries<T: DeserializeOwned>(paths: &[PathBuf]) -> Result<Vec<(&Path, T)>> {
+    let mut entries = Vec::new();
+    for path in paths {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let parsed: Vec<T> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid JSON in {}", path.display()))?;
+        entries.extend(parsed.into_iter().map(|entry| (path.as_path(), entry)));
+    }
+    Ok(entries)
+}
+
+/// Fails on the first key seen twice, naming where each copy came from.
+fn reject_duplicates<S: Display>(
+    what: &str,
+    keys: impl IntoIterator<Item = (S, String)>,
+) -> Result<()> {
+    let mut seen: HashMap<String, S> = HashMap::new();
+    for (source, key) in keys {
+        match seen.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(source);
+            }
+            Entry::Occupied(seen) => {
+                let (key, first, second) = (seen.key(), seen.get().to_string(), source.to_string());
+                if first == second {
+                    bail!("Duplicate {what} {key:?} in {first}");
+                }
+                bail!("Duplicate {what} {key:?} in {first} and {second}");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ccm-aws-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn accounts_files_are_merged_in_order_and_duplicates_rejected() {
+        let (team_a, team_b, team_c) = (
+            temp_path("team-a.json"),
+            temp_path("team-b.json"),
+            temp_path("team-c.json"),
+        );
+        fs::write(
+            &team_a,
+            r#"[{"access_key_id":"AKIAA","secret_access_key":"shh-a"},
+                {"access_key_id":"AKIAORG","secret_access_key":"shh-o","linked_account_id":"111111111111"}]"#,
+        )
+        .unwrap();
+        // Same credential, different linked account: a distinct entry
+        fs::write(
+            &team_b,
+            r#"[{"access_key_id":"AKIAORG","secret_access_key":"shh-o","linked_account_id":"222222222222"},
+                {"access_key_id":"AKIAB","secret_access_key":"shh-b"}]"#,
+        )
+        .unwrap();
+        fs::write(
+            &team_c,
+            r#"[{"access_key_id":"AKIAA","secret_access_key":"shh-a"}]"#,
+        )
+        .unwrap();
+        let load = |files: &[PathBuf]| {
+            let files = files.to_vec();
+            async move {
+                load_account_entries(&AccountSources {
+                    files: &files,
+                    region: "us-east-1",
+                    ..AccountSources::default()
+                })
+                .await
+            }
+        };
+
+        let merged = load(&[team_a.clone(), team_b.clone()])
+            .await
+            .unwrap()
+            .unwrap();
+        let duplicate = load(&[team_a.clone(), team_b.clone(), team_c.clone()]).await;
+        for path in [&team_a, &team_b, &team_c] {
+            fs::remove_file(path).unwrap();
+        }
+
+        let keys: Vec<String> = merged.iter().map(AccountsFileEntry::key).collect();
+        assert_eq!(
+            keys,
+            [
+                "AKIAA",
+                "AKIAORG (linked account 111111111111)",
+                "AKIAORG (linked account 222222222222)",
+                "AKIAB",
+            ]
+        );
+        let err = duplicate.unwrap_err().to_string();
+        assert!(err.contains("Duplicate credential \"AKIAA\""), "{err}");
+        assert!(
+            err.contains("team-a.json") && err.contains("team-c.json"),
+            "{err}"
+        );
+        // The secret never ends up in the message
+        assert!(!err.contains("shh"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn no_source_means_no_entries() {
+        let sources = AccountSources {
+            region: "us-east-1",
+            ..AccountSources::default()
+        };
+        assert!(load_account_entries(&sources).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn the_provider_is_built_from_the_first_source_given() {
+        let (roles, accounts) = (
+            temp_path("build-roles.json"),
+            temp_path("build-accounts.json"),
+        );
+        fs::write(
+            &roles,
+            r#"[{"account_ref":"prod","role_arn":"arn:aws:iam::111111111111:role/CostReader"},
+                {"account_ref":"audit","role_arn":"arn:aws:iam::222222222222:role/CostReader",
+                 "serial_number":"arn:aws:iam::222222222222:mfa/ops"}]"#,
+        )
+        .unwrap();
+        fs::write(
+            &accounts,
+            r#"[{"access_key_id":"AKIAA","secret_access_key":"shh-a"},
+                {"access_key_id":"AKIAORG","secret_access_key":"shh-o","linked_account_id":"111111111111"}]"#,
+        )
+        .unwrap();
+        let profiles = ["dev".to_string()];
+        let sources = AccountSources {
+            assume_roles_files: std::slice::from_ref(&roles),
+            files: std::slice::from_ref(&accounts),
+            region: "us-east-1",
+            profiles: &profiles,
+            ..AccountSources::default()
+        };
+
+        let from_roles = build_provider(&sources).await;
+        let from_accounts = build_provider(&AccountSources {
+            assume_roles_files: &[],
+            ..sources
+        })
+        .await;
+        fs::remove_file(&roles).unwrap();
+        fs::remove_file(&accounts).unwrap();
+
+        let (provider, refs) = from_roles.unwrap();
+        assert_eq!(refs, ["prod", "audit"]);
+        assert_eq!(
+            mfa_serial_number(&provider, &refs).as_deref(),
+            Some("arn:aws:iam::222222222222:mfa/ops")
+        );
+        let (provider, refs) = from_accounts.unwrap();
+        assert_eq!(refs, ["credential-1", "credential-2"]);
+        assert_eq!(provider.linked_accounts["credential-2"], "111111111111");
+        assert_eq!(mfa_serial_number(&provider, &refs), None);
+
+        let (_, refs) = build_provider(&AccountSources {
+            region: "us-east-1",
+            ..AccountSources::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(refs, ["default"]);
+    }
+
+    #[test]
+    fn ssm_and_secret_lists_are_checked_like_files() {
+        let err = parse_account_entries("SSM parameter /ccm/accounts", "[{")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Invalid JSON in SSM parameter /ccm/accounts");
+
+        let err = parse_account_entries(
+            "secret ccm-accounts",
+            r#"[{"access_key_id":"AKIAA","secret_access_key":"shh-a"},
+                {"access_key_id":"AKIAA","secret_access_key":"shh-a"}]"#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(err, "Duplicate credential \"AKIAA\" in secret ccm-accounts");
+    }
+
+    #[test]
+    fn assume_roles_files_reject_a_repeated_account_ref() {
+        let (first, second) = (temp_path("roles-1.json"), temp_path("roles-2.json"));
+        fs::write(
+            &first,
+            r#"[{"account_ref":"prod","role_arn":"arn:aws:iam::111111111111:role/CostReader"}]"#,
+        )
+        .unwrap();
+        fs::write(
+            &second,
+            r#"[{"account_ref":"dev","role_arn":"arn:aws:iam::222222222222:role/CostReader"}]"#,
+        )
+        .unwrap();
+        let third = temp_path("roles-3.json");
+        fs::write(
+            &third,
+            r#"[{"account_ref":"prod","role_arn":"arn:aws:iam::333333333333:role/CostReader"}]"#,
+        )
+        .unwrap();
+
+        let entries = read_assume_roles(&[first.clone(), second.clone()]);
+        let err = read_assume_roles(&[first.clone(), second.clone(), third.clone()])
+            .unwrap_err()
+            .to_string();
+        for path in [&first, &second, &third] {
+            fs::remove_file(path).unwrap();
+        }
+
+        let refs: Vec<String> = entries
+            .unwrap()
+            .into_iter()
+            .map(|e| e.account_ref)
+            .collect();
+        assert_eq!(refs, ["prod", "dev"]);
+        assert!(err.contains("Duplicate account_ref \"prod\""), "{err}");
+        assert!(
+            err.contains("roles-1.json") && err.contains("roles-3.json"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn one_profile_entry_yields_one_account_per_linked_id() {
+        let path = temp_path("profile-accounts.json");
+        fs::write(
+            &path,
+            r#"[{"profile": "payer", "linked_account_ids": ["111111111111", "222222222222", "333333333333"]}]"#,
+        )
+        .unwrap();
+        let mut provider = AwsCostProvider::new("us-east-1");
+
+        let accounts = add_profile_accounts(&mut provider, std::slice::from_ref(&path));
+        fs::remove_file(&path).unwrap();
+
+        let accounts = accounts.unwrap();
+        assert_eq!(
+            accounts,
+            [
+                "payer/111111111111",
+                "payer/222222222222",
+                "payer/333333333333"
+            ]
+        );
+        for account_ref in &accounts {
+            assert_eq!(provider.account_profiles[account_ref], "payer");
+            assert_eq!(
+                provider.linked_accounts[account_ref],
+                account_ref.trim_start_matches("payer/")
+            );
+        }
+    }
+
+    #[test]
+    fn a_linked_account_listed_twice_for_one_profile_is_rejected() {
+        let (first, second) = (temp_path("profiles-1.json"), temp_path("profiles-2.json"));
+        fs::write(
+            &first,
+            r#"[{"profile": "payer", "linked_account_ids": ["111111111111"]}]"#,
+        )
+        .unwrap();
+        // The same id under another profile is a distinct account
+        fs::write(
+            &second,
+            r#"[{"profile": "audit", "linked_account_ids": ["111111111111"]},
+                {"profile": "payer", "linked_account_ids": ["111111111111"]}]"#,
+        )
+        .unwrap();
+        let mut provider = AwsCostProvider::new("us-east-1");
+
+        let err = add_profile_accounts(&mut provider, &[first.clone(), second.clone()]);
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        let err = err.unwrap_err().to_string();
+        assert!(
+            err.contains("Duplicate profile account \"payer/111111111111\""),
+            "{err}"
+        );
+        assert!(provider.account_profiles.is_empty());
+    }
+}
@@ -1,16 +1,71 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use async_trait::async_trait;
-use aws_config::{BehaviorVersion, Region};
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::ProfileFileRegionProvider;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::{BehaviorVersion, ConfigLoader, Region};
 use aws_credential_types::Credentials;
+use aws_credential_types::provider::ProvideCredentials;
 use aws_sdk_costexplorer::Client as CeClient;
-use aws_sdk_costexplorer::types::{DateInterval, Granularity};
+use aws_sdk_costexplorer::config::SharedHttpClient;
+use aws_sdk_costexplorer::types::{
+    ComparisonMetricValue, DateInterval, Dimension, DimensionValues, Expression, Group,
+    GroupDefinition, GroupDefinitionType, Metric,
+};
 use aws_sdk_iam::Client as IamClient;
 use aws_sdk_organizations::Client as OrgClient;
+use aws_sdk_organizations::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_secretsmanager::Client as SecretsClient;
+use aws_sdk_ssm::Client as SsmClient;
 use aws_sdk_sts::Client as StsClient;
-use chrono::NaiveDate;
-use std::collections::HashMap;
+use aws_smithy_http_client::tls;
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
 
-use cloud_cost_core::{AccountSummary, CostProvider};
+use cloud_cost_core::{
+    AccountError, AccountSummary, BlendedSplit, CostDelta, CostDriver, CostProvider,
+    DimensionBreakdown, MetricBreakdown, NativeComparison, SavingsRate, SummaryLimiter,
+    TAG_DIMENSION_PREFIX, UNTAGGED, shared_currency, validate_window,
+};
+use futures::future::join_all;
+
+pub mod accounts;
+
+pub use aws_config::AppName;
+pub use aws_sdk_costexplorer::types::Granularity;
+
+/// Default metric used for totals and the per-service breakdown.
+pub const PRIMARY_METRIC: &str = "UnblendedCost";
+
+/// Cost metrics `AwsCostProvider::metric` may be set to.
+pub const COST_METRICS: &[&str] = &[
+    "UnblendedCost",
+    "BlendedCost",
+    "AmortizedCost",
+    "NetUnblendedCost",
+    "NetAmortizedCost",
+];
+
+/// Usage metrics `AwsCostProvider::usage_forecast` can project.
+pub const USAGE_FORECAST_METRICS: &[&str] = &["UsageQuantity", "NormalizedUsageAmount"];
+
+/// Largest `MaxResults` each paginated API accepts; `AwsCostProvider::page_size` is capped
+/// at these per call.
+const ORGANIZATIONS_MAX_RESULTS: i32 = 20;
+const COMPARISONS_MAX_RESULTS: i32 = 2000;
+const COMPARISON_DRIVERS_MAX_RESULTS: i32 = 10;
+const COST_CATEGORIES_MAX_RESULTS: i32 = 100;
+
+/// Per-service metric behind `AwsCostProvider::amortized_services`.
+const NET_AMORTIZED_METRIC: &str = "NetAmortizedCost";
+
+/// Compared against `PRIMARY_METRIC` by `AwsCostProvider::blended_split`, and against
+/// `NET_AMORTIZED_METRIC` by `AwsCostProvider::savings_rate`.
+const BLENDED_METRIC: &str = "BlendedCost";
 
 #[derive(Debug, Clone)]
 pub struct StaticCredentials {
@@ -35,6 +90,337 @@ impl StaticCredentials {
 pub struct AssumeRoleConfig {
     pub role_arn: String,
     pub external_id: Option<String>,
+    /// Intermediate roles assumed in order before `role_arn`, each with the previous hop's credentials
+    pub via_role_arns: Vec<String>,
+    /// MFA device ARN required by the first role in the chain
+    pub serial_number: Option<String>,
+}
+
+/// Extra Cost Explorer groupings reported next to the per-service breakdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Grouping {
+    /// On-demand vs spot vs reserved (`PURCHASE_TYPE` dimension)
+    PurchaseType,
+    /// Values of the named AWS Cost Category
+    CostCategory(String),
+    /// Values of a cost allocation tag key; spend without a value lands in `UNTAGGED`
+    Tag(String),
+    /// `USAGE_TYPE` values (e.g. `USE1-DataTransfer-Out-Bytes`), optionally only those
+    /// matching a `*` / `?` wildcard pattern, matched client-side
+    UsageType(Option<String>),
+    /// Availability zone (`AZ` dimension); charges without one land in `NO_AZ`
+    AvailabilityZone,
+}
+
+/// Bucket of the `AvailabilityZone` grouping for regional and global charges, which Cost
+/// Explorer reports with an empty or `NoAZ` zone.
+pub const NO_AZ: &str = "No AZ";
+
+impl Grouping {
+    pub fn label(&self) -> String {
+        match self {
+            Grouping::PurchaseType => "PURCHASE_TYPE".to_string(),
+            Grouping::CostCategory(name) => format!("COST_CATEGORY {name}"),
+            Grouping::Tag(key) => format!("{TAG_DIMENSION_PREFIX}{key}"),
+            Grouping::UsageType(None) => "USAGE_TYPE".to_string(),
+            Grouping::UsageType(Some(pattern)) => format!("USAGE_TYPE {pattern}"),
+            Grouping::AvailabilityZone => "AZ".to_string(),
+        }
+    }
+
+    /// Whether a returned group value belongs in the breakdown.
+    fn keeps(&self, value: &str) -> bool {
+        match self {
+            Grouping::UsageType(Some(pattern)) => wildcard_match(pattern, value),
+            _ => true,
+        }
+    }
+
+    fn group_definition(&self) -> GroupDefinition {
+        match self {
+            Grouping::PurchaseType => GroupDefinition::builder()
+                .key("PURCHASE_TYPE")
+                .r#type(GroupDefinitionType::Dimension)
+                .build(),
+            Grouping::CostCategory(name) => GroupDefinition::builder()
+                .key(name)
+                .r#type(GroupDefinitionType::CostCategory)
+                .build(),
+            Grouping::Tag(key) => GroupDefinition::builder()
+                .key(key)
+                .r#type(GroupDefinitionType::Tag)
+                .build(),
+            Grouping::UsageType(_) => GroupDefinition::builder()
+                .key("USAGE_TYPE")
+                .r#type(GroupDefinitionType::Dimension)
+                .build(),
+            Grouping::AvailabilityZone => GroupDefinition::builder()
+                .key("AZ")
+                .r#type(GroupDefinitionType::Dimension)
+                .build(),
+        }
+    }
+}
+
+/// Dimensions `GetCostAndUsage` accepts as a group-by key.
+pub const GROUPABLE_DIMENSIONS: &[&str] = &[
+    "AZ",
+    "INSTANCE_TYPE",
+    "LEGAL_ENTITY_NAME",
+    "INVOICING_ENTITY",
+    "LINKED_ACCOUNT",
+    "OPERATION",
+    "PLATFORM",
+    "PURCHASE_TYPE",
+    "REGION",
+    "SERVICE",
+    "TENANCY",
+    "RECORD_TYPE",
+    "USAGE_TYPE",
+];
+
+/// A cost category definition, as listed by `ListCostCategoryDefinitions`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CostCategory {
+    /// What `--by-cost-category` takes
+    pub name: String,
+    pub effective_start: String,
+    /// `None` for the current version of the definition
+    pub effective_end: Option<String>,
+}
+
+/// One Cost Explorer group-by key, as given to `--group-by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupKey {
+    /// A dimension from `GROUPABLE_DIMENSIONS`, e.g. `service` or `region`
+    Dimension(String),
+    /// A cost allocation tag key (`tag:<key>`)
+    Tag(String),
+    /// A cost category (`cost-category:<name>`)
+    CostCategory(String),
+}
+
+impl GroupKey {
+    pub fn label(&self) -> String {
+        match self {
+            GroupKey::Dimension(name) => name.clone(),
+            GroupKey::Tag(key) => format!("TAG {key}"),
+            GroupKey::CostCategory(name) => format!("COST_CATEGORY {name}"),
+        }
+    }
+
+    fn group_definition(&self) -> GroupDefinition {
+        let (key, kind) = match self {
+            GroupKey::Dimension(name) => (name, GroupDefinitionType::Dimension),
+            GroupKey::Tag(key) => (key, GroupDefinitionType::Tag),
+            GroupKey::CostCategory(name) => (name, GroupDefinitionType::CostCategory),
+        };
+        GroupDefinition::builder().key(key).r#type(kind).build()
+    }
+}
+
+impl std::str::FromStr for GroupKey {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Some(key) = value.strip_prefix("tag:") {
+            if key.is_empty() {
+                bail!("Empty tag key in group-by {value:?}");
+            }
+            return Ok(GroupKey::Tag(key.to_string()));
+        }
+        if let Some(name) = value.strip_prefix("cost-category:") {
+            if name.is_empty() {
+                bail!("Empty cost category in group-by {value:?}");
+            }
+            return Ok(GroupKey::CostCategory(name.to_string()));
+        }
+        let dimension = value.to_ascii_uppercase().replace('-', "_");
+        if !GROUPABLE_DIMENSIONS.contains(&dimension.as_str()) {
+            bail!(
+                "Cannot group by {value:?}; expected tag:<key>, cost-category:<name> or one of {}",
+                GROUPABLE_DIMENSIONS.join(", ").to_ascii_lowercase()
+            );
+        }
+        Ok(GroupKey::Dimension(dimension))
+    }
+}
+
+/// Parses a comma-separated group-by list. Cost Explorer takes at most two distinct keys;
+/// dimensions, tags and cost categories may be mixed in any combination.
+pub fn parse_group_by(spec: &str) -> Result<Vec<GroupKey>> {
+    let keys = spec
+        .split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<GroupKey>>>()?;
+    match keys.as_slice() {
+        [] => bail!("--group-by needs at least one key"),
+        [first, second] if first == second => bail!("Cannot group by {} twice", first.label()),
+        [_] | [_, _] => Ok(keys),
+        _ => bail!(
+            "Cost Explorer groups by at most two keys, got {}",
+            keys.len()
+        ),
+    }
+}
+
+/// What a usage forecast is narrowed to, as given to `forecast-usage --filter`.
+/// `GetUsageForecast` needs exactly one usage type or usage type group, since quantities
+/// in different units cannot be added up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsageFilter {
+    /// `usage-type=<type>`, e.g. `usage-type=USE1-BoxUsage:m5.large`
+    UsageType(String),
+    /// `usage-type-group=<group>`, e.g. `usage-type-group=EC2: Running Hours`
+    UsageTypeGroup(String),
+}
+
+impl UsageFilter {
+    fn expression(&self) -> Expression {
+        let (dimension, value) = match self {
+            UsageFilter::UsageType(value) => (Dimension::UsageType, value),
+            UsageFilter::UsageTypeGroup(value) => (Dimension::UsageTypeGroup, value),
+        };
+        dimension_filter(dimension, std::slice::from_ref(value))
+    }
+}
+
+impl std::fmt::Display for UsageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsageFilter::UsageType(value) => write!(f, "usage type {value}"),
+            UsageFilter::UsageTypeGroup(value) => write!(f, "usage type group {value}"),
+        }
+    }
+}
+
+impl std::str::FromStr for UsageFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let Some((key, usage)) = value.split_once('=') else {
+            bail!(
+                "Usage forecast filter {value:?} must be usage-type=<type> or usage-type-group=<group>"
+            );
+        };
+        let usage = usage.trim();
+        if usage.is_empty() {
+            bail!("Empty value in usage forecast filter {value:?}");
+        }
+        if usage.contains(',') {
+            bail!("GetUsageForecast forecasts one usage type at a time, got {usage:?}");
+        }
+        match key.trim().to_ascii_uppercase().replace('-', "_").as_str() {
+            "USAGE_TYPE" => Ok(UsageFilter::UsageType(usage.to_string())),
+            "USAGE_TYPE_GROUP" => Ok(UsageFilter::UsageTypeGroup(usage.to_string())),
+            _ => bail!(
+                "Usage forecasts filter on usage-type or usage-type-group, not {:?}",
+                key.trim()
+            ),
+        }
+    }
+}
+
+/// Projected usage of one usage type, from `GetUsageForecast`.
+#[derive(Debug, Clone, Default)]
+pub struct UsageForecast {
+    pub total: f64,
+    /// Unit of every quantity, e.g. `Hrs` or `GB`; `None` when Cost Explorer does not say
+    pub unit: Option<String>,
+    /// Forecast per calendar month, keyed by the first day forecast in that month
+    pub months: BTreeMap<NaiveDate, f64>,
+}
+
+/// `cloud-cost-manager-<version>`, the default `AwsCostProvider::app_name`.
+pub fn default_app_name() -> AppName {
+    AppName::new(concat!("cloud-cost-manager-", env!("CARGO_PKG_VERSION")))
+        .expect("crate name and version form a valid app name")
+}
+
+/// Validates a user-supplied app name (letters, digits and ``!#$%&'*+-.^_`|~``, at most 50
+/// characters; no `/` or spaces).
+pub fn parse_app_name(value: &str) -> Result<AppName> {
+    AppName::new(value.to_string()).map_err(|err| anyhow!("Invalid app id {value:?}: {err}"))
+}
+
+/// AWS partition the accounts live in; Cost Explorer has one endpoint per partition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Partition {
+    #[default]
+    Aws,
+    /// China (Beijing, Ningxia)
+    AwsCn,
+    /// AWS GovCloud (US)
+    AwsUsGov,
+}
+
+impl Partition {
+    /// Region hosting the partition's Cost Explorer endpoint.
+    pub fn cost_explorer_region(&self) -> &'static str {
+        match self {
+            Partition::Aws => "us-east-1",
+            Partition::AwsCn => "cn-northwest-1",
+            Partition::AwsUsGov => "us-gov-west-1",
+        }
+    }
+
+    fn contains_region(&self, region: &str) -> bool {
+        match self {
+            Partition::Aws => !region.starts_with("cn-") && !region.starts_with("us-gov-"),
+            Partition::AwsCn => region.starts_with("cn-"),
+            Partition::AwsUsGov => region.starts_with("us-gov-"),
+        }
+    }
+}
+
+impl std::str::FromStr for Partition {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "aws" => Ok(Partition::Aws),
+            "aws-cn" => Ok(Partition::AwsCn),
+            "aws-us-gov" => Ok(Partition::AwsUsGov),
+            other => Err(format!(
+                "unknown partition {other:?}; expected aws, aws-cn or aws-us-gov"
+            )),
+        }
+    }
+}
+
+/// Connection pool and timeout settings shared by every AWS SDK client.
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub connect_timeout: Duration,
+    pub operation_timeout: Duration,
+}
+
+impl HttpSettings {
+    fn build_client(&self) -> SharedHttpClient {
+        let mut hyper_builder = hyper_util::client::legacy::Builder::new(TokioExecutor::new());
+        hyper_builder
+            .pool_timer(TokioTimer::new())
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout);
+
+        aws_smithy_http_client::Builder::new()
+            .hyper_builder(hyper_builder)
+            .tls_provider(tls::Provider::Rustls(
+                tls::rustls_provider::CryptoMode::AwsLc,
+            ))
+            .build_https()
+    }
+
+    fn timeout_config(&self) -> TimeoutConfig {
+        TimeoutConfig::builder()
+            .connect_timeout(self.connect_timeout)
+            .operation_timeout(self.operation_timeout)
+            .build()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,8 +429,134 @@ pub struct AwsCostProvider {
     pub static_credentials: Option<HashMap<String, StaticCredentials>>,
     pub assume_roles: Option<HashMap<String, AssumeRoleConfig>>,
     pub base_profile: Option<String>,
+    /// Current MFA code for roles with a `serial_number`
+    pub mfa_token_code: Option<String>,
+    /// For profile-based accounts, use each profile's configured region and fall back to
+    /// `region` only when the profile has none
+    pub region_from_profile: bool,
+    /// Let `resolve_accounts` drop accounts whose credentials cannot be loaded (e.g. a role
+    /// that is not provisioned yet) instead of failing
+    pub skip_unassumable: bool,
+    /// Fail on malformed Cost Explorer amounts instead of counting them as zero
+    pub strict_amounts: bool,
+    /// Restrict every query to these SERVICE values; totals then cover only these services
+    pub only_services: Vec<String>,
+    /// Also fetch `BlendedCost` (in the same query) and report `AccountSummary::blended`
+    pub blended_split: bool,
+    /// Also fetch `BlendedCost` and `NetAmortizedCost` (in the same query) and report
+    /// `AccountSummary::savings_rate`
+    pub savings_rate: bool,
+    /// Also fetch `NetAmortizedCost` (in the same query) and report it per service as
+    /// `AccountSummary::amortized_services`
+    pub amortized_services: bool,
+    /// Leave `RECORD_TYPE` Tax out of services and totals and report it as `AccountSummary::tax`
+    pub separate_tax: bool,
+    /// Bucket size for current-period queries; buckets are summed into the service map
+    pub granularity: Granularity,
+    /// Per-account overrides of `granularity`, keyed by account reference
+    pub account_granularity: HashMap<String, Granularity>,
+    /// Metric behind services, totals and both comparison windows (one of `COST_METRICS`)
+    pub metric: String,
+    /// Additional metrics fetched alongside `metric` in the same query
+    /// (e.g. `AmortizedCost`, `UsageQuantity`), reported in this order
+    pub extra_metrics: Vec<String>,
+    pub groupings: Vec<Grouping>,
+    pub http_client: Option<SharedHttpClient>,
+    pub timeout_config: Option<TimeoutConfig>,
+    /// Sent in the user agent of every AWS call (`app/<name>`), so the tool's calls can be
+    /// picked out in CloudTrail
+    pub app_name: AppName,
+    /// Payer account reference for consolidated mode. When set, account references are
+    /// linked account ids and each window is fetched with one LINKED_ACCOUNT x SERVICE query.
+    pub consolidated_payer: Option<String>,
+    /// Partition of `region`, checked by `apply_partition`
+    pub partition: Partition,
+    /// Cost Explorer endpoint override (e.g. a VPC endpoint, a partition the SDK does not know,
+    /// or a mock server such as LocalStack); checked by `apply_partition`
+    pub ce_endpoint_url: Option<String>,
+    /// Account reference -> linked account id. Queries for these references carry a
+    /// LINKED_ACCOUNT filter, so one org-wide credential can report on a single member account.
+    pub linked_accounts: HashMap<String, String>,
+    /// Account reference -> shared config profile, for references that are not profile names
+    /// themselves (several linked accounts read through one profile). Profile mode only
+    pub account_profiles: HashMap<String, String>,
+    /// Account id -> name, consulted before asking Organizations per account. Filled from one
+    /// `ListAccounts` with `list_account_names`
+    pub org_account_names: HashMap<String, String>,
+    /// `MaxResults` for paginated calls that take one (Organizations listings, Cost Explorer
+    /// comparisons), capped at each API's maximum; `None` keeps the API defaults.
+    /// Set with `set_page_size`
+    page_size: Option<i32>,
+    /// Records every Cost Explorer request when set (for `--explain`); clones share the log
+    pub audit: Option<AuditLog>,
+    consolidated_cache: ConsolidatedCache,
+    /// Linked account id -> name from Organizations, loaded once in consolidated mode
+    consolidated_names: Arc<OnceCell<HashMap<String, String>>>,
+    /// Loaded configs by account reference, so each account's role is assumed once
+    account_configs: ConfigCache,
+    /// MFA-authenticated sessions by device serial; the one-time code is spent once
+    mfa_sessions: ConfigCache,
+    /// Account id and name by account reference, looked up once and shared by
+    /// `account_identity` and the account's summary
+    account_identities: IdentityCache,
 }
 
+/// One Cost Explorer request (one page of a paginated call), as recorded in an `AuditLog`.
+#[derive(Debug, Clone)]
+pub struct ApiCall {
+    pub account_ref: String,
+    pub operation: &'static str,
+    pub start: NaiveDate,
+    pub end_exclusive: NaiveDate,
+    /// Period the window was compared against, for the comparison APIs
+    pub baseline: Option<(NaiveDate, NaiveDate)>,
+    /// `None` for the comparison APIs, which take none
+    pub granularity: Option<String>,
+    pub metrics: Vec<String>,
+    /// Dimensions, tags or cost categories the results were grouped by
+    pub group_by: Vec<String>,
+}
+
+/// Shared record of the Cost Explorer requests a provider made, in the order they were sent.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog(Arc<std::sync::Mutex<Vec<ApiCall>>>);
+
+impl AuditLog {
+    pub fn calls(&self) -> Vec<ApiCall> {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    fn record(&self, call: ApiCall) {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(call);
+    }
+}
+
+/// One window of the consolidated LINKED_ACCOUNT x SERVICE query.
+#[derive(Debug, Default)]
+struct ConsolidatedWindow {
+    /// linked account id -> service -> amount
+    accounts: HashMap<String, HashMap<String, f64>>,
+    /// linked account id -> name, from the `DimensionValueAttributes` Cost Explorer returns
+    /// with LINKED_ACCOUNT groups
+    names: HashMap<String, String>,
+    /// linked account id -> amounts that could not be parsed and were counted as zero
+    unparseable: HashMap<String, usize>,
+}
+
+type ConsolidatedCache =
+    Arc<Mutex<HashMap<(NaiveDate, NaiveDate), Arc<OnceCell<Arc<ConsolidatedWindow>>>>>>;
+
+type ConfigCache = Arc<Mutex<HashMap<String, Arc<OnceCell<aws_config::SdkConfig>>>>>;
+
+/// account reference -> (account id, account name)
+type IdentityCache = Arc<Mutex<HashMap<String, Arc<OnceCell<(String, String)>>>>>;
+
 impl AwsCostProvider {
     pub fn new(region: impl Into<String>) -> Self {
         Self {
@@ -52,6 +564,36 @@ impl AwsCostProvider {
             static_credentials: None,
             assume_roles: None,
             base_profile: None,
+            mfa_token_code: None,
+            region_from_profile: false,
+            skip_unassumable: false,
+            strict_amounts: false,
+            only_services: Vec::new(),
+            blended_split: false,
+            savings_rate: false,
+            amortized_services: false,
+            separate_tax: false,
+            metric: PRIMARY_METRIC.to_string(),
+            page_size: None,
+            audit: None,
+            granularity: Granularity::Monthly,
+            account_granularity: HashMap::new(),
+            extra_metrics: Vec::new(),
+            groupings: Vec::new(),
+            http_client: None,
+            timeout_config: None,
+            app_name: default_app_name(),
+            consolidated_payer: None,
+            partition: Partition::Aws,
+            ce_endpoint_url: None,
+            linked_accounts: HashMap::new(),
+            account_profiles: HashMap::new(),
+            org_account_names: HashMap::new(),
+            consolidated_cache: ConsolidatedCache::default(),
+            consolidated_names: Arc::default(),
+            account_configs: ConfigCache::default(),
+            mfa_sessions: ConfigCache::default(),
+            account_identities: IdentityCache::default(),
         }
     }
 
@@ -60,10 +602,8 @@ impl AwsCostProvider {
         static_credentials: HashMap<String, StaticCredentials>,
     ) -> Self {
         Self {
-            region: region.into(),
             static_credentials: Some(static_credentials),
-            assume_roles: None,
-            base_profile: None,
+            ..Self::new(region)
         }
     }
 
@@ -73,10 +613,9 @@ impl AwsCostProvider {
         assume_roles: HashMap<String, AssumeRoleConfig>,
     ) -> Self {
         Self {
-            region: region.into(),
-            static_credentials: None,
             assume_roles: Some(assume_roles),
             base_profile,
+            ..Self::new(region)
         }
     }
 }
@@ -89,167 +628,4067 @@ impl CostProvider for AwsCostProvider {
         start: NaiveDate,
         end_exclusive: NaiveDate,
     ) -> Result<AccountSummary> {
-        let config = self.load_config(account_ref).await?;
+        if self.consolidated_payer.is_some() {
+            let window = self.consolidated_window(start, end_exclusive).await?;
+            return Ok(self.consolidated_summary(&window, account_ref).await);
+        }
 
-        let sts = StsClient::new(&config);
-        let ce = CeClient::new(&config);
-        let iam = IamClient::new(&config);
-        let org = OrgClient::new(&config);
+        self.with_config(account_ref, |config| async move {
+            self.account_summary(&config, account_ref, start, end_exclusive)
+                .await
+        })
+        .await
+    }
 
-        let account_id = sts
-            .get_caller_identity()
-            .send()
-            .await
-            .context("GetCallerIdentity failed")?
-            .account
-            .ok_or_else(|| anyhow!("Missing account id"))?;
+    /// In consolidated mode, every linked account comes out of the payer's one grouped query.
+    async fn fetch_all_summaries(
+        &self,
+        accounts: &[String],
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        limiter: &SummaryLimiter<'_>,
+    ) -> Vec<Result<Option<AccountSummary>>> {
+        if self.consolidated_payer.is_none() {
+            return limiter
+                .each(accounts, |account_ref| {
+                    self.fetch_account_summary(account_ref, start, end_exclusive)
+                })
+                .await;
+        }
+        let batch = async {
+            let window = match self.consolidated_window(start, end_exclusive).await {
+                Ok(window) => window,
+                Err(err) => {
+                    let message = format!("{err:#}");
+                    return accounts.iter().map(|_| Err(anyhow!("{message}"))).collect();
+                }
+            };
+            let mut summaries = Vec::with_capacity(accounts.len());
+            for account_ref in accounts {
+                summaries.push(Ok(self.consolidated_summary(&window, account_ref).await));
+            }
+            summaries
+        };
+        limiter.batch(accounts, batch).await
+    }
+
+    async fn service_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<HashMap<String, f64>> {
+        let (services, _unparseable) = self
+            .checked_service_costs(account_ref, start, end_exclusive)
+            .await?;
+        Ok(services)
+    }
+
+    async fn checked_service_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<(HashMap<String, f64>, usize)> {
+        if self.consolidated_payer.is_some() {
+            let window = self.consolidated_window(start, end_exclusive).await?;
+            return Ok((
+                window
+                    .accounts
+                    .get(account_ref)
+                    .cloned()
+                    .unwrap_or_default(),
+                window.unparseable.get(account_ref).copied().unwrap_or(0),
+            ));
+        }
 
-        let account_name = resolve_account_name(&account_id, &org, &iam).await;
+        self.with_config(account_ref, |config| async move {
+            let mut query = self.cost_query(&config, account_ref);
+            let (_total, services) = query.by_service(start, end_exclusive).await?;
+            Ok((services, query.amounts.invalid))
+        })
+        .await
+    }
 
-        let (total, services) = get_costs_by_service(&ce, start, end_exclusive).await?;
+    async fn native_comparison(
+        &self,
+        account_ref: &str,
+        baseline: (NaiveDate, NaiveDate),
+        current: (NaiveDate, NaiveDate),
+    ) -> Result<Option<NativeComparison>> {
+        // Consolidated references are linked account ids without their own credentials.
+        if self.consolidated_payer.is_some() {
+            return Ok(None);
+        }
 
-        Ok(AccountSummary {
-            account_ref: account_ref.to_string(),
-            account_id,
-            account_name,
-            total,
-            services,
+        self.with_config(account_ref, |config| async move {
+            self.cost_query(&config, account_ref)
+                .comparison(baseline, current)
+                .await
+        })
+        .await
+    }
+
+    async fn breakdowns(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<Vec<DimensionBreakdown>> {
+        // Consolidated summaries carry no breakdowns either.
+        if self.consolidated_payer.is_some() || self.groupings.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.with_config(account_ref, |config| async move {
+            self.cost_query(&config, account_ref)
+                .breakdowns(start, end_exclusive, &self.groupings)
+                .await
         })
+        .await
     }
 
-    async fn total_cost(
+    async fn daily_costs(
         &self,
         account_ref: &str,
         start: NaiveDate,
         end_exclusive: NaiveDate,
-    ) -> Result<f64> {
-        let config = self.load_config(account_ref).await?;
+    ) -> Result<Option<BTreeMap<NaiveDate, f64>>> {
+        self.daily_totals(account_ref, start, end_exclusive)
+            .await
+            .map(Some)
+    }
 
-        let ce = CeClient::new(&config);
-        let (total, _services) = get_costs_by_service(&ce, start, end_exclusive).await?;
-        Ok(total)
+    async fn forecast(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<Option<f64>> {
+        // `start` is the report's day in progress, not necessarily today in UTC
+        if start >= end_exclusive {
+            return Ok(Some(0.0));
+        }
+        let config_ref = self.consolidated_payer.as_deref().unwrap_or(account_ref);
+        self.with_config(config_ref, |config| async move {
+            self.cost_query(&config, account_ref)
+                .forecast(start, end_exclusive)
+                .await
+        })
+        .await
+        .map(Some)
     }
 }
 
 impl AwsCostProvider {
-    async fn load_config(&self, account_ref: &str) -> Result<aws_config::SdkConfig> {
-        if let Some(creds) = &self.static_credentials {
-            let entry = creds
-                .get(account_ref)
-                .ok_or_else(|| anyhow!("Unknown account reference: {account_ref}"))?
-                .clone();
-            let config = aws_config::defaults(BehaviorVersion::latest())
-                .region(Region::new(self.region.clone()))
-                .credentials_provider(entry.into_provider())
-                .load()
-                .await;
-            Ok(config)
-        } else if let Some(roles) = &self.assume_roles {
-            let role = roles
+    /// One linked account's summary out of the payer's consolidated query.
+    async fn consolidated_summary(
+        &self,
+        window: &ConsolidatedWindow,
+        account_ref: &str,
+    ) -> AccountSummary {
+        let services = window
+            .accounts
+            .get(account_ref)
+            .cloned()
+            .unwrap_or_default();
+        // Organizations is only asked when the query response left the name out
+        let account_name = match window.names.get(account_ref) {
+            Some(name) => name.clone(),
+            None => self
+                .consolidated_account_names()
+                .await
                 .get(account_ref)
-                .ok_or_else(|| anyhow!("Unknown account reference: {account_ref}"))?
-                .clone();
-            let mut base = aws_config::defaults(BehaviorVersion::latest());
-            if let Some(profile) = &self.base_profile {
-                base = base.profile_name(profile);
-            }
-            let base_config = base.region(Region::new(self.region.clone())).load().await;
-            let sts = StsClient::new(&base_config);
-            let mut assume = sts
-                .assume_role()
-                .role_arn(role.role_arn)
-                .role_session_name(format!("cloud-cost-manager-{}", account_ref));
-            if let Some(external_id) = role.external_id {
-                assume = assume.external_id(external_id);
-            }
-            let resp = assume.send().await.context("AssumeRole failed")?;
-            let creds = resp
-                .credentials()
-                .ok_or_else(|| anyhow!("Missing credentials from AssumeRole"))?;
-            let creds = Credentials::new(
-                creds.access_key_id().to_string(),
-                creds.secret_access_key().to_string(),
-                Some(creds.session_token().to_string()),
-                None,
-                "assume-role",
-            );
-            let config = aws_config::defaults(BehaviorVersion::latest())
-                .region(Region::new(self.region.clone()))
-                .credentials_provider(creds)
-                .load()
-                .await;
-            Ok(config)
-        } else {
-            let config = aws_config::defaults(BehaviorVersion::latest())
-                .profile_name(account_ref)
-                .region(Region::new(self.region.clone()))
-                .load()
-                .await;
-            Ok(config)
+                .cloned()
+                .unwrap_or_else(|| account_ref.to_string()),
+        };
+        AccountSummary {
+            account_ref: account_ref.to_string(),
+            account_id: account_ref.to_string(),
+            account_name,
+            total: services.values().sum(),
+            services,
+            raw_services: None,
+            tax: 0.0,
+            blended: None,
+            savings_rate: None,
+            amortized_services: None,
+            metrics: Vec::new(),
+            secondary: None,
+            breakdowns: Vec::new(),
+            azs: HashMap::new(),
+            unparseable_amounts: window.unparseable.get(account_ref).copied().unwrap_or(0),
+            currency: None,
         }
     }
-}
 
-async fn resolve_account_name(account_id: &str, org: &OrgClient, iam: &IamClient) -> String {
-    if let Ok(resp) = org.describe_account().account_id(account_id).send().await
-        && let Some(acct) = resp.account()
-        && let Some(name) = acct.name()
-    {
-        return name.to_string();
-    }
+    /// Per-account summary with credentials already loaded.
+    async fn account_summary(
+        &self,
+        config: &aws_config::SdkConfig,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<AccountSummary> {
+        let mut query = self.cost_query(config, account_ref);
+        let (account_id, account_name) = self.identity(config, account_ref).await?;
 
-    if let Ok(resp) = iam.list_account_aliases().send().await
-        && let Some(alias) = resp.account_aliases().first()
-    {
-        return alias.to_string();
-    }
+        let mut requested = vec![self.metric.clone()];
+        for metric in &self.extra_metrics {
+            if !requested.contains(metric) {
+                requested.push(metric.clone());
+            }
+        }
+        let extra = requested.len();
+        if self.blended_split {
+            for metric in [BLENDED_METRIC, PRIMARY_METRIC] {
+                if !requested.iter().any(|m| m == metric) {
+                    requested.push(metric.to_string());
+                }
+            }
+        }
+        if self.amortized_services && !requested.iter().any(|m| m == NET_AMORTIZED_METRIC) {
+            requested.push(NET_AMORTIZED_METRIC.to_string());
+        }
+        if self.savings_rate {
+            for metric in [BLENDED_METRIC, NET_AMORTIZED_METRIC] {
+                if !requested.iter().any(|m| m == metric) {
+                    requested.push(metric.to_string());
+                }
+            }
+        }
+        let mut by_metric = query
+            .by_metric(
+                start,
+                end_exclusive,
+                &requested,
+                self.granularity_for(account_ref),
+            )
+            .await?;
 
-    account_id.to_string()
-}
+        let sum = |metric: &str| -> f64 {
+            by_metric
+                .get(metric)
+                .map(|services| services.values().sum())
+                .unwrap_or(0.0)
+        };
+        // The split is always blended against unblended, whichever metric the totals use
+        let blended = self.blended_split.then(|| {
+            let (blended, unblended) = (sum(BLENDED_METRIC), sum(PRIMARY_METRIC));
+            BlendedSplit {
+                blended,
+                unblended,
+                savings: blended - unblended,
+            }
+        });
+        let savings_rate = self
+            .savings_rate
+            .then(|| SavingsRate::new(sum(BLENDED_METRIC), sum(NET_AMORTIZED_METRIC)));
+        let amortized_services = self.amortized_services.then(|| {
+            by_metric
+                .get(NET_AMORTIZED_METRIC)
+                .cloned()
+                .unwrap_or_default()
+        });
+        let services = by_metric.remove(&self.metric).unwrap_or_default();
+        let total: f64 = services.values().sum();
+        let metrics = requested[1..extra]
+            .iter()
+            .map(|metric| {
+                let services = by_metric.remove(metric).unwrap_or_default();
+                MetricBreakdown {
+                    metric: metric.clone(),
+                    total: services.values().sum(),
+                    services,
+                }
+            })
+            .collect();
 
-async fn get_costs_by_service(
-    ce: &CeClient,
-    start: NaiveDate,
-    end_exclusive: NaiveDate,
-) -> Result<(f64, HashMap<String, f64>)> {
-    let time_period = DateInterval::builder()
-        .start(start.format("%Y-%m-%d").to_string())
-        .end(end_exclusive.format("%Y-%m-%d").to_string())
-        .build()?;
-
-    let resp = ce
-        .get_cost_and_usage()
-        .time_period(time_period)
-        .granularity(Granularity::Monthly)
-        .metrics("UnblendedCost")
-        .group_by(
-            aws_sdk_costexplorer::types::GroupDefinition::builder()
-                .key("SERVICE")
-                .r#type(aws_sdk_costexplorer::types::GroupDefinitionType::Dimension)
-                .build(),
-        )
-        .send()
-        .await
-        .context("GetCostAndUsage failed")?;
+        let breakdowns = query
+            .breakdowns(start, end_exclusive, &self.groupings)
+            .await?;
+        let azs = breakdowns
+            .iter()
+            .find(|b| b.dimension == Grouping::AvailabilityZone.label())
+            .map(|b| b.values.clone())
+            .unwrap_or_default();
 
-    let mut total = 0.0_f64;
-    let mut services: HashMap<String, f64> = HashMap::new();
+        let tax = if self.separate_tax {
+            query.filter = self.tax_filter(account_ref);
+            query.total(start, end_exclusive).await?
+        } else {
+            0.0
+        };
 
-    for result in resp.results_by_time() {
-        for g in result.groups() {
-            let svc = g.keys().first().map(|s| s.as_str()).unwrap_or("Unknown");
-            let amt = if let Some(metrics) = g.metrics()
-                && let Some(unblended) = metrics.get("UnblendedCost")
-                && let Some(amount) = unblended.amount()
-            {
-                amount.parse::<f64>().unwrap_or(0.0)
-            } else {
-                0.0
-            };
-            *services.entry(svc.to_string()).or_insert(0.0) += amt;
-            total += amt;
-        }
+        Ok(AccountSummary {
+            account_ref: account_ref.to_string(),
+            account_id,
+            account_name,
+            total,
+            services,
+            raw_services: None,
+            tax,
+            blended,
+            savings_rate,
+            amortized_services,
+            metrics,
+            secondary: None,
+            breakdowns,
+            azs,
+            unparseable_amounts: query.amounts.invalid,
+            currency: query.amounts.currency.clone(),
+        })
+    }
+
+    /// Granularity used for an account's current-period query.
+    pub fn granularity_for(&self, account_ref: &str) -> &Granularity {
+        self.account_granularity
+            .get(account_ref)
+            .unwrap_or(&self.granularity)
     }
 
-    Ok((total, services))
+    /// Sets the page size for paginated calls: bigger pages mean fewer round-trips for large
+    /// organizations. Values above an API's maximum are capped to it for that API.
+    pub fn set_page_size(&mut self, page_size: u32) -> Result<()> {
+        if page_size == 0 {
+            bail!("Page size must be at least 1");
+        }
+        self.page_size = Some(i32::try_from(page_size).unwrap_or(i32::MAX));
+        Ok(())
+    }
+
+    fn page_size_for(&self, max_results: i32) -> Option<i32> {
+        self.page_size.map(|size| size.min(max_results))
+    }
+
+    /// Builds one pooled HTTP client that all per-account SDK configs reuse.
+    pub fn apply_http_settings(&mut self, settings: &HttpSettings) {
+        self.http_client = Some(settings.build_client());
+        self.timeout_config = Some(settings.timeout_config());
+    }
+
+    /// Lists cost allocation tag keys with spend in the window, or the values of `tag_key`
+    /// when given (Cost Explorer `GetTags`).
+    pub async fn list_tags(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        tag_key: Option<&str>,
+    ) -> Result<Vec<String>> {
+        self.with_config(account_ref, |config| async move {
+            let ce = self.ce_client(&config);
+            let time_period = date_interval(start, end_exclusive, self.today())?;
+            let mut tags = Vec::new();
+            let mut next_page_token = None;
+            loop {
+                let resp = ce
+                    .get_tags()
+                    .time_period(time_period.clone())
+                    .set_tag_key(tag_key.map(String::from))
+                    .set_next_page_token(next_page_token)
+                    .send()
+                    .await
+                    .context("GetTags failed")?;
+                tags.extend(resp.tags().iter().cloned());
+                next_page_token = resp.next_page_token().map(String::from);
+                if next_page_token.is_none() {
+                    break;
+                }
+            }
+            Ok(tags)
+        })
+        .await
+    }
+
+    /// Cost category definitions visible to `account_ref` (categories live in the payer
+    /// account), sorted by name. `effective_on` (`YYYY-MM-DD`) picks the versions in effect
+    /// that day; the API defaults to the current ones.
+    pub async fn list_cost_categories(
+        &self,
+        account_ref: &str,
+        effective_on: Option<NaiveDate>,
+    ) -> Result<Vec<CostCategory>> {
+        let mut categories = self
+            .with_config(account_ref, |config| async move {
+                let ce = self.ce_client(&config);
+                let mut categories = Vec::new();
+                let mut next_token = None;
+                loop {
+                    let resp = ce
+                        .list_cost_category_definitions()
+                        .set_effective_on(effective_on.map(|day| day.to_string()))
+                        .set_max_results(self.page_size_for(COST_CATEGORIES_MAX_RESULTS))
+                        .set_next_token(next_token)
+                        .send()
+                        .await
+                        .context("ListCostCategoryDefinitions failed")?;
+                    categories.extend(resp.cost_category_references().iter().map(|category| {
+                        CostCategory {
+                            name: category.name().unwrap_or_default().to_string(),
+                            effective_start: category
+                                .effective_start()
+                                .unwrap_or_default()
+                                .to_string(),
+                            effective_end: category.effective_end().map(String::from),
+                        }
+                    }));
+                    next_token = resp.next_token().map(String::from);
+                    if next_token.is_none() {
+                        break;
+                    }
+                }
+                Ok(categories)
+            })
+            .await?;
+        categories.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.effective_start.cmp(&b.effective_start))
+        });
+        Ok(categories)
+    }
+
+    /// Per-service spend for each of the last `months` calendar months, oldest first, summed
+    /// over `accounts`; the current month is month-to-date. Each account takes one
+    /// monthly-granularity query (one in consolidated mode, through the payer). `months` is
+    /// capped at `MAX_TREND_MONTHS`, and months without spend have an empty map. Also returns
+    /// the currency the accounts share; accounts billed in different ones are rejected.
+    pub async fn service_trend(
+        &self,
+        accounts: &[String],
+        today: NaiveDate,
+        months: u32,
+    ) -> Result<(Vec<(NaiveDate, HashMap<String, f64>)>, Option<String>)> {
+        let months = months.clamp(1, MAX_TREND_MONTHS);
+        let current_month = today.with_day(1).unwrap_or(today);
+        let start = current_month
+            .checked_sub_months(Months::new(months - 1))
+            .ok_or_else(|| anyhow!("{months} months before {today} is out of range"))?;
+        let end_exclusive = today + chrono::Duration::days(1);
+
+        let queried: Vec<&str> = match &self.consolidated_payer {
+            Some(payer) => vec![payer.as_str()],
+            None => accounts.iter().map(String::as_str).collect(),
+        };
+        let per_account = futures::future::try_join_all(queried.into_iter().map(|account_ref| {
+            self.with_config(account_ref, move |config| async move {
+                let mut query = self.cost_query(&config, account_ref);
+                let months = query.monthly_services(start, end_exclusive).await?;
+                Ok((months, query.amounts.currency))
+            })
+        }))
+        .await?;
+        let currency =
+            shared_currency(per_account.iter().map(|(_, currency)| currency.as_deref()))?;
+
+        let mut trend: BTreeMap<NaiveDate, HashMap<String, f64>> = (0..months)
+            .filter_map(|offset| start.checked_add_months(Months::new(offset)))
+            .map(|month| (month, HashMap::new()))
+            .collect();
+        for (account, _) in per_account {
+            for (month, services) in account {
+                let totals = trend.entry(month).or_default();
+                for (svc, amt) in services {
+                    *totals.entry(svc).or_insert(0.0) += amt;
+                }
+            }
+        }
+        Ok((trend.into_iter().collect(), currency))
+    }
+
+    /// Daily `metric` totals for one account in the window.
+    pub async fn daily_totals(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, f64>> {
+        let config_ref = self.consolidated_payer.as_deref().unwrap_or(account_ref);
+        self.with_config(config_ref, |config| async move {
+            self.cost_query(&config, account_ref)
+                .daily_totals(start, end_exclusive)
+                .await
+        })
+        .await
+    }
+
+    /// Primary-metric spend grouped by one or two keys (see `parse_group_by`), nested as
+    /// first key value -> second key value -> amount. With a single key the inner map
+    /// holds one "Total" entry. Also returns the currency the amounts came in.
+    pub async fn grouped_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        keys: &[GroupKey],
+    ) -> Result<(HashMap<String, HashMap<String, f64>>, Option<String>)> {
+        let config_ref = self.consolidated_payer.as_deref().unwrap_or(account_ref);
+        self.with_config(config_ref, |config| async move {
+            let mut query = self.cost_query(&config, account_ref);
+            let groups = query.by_group_keys(start, end_exclusive, keys).await?;
+            Ok((groups, query.amounts.currency))
+        })
+        .await
+    }
+
+    /// Cost Explorer's forecast of `metric` (one of `USAGE_FORECAST_METRICS`) for the usage
+    /// type in `filter`, over a window clamped to start today (UTC).
+    pub async fn usage_forecast(
+        &self,
+        account_ref: &str,
+        filter: &UsageFilter,
+        metric: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<UsageForecast> {
+        let start = start.max(self.today());
+        if start >= end_exclusive {
+            return Ok(UsageForecast::default());
+        }
+        let config_ref = self.consolidated_payer.as_deref().unwrap_or(account_ref);
+        self.with_config(config_ref, |config| async move {
+            self.cost_query(&config, account_ref)
+                .usage_forecast(start, end_exclusive, filter, metric)
+                .await
+        })
+        .await
+    }
+
+    /// Linked account ids with spend in any of the windows, using the consolidated query (one
+    /// per window, shared with the report). Pass both report windows, so accounts that only
+    /// spent in the previous period still count towards its total.
+    pub async fn consolidated_account_ids(
+        &self,
+        windows: &[(NaiveDate, NaiveDate)],
+    ) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = Vec::new();
+        for &(start, end_exclusive) in windows {
+            let window = self.consolidated_window(start, end_exclusive).await?;
+            ids.extend(window.accounts.keys().cloned());
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Runs the consolidated query once per window; concurrent callers share the result.
+    async fn consolidated_window(
+        &self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<Arc<ConsolidatedWindow>> {
+        let payer = self
+            .consolidated_payer
+            .as_deref()
+            .ok_or_else(|| anyhow!("Consolidated mode is not enabled"))?;
+        let cell = self
+            .consolidated_cache
+            .lock()
+            .await
+            .entry((start, end_exclusive))
+            .or_default()
+            .clone();
+        let window = cell
+            .get_or_try_init(|| async {
+                self.with_config(payer, |config| async move {
+                    self.cost_query(&config, payer)
+                        .by_account_and_service(start, end_exclusive)
+                        .await
+                })
+                .await
+                .map(Arc::new)
+            })
+            .await?;
+        Ok(window.clone())
+    }
+
+    /// Names of the organization's accounts, listed once with the payer's credentials.
+    /// Empty when Organizations is not accessible, so callers fall back to account ids.
+    async fn consolidated_account_names(&self) -> &HashMap<String, String> {
+        self.consolidated_names
+            .get_or_init(|| async {
+                match self.list_organization_accounts().await {
+                    Ok(names) => names,
+                    Err(err) => {
+                        tracing::warn!(error = %format!("{err:#}"), "account names unavailable; showing account ids");
+                        HashMap::new()
+                    }
+                }
+            })
+            .await
+    }
+
+    /// All accounts in the payer's organization (`ListAccounts`), id -> name.
+    async fn list_organization_accounts(&self) -> Result<HashMap<String, String>> {
+        let payer = self
+            .consolidated_payer
+            .as_deref()
+            .ok_or_else(|| anyhow!("Consolidated mode is not enabled"))?;
+        let config = self.config(payer).await?;
+        list_all_org_accounts(
+            &OrgClient::new(&config),
+            self.page_size_for(ORGANIZATIONS_MAX_RESULTS),
+        )
+        .await
+    }
+
+    /// Resolves credentials for every account up front. With `skip_unassumable`, accounts
+    /// whose credentials fail (an unknown profile, a role that cannot be assumed) are logged
+    /// and returned as skipped; otherwise the first failure is returned. The loaded configs
+    /// are kept for the report. Consolidated mode only needs the payer, so accounts pass
+    /// through unchanged.
+    pub async fn resolve_accounts(
+        &self,
+        accounts: Vec<String>,
+    ) -> Result<(Vec<String>, Vec<AccountError>)> {
+        if !self.skip_unassumable || self.consolidated_payer.is_some() {
+            return Ok((accounts, Vec::new()));
+        }
+
+        let loaded = join_all(
+            accounts
+                .iter()
+                .map(|account_ref| self.resolve_credentials(account_ref)),
+        )
+        .await;
+
+        let mut usable = Vec::with_capacity(accounts.len());
+        let mut skipped = Vec::new();
+        for (account_ref, outcome) in accounts.into_iter().zip(loaded) {
+            match outcome {
+                Ok(_) => usable.push(account_ref),
+                Err(err) => {
+                    tracing::warn!(account = %account_ref, error = %format!("{err:#}"), "skipping account whose credentials could not be loaded");
+                    skipped.push(AccountError {
+                        account_ref,
+                        error: format!("skipped, credentials unavailable: {err:#}"),
+                    });
+                }
+            }
+        }
+        Ok((usable, skipped))
+    }
+
+    /// Loads `account_ref`'s config and resolves its credentials. Config loading is lazy,
+    /// so a missing profile or broken credential source only shows up here.
+    async fn resolve_credentials(&self, account_ref: &str) -> Result<()> {
+        let config = self.config(account_ref).await?;
+        let credentials = config
+            .credentials_provider()
+            .ok_or_else(|| anyhow!("No credentials configured for {account_ref}"))?;
+        credentials
+            .provide_credentials()
+            .await
+            .with_context(|| format!("Failed to resolve credentials for {account_ref}"))?;
+        Ok(())
+    }
+
+    /// Resolves the AWS account id behind an account reference.
+    pub async fn account_id(&self, account_ref: &str) -> Result<String> {
+        if self.consolidated_payer.is_some() {
+            return Ok(account_ref.to_string());
+        }
+        if let Some(linked_id) = self.linked_accounts.get(account_ref) {
+            return Ok(linked_id.clone());
+        }
+        let config = self.config(account_ref).await?;
+        StsClient::new(&config)
+            .get_caller_identity()
+            .send()
+            .await
+            .with_context(|| format!("GetCallerIdentity failed for {account_ref}"))?
+            .account
+            .ok_or_else(|| anyhow!("Missing account id for {account_ref}"))
+    }
+
+    /// The account id and name behind an account reference, looked up once per account and
+    /// reused by its summary, so matching accounts by name before the report costs no extra
+    /// calls. Consolidated references are account ids, named from the payer's organization.
+    pub async fn account_identity(&self, account_ref: &str) -> Result<(String, String)> {
+        if self.consolidated_payer.is_some() {
+            let name = self
+                .consolidated_account_names()
+                .await
+                .get(account_ref)
+                .cloned()
+                .unwrap_or_else(|| account_ref.to_string());
+            return Ok((account_ref.to_string(), name));
+        }
+        let config = self.config(account_ref).await?;
+        self.identity(&config, account_ref).await
+    }
+
+    /// `account_identity` with credentials already loaded: the linked account id or
+    /// `GetCallerIdentity`, named by `resolve_account_name`.
+    async fn identity(
+        &self,
+        config: &aws_config::SdkConfig,
+        account_ref: &str,
+    ) -> Result<(String, String)> {
+        let cell = self
+            .account_identities
+            .lock()
+            .await
+            .entry(account_ref.to_string())
+            .or_default()
+            .clone();
+        let identity = cell
+            .get_or_try_init(|| async {
+                let account_id = match self.linked_accounts.get(account_ref) {
+                    Some(linked_id) => linked_id.clone(),
+                    None => StsClient::new(config)
+                        .get_caller_identity()
+                        .send()
+                        .await
+                        .context("GetCallerIdentity failed")?
+                        .account
+                        .ok_or_else(|| anyhow!("Missing account id"))?,
+                };
+                let account_name = resolve_account_name(
+                    &account_id,
+                    &self.org_account_names,
+                    &OrgClient::new(config),
+                    &IamClient::new(config),
+                )
+                .await;
+                Ok::<_, anyhow::Error>((account_id, account_name))
+            })
+            .await?;
+        Ok(identity.clone())
+    }
+
+    /// Setup check for one account reference: its credentials resolve (`GetCallerIdentity`)
+    /// and Cost Explorer answers a one-day query ending at `day`. Returns the account id.
+    pub async fn check_access(&self, account_ref: &str, day: NaiveDate) -> Result<String> {
+        let config = self.config(account_ref).await?;
+        let account_id = StsClient::new(&config)
+            .get_caller_identity()
+            .send()
+            .await
+            .context("GetCallerIdentity failed")?
+            .account
+            .ok_or_else(|| anyhow!("Missing account id"))?;
+        let start = day
+            .pred_opt()
+            .ok_or_else(|| anyhow!("No day before {day}"))?;
+        self.cost_query(&config, account_ref)
+            .total(start, day)
+            .await?;
+        Ok(account_id)
+    }
+
+    /// Names of every account in the organization, from one paginated `ListAccounts`, for
+    /// `org_account_names`. Must be called with credentials for the management or a delegated
+    /// admin account.
+    pub async fn list_account_names(
+        &self,
+        org_profile: Option<&str>,
+    ) -> Result<HashMap<String, String>> {
+        let mut loader = self.config_loader();
+        if let Some(profile) = org_profile {
+            loader = loader.profile_name(profile);
+        }
+        let config = loader.load().await;
+        list_all_org_accounts(
+            &OrgClient::new(&config),
+            self.page_size_for(ORGANIZATIONS_MAX_RESULTS),
+        )
+        .await
+    }
+
+    /// Lists the ids of all accounts under an organizational unit, including nested OUs.
+    /// Must be called with credentials for the management or a delegated admin account.
+    pub async fn list_ou_accounts(
+        &self,
+        org_profile: Option<&str>,
+        ou_id: &str,
+    ) -> Result<Vec<String>> {
+        let mut loader = self.config_loader();
+        if let Some(profile) = org_profile {
+            loader = loader.profile_name(profile);
+        }
+        let config = loader.load().await;
+        let org = OrgClient::new(&config);
+
+        let mut account_ids = Vec::new();
+        let mut parents = vec![ou_id.to_string()];
+
+        while let Some(parent) = parents.pop() {
+            let mut next_token = None;
+            loop {
+                let resp = org
+                    .list_accounts_for_parent()
+                    .parent_id(&parent)
+                    .set_max_results(self.page_size_for(ORGANIZATIONS_MAX_RESULTS))
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                    .map_err(|err| organizations_error(err, "ListAccountsForParent", &parent))?;
+                account_ids.extend(
+                    resp.accounts()
+                        .iter()
+                        .filter_map(|a| a.id().map(String::from)),
+                );
+                next_token = resp.next_token().map(String::from);
+                if next_token.is_none() {
+                    break;
+                }
+            }
+
+            let mut next_token = None;
+            loop {
+                let resp = org
+                    .list_organizational_units_for_parent()
+                    .parent_id(&parent)
+                    .set_max_results(self.page_size_for(ORGANIZATIONS_MAX_RESULTS))
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                    .map_err(|err| {
+                        organizations_error(err, "ListOrganizationalUnitsForParent", &parent)
+                    })?;
+                parents.extend(
+                    resp.organizational_units()
+                        .iter()
+                        .filter_map(|ou| ou.id().map(String::from)),
+                );
+                next_token = resp.next_token().map(String::from);
+                if next_token.is_none() {
+                    break;
+                }
+            }
+        }
+
+        Ok(account_ids)
+    }
+
+    fn cost_query(&self, config: &aws_config::SdkConfig, account_ref: &str) -> CostQuery {
+        CostQuery {
+            ce: self.ce_client(config),
+            account_ref: account_ref.to_string(),
+            audit: self.audit.clone(),
+            metric: self.metric.clone(),
+            page_size: self.page_size,
+            filter: self.cost_filter(account_ref),
+            amounts: AmountCheck::new(self.strict_amounts),
+            today: self.today(),
+        }
+    }
+
+    /// Today in UTC, the day Cost Explorer counts its retention limits from.
+    fn today(&self) -> NaiveDate {
+        Utc::now().date_naive()
+    }
+
+    /// Filter applied to every Cost Explorer query; conditions are ANDed together.
+    fn cost_filter(&self, account_ref: &str) -> Option<Expression> {
+        let mut conditions = self.scope_conditions(account_ref);
+        if self.separate_tax {
+            conditions.push(Expression::builder().not(tax_records()).build());
+        }
+        and_all(conditions)
+    }
+
+    /// The usual scope, restricted to tax records, for the `separate_tax` query.
+    fn tax_filter(&self, account_ref: &str) -> Option<Expression> {
+        let mut conditions = self.scope_conditions(account_ref);
+        conditions.push(tax_records());
+        and_all(conditions)
+    }
+
+    /// Services and linked account that `account_ref` is limited to.
+    fn scope_conditions(&self, account_ref: &str) -> Vec<Expression> {
+        let mut conditions = Vec::new();
+        if !self.only_services.is_empty() {
+            conditions.push(dimension_filter(Dimension::Service, &self.only_services));
+        }
+        if let Some(linked_id) = self.linked_accounts.get(account_ref) {
+            conditions.push(dimension_filter(
+                Dimension::LinkedAccount,
+                std::slice::from_ref(linked_id),
+            ));
+        } else if self
+            .consolidated_payer
+            .as_deref()
+            .is_some_and(|payer| payer != account_ref)
+        {
+            // Consolidated references are linked account ids queried with the payer's credentials
+            conditions.push(dimension_filter(
+                Dimension::LinkedAccount,
+                &[account_ref.to_string()],
+            ));
+        }
+        conditions
+    }
+
+    /// Checks that the region belongs to `partition` and sets the partition and optional
+    /// Cost Explorer endpoint override.
+    pub fn apply_partition(
+        &mut self,
+        partition: Partition,
+        ce_endpoint_url: Option<String>,
+    ) -> Result<()> {
+        if !partition.contains_region(&self.region) {
+            bail!(
+                "Region {} is not in the {:?} partition; use --region {}",
+                self.region,
+                partition,
+                partition.cost_explorer_region()
+            );
+        }
+        if let Some(url) = &ce_endpoint_url {
+            validate_endpoint_url(url)?;
+        }
+        self.partition = partition;
+        self.ce_endpoint_url = ce_endpoint_url;
+        Ok(())
+    }
+
+    /// Cost Explorer client, honoring the endpoint override when one is set.
+    fn ce_client(&self, config: &aws_config::SdkConfig) -> CeClient {
+        match &self.ce_endpoint_url {
+            Some(url) => CeClient::from_conf(
+                aws_sdk_costexplorer::config::Builder::from(config)
+                    .endpoint_url(url)
+                    .build(),
+            ),
+            None => CeClient::new(config),
+        }
+    }
+
+    /// Config loader with the provider's region and shared HTTP settings applied.
+    fn config_loader(&self) -> ConfigLoader {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(self.region.clone()));
+        if let Some(client) = &self.http_client {
+            loader = loader.http_client(client.clone());
+        }
+        if let Some(timeouts) = &self.timeout_config {
+            loader = loader.timeout_config(timeouts.clone());
+        }
+        loader.app_name(self.app_name.clone())
+    }
+
+    /// Runs `call` with `account_ref`'s config. Assumed-role sessions can expire partway
+    /// through a long report; on `ExpiredToken` the credentials are loaded again and the
+    /// call retried once. Throttling is left to the SDK's own retries.
+    async fn with_config<T, F, Fut>(&self, account_ref: &str, call: F) -> Result<T>
+    where
+        F: Fn(aws_config::SdkConfig) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let config = self.config(account_ref).await?;
+        match call(config).await {
+            Err(err) if is_expired_token(&err) => {
+                tracing::warn!(account = %account_ref, error = %format!("{err:#}"), "credentials expired; refreshing and retrying once");
+                self.account_configs.lock().await.remove(account_ref);
+                let config = self.config(account_ref).await?;
+                call(config).await
+            }
+            result => result,
+        }
+    }
+
+    /// `account_ref`'s config, loaded (and its role assumed) on first use and shared by
+    /// every later call for the account.
+    async fn config(&self, account_ref: &str) -> Result<aws_config::SdkConfig> {
+        let cell = self
+            .account_configs
+            .lock()
+            .await
+            .entry(account_ref.to_string())
+            .or_default()
+            .clone();
+        let config = cell
+            .get_or_try_init(|| self.load_config(account_ref))
+            .await?;
+        Ok(config.clone())
+    }
+
+    async fn load_config(&self, account_ref: &str) -> Result<aws_config::SdkConfig> {
+        if let Some(creds) = &self.static_credentials {
+            let entry = creds
+                .get(account_ref)
+                .ok_or_else(|| anyhow!("Unknown account reference: {account_ref}"))?
+                .clone();
+            let config = self
+                .config_loader()
+                .credentials_provider(entry.into_provider())
+                .load()
+                .await;
+            Ok(config)
+        } else if let Some(roles) = &self.assume_roles {
+            let role = roles
+                .get(account_ref)
+                .ok_or_else(|| anyhow!("Unknown account reference: {account_ref}"))?
+                .clone();
+            let mut base = self.config_loader();
+            if let Some(profile) = &self.base_profile {
+                base = base.profile_name(profile);
+            }
+            self.assume_role_chain(base.load().await, &role, account_ref)
+                .await
+        } else {
+            let profile = self
+                .account_profiles
+                .get(account_ref)
+                .map_or(account_ref, String::as_str);
+            let mut loader = self.config_loader().profile_name(profile);
+            if self.region_from_profile {
+                loader = loader.region(
+                    RegionProviderChain::first_try(
+                        ProfileFileRegionProvider::builder()
+                            .profile_name(profile)
+                            .build(),
+                    )
+                    .or_else(Region::new(self.region.clone())),
+                );
+            }
+            Ok(loader.load().await)
+        }
+    }
+
+    /// Assumes `role`'s intermediate roles in order, then its target role, starting from the
+    /// credentials in `config` (or, for a role behind MFA, the MFA session made from them).
+    async fn assume_role_chain(
+        &self,
+        mut config: aws_config::SdkConfig,
+        role: &AssumeRoleConfig,
+        account_ref: &str,
+    ) -> Result<aws_config::SdkConfig> {
+        let session_name = format!("cloud-cost-manager-{}", account_ref);
+        if let Some(serial) = &role.serial_number {
+            config = self.mfa_session(&config, serial, account_ref).await?;
+        }
+        for hop in &role.via_role_arns {
+            config = self.assume_role(&config, hop, None, &session_name).await?;
+        }
+        self.assume_role(
+            &config,
+            &role.role_arn,
+            role.external_id.as_deref(),
+            &session_name,
+        )
+        .await
+    }
+
+    /// Session credentials authenticated with `serial`'s MFA code (`GetSessionToken`). An
+    /// MFA code can only be used once, so the session is made on first use and shared by
+    /// every account, window and retry; roles are then assumed from it without MFA.
+    async fn mfa_session(
+        &self,
+        base: &aws_config::SdkConfig,
+        serial: &str,
+        account_ref: &str,
+    ) -> Result<aws_config::SdkConfig> {
+        let cell = self
+            .mfa_sessions
+            .lock()
+            .await
+            .entry(serial.to_string())
+            .or_default()
+            .clone();
+        let session = cell
+            .get_or_try_init(|| async {
+                let code = self.mfa_token_code.as_deref().ok_or_else(|| {
+                    anyhow!("Role for {account_ref} requires an MFA code for {serial}")
+                })?;
+                let resp = StsClient::new(base)
+                    .get_session_token()
+                    .serial_number(serial)
+                    .token_code(code)
+                    .send()
+                    .await
+                    .map_err(|err| {
+                        if err.code() == Some("AccessDenied")
+                            && err
+                                .message()
+                                .is_some_and(|m| m.contains("MultiFactorAuthentication"))
+                        {
+                            anyhow!(
+                                "MFA code for {serial} was rejected (expired or invalid); \
+                                 retry with a fresh code"
+                            )
+                        } else {
+                            anyhow!(err).context(format!("GetSessionToken failed for {serial}"))
+                        }
+                    })?;
+                let creds = resp
+                    .credentials()
+                    .ok_or_else(|| anyhow!("Missing credentials from GetSessionToken"))?;
+                Ok::<_, anyhow::Error>(self.session_config(creds, "mfa-session").await)
+            })
+            .await?;
+        Ok(session.clone())
+    }
+
+    /// Assumes `role_arn` with the credentials in `config` and returns a config for the new session.
+    async fn assume_role(
+        &self,
+        config: &aws_config::SdkConfig,
+        role_arn: &str,
+        external_id: Option<&str>,
+        session_name: &str,
+    ) -> Result<aws_config::SdkConfig> {
+        let sts = StsClient::new(config);
+        let mut assume = sts
+            .assume_role()
+            .role_arn(role_arn)
+            .role_session_name(session_name);
+        if let Some(external_id) = external_id {
+            assume = assume.external_id(external_id);
+        }
+        let resp = assume
+            .send()
+            .await
+            .map_err(|err| anyhow!(err).context(format!("AssumeRole failed for {role_arn}")))?;
+        let creds = resp
+            .credentials()
+            .ok_or_else(|| anyhow!("Missing credentials from AssumeRole"))?;
+        Ok(self.session_config(creds, "assume-role").await)
+    }
+
+    /// Config for temporary credentials returned by STS.
+    async fn session_config(
+        &self,
+        creds: &aws_sdk_sts::types::Credentials,
+        source: &'static str,
+    ) -> aws_config::SdkConfig {
+        let creds = Credentials::new(
+            creds.access_key_id().to_string(),
+            creds.secret_access_key().to_string(),
+            Some(creds.session_token().to_string()),
+            None,
+            source,
+        );
+        self.config_loader()
+            .credentials_provider(creds)
+            .load()
+            .await
+    }
+}
+
+/// Reads an SSM parameter (decrypting SecureStrings) with `profile` or the default chain.
+pub async fn load_ssm_parameter(region: &str, profile: Option<&str>, name: &str) -> Result<String> {
+    let config = bootstrap_config(region, profile).await;
+    let resp = SsmClient::new(&config)
+        .get_parameter()
+        .name(name)
+        .with_decryption(true)
+        .send()
+        .await
+        .map_err(|err| config_source_error(err, "SSM parameter", name))?;
+    resp.parameter()
+        .and_then(|p| p.value())
+        .map(String::from)
+        .ok_or_else(|| anyhow!("SSM parameter {name} has no value"))
+}
+
+/// Reads a Secrets Manager secret string with `profile` or the default chain.
+pub async fn load_secret_string(region: &str, profile: Option<&str>, name: &str) -> Result<String> {
+    let config = bootstrap_config(region, profile).await;
+    let resp = SecretsClient::new(&config)
+        .get_secret_value()
+        .secret_id(name)
+        .send()
+        .await
+        .map_err(|err| config_source_error(err, "secret", name))?;
+    resp.secret_string().map(String::from).ok_or_else(|| {
+        anyhow!("Secret {name} has no string value (binary secrets are not supported)")
+    })
+}
+
+async fn bootstrap_config(region: &str, profile: Option<&str>) -> aws_config::SdkConfig {
+    let mut loader =
+        aws_config::defaults(BehaviorVersion::latest()).region(Region::new(region.to_string()));
+    if let Some(profile) = profile {
+        loader = loader.profile_name(profile);
+    }
+    loader.load().await
+}
+
+fn config_source_error<E, R>(err: SdkError<E, R>, kind: &str, name: &str) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    R: std::fmt::Debug + Send + Sync + 'static,
+{
+    match err.code() {
+        Some("AccessDeniedException") => anyhow!(
+            "Access denied reading {kind} {name}; check the bootstrap identity's IAM and KMS permissions"
+        ),
+        Some("DecryptionFailure") | Some("InvalidKeyId") => {
+            anyhow!("Could not decrypt {kind} {name}; check kms:Decrypt on its KMS key")
+        }
+        Some("ParameterNotFound") | Some("ResourceNotFoundException") => {
+            anyhow!("{kind} {name} not found")
+        }
+        _ => anyhow!(err).context(format!("Reading {kind} {name} failed")),
+    }
+}
+
+fn organizations_error<E, R>(err: SdkError<E, R>, operation: &str, parent: &str) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    R: std::fmt::Debug + Send + Sync + 'static,
+{
+    if err.code() == Some("AccessDeniedException") {
+        anyhow!(
+            "Access denied for organizations:{operation} on {parent}; \
+             use management or delegated administrator credentials"
+        )
+    } else {
+        anyhow!(err).context(format!("{operation} failed for {parent}"))
+    }
+}
+
+/// Endpoint overrides must be absolute https URLs; plain http is only accepted for a loopback
+/// host, where mock servers (LocalStack, recorded fixtures) listen.
+fn validate_endpoint_url(url: &str) -> Result<()> {
+    let uri: http::Uri = url
+        .parse()
+        .with_context(|| format!("Invalid Cost Explorer endpoint URL {url:?}"))?;
+    let Some(host) = uri.host().filter(|host| !host.is_empty()) else {
+        bail!("Cost Explorer endpoint URL has no host: {url}");
+    };
+    match uri.scheme_str() {
+        Some("https") => Ok(()),
+        Some("http") if is_loopback(host) => Ok(()),
+        Some("http") => {
+            bail!("Cost Explorer endpoint URL must use https unless the host is local: {url}")
+        }
+        _ => bail!("Cost Explorer endpoint URL must be an absolute https URL: {url}"),
+    }
+}
+
+/// `localhost` or a loopback IP literal, bracketed or not.
+fn is_loopback(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Every account in the caller's organization, id -> name, following `ListAccounts` pages.
+async fn list_all_org_accounts(
+    org: &OrgClient,
+    page_size: Option<i32>,
+) -> Result<HashMap<String, String>> {
+    let mut names = HashMap::new();
+    let mut next_token = None;
+    loop {
+        let resp = org
+            .list_accounts()
+            .set_max_results(page_size)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|err| organizations_error(err, "ListAccounts", "the organization"))?;
+        for account in resp.accounts() {
+            if let (Some(id), Some(name)) = (account.id(), account.name()) {
+                names.insert(id.to_string(), name.to_string());
+            }
+        }
+        next_token = resp.next_token().map(String::from);
+        if next_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(names)
+}
+
+async fn resolve_account_name(
+    account_id: &str,
+    prefetched: &HashMap<String, String>,
+    org: &OrgClient,
+    iam: &IamClient,
+) -> String {
+    if let Some(name) = prefetched.get(account_id) {
+        return name.clone();
+    }
+
+    if let Ok(resp) = org.describe_account().account_id(account_id).send().await
+        && let Some(acct) = resp.account()
+        && let Some(name) = acct.name()
+    {
+        return name.to_string();
+    }
+
+    if let Ok(resp) = iam.list_account_aliases().send().await
+        && let Some(alias) = resp.account_aliases().first()
+    {
+        return alias.to_string();
+    }
+
+    account_id.to_string()
+}
+
+/// Months of history Cost Explorer keeps, counting the current month.
+const CE_RETENTION_MONTHS: u32 = 14;
+
+/// Most months `AwsCostProvider::service_trend` covers: everything Cost Explorer retains,
+/// counting the current month.
+pub const MAX_TREND_MONTHS: u32 = CE_RETENTION_MONTHS;
+
+/// `GetCostForecast` names metrics differently from `GetCostAndUsage`.
+fn forecast_metric(metric: &str) -> Result<Metric> {
+    Ok(match metric {
+        "UnblendedCost" => Metric::UnblendedCost,
+        "BlendedCost" => Metric::BlendedCost,
+        "AmortizedCost" => Metric::AmortizedCost,
+        "NetUnblendedCost" => Metric::NetUnblendedCost,
+        "NetAmortizedCost" => Metric::NetAmortizedCost,
+        _ => bail!("Cost Explorer cannot forecast {metric}"),
+    })
+}
+
+/// Usage metrics in `GetUsageForecast` spelling.
+fn usage_forecast_metric(metric: &str) -> Result<Metric> {
+    Ok(match metric {
+        "UsageQuantity" => Metric::UsageQuantity,
+        "NormalizedUsageAmount" => Metric::NormalizedUsageAmount,
+        _ => bail!(
+            "Cost Explorer cannot forecast usage as {metric}; expected one of {}",
+            USAGE_FORECAST_METRICS.join(", ")
+        ),
+    })
+}
+
+/// Builds the query window, rejecting ranges Cost Explorer no longer retains
+/// (it silently returns empty results for those).
+fn date_interval(
+    start: NaiveDate,
+    end_exclusive: NaiveDate,
+    today: NaiveDate,
+) -> Result<DateInterval> {
+    date_interval_for(start, end_exclusive, &Granularity::Monthly, today)
+}
+
+/// Days of history Cost Explorer keeps at hourly granularity; also the longest hourly query.
+const CE_HOURLY_MAX_DAYS: i64 = 14;
+
+fn date_interval_for(
+    start: NaiveDate,
+    end_exclusive: NaiveDate,
+    granularity: &Granularity,
+    today: NaiveDate,
+) -> Result<DateInterval> {
+    validate_window(start, end_exclusive)?;
+    let earliest = earliest_retained_date(today);
+    if start < earliest {
+        bail!(
+            "Requested range starts {start}, but Cost Explorer only retains {CE_RETENTION_MONTHS} months \
+             of history (earliest available: {earliest})"
+        );
+    }
+
+    // Hourly queries take timestamps and are limited to the last 14 days
+    let format = if *granularity == Granularity::Hourly {
+        let days = (end_exclusive - start).num_days();
+        if days > CE_HOURLY_MAX_DAYS {
+            bail!(
+                "Hourly granularity covers at most {CE_HOURLY_MAX_DAYS} days; requested range is {days} days"
+            );
+        }
+        let earliest_hourly = today - chrono::Duration::days(CE_HOURLY_MAX_DAYS);
+        if start < earliest_hourly {
+            bail!(
+                "Hourly data is only retained for {CE_HOURLY_MAX_DAYS} days (earliest available: {earliest_hourly})"
+            );
+        }
+        "%Y-%m-%dT00:00:00Z"
+    } else {
+        "%Y-%m-%d"
+    };
+
+    Ok(DateInterval::builder()
+        .start(start.format(format).to_string())
+        .end(end_exclusive.format(format).to_string())
+        .build()?)
+}
+
+/// Whether `err` (anywhere in its context chain) is an SDK error for expired session
+/// credentials: `ExpiredTokenException` from Cost Explorer, `ExpiredToken` from STS.
+fn is_expired_token(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            sdk_error_code(cause),
+            Some("ExpiredToken" | "ExpiredTokenException")
+        )
+    })
+}
+
+/// The service error code of `cause` when it is the SDK error of an operation made under
+/// `with_config`. Call sites wrap these errors with context, so each type is tried in turn.
+fn sdk_error_code<'a>(cause: &'a (dyn std::error::Error + 'static)) -> Option<&'a str> {
+    use aws_sdk_costexplorer::operation::{
+        get_cost_and_usage::GetCostAndUsageError,
+        get_cost_and_usage_comparisons::GetCostAndUsageComparisonsError,
+        get_cost_comparison_drivers::GetCostComparisonDriversError,
+        get_cost_forecast::GetCostForecastError, get_tags::GetTagsError,
+        get_usage_forecast::GetUsageForecastError,
+        list_cost_category_definitions::ListCostCategoryDefinitionsError,
+    };
+    use aws_sdk_sts::operation::get_caller_identity::GetCallerIdentityError;
+
+    fn code<'a, E>(cause: &'a (dyn std::error::Error + 'static)) -> Option<&'a str>
+    where
+        E: ProvideErrorMetadata + std::error::Error + 'static,
+    {
+        cause
+            .downcast_ref::<SdkError<E, aws_sdk_costexplorer::config::http::HttpResponse>>()?
+            .code()
+    }
+
+    code::<GetCostAndUsageError>(cause)
+        .or_else(|| code::<GetCostForecastError>(cause))
+        .or_else(|| code::<GetUsageForecastError>(cause))
+        .or_else(|| code::<GetCostAndUsageComparisonsError>(cause))
+        .or_else(|| code::<GetCostComparisonDriversError>(cause))
+        .or_else(|| code::<GetTagsError>(cause))
+        .or_else(|| code::<ListCostCategoryDefinitionsError>(cause))
+        .or_else(|| code::<GetCallerIdentityError>(cause))
+}
+
+/// Errors that mean the comparison APIs cannot serve this caller (not launched in the
+/// partition, or missing IAM permissions for the new actions). A rejected request is not
+/// one of them: `comparison` checks the windows before asking.
+fn comparison_unavailable<E, R>(err: &SdkError<E, R>) -> bool
+where
+    E: ProvideErrorMetadata,
+{
+    matches!(
+        err.code(),
+        Some("UnknownOperationException" | "AccessDeniedException")
+    )
+}
+
+/// Whether (start, end-exclusive) is exactly one calendar month, the only periods the
+/// comparison APIs accept.
+fn is_whole_month((start, end_exclusive): (NaiveDate, NaiveDate)) -> bool {
+    start.day() == 1 && start.checked_add_months(Months::new(1)) == Some(end_exclusive)
+}
+
+fn cost_delta(value: &ComparisonMetricValue) -> CostDelta {
+    CostDelta {
+        baseline: comparison_amount(value.baseline_time_period_amount()),
+        current: comparison_amount(value.comparison_time_period_amount()),
+        difference: comparison_amount(value.difference()),
+    }
+}
+
+fn comparison_amount(amount: Option<&str>) -> f64 {
+    amount
+        .and_then(|a| a.trim().parse::<f64>().ok())
+        .filter(|a| a.is_finite())
+        .unwrap_or(0.0)
+}
+
+/// First value of the dimension a comparison row was grouped by.
+fn selector_value(selector: Option<&Expression>) -> Option<String> {
+    selector?.dimensions()?.values().first().cloned()
+}
+
+/// Parses a granularity name from config files (`monthly`, `daily` or `hourly`, any case).
+pub fn parse_granularity(name: &str) -> Result<Granularity> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "monthly" => Ok(Granularity::Monthly),
+        "daily" => Ok(Granularity::Daily),
+        "hourly" => Ok(Granularity::Hourly),
+        other => bail!("Unknown granularity {other:?}; expected monthly, daily or hourly"),
+    }
+}
+
+/// Tag and cost category keys come back as "<key>$<value>"; an empty value means untagged,
+/// and is `None` like a missing key.
+fn group_value(key: Option<&String>) -> Option<&str> {
+    key.map(|k| k.split_once('$').map_or(k.as_str(), |(_, value)| value))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Case-insensitive glob match: `*` matches any run of characters, `?` exactly one.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn and_all(mut conditions: Vec<Expression>) -> Option<Expression> {
+    match conditions.len() {
+        0 => None,
+        1 => conditions.pop(),
+        _ => Some(Expression::builder().set_and(Some(conditions)).build()),
+    }
+}
+
+fn tax_records() -> Expression {
+    dimension_filter(Dimension::RecordType, &["Tax".to_string()])
+}
+
+fn dimension_filter(dimension: Dimension, values: &[String]) -> Expression {
+    Expression::builder()
+        .dimensions(
+            DimensionValues::builder()
+                .key(dimension)
+                .set_values(Some(values.to_vec()))
+                .build(),
+        )
+        .build()
+}
+
+fn earliest_retained_date(today: NaiveDate) -> NaiveDate {
+    let first_of_month = today.with_day(1).unwrap_or(today);
+    first_of_month
+        .checked_sub_months(Months::new(CE_RETENTION_MONTHS - 1))
+        .unwrap_or(first_of_month)
+}
+
+/// One account's Cost Explorer client plus the filter and amount checks every query applies.
+struct CostQuery {
+    ce: CeClient,
+    account_ref: String,
+    audit: Option<AuditLog>,
+    /// Cost metric behind services and totals (`AwsCostProvider::metric`)
+    metric: String,
+    /// Uncapped `AwsCostProvider::page_size`
+    page_size: Option<i32>,
+    filter: Option<Expression>,
+    amounts: AmountCheck,
+    /// Day Cost Explorer's retention limits count back from
+    today: NaiveDate,
+}
+
+impl CostQuery {
+    /// A `GetCostAndUsage` request for the primary metric at monthly granularity, ungrouped;
+    /// callers override the rest.
+    fn call(&self, start: NaiveDate, end_exclusive: NaiveDate) -> ApiCall {
+        ApiCall {
+            account_ref: self.account_ref.clone(),
+            operation: "GetCostAndUsage",
+            start,
+            end_exclusive,
+            baseline: None,
+            granularity: Some(Granularity::Monthly.as_str().to_string()),
+            metrics: vec![self.metric.clone()],
+            group_by: Vec::new(),
+        }
+    }
+
+    fn record(&self, call: ApiCall) {
+        if let Some(audit) = &self.audit {
+            audit.record(call);
+        }
+    }
+
+    async fn by_service(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<(f64, HashMap<String, f64>)> {
+        let metric = self.metric.clone();
+        let services = self
+            .by_metric(
+                start,
+                end_exclusive,
+                std::slice::from_ref(&metric),
+                &Granularity::Monthly,
+            )
+            .await?
+            .remove(&metric)
+            .unwrap_or_default();
+        let total = services.values().sum();
+        Ok((total, services))
+    }
+
+    /// Fetches per-service costs for each requested metric: metric -> service -> amount.
+    async fn by_metric(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        metrics: &[String],
+        granularity: &Granularity,
+    ) -> Result<HashMap<String, HashMap<String, f64>>> {
+        let time_period = date_interval_for(start, end_exclusive, granularity, self.today)?;
+        let mut by_metric: HashMap<String, HashMap<String, f64>> = metrics
+            .iter()
+            .map(|m| (m.clone(), HashMap::new()))
+            .collect();
+        let mut next_page_token = None;
+
+        // Daily and hourly buckets times services quickly run past one page
+        loop {
+            self.record(ApiCall {
+                granularity: Some(granularity.as_str().to_string()),
+                metrics: metrics.to_vec(),
+                group_by: vec!["SERVICE".to_string()],
+                ..self.call(start, end_exclusive)
+            });
+            let resp = self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(granularity.clone())
+                .set_metrics(Some(metrics.to_vec()))
+                .set_filter(self.filter.clone())
+                .group_by(
+                    GroupDefinition::builder()
+                        .key("SERVICE")
+                        .r#type(GroupDefinitionType::Dimension)
+                        .build(),
+                )
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+                .map_err(|err| {
+                    if *granularity == Granularity::Hourly
+                        && err
+                            .message()
+                            .is_some_and(|m| m.to_ascii_lowercase().contains("hourly"))
+                    {
+                        anyhow!(
+                            "Hourly cost data is not available for this account; enable hourly granularity \
+                             in Cost Explorer preferences (it is a paid opt-in)"
+                        )
+                    } else {
+                        anyhow!(err).context("GetCostAndUsage failed")
+                    }
+                })?;
+
+            for result in resp.results_by_time() {
+                for g in result.groups() {
+                    let svc = g.keys().first().map(|s| s.as_str()).unwrap_or("Unknown");
+                    for (metric, services) in by_metric.iter_mut() {
+                        let amt = self.amounts.parse(g, metric)?;
+                        *services.entry(svc.to_string()).or_insert(0.0) += amt;
+                    }
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(by_metric)
+    }
+
+    /// Primary-metric total for the window, without grouping.
+    async fn total(&mut self, start: NaiveDate, end_exclusive: NaiveDate) -> Result<f64> {
+        self.record(self.call(start, end_exclusive));
+        let resp = self
+            .ce
+            .get_cost_and_usage()
+            .time_period(date_interval(start, end_exclusive, self.today)?)
+            .granularity(Granularity::Monthly)
+            .metrics(&self.metric)
+            .set_filter(self.filter.clone())
+            .send()
+            .await
+            .context("GetCostAndUsage (total) failed")?;
+
+        let mut total = 0.0;
+        for result in resp.results_by_time() {
+            let amount = result
+                .total()
+                .and_then(|total| total.get(&self.metric))
+                .and_then(|value| value.amount());
+            if let Some(amount) = amount {
+                total += self.amounts.parse_amount(amount, &self.metric, "total")?;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Cost Explorer's forecast of the metric over a window starting today or later.
+    async fn forecast(&mut self, start: NaiveDate, end_exclusive: NaiveDate) -> Result<f64> {
+        let metric = forecast_metric(&self.metric)?;
+        self.record(ApiCall {
+            operation: "GetCostForecast",
+            ..self.call(start, end_exclusive)
+        });
+        let resp = self
+            .ce
+            .get_cost_forecast()
+            .time_period(date_interval(start, end_exclusive, self.today)?)
+            .granularity(Granularity::Monthly)
+            .metric(metric)
+            .set_filter(self.filter.clone())
+            .send()
+            .await
+            .context("GetCostForecast failed")?;
+
+        let amount = resp
+            .total()
+            .and_then(|total| total.amount())
+            .ok_or_else(|| anyhow!("GetCostForecast returned no total"))?;
+        self.amounts.parse_amount(amount, &self.metric, "forecast")
+    }
+
+    /// Cost Explorer's usage forecast for one usage type, per month and in total. The
+    /// account scope still applies; `usage` narrows it to the forecast usage type.
+    async fn usage_forecast(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        usage: &UsageFilter,
+        metric: &str,
+    ) -> Result<UsageForecast> {
+        let forecast_metric = usage_forecast_metric(metric)?;
+        self.record(ApiCall {
+            operation: "GetUsageForecast",
+            metrics: vec![metric.to_string()],
+            ..self.call(start, end_exclusive)
+        });
+        let filter = and_all(
+            self.filter
+                .clone()
+                .into_iter()
+                .chain([usage.expression()])
+                .collect(),
+        );
+        let resp = self
+            .ce
+            .get_usage_forecast()
+            .time_period(date_interval(start, end_exclusive, self.today)?)
+            .granularity(Granularity::Monthly)
+            .metric(forecast_metric)
+            .set_filter(filter)
+            .send()
+            .await
+            .context("GetUsageForecast failed")?;
+
+        let mut months = BTreeMap::new();
+        for result in resp.forecast_results_by_time() {
+            let month = result
+                .time_period()
+                .and_then(|period| NaiveDate::parse_from_str(period.start(), "%Y-%m-%d").ok());
+            let (Some(month), Some(mean)) = (month, result.mean_value()) else {
+                continue;
+            };
+            months.insert(
+                month,
+                self.amounts.parse_amount(mean, metric, "usage forecast")?,
+            );
+        }
+        let total = match resp.total().and_then(|total| total.amount()) {
+            Some(amount) => self
+                .amounts
+                .parse_amount(amount, metric, "usage forecast")?,
+            None => months.values().sum(),
+        };
+        Ok(UsageForecast {
+            total,
+            unit: resp
+                .total()
+                .and_then(|total| total.unit())
+                .map(str::to_string),
+            months,
+        })
+    }
+
+    /// Per-service spend per calendar month in the window, keyed by the first of the month.
+    async fn monthly_services(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, HashMap<String, f64>>> {
+        let time_period = date_interval(start, end_exclusive, self.today)?;
+        let mut months: BTreeMap<NaiveDate, HashMap<String, f64>> = BTreeMap::new();
+        let mut next_page_token = None;
+
+        loop {
+            self.record(ApiCall {
+                group_by: vec!["SERVICE".to_string()],
+                ..self.call(start, end_exclusive)
+            });
+            let resp = self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(Granularity::Monthly)
+                .metrics(&self.metric)
+                .set_filter(self.filter.clone())
+                .group_by(
+                    GroupDefinition::builder()
+                        .key("SERVICE")
+                        .r#type(GroupDefinitionType::Dimension)
+                        .build(),
+                )
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+                .context("GetCostAndUsage (monthly by SERVICE) failed")?;
+
+            for result in resp.results_by_time() {
+                let Some(month) = result
+                    .time_period()
+                    .and_then(|period| NaiveDate::parse_from_str(period.start(), "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                let services = months.entry(month).or_default();
+                for g in result.groups() {
+                    let svc = g.keys().first().map(|s| s.as_str()).unwrap_or("Unknown");
+                    *services.entry(svc.to_string()).or_insert(0.0) +=
+                        self.amounts.parse(g, &self.metric)?;
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(months)
+    }
+
+    async fn daily_totals(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, f64>> {
+        let time_period = date_interval(start, end_exclusive, self.today)?;
+        let mut days = BTreeMap::new();
+        let mut next_page_token = None;
+
+        // Cost Explorer pages long ranges of days too
+        loop {
+            self.record(ApiCall {
+                granularity: Some(Granularity::Daily.as_str().to_string()),
+                ..self.call(start, end_exclusive)
+            });
+            let resp = self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(Granularity::Daily)
+                .metrics(&self.metric)
+                .set_filter(self.filter.clone())
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+                .context("GetCostAndUsage (daily) failed")?;
+
+            for result in resp.results_by_time() {
+                let Some(day) = result
+                    .time_period()
+                    .and_then(|period| NaiveDate::parse_from_str(period.start(), "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                let amount = result
+                    .total()
+                    .and_then(|total| total.get(&self.metric))
+                    .and_then(|value| value.amount());
+                let amount = match amount {
+                    Some(amount) => {
+                        self.amounts
+                            .parse_amount(amount, &self.metric, &day.to_string())?
+                    }
+                    None => 0.0,
+                };
+                days.insert(day, amount);
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(days)
+    }
+
+    /// One paginated query grouped by LINKED_ACCOUNT and SERVICE, covering every account the
+    /// caller's credentials can see (typically the payer account).
+    async fn by_account_and_service(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<ConsolidatedWindow> {
+        let time_period = date_interval(start, end_exclusive, self.today)?;
+        let mut window = ConsolidatedWindow::default();
+        let mut next_page_token = None;
+
+        loop {
+            self.record(ApiCall {
+                group_by: vec!["LINKED_ACCOUNT".to_string(), "SERVICE".to_string()],
+                ..self.call(start, end_exclusive)
+            });
+            let resp = self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(Granularity::Monthly)
+                .metrics(&self.metric)
+                .set_filter(self.filter.clone())
+                .group_by(
+                    GroupDefinition::builder()
+                        .key("LINKED_ACCOUNT")
+                        .r#type(GroupDefinitionType::Dimension)
+                        .build(),
+                )
+                .group_by(
+                    GroupDefinition::builder()
+                        .key("SERVICE")
+                        .r#type(GroupDefinitionType::Dimension)
+                        .build(),
+                )
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+                .context("GetCostAndUsage by LINKED_ACCOUNT and SERVICE failed")?;
+
+            for attributes in resp.dimension_value_attributes() {
+                let name = attributes
+                    .attributes()
+                    .and_then(|attributes| attributes.get("description"))
+                    .filter(|name| !name.is_empty());
+                if let (Some(account_id), Some(name)) = (attributes.value(), name) {
+                    window.names.insert(account_id.to_string(), name.clone());
+                }
+            }
+            for result in resp.results_by_time() {
+                for g in result.groups() {
+                    let keys = g.keys();
+                    let Some(account_id) = keys.first() else {
+                        continue;
+                    };
+                    let svc = keys.get(1).map(|s| s.as_str()).unwrap_or("Unknown");
+                    let invalid = self.amounts.invalid;
+                    *window
+                        .accounts
+                        .entry(account_id.clone())
+                        .or_default()
+                        .entry(svc.to_string())
+                        .or_insert(0.0) += self.amounts.parse(g, &self.metric)?;
+                    if self.amounts.invalid > invalid {
+                        *window.unparseable.entry(account_id.clone()).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(window)
+    }
+
+    /// One `DimensionBreakdown` per grouping, in order.
+    async fn breakdowns(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        groupings: &[Grouping],
+    ) -> Result<Vec<DimensionBreakdown>> {
+        let mut breakdowns = Vec::with_capacity(groupings.len());
+        for grouping in groupings {
+            breakdowns.push(DimensionBreakdown {
+                dimension: grouping.label(),
+                values: self.by_grouping(start, end_exclusive, grouping).await?,
+            });
+        }
+        Ok(breakdowns)
+    }
+
+    /// Fetches primary-metric spend grouped by `grouping`. Usage the dimension does not
+    /// apply to (e.g. non-compute services for `PURCHASE_TYPE`) lands in one bucket.
+    async fn by_grouping(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        grouping: &Grouping,
+    ) -> Result<HashMap<String, f64>> {
+        let time_period = date_interval(start, end_exclusive, self.today)?;
+        let mut values: HashMap<String, f64> = HashMap::new();
+        let mut next_page_token = None;
+
+        // Usage types run into the hundreds, enough to page
+        loop {
+            self.record(ApiCall {
+                group_by: vec![grouping.label()],
+                ..self.call(start, end_exclusive)
+            });
+            let resp = match self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(Granularity::Monthly)
+                .metrics(&self.metric)
+                .set_filter(self.filter.clone())
+                .group_by(grouping.group_definition())
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+            {
+                Ok(resp) => resp,
+                // Cost categories are defined per payer, and some partitions cannot group by
+                // zone; accounts that cannot use the grouping get no buckets
+                Err(err)
+                    if matches!(
+                        grouping,
+                        Grouping::CostCategory(_) | Grouping::AvailabilityZone
+                    ) && err.code() == Some("ValidationException") =>
+                {
+                    tracing::warn!(grouping = %grouping.label(), error = %err, "grouping unavailable");
+                    return Ok(HashMap::new());
+                }
+                Err(err) => {
+                    return Err(anyhow!(err)
+                        .context(format!("GetCostAndUsage by {} failed", grouping.label())));
+                }
+            };
+
+            for result in resp.results_by_time() {
+                for g in result.groups() {
+                    let key = match group_value(g.keys().first()) {
+                        Some("NoAZ") if *grouping == Grouping::AvailabilityZone => NO_AZ,
+                        Some(k) => k,
+                        None if matches!(grouping, Grouping::Tag(_)) => UNTAGGED,
+                        None if *grouping == Grouping::AvailabilityZone => NO_AZ,
+                        None => "Not applicable",
+                    };
+                    if !grouping.keeps(key) {
+                        continue;
+                    }
+                    *values.entry(key.to_string()).or_insert(0.0) +=
+                        self.amounts.parse(g, &self.metric)?;
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(values)
+    }
+
+    async fn by_group_keys(
+        &mut self,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        keys: &[GroupKey],
+    ) -> Result<HashMap<String, HashMap<String, f64>>> {
+        let time_period = date_interval(start, end_exclusive, self.today)?;
+        let labels: Vec<String> = keys.iter().map(GroupKey::label).collect();
+        let mut groups: HashMap<String, HashMap<String, f64>> = HashMap::new();
+        let mut next_page_token = None;
+
+        loop {
+            self.record(ApiCall {
+                group_by: labels.clone(),
+                ..self.call(start, end_exclusive)
+            });
+            let resp = self
+                .ce
+                .get_cost_and_usage()
+                .time_period(time_period.clone())
+                .granularity(Granularity::Monthly)
+                .metrics(&self.metric)
+                .set_filter(self.filter.clone())
+                .set_group_by(Some(keys.iter().map(GroupKey::group_definition).collect()))
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+                .with_context(|| format!("GetCostAndUsage by {} failed", labels.join(" and ")))?;
+
+            for result in resp.results_by_time() {
+                for g in result.groups() {
+                    let outer = group_value(g.keys().first()).unwrap_or("Not applicable");
+                    let inner = if keys.len() > 1 {
+                        group_value(g.keys().get(1)).unwrap_or("Not applicable")
+                    } else {
+                        "Total"
+                    };
+                    *groups
+                        .entry(outer.to_string())
+                        .or_default()
+                        .entry(inner.to_string())
+                        .or_insert(0.0) += self.amounts.parse(g, &self.metric)?;
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// AWS-computed period-over-period change by service (`GetCostAndUsageComparisons`)
+    /// and what drove it (`GetCostComparisonDrivers`). `None` when the APIs are unavailable.
+    async fn comparison(
+        &self,
+        baseline: (NaiveDate, NaiveDate),
+        current: (NaiveDate, NaiveDate),
+    ) -> Result<Option<NativeComparison>> {
+        if !is_whole_month(baseline) || !is_whole_month(current) {
+            bail!(
+                "Cost Explorer comparisons only cover whole months, not {} to {} against {} to {} (exclusive)",
+                current.0,
+                current.1,
+                baseline.0,
+                baseline.1
+            );
+        }
+        let baseline_period = date_interval(baseline.0, baseline.1, self.today)?;
+        let comparison_period = date_interval(current.0, current.1, self.today)?;
+        let by_service = GroupDefinition::builder()
+            .key("SERVICE")
+            .r#type(GroupDefinitionType::Dimension)
+            .build();
+
+        let mut comparison = NativeComparison::default();
+        let mut next_page_token = None;
+        loop {
+            self.record(ApiCall {
+                operation: "GetCostAndUsageComparisons",
+                baseline: Some(baseline),
+                granularity: None,
+                group_by: vec!["SERVICE".to_string()],
+                ..self.call(current.0, current.1)
+            });
+            let resp = match self
+                .ce
+                .get_cost_and_usage_comparisons()
+                .baseline_time_period(baseline_period.clone())
+                .comparison_time_period(comparison_period.clone())
+                .metric_for_comparison(&self.metric)
+                .set_filter(self.filter.clone())
+                .group_by(by_service.clone())
+                .set_max_results(self.page_size.map(|size| size.min(COMPARISONS_MAX_RESULTS)))
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+            {
+                Ok(resp) => resp,
+                Err(err) if comparison_unavailable(&err) => {
+                    tracing::warn!(error = %err, "native comparison unavailable; using computed change");
+                    return Ok(None);
+                }
+                Err(err) => {
+                    return Err(anyhow!(err).context("GetCostAndUsageComparisons failed"));
+                }
+            };
+
+            if let Some(total) = resp
+                .total_cost_and_usage()
+                .and_then(|metrics| metrics.get(&self.metric))
+            {
+                comparison.total = cost_delta(total);
+            }
+            for row in resp.cost_and_usage_comparisons() {
+                let value = row.metrics().and_then(|metrics| metrics.get(&self.metric));
+                if let (Some(service), Some(value)) =
+                    (selector_value(row.cost_and_usage_selector()), value)
+                {
+                    comparison.services.insert(service, cost_delta(value));
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        let mut next_page_token = None;
+        loop {
+            self.record(ApiCall {
+                operation: "GetCostComparisonDrivers",
+                baseline: Some(baseline),
+                granularity: None,
+                group_by: vec!["SERVICE".to_string()],
+                ..self.call(current.0, current.1)
+            });
+            let resp = match self
+                .ce
+                .get_cost_comparison_drivers()
+                .baseline_time_period(baseline_period.clone())
+                .comparison_time_period(comparison_period.clone())
+                .metric_for_comparison(&self.metric)
+                .set_filter(self.filter.clone())
+                .group_by(by_service.clone())
+                .set_max_results(
+                    self.page_size
+                        .map(|size| size.min(COMPARISON_DRIVERS_MAX_RESULTS)),
+                )
+                .set_next_page_token(next_page_token)
+                .send()
+                .await
+            {
+                Ok(resp) => resp,
+                // Deltas without drivers are still better than our own subtraction.
+                Err(err) if comparison_unavailable(&err) => {
+                    tracing::warn!(error = %err, "cost comparison drivers unavailable");
+                    break;
+                }
+                Err(err) => return Err(anyhow!(err).context("GetCostComparisonDrivers failed")),
+            };
+
+            for row in resp.cost_comparison_drivers() {
+                let service = selector_value(row.cost_selector()).unwrap_or_default();
+                for driver in row.cost_drivers() {
+                    let difference = driver
+                        .metrics()
+                        .and_then(|metrics| metrics.get(&self.metric))
+                        .map_or(0.0, |value| comparison_amount(value.difference()));
+                    comparison.drivers.push(CostDriver {
+                        service: service.clone(),
+                        driver_type: driver.r#type().unwrap_or_default().to_string(),
+                        name: driver.name().unwrap_or_default().to_string(),
+                        difference,
+                    });
+                }
+            }
+
+            next_page_token = resp.next_page_token().map(String::from);
+            if next_page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(Some(comparison))
+    }
+}
+
+/// Parses Cost Explorer amount strings, counting (or, when strict, rejecting) malformed ones
+/// instead of silently treating them as zero. Also notes the currency the costs came in.
+struct AmountCheck {
+    strict: bool,
+    invalid: usize,
+    /// `Unit` of the first cost metric amount seen, e.g. `USD`
+    currency: Option<String>,
+}
+
+impl AmountCheck {
+    fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            invalid: 0,
+            currency: None,
+        }
+    }
+
+    fn parse(&mut self, group: &Group, metric: &str) -> Result<f64> {
+        let Some(value) = group.metrics().and_then(|metrics| metrics.get(metric)) else {
+            return Ok(0.0);
+        };
+        if self.currency.is_none() && COST_METRICS.contains(&metric) {
+            self.currency = value
+                .unit()
+                .filter(|unit| !unit.is_empty())
+                .map(String::from);
+        }
+        let Some(amount) = value.amount() else {
+            return Ok(0.0);
+        };
+
+        self.parse_amount(amount, metric, &group.keys().join("/"))
+    }
+
+    /// Parses one amount; `keys` identifies it in warnings and errors.
+    fn parse_amount(&mut self, amount: &str, metric: &str, keys: &str) -> Result<f64> {
+        match amount.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => {
+                if self.strict {
+                    bail!("Unparseable {metric} amount {amount:?} for {keys}");
+                }
+                tracing::warn!(metric, amount, keys = %keys, "unparseable Cost Explorer amount counted as 0");
+                self.invalid += 1;
+                Ok(0.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_http_client::test_util::infallible_client_fn;
+    use cloud_cost_core::{
+        ReportOptions, generate_report_for_windows, month_to_date, month_window,
+        previous_month_same_point,
+    };
+    use std::collections::VecDeque;
+
+    /// One request the mock answered.
+    #[derive(Debug, Clone)]
+    struct Request {
+        operation: String,
+        uri: String,
+        body: String,
+        /// Access key id the request was signed with
+        access_key: String,
+    }
+
+    /// Canned AWS answers per operation (`GetCostAndUsage`, `AssumeRole`, ...), served in
+    /// order. Operations without an answer left fail with AccessDenied.
+    #[derive(Clone, Default)]
+    struct MockAws {
+        responses: Arc<std::sync::Mutex<HashMap<String, VecDeque<(u16, String)>>>>,
+        requests: Arc<std::sync::Mutex<Vec<Request>>>,
+    }
+
+    impl MockAws {
+        fn respond(self, operation: &str, body: impl Into<String>) -> Self {
+            self.respond_with(operation, 200, body)
+        }
+
+        fn respond_with(self, operation: &str, status: u16, body: impl Into<String>) -> Self {
+            self.responses
+                .lock()
+                .unwrap()
+                .entry(operation.to_string())
+                .or_default()
+                .push_back((status, body.into()));
+            self
+        }
+
+        fn client(&self) -> SharedHttpClient {
+            let mock = self.clone();
+            infallible_client_fn(move |request| {
+                let body = request
+                    .body()
+                    .bytes()
+                    .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+                    .unwrap_or_default();
+                // JSON protocols name the operation in a header, query protocols in the body
+                let operation = match request
+                    .headers()
+                    .get("x-amz-target")
+                    .and_then(|target| target.to_str().ok())
+                {
+                    Some(target) => target.rsplit('.').next().unwrap_or(target).to_string(),
+                    None => form_value(&body, "Action").unwrap_or_default(),
+                };
+                let access_key = request
+                    .headers()
+                    .get("authorization")
+                    .and_then(|auth| auth.to_str().ok())
+                    .and_then(|auth| auth.split_once("Credential="))
+                    .and_then(|(_, credential)| credential.split('/').next())
+                    .unwrap_or_default()
+                    .to_string();
+                mock.requests.lock().unwrap().push(Request {
+                    operation: operation.clone(),
+                    uri: request.uri().to_string(),
+                    body,
+                    access_key,
+                });
+
+                let (status, body) = mock
+                    .responses
+                    .lock()
+                    .unwrap()
+                    .get_mut(&operation)
+                    .and_then(VecDeque::pop_front)
+                    .unwrap_or_else(|| {
+                        (
+                            400,
+                            r#"{"__type":"AccessDeniedException","message":"not mocked"}"#
+                                .to_string(),
+                        )
+                    });
+                let content_type = if body.starts_with('<') {
+                    "text/xml"
+                } else {
+                    "application/x-amz-json-1.1"
+                };
+                http::Response::builder()
+                    .status(status)
+                    .header("content-type", content_type)
+                    .body(body)
+                    .unwrap()
+            })
+        }
+
+        fn requests(&self, operation: &str) -> Vec<Request> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|request| request.operation == operation)
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// Percent-decoded value of `key` in a form-encoded body.
+    fn form_value(body: &str, key: &str) -> Option<String> {
+        let raw = body
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))?;
+        let mut bytes = Vec::with_capacity(raw.len());
+        let mut chars = raw.bytes();
+        while let Some(byte) = chars.next() {
+            match byte {
+                b'%' => {
+                    let hex: String = chars.by_ref().take(2).map(char::from).collect();
+                    bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                }
+                b'+' => bytes.push(b' '),
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+
+    fn json_body(request: &Request) -> serde_json::Value {
+        serde_json::from_str(&request.body).unwrap()
+    }
+
+    fn test_credentials() -> Credentials {
+        Credentials::new("AKIDBASE", "base-secret", None, None, "test")
+    }
+
+    /// Provider with static credentials for `accounts`, answered by `mock`. Account names
+    /// are prefilled so no Organizations or IAM lookups are made.
+    fn provider(mock: &MockAws, accounts: &[&str]) -> AwsCostProvider {
+        let credentials = accounts
+            .iter()
+            .map(|account| {
+                (
+                    account.to_string(),
+                    StaticCredentials {
+                        access_key_id: "AKIDBASE".to_string(),
+                        secret_access_key: "base-secret".to_string(),
+                        session_token: None,
+                    },
+                )
+            })
+            .collect();
+        let mut provider = AwsCostProvider::with_static_credentials("us-east-1", credentials);
+        provider.http_client = Some(mock.client());
+        provider.org_account_names =
+            HashMap::from([("111111111111".to_string(), "Production".to_string())]);
+        provider
+    }
+
+    fn assume_role_response(access_key: &str) -> String {
+        format!(
+            "<AssumeRoleResponse xmlns=\"https://sts.amazonaws.com/doc/2011-06-15/\">\
+             <AssumeRoleResult><Credentials>\
+             <AccessKeyId>{access_key}</AccessKeyId>\
+             <SecretAccessKey>secret-{access_key}</SecretAccessKey>\
+             <SessionToken>token-{access_key}</SessionToken>\
+             <Expiration>2099-01-01T00:00:00Z</Expiration>\
+             </Credentials>\
+             <AssumedRoleUser><AssumedRoleId>AROATEST:session</AssumedRoleId>\
+             <Arn>arn:aws:sts::111111111111:assumed-role/test/session</Arn></AssumedRoleUser>\
+             </AssumeRoleResult>\
+             <ResponseMetadata><RequestId>1</RequestId></ResponseMetadata>\
+             </AssumeRoleResponse>"
+        )
+    }
+
+    fn caller_identity_response(account_id: &str) -> String {
+        format!(
+            "<GetCallerIdentityResponse xmlns=\"https://sts.amazonaws.com/doc/2011-06-15/\">\
+             <GetCallerIdentityResult>\
+             <Arn>arn:aws:iam::{account_id}:user/test</Arn>\
+             <UserId>AIDATEST</UserId><Account>{account_id}</Account>\
+             </GetCallerIdentityResult>\
+             <ResponseMetadata><RequestId>1</RequestId></ResponseMetadata>\
+             </GetCallerIdentityResponse>"
+        )
+    }
+
+    /// A `GetCostAndUsage` page with one time bucket; each group is (keys, [(metric, amount)]).
+    fn cost_page(groups: &[(&[&str], &[(&str, &str)])], next_page_token: Option<&str>) -> String {
+        let groups: Vec<serde_json::Value> = groups
+            .iter()
+            .map(|(keys, metrics)| {
+                let metrics: serde_json::Map<String, serde_json::Value> = metrics
+                    .iter()
+                    .map(|(metric, amount)| {
+                        (
+                            metric.to_string(),
+                            serde_json::json!({ "Amount": amount, "Unit": "USD" }),
+                        )
+                    })
+                    .collect();
+                serde_json::json!({ "Keys": keys, "Metrics": metrics })
+            })
+            .collect();
+        let mut page = serde_json::json!({
+            "ResultsByTime": [{
+                "TimePeriod": { "Start": "2024-08-01", "End": "2024-08-15" },
+                "Total": {},
+                "Groups": groups,
+                "Estimated": false,
+            }],
+        });
+        if let Some(token) = next_page_token {
+            page["NextPageToken"] = serde_json::json!(token);
+        }
+        page.to_string()
+    }
+
+    /// `cost_page` with `UnblendedCost` only.
+    fn unblended_page(groups: &[(&[&str], &str)], next_page_token: Option<&str>) -> String {
+        let groups: Vec<(&[&str], Vec<(&str, &str)>)> = groups
+            .iter()
+            .map(|(keys, amount)| (*keys, vec![(PRIMARY_METRIC, *amount)]))
+            .collect();
+        let groups: Vec<(&[&str], &[(&str, &str)])> = groups
+            .iter()
+            .map(|(keys, metrics)| (*keys, metrics.as_slice()))
+            .collect();
+        cost_page(&groups, next_page_token)
+    }
+
+    #[tokio::test]
+    async fn role_chain_assumes_each_hop_with_the_previous_credentials() {
+        let mock = MockAws::default()
+            .respond("AssumeRole", assume_role_response("ASIAHOP1"))
+            .respond("AssumeRole", assume_role_response("ASIATARGET"));
+        let provider = provider(&mock, &[]);
+        let role = AssumeRoleConfig {
+            role_arn: "arn:aws:iam::222222222222:role/CostReader".to_string(),
+            external_id: Some("ext-1".to_string()),
+            via_role_arns: vec!["arn:aws:iam::111111111111:role/Hub".to_string()],
+            serial_number: None,
+        };
+        let base = provider
+            .config_loader()
+            .credentials_provider(test_credentials())
+            .load()
+            .await;
+
+        let config = provider
+            .assume_role_chain(base, &role, "prod")
+            .await
+            .unwrap();
+
+        let calls = mock.requests("AssumeRole");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            form_value(&calls[0].body, "RoleArn").as_deref(),
+            Some("arn:aws:iam::111111111111:role/Hub")
+        );
+        assert_eq!(calls[0].access_key, "AKIDBASE");
+        assert_eq!(form_value(&calls[0].body, "ExternalId"), None);
+        assert_eq!(
+            form_value(&calls[1].body, "RoleArn").as_deref(),
+            Some("arn:aws:iam::222222222222:role/CostReader")
+        );
+        assert_eq!(calls[1].access_key, "ASIAHOP1");
+        assert_eq!(
+            form_value(&calls[1].body, "ExternalId").as_deref(),
+            Some("ext-1")
+        );
+
+        let credentials = config
+            .credentials_provider()
+            .unwrap()
+            .provide_credentials()
+            .await
+            .unwrap();
+        assert_eq!(credentials.access_key_id(), "ASIATARGET");
+    }
+
+    #[tokio::test]
+    async fn single_role_is_assumed_once() {
+        let mock = MockAws::default().respond("AssumeRole", assume_role_response("ASIATARGET"));
+        let provider = provider(&mock, &[]);
+        let role = AssumeRoleConfig {
+            role_arn: "arn:aws:iam::222222222222:role/CostReader".to_string(),
+            external_id: None,
+            via_role_arns: Vec::new(),
+            serial_number: None,
+        };
+        let base = provider
+            .config_loader()
+            .credentials_provider(test_credentials())
+            .load()
+            .await;
+
+        provider
+            .assume_role_chain(base, &role, "prod")
+            .await
+            .unwrap();
+
+        let calls = mock.requests("AssumeRole");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].access_key, "AKIDBASE");
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn ranges_older_than_fourteen_months_are_rejected() {
+        let today = day(2024, 8, 14);
+        // 14 months counting August 2024 go back to July 2023
+        assert_eq!(earliest_retained_date(today), day(2023, 7, 1));
+
+        let err = date_interval(day(2023, 6, 1), day(2023, 7, 1), today).unwrap_err();
+        assert!(err.to_string().contains("only retains 14 months"), "{err}");
+        assert!(err.to_string().contains("2023-07-01"), "{err}");
+        assert!(date_interval(day(2023, 6, 30), day(2024, 8, 15), today).is_err());
+    }
+
+    #[test]
+    fn ranges_within_retention_are_accepted() {
+        let today = day(2024, 8, 14);
+        let interval = date_interval(day(2023, 7, 1), day(2023, 8, 1), today).unwrap();
+        assert_eq!(interval.start(), "2023-07-01");
+        assert_eq!(interval.end(), "2023-08-01");
+        assert!(date_interval(day(2024, 8, 1), day(2024, 8, 15), today).is_ok());
+    }
+
+    #[test]
+    fn hourly_ranges_are_limited_to_fourteen_days() {
+        let today = day(2024, 8, 20);
+        let interval = date_interval_for(
+            day(2024, 8, 10),
+            day(2024, 8, 20),
+            &Granularity::Hourly,
+            today,
+        )
+        .unwrap();
+        assert_eq!(interval.start(), "2024-08-10T00:00:00Z");
+        assert!(
+            date_interval_for(
+                day(2024, 8, 1),
+                day(2024, 8, 20),
+                &Granularity::Hourly,
+                today
+            )
+            .is_err()
+        );
+        assert!(
+            date_interval_for(
+                day(2024, 7, 1),
+                day(2024, 7, 5),
+                &Granularity::Hourly,
+                today
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn consolidated_accounts_cover_both_windows_with_one_query_each() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["111111111111", "Amazon EC2"], "100"),
+                        (&["222222222222", "AWS Lambda"], "5"),
+                    ],
+                    None,
+                ),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["222222222222", "AWS Lambda"], "4"),
+                        (&["333333333333", "Amazon S3"], "20"),
+                    ],
+                    None,
+                ),
+            );
+        let mut provider = provider(&mock, &["payer"]);
+        provider.consolidated_payer = Some("payer".to_string());
+        let today = Utc::now().date_naive();
+        let current = month_to_date(today);
+        let previous = previous_month_same_point(today).unwrap();
+
+        let accounts = provider
+            .consolidated_account_ids(&[current, previous])
+            .await
+            .unwrap();
+        assert_eq!(accounts, ["111111111111", "222222222222", "333333333333"]);
+
+        let report = generate_report_for_windows(
+            &provider,
+            &accounts,
+            current,
+            previous,
+            &ReportOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        // One query per window, shared by the account listing and every account's summary
+        assert_eq!(mock.requests("GetCostAndUsage").len(), 2);
+        assert_eq!(report.summaries.len(), 3);
+        assert_eq!(report.total_all, Some(105.0));
+        // The account that only spent last month is part of the comparison
+        assert_eq!(report.prev_total, Some(24.0));
+        assert_eq!(report.removed_services, ["Amazon S3"]);
+    }
+
+    #[tokio::test]
+    async fn consolidated_query_follows_next_page_token() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["111111111111", "Amazon EC2"], "100")], Some("page-2")),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["222222222222", "AWS Lambda"], "5")], None),
+            );
+        let mut provider = provider(&mock, &["payer"]);
+        provider.consolidated_payer = Some("payer".to_string());
+
+        let accounts = provider
+            .consolidated_account_ids(&[month_to_date(Utc::now().date_naive())])
+            .await
+            .unwrap();
+
+        assert_eq!(accounts, ["111111111111", "222222222222"]);
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(json_body(&calls[1])["NextPageToken"], "page-2");
+    }
+
+    #[tokio::test]
+    async fn service_breakdown_follows_next_page_token() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[(&["Amazon EC2"], "10"), (&["AWS Lambda"], "1")],
+                    Some("page-2"),
+                ),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "12"), (&["Amazon S3"], "3")], None),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.granularity = Granularity::Daily;
+        let audit = AuditLog::default();
+        provider.audit = Some(audit.clone());
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.services["Amazon EC2"], 22.0);
+        assert_eq!(summary.services["AWS Lambda"], 1.0);
+        assert_eq!(summary.services["Amazon S3"], 3.0);
+        assert_eq!(summary.total, 26.0);
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(json_body(&calls[0])["Granularity"], "DAILY");
+        assert_eq!(json_body(&calls[1])["NextPageToken"], "page-2");
+        // Each page is a separate request in the audit log
+        assert_eq!(audit.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn garbage_amount_is_counted_as_zero() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "12,5x"), (&["AWS Lambda"], "1")], None),
+            );
+        let provider = provider(&mock, &["prod"]);
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.services["Amazon EC2"], 0.0);
+        assert_eq!(summary.total, 1.0);
+        assert_eq!(summary.unparseable_amounts, 1);
+    }
+
+    #[tokio::test]
+    async fn garbage_amount_fails_in_strict_mode() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "12,5x")], None),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.strict_amounts = true;
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let err = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("12,5x"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn consolidated_summaries_count_garbage_amounts_in_both_windows() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["111111111111", "Amazon EC2"], "n/a"),
+                        (&["111111111111", "AWS Lambda"], "5"),
+                        (&["222222222222", "Amazon S3"], "3"),
+                    ],
+                    None,
+                ),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["111111111111", "Amazon EC2"], "1e999"),
+                        (&["222222222222", "Amazon S3"], "2"),
+                    ],
+                    None,
+                ),
+            );
+        let mut provider = provider(&mock, &["payer"]);
+        provider.consolidated_payer = Some("payer".to_string());
+        let today = Utc::now().date_naive();
+        let current = month_to_date(today);
+        let previous = previous_month_same_point(today).unwrap();
+        let accounts = ["111111111111".to_string(), "222222222222".to_string()];
+
+        let report = generate_report_for_windows(
+            &provider,
+            &accounts,
+            current,
+            previous,
+            &ReportOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let summary = |account_id: &str| {
+            report
+                .summaries
+                .iter()
+                .find(|s| s.account_id == account_id)
+                .unwrap()
+        };
+        assert_eq!(summary("111111111111").total, 5.0);
+        // One garbage amount in each window
+        assert_eq!(summary("111111111111").unparseable_amounts, 2);
+        assert_eq!(summary("222222222222").unparseable_amounts, 0);
+        assert_eq!(report.prev_total, Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn only_services_filters_every_query_and_the_total() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "40"), (&["Amazon S3"], "2")], None),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "30"), (&["Amazon S3"], "1")], None),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.only_services = vec!["Amazon EC2".to_string(), "Amazon S3".to_string()];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+        let previous = provider
+            .service_costs("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total, 42.0);
+        assert_eq!(summary.services.len(), 2);
+        assert_eq!(previous.values().sum::<f64>(), 31.0);
+        for call in mock.requests("GetCostAndUsage") {
+            assert_eq!(
+                json_body(&call)["Filter"],
+                serde_json::json!({
+                    "Dimensions": { "Key": "SERVICE", "Values": ["Amazon EC2", "Amazon S3"] }
+                })
+            );
+        }
+    }
+
+    /// Last month and the month before it, as whole (start, end-exclusive) months.
+    fn last_two_months() -> ((NaiveDate, NaiveDate), (NaiveDate, NaiveDate)) {
+        let this_month = month_to_date(Utc::now().date_naive()).0;
+        (
+            month_window(this_month - Months::new(2)).unwrap(),
+            month_window(this_month - Months::new(1)).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn native_comparison_rejects_partial_months_up_front() {
+        let mock = MockAws::default();
+        let provider = provider(&mock, &["prod"]);
+        // Month-to-date windows as of the 15th
+        let today = day(2024, 8, 15);
+
+        let err = provider
+            .native_comparison(
+                "prod",
+                previous_month_same_point(today).unwrap(),
+                month_to_date(today),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("whole months"), "{err:#}");
+        assert!(mock.requests("GetCostAndUsageComparisons").is_empty());
+    }
+
+    #[tokio::test]
+    async fn native_comparison_validation_errors_are_not_swallowed() {
+        let mock = MockAws::default().respond_with(
+            "GetCostAndUsageComparisons",
+            400,
+            r#"{"__type":"ValidationException","message":"invalid time period"}"#,
+        );
+        let provider = provider(&mock, &["prod"]);
+        let (baseline, current) = last_two_months();
+
+        let err = provider
+            .native_comparison("prod", baseline, current)
+            .await
+            .unwrap_err();
+
+        assert!(
+            format!("{err:#}").contains("GetCostAndUsageComparisons failed"),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn native_comparison_falls_back_when_the_api_is_unavailable() {
+        let mock = MockAws::default().respond_with(
+            "GetCostAndUsageComparisons",
+            400,
+            r#"{"__type":"UnknownOperationException","message":"not in this partition"}"#,
+        );
+        let provider = provider(&mock, &["prod"]);
+        let (baseline, current) = last_two_months();
+
+        let comparison = provider
+            .native_comparison("prod", baseline, current)
+            .await
+            .unwrap();
+
+        assert!(comparison.is_none());
+        assert_eq!(mock.requests("GetCostAndUsageComparisons").len(), 1);
+    }
+
+    /// A `ListAccounts` page of (id, name) accounts.
+    fn list_accounts_page(accounts: &[(&str, &str)], next_token: Option<&str>) -> String {
+        let accounts: Vec<serde_json::Value> = accounts
+            .iter()
+            .map(|(id, name)| {
+                serde_json::json!({
+                    "Id": id,
+                    "Name": name,
+                    "Arn": format!("arn:aws:organizations::111111111111:account/o-test/{id}"),
+                    "Email": format!("{id}@example.com"),
+                    "Status": "ACTIVE",
+                })
+            })
+            .collect();
+        let mut page = serde_json::json!({ "Accounts": accounts });
+        if let Some(token) = next_token {
+            page["NextToken"] = serde_json::json!(token);
+        }
+        page.to_string()
+    }
+
+    /// Consolidated provider for `payer` whose current-window query returns two linked
+    /// accounts without names.
+    fn unnamed_linked_accounts(mock: MockAws) -> (MockAws, AwsCostProvider) {
+        let mock = mock.respond(
+            "GetCostAndUsage",
+            unblended_page(
+                &[
+                    (&["222222222222", "Amazon EC2"], "10"),
+                    (&["333333333333", "Amazon S3"], "5"),
+                ],
+                None,
+            ),
+        );
+        let mut provider = provider(&mock, &["payer"]);
+        provider.consolidated_payer = Some("payer".to_string());
+        (mock, provider)
+    }
+
+    #[tokio::test]
+    async fn consolidated_names_come_from_one_list_accounts_call() {
+        let (mock, provider) = unnamed_linked_accounts(MockAws::default().respond(
+            "ListAccounts",
+            list_accounts_page(
+                &[("222222222222", "Staging"), ("333333333333", "Data")],
+                None,
+            ),
+        ));
+        let accounts = ["222222222222".to_string(), "333333333333".to_string()];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summaries = provider
+            .fetch_all_summaries(&accounts, start, end_exclusive, &SummaryLimiter::default())
+            .await;
+
+        let names: Vec<String> = summaries
+            .into_iter()
+            .map(|summary| summary.unwrap().unwrap().account_name)
+            .collect();
+        assert_eq!(names, ["Staging", "Data"]);
+        assert_eq!(mock.requests("ListAccounts").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn consolidated_names_come_from_the_query_response_before_organizations() {
+        let mut page: serde_json::Value = serde_json::from_str(&unblended_page(
+            &[
+                (&["222222222222", "Amazon EC2"], "10"),
+                (&["333333333333", "Amazon S3"], "5"),
+            ],
+            None,
+        ))
+        .unwrap();
+        page["DimensionValueAttributes"] = serde_json::json!([
+            { "Value": "222222222222", "Attributes": { "description": "Staging" } },
+            { "Value": "333333333333", "Attributes": { "description": "" } },
+        ]);
+        let mock = MockAws::default()
+            .respond("GetCostAndUsage", page.to_string())
+            .respond(
+                "ListAccounts",
+                list_accounts_page(&[("333333333333", "Data")], None),
+            );
+        let mut provider = provider(&mock, &["payer"]);
+        provider.consolidated_payer = Some("payer".to_string());
+        let accounts = ["222222222222".to_string(), "333333333333".to_string()];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let staging = provider
+            .fetch_account_summary(&accounts[0], start, end_exclusive)
+            .await
+            .unwrap();
+        // Named by the query response: Organizations is not asked
+        assert_eq!(staging.account_name, "Staging");
+        assert!(mock.requests("ListAccounts").is_empty());
+
+        // A blank description falls back to Organizations
+        let data = provider
+            .fetch_account_summary(&accounts[1], start, end_exclusive)
+            .await
+            .unwrap();
+        assert_eq!(data.account_name, "Data");
+        assert_eq!(mock.requests("ListAccounts").len(), 1);
+        assert_eq!(mock.requests("GetCostAndUsage").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn consolidated_names_fall_back_to_ids_without_organizations_access() {
+        // ListAccounts is not mocked, so it is denied
+        let (mock, provider) = unnamed_linked_accounts(MockAws::default());
+        let accounts = ["222222222222".to_string(), "333333333333".to_string()];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summaries = provider
+            .fetch_all_summaries(&accounts, start, end_exclusive, &SummaryLimiter::default())
+            .await;
+
+        let names: Vec<String> = summaries
+            .into_iter()
+            .map(|summary| summary.unwrap().unwrap().account_name)
+            .collect();
+        assert_eq!(names, accounts);
+        assert_eq!(mock.requests("ListAccounts").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cost_explorer_endpoint_override_is_used() {
+        let mock = MockAws::default().respond(
+            "GetCostAndUsage",
+            unblended_page(&[(&["Amazon EC2"], "10")], None),
+        );
+        let mut provider = provider(&mock, &["prod"]);
+        provider
+            .apply_partition(
+                Partition::Aws,
+                Some("https://ce.internal.example.com".to_string()),
+            )
+            .unwrap();
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let services = provider
+            .service_costs("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(services["Amazon EC2"], 10.0);
+        let calls = mock.requests("GetCostAndUsage");
+        assert!(
+            calls[0].uri.starts_with("https://ce.internal.example.com"),
+            "{}",
+            calls[0].uri
+        );
+    }
+
+    #[test]
+    fn partition_and_endpoint_are_validated() {
+        let mut provider = AwsCostProvider::new("us-east-1");
+        let err = provider
+            .apply_partition(Partition::AwsUsGov, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("--region us-gov-west-1"), "{err}");
+
+        let mut provider = AwsCostProvider::new("cn-northwest-1");
+        provider.apply_partition(Partition::AwsCn, None).unwrap();
+        assert_eq!(provider.partition, Partition::AwsCn);
+
+        for url in [
+            "http://ce.example.com",
+            "ce.example.com",
+            "ftp://ce.example.com",
+        ] {
+            assert!(validate_endpoint_url(url).is_err(), "{url}");
+        }
+        for url in [
+            "https://ce.example.com",
+            "http://localhost:4566",
+            "http://127.0.0.1:4566",
+            "http://[::1]:4566",
+        ] {
+            validate_endpoint_url(url).unwrap();
+        }
+    }
+
+    #[test]
+    fn endpoint_override_allows_plain_http_only_to_loopback_hosts() {
+        for host in [
+            "localhost",
+            "LOCALHOST",
+            "127.0.0.1",
+            "127.8.9.10",
+            "[::1]",
+            "::1",
+        ] {
+            assert!(is_loopback(host), "{host}");
+        }
+        for host in [
+            "localhost.example.com",
+            "10.0.0.1",
+            "[::2]",
+            "ce.example.com",
+        ] {
+            assert!(!is_loopback(host), "{host}");
+        }
+
+        let mut provider = AwsCostProvider::new("us-east-1");
+        let err = provider
+            .apply_partition(Partition::Aws, Some("http://10.0.0.1:4566".to_string()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cost Explorer endpoint URL must use https unless the host is local: http://10.0.0.1:4566"
+        );
+        // A rejected override leaves the previous endpoint in place
+        assert_eq!(provider.ce_endpoint_url, None);
+
+        provider
+            .apply_partition(Partition::Aws, Some("http://127.0.0.1:4566".to_string()))
+            .unwrap();
+        assert_eq!(
+            provider.ce_endpoint_url.as_deref(),
+            Some("http://127.0.0.1:4566")
+        );
+    }
+
+    #[tokio::test]
+    async fn org_accounts_are_listed_across_pages() {
+        let mock = MockAws::default()
+            .respond(
+                "ListAccounts",
+                list_accounts_page(
+                    &[("111111111111", "Production"), ("222222222222", "Staging")],
+                    Some("page-2"),
+                ),
+            )
+            .respond(
+                "ListAccounts",
+                list_accounts_page(&[("333333333333", "Data")], None),
+            );
+        let provider = provider(&mock, &["payer"]);
+        let config = provider.load_config("payer").await.unwrap();
+
+        let names = list_all_org_accounts(&OrgClient::new(&config), Some(2))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            names,
+            HashMap::from([
+                ("111111111111".to_string(), "Production".to_string()),
+                ("222222222222".to_string(), "Staging".to_string()),
+                ("333333333333".to_string(), "Data".to_string()),
+            ])
+        );
+        let calls = mock.requests("ListAccounts");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(json_body(&calls[0])["MaxResults"], 2);
+        assert_eq!(json_body(&calls[1])["NextToken"], "page-2");
+    }
+
+    #[tokio::test]
+    async fn prefetched_names_replace_a_describe_account_call_per_account() {
+        let accounts = ["a", "b", "c"].map(String::from);
+        let linked = |mock: &MockAws| {
+            let mut provider = provider(mock, &["a", "b", "c"]);
+            provider.org_account_names.clear();
+            provider.linked_accounts = HashMap::from([
+                ("a".to_string(), "111111111111".to_string()),
+                ("b".to_string(), "222222222222".to_string()),
+                ("c".to_string(), "333333333333".to_string()),
+            ]);
+            provider
+        };
+        let with_costs = |mock: MockAws| {
+            (0..3).fold(mock, |mock, _| {
+                mock.respond(
+                    "GetCostAndUsage",
+                    unblended_page(&[(&["Amazon EC2"], "10")], None),
+                )
+            })
+        };
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        // Without prefetching, every account asks Organizations (denied here) on its own
+        let mock = with_costs(MockAws::default());
+        let summaries = linked(&mock)
+            .fetch_all_summaries(&accounts, start, end_exclusive, &SummaryLimiter::default())
+            .await;
+        assert!(summaries.iter().all(Result::is_ok));
+        assert_eq!(mock.requests("DescribeAccount").len(), 3);
+
+        let mock = with_costs(MockAws::default().respond(
+            "ListAccounts",
+            list_accounts_page(
+                &[
+                    ("111111111111", "Production"),
+                    ("222222222222", "Staging"),
+                    ("333333333333", "Data"),
+                ],
+                None,
+            ),
+        ));
+        let mut provider = linked(&mock);
+        let config = provider.load_config("a").await.unwrap();
+        provider.org_account_names = list_all_org_accounts(&OrgClient::new(&config), None)
+            .await
+            .unwrap();
+
+        let names: Vec<String> = provider
+            .fetch_all_summaries(&accounts, start, end_exclusive, &SummaryLimiter::default())
+            .await
+            .into_iter()
+            .map(|summary| summary.unwrap().unwrap().account_name)
+            .collect();
+
+        assert_eq!(names, ["Production", "Staging", "Data"]);
+        assert_eq!(mock.requests("ListAccounts").len(), 1);
+        assert!(mock.requests("DescribeAccount").is_empty());
+    }
+
+    fn session_token_response(access_key: &str) -> String {
+        format!(
+            "<GetSessionTokenResponse xmlns=\"https://sts.amazonaws.com/doc/2011-06-15/\">\
+             <GetSessionTokenResult><Credentials>\
+             <AccessKeyId>{access_key}</AccessKeyId>\
+             <SecretAccessKey>secret-{access_key}</SecretAccessKey>\
+             <SessionToken>token-{access_key}</SessionToken>\
+             <Expiration>2099-01-01T00:00:00Z</Expiration>\
+             </Credentials></GetSessionTokenResult>\
+             <ResponseMetadata><RequestId>1</RequestId></ResponseMetadata>\
+             </GetSessionTokenResponse>"
+        )
+    }
+
+    fn mfa_role(account_id: &str) -> AssumeRoleConfig {
+        AssumeRoleConfig {
+            role_arn: format!("arn:aws:iam::{account_id}:role/CostReader"),
+            external_id: None,
+            via_role_arns: Vec::new(),
+            serial_number: Some("arn:aws:iam::111111111111:mfa/alice".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn mfa_code_is_spent_once_across_accounts() {
+        let mock = MockAws::default()
+            .respond("GetSessionToken", session_token_response("ASIAMFA"))
+            .respond("AssumeRole", assume_role_response("ASIAPROD"))
+            .respond("AssumeRole", assume_role_response("ASIASTAGING"))
+            .respond("AssumeRole", assume_role_response("ASIAPROD2"));
+        let mut provider = provider(&mock, &[]);
+        provider.mfa_token_code = Some("123456".to_string());
+        let base = provider
+            .config_loader()
+            .credentials_provider(test_credentials())
+            .load()
+            .await;
+
+        // Two accounts, then the first again as a second window or an expired-token retry would
+        for (account_ref, account_id) in [
+            ("prod", "222222222222"),
+            ("staging", "333333333333"),
+            ("prod", "222222222222"),
+        ] {
+            provider
+                .assume_role_chain(base.clone(), &mfa_role(account_id), account_ref)
+                .await
+                .unwrap();
+        }
+
+        let sessions = mock.requests("GetSessionToken");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].access_key, "AKIDBASE");
+        assert_eq!(
+            form_value(&sessions[0].body, "SerialNumber").as_deref(),
+            Some("arn:aws:iam::111111111111:mfa/alice")
+        );
+        assert_eq!(
+            form_value(&sessions[0].body, "TokenCode").as_deref(),
+            Some("123456")
+        );
+        let assumed = mock.requests("AssumeRole");
+        assert_eq!(assumed.len(), 3);
+        for call in assumed {
+            assert_eq!(call.access_key, "ASIAMFA");
+            assert_eq!(form_value(&call.body, "TokenCode"), None);
+            assert_eq!(form_value(&call.body, "SerialNumber"), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_mfa_code_asks_for_a_fresh_one() {
+        let mock = MockAws::default().respond_with(
+            "GetSessionToken",
+            403,
+            "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+             <Message>MultiFactorAuthentication failed with invalid MFA one time pass code.</Message>\
+             </Error><RequestId>1</RequestId></ErrorResponse>",
+        );
+        let mut provider = provider(&mock, &[]);
+        provider.mfa_token_code = Some("000000".to_string());
+        let base = provider
+            .config_loader()
+            .credentials_provider(test_credentials())
+            .load()
+            .await;
+
+        let err = provider
+            .assume_role_chain(base, &mfa_role("222222222222"), "prod")
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("retry with a fresh code"),
+            "{err:#}"
+        );
+        assert!(mock.requests("AssumeRole").is_empty());
+    }
+
+    #[tokio::test]
+    async fn accounts_without_credentials_are_skipped() {
+        let mock = MockAws::default();
+        let mut provider = provider(&mock, &["prod"]);
+        provider.skip_unassumable = true;
+
+        let (usable, skipped) = provider
+            .resolve_accounts(vec!["prod".to_string(), "ghost".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(usable, ["prod"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].account_ref, "ghost");
+        assert!(
+            skipped[0].error.contains("Unknown account reference"),
+            "{}",
+            skipped[0].error
+        );
+        // The resolved config is kept for the report
+        assert!(provider.account_configs.lock().await.contains_key("prod"));
+    }
+
+    #[tokio::test]
+    async fn separate_tax_keeps_tax_records_out_of_services() {
+        let tax_total = serde_json::json!({
+            "ResultsByTime": [{
+                "TimePeriod": { "Start": "2024-08-01", "End": "2024-08-15" },
+                "Total": { PRIMARY_METRIC: { "Amount": "4.2", "Unit": "USD" } },
+                "Groups": [],
+                "Estimated": false,
+            }],
+        });
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "40"), (&["Amazon S3"], "2")], None),
+            )
+            .respond("GetCostAndUsage", tax_total.to_string());
+        let mut provider = provider(&mock, &["prod"]);
+        provider.separate_tax = true;
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total, 42.0);
+        assert_eq!(summary.tax, 4.2);
+        assert!(!summary.services.contains_key("Tax"));
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 2);
+        let tax_records = serde_json::json!({
+            "Dimensions": { "Key": "RECORD_TYPE", "Values": ["Tax"] }
+        });
+        assert_eq!(
+            json_body(&calls[0])["Filter"],
+            serde_json::json!({ "Not": tax_records })
+        );
+        assert_eq!(json_body(&calls[1])["Filter"], tax_records);
+    }
+
+    #[tokio::test]
+    async fn tax_stays_in_services_by_default() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "40"), (&["Tax"], "4.2")], None),
+            );
+        let provider = provider(&mock, &["prod"]);
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.total, 44.2);
+        assert_eq!(summary.tax, 0.0);
+        assert_eq!(summary.services["Tax"], 4.2);
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 1);
+        assert!(json_body(&calls[0]).get("Filter").is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_refreshed_and_retried_once() {
+        let mock = MockAws::default()
+            .respond_with(
+                "GetCostAndUsage",
+                400,
+                r#"{"__type":"ExpiredTokenException","message":"The security token included in the request is expired"}"#,
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "12")], None),
+            );
+        let provider = provider(&mock, &["prod"]);
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let services = provider
+            .service_costs("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        assert_eq!(services["Amazon EC2"], 12.0);
+        assert_eq!(mock.requests("GetCostAndUsage").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn other_errors_mentioning_expired_tokens_are_not_retried() {
+        let mock = MockAws::default()
+            .respond_with(
+                "GetCostAndUsage",
+                400,
+                r#"{"__type":"AccessDeniedException","message":"ExpiredToken is not the problem here"}"#,
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "12")], None),
+            );
+        let provider = provider(&mock, &["prod"]);
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let err = provider
+            .service_costs("prod", start, end_exclusive)
+            .await
+            .unwrap_err();
+
+        assert!(!is_expired_token(&err), "{err:#}");
+        assert_eq!(mock.requests("GetCostAndUsage").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn both_windows_use_the_configured_metric() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                cost_page(
+                    &[(
+                        &["Amazon EC2"],
+                        &[("BlendedCost", "30"), (PRIMARY_METRIC, "99")],
+                    )],
+                    None,
+                ),
+            )
+            .respond(
+                "GetCostAndUsage",
+                cost_page(&[(&["Amazon EC2"], &[("BlendedCost", "20")])], None),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.metric = "BlendedCost".to_string();
+        let today = Utc::now().date_naive();
+
+        let report = generate_report_for_windows(
+            &provider,
+            &["prod".to_string()],
+            month_to_date(today),
+            previous_month_same_point(today).unwrap(),
+            &ReportOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.total_all, Some(30.0));
+        assert_eq!(report.prev_total, Some(20.0));
+        assert_eq!(report.delta, Some(10.0));
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 2);
+        for call in &calls {
+            assert_eq!(
+                json_body(call)["Metrics"],
+                serde_json::json!(["BlendedCost"])
+            );
+        }
+    }
+
+    /// Daily `GetCostAndUsage` response with `UnblendedCost` in each day's total.
+    fn daily_page(days: &[(NaiveDate, &str)], next_page_token: Option<&str>) -> String {
+        let results: Vec<serde_json::Value> = days
+            .iter()
+            .map(|(day, amount)| {
+                serde_json::json!({
+                    "TimePeriod": {
+                        "Start": day.to_string(),
+                        "End": day.succ_opt().unwrap().to_string(),
+                    },
+                    "Total": { PRIMARY_METRIC: { "Amount": amount, "Unit": "USD" } },
+                    "Groups": [],
+                    "Estimated": false,
+                })
+            })
+            .collect();
+        let mut page = serde_json::json!({ "ResultsByTime": results });
+        if let Some(token) = next_page_token {
+            page["NextPageToken"] = serde_json::json!(token);
+        }
+        page.to_string()
+    }
+
+    #[tokio::test]
+    async fn daily_totals_follow_next_page_token() {
+        let start = month_to_date(Utc::now().date_naive()).0 - Months::new(1);
+        let day = |offset| start + chrono::Duration::days(offset);
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                daily_page(&[(day(0), "10"), (day(1), "12")], Some("page-2")),
+            )
+            .respond("GetCostAndUsage", daily_page(&[(day(2), "7.5")], None));
+        let provider = provider(&mock, &["prod"]);
+
+        let days = provider.daily_totals("prod", start, day(3)).await.unwrap();
+
+        assert_eq!(
+            days,
+            BTreeMap::from([(day(0), 10.0), (day(1), 12.0), (day(2), 7.5)])
+        );
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(json_body(&calls[1])["NextPageToken"], "page-2");
+    }
+
+    fn forecast_response(amount: &str) -> String {
+        serde_json::json!({
+            "Total": { "Amount": amount, "Unit": "USD" },
+            "ForecastResultsByTime": [],
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn prorated_projection_forecasts_from_the_report_day() {
+        // The 15th of last month: the report's day is not today in UTC
+        let this_month = month_to_date(Utc::now().date_naive()).0;
+        let start = this_month - Months::new(1);
+        let report_day = start + chrono::Duration::days(14);
+        let days_in_month = (this_month - start).num_days();
+        let days: Vec<(NaiveDate, &str)> = (0..15)
+            .map(|offset| (start + chrono::Duration::days(offset), "10"))
+            .collect();
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "150")], None),
+            )
+            .respond("GetCostAndUsage", daily_page(&days, None))
+            .respond("GetCostForecast", forecast_response("200"));
+        let provider = provider(&mock, &["prod"]);
+        let options = ReportOptions {
+            compare_previous: false,
+            prorate: true,
+            blend_forecast: true,
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_for_windows(
+            &provider,
+            &["prod".to_string()],
+            (start, report_day.succ_opt().unwrap()),
+            previous_month_same_point(report_day).unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let projection = report.prorated_projection.unwrap();
+        // The 1st to the 14th are complete; the report day itself is not counted
+        assert_eq!(projection.complete_days, 14);
+        assert_eq!(projection.complete_total, 140.0);
+        assert_eq!(projection.complete_daily_rate, 10.0);
+        assert_eq!(
+            projection.prorated_total,
+            140.0 + 10.0 * (days_in_month - 14) as f64
+        );
+        assert_eq!(projection.forecast_total, Some(140.0 + 200.0));
+        assert_eq!(
+            projection.estimate,
+            (projection.prorated_total + 340.0) / 2.0
+        );
+        let forecast = mock.requests("GetCostForecast");
+        assert_eq!(forecast.len(), 1);
+        assert_eq!(
+            json_body(&forecast[0])["TimePeriod"],
+            serde_json::json!({
+                "Start": report_day.to_string(),
+                "End": this_month.to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn usage_forecast_is_read_per_month_and_in_total() {
+        let this_month = month_to_date(Utc::now().date_naive()).0;
+        let next_month = this_month + Months::new(1);
+        let months = serde_json::json!([
+            {
+                "TimePeriod": { "Start": this_month.to_string(), "End": next_month.to_string() },
+                "MeanValue": "744",
+            },
+            {
+                "TimePeriod": {
+                    "Start": next_month.to_string(),
+                    "End": (next_month + Months::new(1)).to_string(),
+                },
+                "MeanValue": "720.5",
+            },
+        ]);
+        let mock = MockAws::default()
+            .respond(
+                "GetUsageForecast",
+                serde_json::json!({
+                    "Total": { "Amount": "1464.5", "Unit": "Hrs" },
+                    "ForecastResultsByTime": months,
+                })
+                .to_string(),
+            )
+            // Without a total, the months are added up
+            .respond(
+                "GetUsageForecast",
+                serde_json::json!({ "ForecastResultsByTime": months }).to_string(),
+            );
+        let provider = provider(&mock, &["prod"]);
+        let filter: UsageFilter = "usage-type-group=EC2: Running Hours".parse().unwrap();
+        let end_exclusive = next_month + Months::new(1);
+
+        let forecast = provider
+            .usage_forecast("prod", &filter, "UsageQuantity", this_month, end_exclusive)
+            .await
+            .unwrap();
+        assert_eq!(forecast.total, 1464.5);
+        assert_eq!(forecast.unit.as_deref(), Some("Hrs"));
+        assert_eq!(
+            forecast.months,
+            BTreeMap::from([(this_month, 744.0), (next_month, 720.5)])
+        );
+
+        let forecast = provider
+            .usage_forecast("prod", &filter, "UsageQuantity", this_month, end_exclusive)
+            .await
+            .unwrap();
+        assert_eq!(forecast.total, 744.0 + 720.5);
+        assert_eq!(forecast.unit, None);
+
+        let calls = mock.requests("GetUsageForecast");
+        let body = json_body(&calls[0]);
+        assert_eq!(body["Metric"], "USAGE_QUANTITY");
+        // The window starts today at the earliest
+        assert_eq!(
+            body["TimePeriod"]["Start"],
+            this_month.max(Utc::now().date_naive()).to_string()
+        );
+        assert_eq!(
+            body["Filter"]["Dimensions"],
+            serde_json::json!({ "Key": "USAGE_TYPE_GROUP", "Values": ["EC2: Running Hours"] })
+        );
+    }
+
+    #[test]
+    fn usage_forecast_filters_name_one_usage_type_or_group() {
+        assert_eq!(
+            "usage-type=USE1-BoxUsage:m5.large"
+                .parse::<UsageFilter>()
+                .unwrap(),
+            UsageFilter::UsageType("USE1-BoxUsage:m5.large".to_string())
+        );
+        assert_eq!(
+            "USAGE_TYPE_GROUP = EC2: Running Hours"
+                .parse::<UsageFilter>()
+                .unwrap(),
+            UsageFilter::UsageTypeGroup("EC2: Running Hours".to_string())
+        );
+        for invalid in [
+            "USE1-BoxUsage:m5.large",
+            "usage-type=",
+            "usage-type=a,b",
+            "service=Amazon EC2",
+        ] {
+            assert!(invalid.parse::<UsageFilter>().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn blank_tag_values_count_as_untagged_spend() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "100")], None),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["team$platform"], "75"),
+                        (&["team$"], "20"),
+                        (&["team$ "], "5"),
+                    ],
+                    None,
+                ),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.groupings = vec![Grouping::Tag("team".to_string())];
+        let today = Utc::now().date_naive();
+        let options = ReportOptions {
+            compare_previous: false,
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_for_windows(
+            &provider,
+            &["prod".to_string()],
+            month_to_date(today),
+            previous_month_same_point(today).unwrap(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.untagged_spend, Some(25.0));
+        assert_eq!(report.untagged_pct, Some(25.0));
+        let breakdown = &report.breakdowns[0];
+        assert_eq!(breakdown.values[UNTAGGED], 25.0);
+        assert_eq!(breakdown.values["platform"], 75.0);
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(
+            json_body(&calls[1])["GroupBy"],
+            serde_json::json!([{ "Type": "TAG", "Key": "team" }])
+        );
+    }
+
+    #[test]
+    fn wildcard_match_handles_stars_and_single_characters() {
+        assert!(wildcard_match(
+            "*DataTransfer*",
+            "USE1-DataTransfer-Out-Bytes"
+        ));
+        assert!(wildcard_match(
+            "*datatransfer*",
+            "USE1-DataTransfer-Out-Bytes"
+        ));
+        assert!(wildcard_match("USE?-*", "USE1-BoxUsage:t3.micro"));
+        assert!(wildcard_match("*-Out-Bytes", "EUC1-DataTransfer-Out-Bytes"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("*DataTransfer*", "USE1-BoxUsage:t3.micro"));
+        assert!(!wildcard_match("USE?-*", "USE12-BoxUsage"));
+        assert!(!wildcard_match(
+            "*-In-Bytes",
+            "USE1-DataTransfer-In-Bytes-Extra"
+        ));
+        assert!(!wildcard_match("?", ""));
+    }
+
+    #[tokio::test]
+    async fn usage_type_pattern_keeps_only_matching_keys() {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "100")], None),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(
+                    &[
+                        (&["USE1-DataTransfer-Out-Bytes"], "7"),
+                        (&["EUC1-DataTransfer-Regional-Bytes"], "2"),
+                        (&["USE1-BoxUsage:t3.micro"], "91"),
+                    ],
+                    None,
+                ),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.groupings = vec![Grouping::UsageType(Some("*DataTransfer*".to_string()))];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        let values = &summary.breakdowns[0].values;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["USE1-DataTransfer-Out-Bytes"], 7.0);
+        assert_eq!(values["EUC1-DataTransfer-Regional-Bytes"], 2.0);
+    }
+
+    /// `prod` summarized with `--by-az`, the zone query answered by `az_response`.
+    async fn az_summary(status: u16, az_response: String) -> (MockAws, AccountSummary) {
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                unblended_page(&[(&["Amazon EC2"], "100")], None),
+            )
+            .respond_with("GetCostAndUsage", status, az_response);
+        let mut provider = provider(&mock, &["prod"]);
+        provider.groupings = vec![Grouping::AvailabilityZone];
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+        (mock, summary)
+    }
+
+    #[tokio::test]
+    async fn az_spend_is_reported_per_zone_with_regional_charges_apart() {
+        let (mock, summary) = az_summary(
+            200,
+            unblended_page(
+                &[
+                    (&["us-east-1a"], "50"),
+                    (&["us-east-1b"], "20"),
+                    (&["NoAZ"], "25"),
+                    (&[""], "5"),
+                ],
+                None,
+            ),
+        )
+        .await;
+
+        assert_eq!(
+            summary.azs,
+            HashMap::from([
+                ("us-east-1a".to_string(), 50.0),
+                ("us-east-1b".to_string(), 20.0),
+                (NO_AZ.to_string(), 30.0),
+            ])
+        );
+        assert_eq!(summary.breakdowns[0].dimension, "AZ");
+        let calls = mock.requests("GetCostAndUsage");
+        assert_eq!(
+            json_body(&calls[1])["GroupBy"],
+            serde_json::json!([{ "Type": "DIMENSION", "Key": "AZ" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn az_grouping_that_is_unsupported_or_empty_leaves_no_zones() {
+        let (_, unsupported) = az_summary(
+            400,
+            r#"{"__type":"ValidationException","message":"AZ is not a valid dimension"}"#
+                .to_string(),
+        )
+        .await;
+        let (_, empty) = az_summary(200, unblended_page(&[], None)).await;
+
+        for summary in [unsupported, empty] {
+            assert!(summary.azs.is_empty());
+            assert!(summary.breakdowns[0].values.is_empty());
+            // The rest of the summary is unaffected
+            assert_eq!(summary.total, 100.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn amortized_services_spread_savings_plan_spend() {
+        // The Savings Plan fee is billed as its own line unblended; amortized, it moves onto
+        // the EC2 and Lambda usage it covered at the discounted rate
+        let mock = MockAws::default()
+            .respond(
+                "GetCallerIdentity",
+                caller_identity_response("111111111111"),
+            )
+            .respond(
+                "GetCostAndUsage",
+                cost_page(
+                    &[
+                        (
+                            &["Amazon EC2"],
+                            &[(PRIMARY_METRIC, "10"), (NET_AMORTIZED_METRIC, "55")],
+                        ),
+                        (
+                            &["AWS Lambda"],
+                            &[(PRIMARY_METRIC, "0"), (NET_AMORTIZED_METRIC, "15")],
+                        ),
+                        (
+                            &["Savings Plans for AWS Compute usage"],
+                            &[(PRIMARY_METRIC, "80"), (NET_AMORTIZED_METRIC, "0")],
+                        ),
+                    ],
+                    None,
+                ),
+            );
+        let mut provider = provider(&mock, &["prod"]);
+        provider.amortized_services = true;
+        let (start, end_exclusive) = month_to_date(Utc::now().date_naive());
+
+        let summary = provider
+            .fetch_account_summary("prod", start, end_exclusive)
+            .await
+            .unwrap();
+
+        let amortized = summary.amortized_services.unwrap();
+        assert_eq!(summary.total, 90.0);
+        assert_eq!(amortized.values().sum::<f64>(), 70.0);
+        assert_eq!(summary.services["Amazon EC2"], 10.0);
+        assert_eq!(amortized["Amazon EC2"], 55.0);
+        assert_eq!(amortized["AWS Lambda"], 15.0);
+        assert_eq!(amortized["Savings Plans for AWS Compute usage"], 0.0);
+        assert_eq!(
+            json_body(&mock.requests("GetCostAndUsage")[0])["Metrics"],
+            serde_json::json!([PRIMARY_METRIC, NET_AMORTIZED_METRIC])
+        );
+    }
 }
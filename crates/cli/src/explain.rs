@@ -0,0 +1,71 @@
+use cloud_cost_aws::ApiCall;
+use cloud_cost_core::Report;
+use std::fmt::Write;
+
+/// Provenance for `--explain`: the windows behind the report, how each figure was derived
+/// and every Cost Explorer request that fed it. `method` describes the data source (metric,
+/// granularity, filters) in the provider's own terms.
+pub fn render(report: &Report, method: &[String], calls: &[ApiCall]) -> String {
+    let mut out = String::from("How this report was computed:\n");
+    writeln!(
+        out,
+        "- Current period: {} to {} (exclusive), {} day(s)",
+        report.month_start, report.month_end_exclusive, report.days_elapsed
+    )
+    .unwrap();
+    for line in method {
+        writeln!(out, "- {line}").unwrap();
+    }
+    out.push_str(
+        "- Account total: sum of the account's services; total across accounts: sum of the \
+         account totals\n",
+    );
+    if report.prev_total.is_some() {
+        writeln!(
+            out,
+            "- Previous period: {} to {} (exclusive), per-service costs of the same accounts; \
+             change = total - previous total",
+            report.prev_start, report.prev_end_exclusive
+        )
+        .unwrap();
+    }
+    if let Some(periods) = report.baseline_periods {
+        writeln!(
+            out,
+            "- Baseline: per-service average over that period and the same window in the {} \
+             month(s) before it",
+            periods - 1
+        )
+        .unwrap();
+    }
+    if report.daily_run_rate.is_some() {
+        writeln!(
+            out,
+            "- Run rate: total / {} day(s); projection: run rate x days in the month",
+            report.days_elapsed
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\nAPI calls: {}", calls.len()).unwrap();
+    for call in calls {
+        write!(
+            out,
+            "- {}: {} {} to {}",
+            call.account_ref, call.operation, call.start, call.end_exclusive
+        )
+        .unwrap();
+        if let Some((start, end_exclusive)) = call.baseline {
+            write!(out, " against {start} to {end_exclusive}").unwrap();
+        }
+        if let Some(granularity) = &call.granularity {
+            write!(out, ", {granularity}").unwrap();
+        }
+        write!(out, ", {}", call.metrics.join("+")).unwrap();
+        if !call.group_by.is_empty() {
+            write!(out, ", by {}", call.group_by.join(" and ")).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
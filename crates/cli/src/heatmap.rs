@@ -0,0 +1,126 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const CELL: u32 = 44;
+const GAP: u32 = 4;
+const HEADER: u32 = 56;
+const NO_DATA: &str = "#ebedf0";
+/// Light to dark; a day's shade is its share of the busiest day.
+const SHADES: [&str; 5] = ["#c6e48b", "#9be07f", "#7bc96f", "#239a3b", "#196127"];
+
+/// Calendar heatmap (Monday-first weeks) of daily spend for the month starting at
+/// `month_start`. Days without data, such as ones Cost Explorer has not reported yet,
+/// are drawn uncolored.
+pub fn render_svg(month_start: NaiveDate, daily: &BTreeMap<NaiveDate, f64>) -> String {
+    let next_month = month_start
+        .checked_add_months(chrono::Months::new(1))
+        .unwrap_or(month_start);
+    let days_in_month = (next_month - month_start).num_days();
+    let first_column = month_start.weekday().num_days_from_monday();
+    let weeks = (first_column + days_in_month as u32).div_ceil(7);
+
+    let max = daily.values().copied().fold(0.0_f64, f64::max);
+    let width = 7 * (CELL + GAP) + GAP;
+    let height = HEADER + weeks * (CELL + GAP) + GAP;
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="sans-serif">"##
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r##"  <text x="{GAP}" y="22" font-size="16">Daily spend, {} (max ${:.2})</text>"##,
+        month_start.format("%B %Y"),
+        max
+    )
+    .unwrap();
+    for (idx, name) in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+        .iter()
+        .enumerate()
+    {
+        writeln!(
+            svg,
+            r##"  <text x="{}" y="{}" font-size="11" fill="#555">{name}</text>"##,
+            GAP + idx as u32 * (CELL + GAP),
+            HEADER - 8
+        )
+        .unwrap();
+    }
+
+    for offset in 0..days_in_month {
+        let day = month_start + Duration::days(offset);
+        let slot = first_column + offset as u32;
+        let x = GAP + (slot % 7) * (CELL + GAP);
+        let y = HEADER + (slot / 7) * (CELL + GAP);
+        let (fill, title) = match daily.get(&day) {
+            Some(amount) => (shade(*amount, max), format!("{day}: ${amount:.2}")),
+            None => (NO_DATA, format!("{day}: no data")),
+        };
+        writeln!(
+            svg,
+            r##"  <rect x="{x}" y="{y}" width="{CELL}" height="{CELL}" rx="4" fill="{fill}"><title>{title}</title></rect>"##
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r##"  <text x="{}" y="{}" font-size="11" fill="#333">{}</text>"##,
+            x + 4,
+            y + 14,
+            day.day()
+        )
+        .unwrap();
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn shade(amount: f64, max: f64) -> &'static str {
+    if max <= 0.0 || amount <= 0.0 {
+        return SHADES[0];
+    }
+    let idx = ((amount / max) * (SHADES.len() - 1) as f64).round() as usize;
+    SHADES[idx.min(SHADES.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 8, day).unwrap()
+    }
+
+    #[test]
+    fn every_day_gets_a_cell_shaded_by_its_share_of_the_busiest_day() {
+        let daily = BTreeMap::from([
+            (day(1), 100.0),
+            (day(2), 50.0),
+            (day(3), 0.0),
+            (day(4), 10.0),
+        ]);
+
+        let svg = render_svg(day(1), &daily);
+
+        // August 2024 starts on a Thursday and spans five Monday-first weeks
+        assert_eq!(svg.matches("<rect ").count(), 31);
+        assert!(svg.contains(r#"height="300""#), "{svg}");
+        assert!(svg.contains(r#"<rect x="148" y="56""#), "{svg}");
+        for (fill, title) in [
+            ("#196127", "2024-08-01: $100.00"),
+            ("#7bc96f", "2024-08-02: $50.00"),
+            ("#c6e48b", "2024-08-03: $0.00"),
+            ("#c6e48b", "2024-08-04: $10.00"),
+        ] {
+            assert!(
+                svg.contains(&format!(r#"fill="{fill}"><title>{title}</title>"#)),
+                "{title}: {svg}"
+            );
+        }
+        // The rest of the month has not been reported
+        assert_eq!(svg.matches(&format!(r#"fill="{NO_DATA}""#)).count(), 27);
+    }
+}
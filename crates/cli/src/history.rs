@@ -0,0 +1,359 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDate;
+use cloud_cost_core::Report;
+use rusqlite::{Connection, params};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE reports (
+        id INTEGER PRIMARY KEY,
+        report_date TEXT NOT NULL UNIQUE,
+        generated_at TEXT NOT NULL,
+        month_start TEXT NOT NULL,
+        month_end_exclusive TEXT NOT NULL,
+        total_all REAL NOT NULL,
+        prev_total REAL NOT NULL,
+        delta REAL NOT NULL,
+        delta_pct REAL NOT NULL,
+        report_json TEXT NOT NULL
+    );
+    CREATE TABLE account_summaries (
+        report_id INTEGER NOT NULL REFERENCES reports(id),
+        account_ref TEXT NOT NULL,
+        account_id TEXT NOT NULL,
+        account_name TEXT NOT NULL,
+        total REAL NOT NULL
+    );
+    CREATE INDEX account_summaries_report_id ON account_summaries(report_id);
+",
+    "
+    CREATE TABLE reports_new (
+        id INTEGER PRIMARY KEY,
+        report_date TEXT NOT NULL UNIQUE,
+        generated_at TEXT NOT NULL,
+        month_start TEXT NOT NULL,
+        month_end_exclusive TEXT NOT NULL,
+        total_all REAL NOT NULL,
+        prev_total REAL,
+        delta REAL,
+        delta_pct REAL,
+        report_json TEXT NOT NULL
+    );
+    INSERT INTO reports_new SELECT * FROM reports;
+    DROP TABLE reports;
+    ALTER TABLE reports_new RENAME TO reports;
+",
+];
+
+/// One stored report, as shown by the `history` subcommand.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub report_date: NaiveDate,
+    pub total_all: f64,
+    /// `None` for reports generated with `--no-comparison`
+    pub prev_total: Option<f64>,
+    pub delta: Option<f64>,
+    pub delta_pct: Option<f64>,
+}
+
+/// Month-end totals, taking the latest stored report of each month.
+#[derive(Debug, Clone, Default)]
+pub struct MonthlyTrend {
+    pub months: Vec<NaiveDate>,
+    pub totals: Vec<f64>,
+    /// account name -> total per month, aligned with `months` (0 when absent)
+    pub accounts: BTreeMap<String, Vec<f64>>,
+}
+
+/// SQLite store of generated reports, one per report date.
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Opening history store {}", path.display()))?;
+        Self::with_connection(conn)
+    }
+
+    /// Brings the schema of `conn` up to date.
+    fn with_connection(conn: Connection) -> Result<Self> {
+        let mut store = Self { conn };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn migrate(&mut self) -> Result<()> {
+        let applied: i64 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (idx, sql) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            let tx = self.conn.transaction()?;
+            tx.execute_batch(sql)
+                .with_context(|| format!("Applying history migration {}", idx + 1))?;
+            tx.pragma_update(None, "user_version", (idx + 1) as i64)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Stores `report` under `report_date`, replacing any report already stored for that date.
+    /// A report mixing currencies has no total and cannot be stored.
+    pub fn save(&mut self, report_date: NaiveDate, report: &Report) -> Result<()> {
+        let total_all = report
+            .total_all
+            .ok_or_else(|| anyhow!("Cannot store a report whose accounts mix currencies"))?;
+        let date = report_date.to_string();
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "DELETE FROM account_summaries
+             WHERE report_id IN (SELECT id FROM reports WHERE report_date = ?1)",
+            params![date],
+        )?;
+        tx.execute("DELETE FROM reports WHERE report_date = ?1", params![date])?;
+
+        tx.execute(
+            "INSERT INTO reports (
+                report_date, generated_at, month_start, month_end_exclusive,
+                total_all, prev_total, delta, delta_pct, report_json
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                date,
+                report.generated_at.to_rfc3339(),
+                report.month_start.to_string(),
+                report.month_end_exclusive.to_string(),
+                total_all,
+                report.prev_total,
+                report.delta,
+                report.delta_pct,
+                serde_json::to_string(report)?,
+            ],
+        )?;
+        let report_id = tx.last_insert_rowid();
+
+        for s in &report.summaries {
+            tx.execute(
+                "INSERT INTO account_summaries (report_id, account_ref, account_id, account_name, total)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![report_id, s.account_ref, s.account_id, s.account_name, s.total],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The most recent `limit` stored reports, oldest first.
+    pub fn entries(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT report_date, total_all, prev_total, delta, delta_pct
+             FROM reports ORDER BY report_date DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+                row.get::<_, Option<f64>>(4)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (date, total_all, prev_total, delta, delta_pct) = row?;
+            entries.push(HistoryEntry {
+                report_date: NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .with_context(|| format!("Invalid report_date {date:?} in history store"))?,
+                total_all,
+                prev_total,
+                delta,
+                delta_pct,
+            });
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Totals for the most recent `months` months that have stored reports, oldest first.
+    pub fn monthly_trend(&self, months: usize) -> Result<MonthlyTrend> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.month_start, r.total_all, a.account_name, a.total
+             FROM reports r
+             LEFT JOIN account_summaries a ON a.report_id = r.id
+             WHERE r.report_date = (
+                 SELECT MAX(r2.report_date) FROM reports r2 WHERE r2.month_start = r.month_start
+             )
+             AND r.month_start IN (
+                 SELECT DISTINCT month_start FROM reports ORDER BY month_start DESC LIMIT ?1
+             )
+             ORDER BY r.month_start",
+        )?;
+        let rows = stmt.query_map(params![months as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+            ))
+        })?;
+
+        let mut trend = MonthlyTrend::default();
+        let mut per_account: Vec<(usize, String, f64)> = Vec::new();
+        for row in rows {
+            let (month, total_all, account_name, account_total) = row?;
+            let month = NaiveDate::parse_from_str(&month, "%Y-%m-%d")
+                .with_context(|| format!("Invalid month_start {month:?} in history store"))?;
+            if trend.months.last() != Some(&month) {
+                trend.months.push(month);
+                trend.totals.push(total_all);
+            }
+            if let (Some(name), Some(total)) = (account_name, account_total) {
+                per_account.push((trend.months.len() - 1, name, total));
+            }
+        }
+
+        for (idx, name, total) in per_account {
+            trend
+                .accounts
+                .entry(name)
+                .or_insert_with(|| vec![0.0; trend.months.len()])[idx] += total;
+        }
+        Ok(trend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixed_report;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn in_memory() -> HistoryStore {
+        HistoryStore::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    /// `fixed_report` for the month starting `month_start`, with `prod` spending `total`.
+    fn report(month_start: NaiveDate, total: f64) -> Report {
+        let mut report = fixed_report();
+        report.month_start = month_start;
+        report.summaries.truncate(1);
+        report.summaries[0].total = total;
+        report.total_all = Some(total);
+        report
+    }
+
+    fn count(store: &HistoryStore, table: &str) -> i64 {
+        store
+            .conn
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn saving_the_same_date_again_replaces_the_report() {
+        let mut store = in_memory();
+        let today = day(2024, 8, 15);
+
+        store.save(today, &fixed_report()).unwrap();
+        store.save(today, &report(day(2024, 8, 1), 42.0)).unwrap();
+
+        let entries = store.entries(10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].report_date, today);
+        assert_eq!(entries[0].total_all, 42.0);
+        assert_eq!(entries[0].prev_total, None);
+        // The first report's two accounts went with it
+        assert_eq!(count(&store, "account_summaries"), 1);
+        let generated_at: String = store
+            .conn
+            .query_row("SELECT generated_at FROM reports", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(generated_at, fixed_report().generated_at.to_rfc3339());
+    }
+
+    #[test]
+    fn entries_are_the_latest_reports_oldest_first() {
+        let mut store = in_memory();
+        for (date, total) in [(15, 15.0), (13, 13.0), (14, 14.0)] {
+            store
+                .save(day(2024, 8, date), &report(day(2024, 8, 1), total))
+                .unwrap();
+        }
+
+        let entries = store.entries(2).unwrap();
+        let dates: Vec<NaiveDate> = entries.iter().map(|e| e.report_date).collect();
+        assert_eq!(dates, [day(2024, 8, 14), day(2024, 8, 15)]);
+        assert_eq!(entries[1].total_all, 15.0);
+        assert_eq!(store.entries(10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn monthly_trend_takes_the_latest_report_of_each_month() {
+        let mut store = in_memory();
+        store
+            .save(day(2024, 7, 31), &report(day(2024, 7, 1), 70.0))
+            .unwrap();
+        store
+            .save(day(2024, 7, 15), &report(day(2024, 7, 1), 35.0))
+            .unwrap();
+        let mut august = fixed_report();
+        august.summaries[1].account_name = "staging".to_string();
+        store.save(day(2024, 8, 15), &august).unwrap();
+        store
+            .save(day(2024, 6, 30), &report(day(2024, 6, 1), 60.0))
+            .unwrap();
+
+        let trend = store.monthly_trend(2).unwrap();
+        assert_eq!(trend.months, [day(2024, 7, 1), day(2024, 8, 1)]);
+        assert_eq!(trend.totals, [70.0, 129.75]);
+        // Aligned with the months, 0 where an account has no report
+        assert_eq!(trend.accounts["prod"], [70.0, 120.25]);
+        assert_eq!(trend.accounts["staging"], [0.0, 9.5]);
+
+        assert_eq!(store.monthly_trend(12).unwrap().months.len(), 3);
+    }
+
+    #[test]
+    fn migration_2_keeps_v1_rows_and_allows_missing_comparisons() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute(
+            "INSERT INTO reports (
+                report_date, generated_at, month_start, month_end_exclusive,
+                total_all, prev_total, delta, delta_pct, report_json
+             ) VALUES ('2024-07-15', '2024-07-15T06:00:00+00:00', '2024-07-01', '2024-07-15',
+                       110.0, 100.0, 10.0, 10.0, '{}')",
+            [],
+        )
+        .unwrap();
+
+        let mut store = HistoryStore::with_connection(conn).unwrap();
+        let version: i64 = store
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        // prev_total is nullable now: a report without a comparison can be stored
+        store.save(day(2024, 8, 15), &fixed_report()).unwrap();
+
+        let entries = store.entries(10).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].report_date, day(2024, 7, 15));
+        assert_eq!(entries[0].total_all, 110.0);
+        assert_eq!(entries[0].prev_total, Some(100.0));
+        assert_eq!(entries[0].delta_pct, Some(10.0));
+        assert_eq!(entries[1].prev_total, None);
+    }
+}
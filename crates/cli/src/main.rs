@@ -1,17 +1,57 @@
-use anyhow::Result;
-use chrono::Utc;
-use clap::Parser;
-use cloud_cost_aws::{AwsCostProvider, StaticCredentials};
-use cloud_cost_core::generate_report;
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{Months, NaiveDate};
+use clap::{Parser, Subcommand, ValueEnum};
+use cloud_cost_aws::accounts::{AccountSources, NO_ACCOUNTS, build_provider, mfa_serial_number};
+use cloud_cost_aws::{
+    AppName, AuditLog, AwsCostProvider, COST_METRICS, Granularity, GroupKey, Grouping,
+    MAX_TREND_MONTHS, PRIMARY_METRIC, Partition, USAGE_FORECAST_METRICS, UsageFilter,
+    parse_app_name, parse_group_by,
+};
+use cloud_cost_core::render::{self, MoneyFormat, TextOptions};
+use cloud_cost_core::{
+    AccountOrder, CostProvider, DEFAULT_OVERHEAD_SERVICES, MetricBreakdown, OtherThreshold,
+    Progress, Report, ReportOptions, ServiceGroupRule, Tz, default_service_aliases,
+    generate_report_for_windows, month_to_date, month_window, parse_month, parse_other_threshold,
+    parse_timezone, previous_month_same_point, shared_currency, today_in,
+};
+use cloud_cost_cur::CurCostProvider;
+use futures::future::{join_all, try_join_all};
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+mod explain;
+mod heatmap;
+mod history;
+mod webhook;
+
+use history::HistoryStore;
 
 #[derive(Parser, Debug)]
 #[command(name = "cloud-cost-manager")]
 #[command(about = "Multi-account AWS cost summary", long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// SQLite file where each generated report is stored (and `history` reads from)
+    #[arg(long, global = true)]
+    store: Option<PathBuf>,
+
+    /// Where cost data comes from: Cost Explorer, or exported Cost and Usage Report files
+    #[arg(long, value_enum, default_value_t = Cloud::Aws)]
+    cloud: Cloud,
+
+    /// CUR file for --cloud cur: a local path or s3://bucket/key, .csv, .csv.gz or .parquet
+    /// (repeatable; include the previous month's file for the comparison)
+    #[arg(long = "cur-path", required_if_eq("cloud", "cur"))]
+    cur_paths: Vec<String>,
+
     /// Comma-separated list of AWS shared config profiles
     #[arg(long, value_delimiter = ',')]
     profiles: Vec<String>,
@@ -20,83 +60,1970 @@ struct Args {
     #[arg(long, default_value = "us-east-1")]
     region: String,
 
-    /// Load AWS credentials from a JSON file (overrides profiles)
+    /// App id added to the user agent of every AWS call, for attribution in CloudTrail
+    /// (default cloud-cost-manager-<version>)
+    #[arg(long, value_parser = parse_app_name)]
+    app_id: Option<AppName>,
+
+    /// IANA timezone (e.g. America/Los_Angeles) in which "today" is taken before deriving the
+    /// report windows; UTC by default
+    #[arg(long, value_parser = parse_timezone)]
+    timezone: Option<Tz>,
+
+    /// With profiles, use each profile's own region (falls back to --region)
+    #[arg(long)]
+    region_from_profile: bool,
+
+    /// AWS partition of the accounts: aws, aws-cn (China) or aws-us-gov (GovCloud)
+    #[arg(long, default_value = "aws")]
+    partition: Partition,
+
+    /// Custom Cost Explorer endpoint URL: https, or http to localhost for a mock server
+    #[arg(long, visible_alias = "endpoint-url")]
+    ce_endpoint_url: Option<String>,
+
+    /// Read several linked accounts through one profile: a JSON list of
+    /// {"profile", "linked_account_ids"} entries, one account per id (repeatable)
+    #[arg(long, conflicts_with_all = ["accounts_file", "accounts_from_ssm", "accounts_from_secret", "assume_roles_file"])]
+    profile_accounts_file: Vec<PathBuf>,
+
+    /// Load AWS credentials from a JSON file (overrides profiles); repeatable, entries are
+    /// merged in order
+    #[arg(long)]
+    accounts_file: Vec<PathBuf>,
+
+    /// Load the accounts JSON from this SSM parameter (SecureString supported)
+    #[arg(long, conflicts_with = "accounts_file")]
+    accounts_from_ssm: Option<String>,
+
+    /// Load the accounts JSON from this Secrets Manager secret
+    #[arg(long, conflicts_with_all = ["accounts_file", "accounts_from_ssm"])]
+    accounts_from_secret: Option<String>,
+
+    /// Profile used to read --accounts-from-ssm/--accounts-from-secret (defaults to the default chain)
+    #[arg(long)]
+    bootstrap_profile: Option<String>,
+
+    /// Load role ARNs from a JSON file (overrides profiles/accounts); repeatable, entries are
+    /// merged in order and an account_ref may only appear once
+    #[arg(long, conflicts_with_all = ["accounts_file", "accounts_from_ssm", "accounts_from_secret"])]
+    assume_roles_file: Vec<PathBuf>,
+
+    /// Base profile for STS AssumeRole calls
+    #[arg(long, requires = "assume_roles_file")]
+    base_profile: Option<String>,
+
+    /// MFA code for roles with a `serial_number` (prompted for when omitted)
+    #[arg(long, requires = "assume_roles_file")]
+    mfa_code: Option<String>,
+
+    /// Fetch every linked account in one LINKED_ACCOUNT x SERVICE query using the single
+    /// configured profile/credential (typically the payer account)
+    #[arg(long, conflicts_with_all = ["metrics", "by_purchase_type", "by_cost_category", "by_tag", "by_usage_type", "by_az", "separate_tax", "blended_savings", "savings_rate", "amortized_services"])]
+    consolidated: bool,
+
+    /// Bucket size for current-period queries (hourly must be enabled in Cost Explorer and
+    /// covers at most the last 14 days)
+    #[arg(long, value_enum, default_value_t = GranularityArg::Monthly)]
+    granularity: GranularityArg,
+
+    /// Only fetch these services (repeatable, exact Cost Explorer SERVICE names,
+    /// e.g. "Amazon Elastic Compute Cloud - Compute"); totals cover only these services
+    #[arg(long = "only-service")]
+    only_services: Vec<String>,
+
+    /// Fail when Cost Explorer returns a malformed amount instead of counting it as zero
+    #[arg(long)]
+    strict_amounts: bool,
+
+    /// Report tax (RECORD_TYPE Tax) on its own line, leaving it out of services and totals
+    #[arg(long)]
+    separate_tax: bool,
+
+    /// Also fetch BlendedCost and print blended vs unblended cost and the difference
+    /// (what RI / Savings Plans sharing moved between accounts)
+    #[arg(long)]
+    blended_savings: bool,
+
+    /// Also fetch BlendedCost and NetAmortizedCost and print the effective savings rate,
+    /// (blended - net amortized) / blended, with blended standing in for on-demand cost
+    #[arg(long)]
+    savings_rate: bool,
+
+    /// Also fetch NetAmortizedCost per service (same query), with RI and Savings Plan
+    /// discounts spread over the services that used them, for unit economics
+    #[arg(long)]
+    amortized_services: bool,
+
+    /// Skip accounts whose credentials cannot be resolved (e.g. a profile missing from the
+    /// AWS config, or a role not provisioned yet) and list them as missing, even with
+    /// --fail-on-error
+    #[arg(long)]
+    skip_unassumable: bool,
+
+    /// Cost Explorer metric behind services, totals and the comparison with the previous
+    /// period; both periods always use the same metric
+    #[arg(long, default_value = PRIMARY_METRIC, value_parser = COST_METRICS.to_vec())]
+    primary_metric: String,
+
+    /// Fetch another Cost Explorer metric and print it as a column next to --primary-metric
+    /// (repeatable; all metrics come from the same query)
+    #[arg(
+        long = "metric",
+        alias = "secondary-metric",
+        value_parser = [
+            "AmortizedCost",
+            "BlendedCost",
+            "NetAmortizedCost",
+            "NetUnblendedCost",
+            "UsageQuantity",
+            "NormalizedUsageAmount",
+        ]
+    )]
+    metrics: Vec<String>,
+
+    /// Additional metric columns shown in text and CSV output, in this order, e.g.
+    /// unblended,amortized,usage. Each must be --primary-metric (always the main amount) or
+    /// a fetched --metric; the others are still fetched and stay in JSON
+    #[arg(long, value_delimiter = ',', value_parser = parse_metric_column)]
+    columns: Vec<String>,
+
+    /// Break spend down by purchase type (on-demand, spot, reserved, savings plans)
+    #[arg(long)]
+    by_purchase_type: bool,
+
+    /// Break spend down by the values of this AWS Cost Category
+    #[arg(long)]
+    by_cost_category: Option<String>,
+
+    /// Break spend down by the values of this cost allocation tag key and report the
+    /// untagged share
+    #[arg(long)]
+    by_tag: Option<String>,
+
+    /// Break spend down by USAGE_TYPE, e.g. to isolate data transfer across services
+    #[arg(long)]
+    by_usage_type: bool,
+
+    /// Only keep usage types matching this wildcard pattern (`*` and `?`, case-insensitive),
+    /// e.g. '*DataTransfer*'
+    #[arg(long, requires = "by_usage_type")]
+    usage_type_filter: Option<String>,
+
+    /// Break spend down by availability zone, e.g. to spot cross-AZ data transfer
+    #[arg(long)]
+    by_az: bool,
+
+    /// Also break the previous period down by --by-purchase-type / --by-cost-category and
+    /// list the change per group
+    #[arg(long, conflicts_with = "no_comparison")]
+    compare_breakdowns: bool,
+
+    /// Compare against the average of the same window over the last N periods instead of
+    /// the previous one alone (one extra Cost Explorer query per account per period)
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=12),
+        conflicts_with_all = ["no_comparison", "compare_breakdowns", "native_comparison"]
+    )]
+    baseline_periods: u32,
+
+    /// Report support plans and tax as overhead, separate from usage-driven service spend
+    #[arg(long)]
+    split_overhead: bool,
+
+    /// Sum services below this into one "Other" entry: dollars (25) or percent of the total (2%)
+    #[arg(long, value_parser = parse_other_threshold)]
+    other_threshold: Option<OtherThreshold>,
+
+    /// Also list the five largest credits and refunds (negative amounts), which never reach
+    /// the top services
+    #[arg(long)]
+    top_credits: bool,
+
+    /// Leave accounts that spent less than this many dollars out of the account list
+    #[arg(long)]
+    min_account_spend: Option<f64>,
+
+    /// Still count accounts below --min-account-spend in the totals (they are dropped from
+    /// every total otherwise)
+    #[arg(long, requires = "min_account_spend")]
+    count_small_accounts: bool,
+
+    /// Also project the month from complete days only, leaving out today's partial spend
+    /// (one daily query per account)
+    #[arg(long)]
+    prorate: bool,
+
+    /// Average the prorated projection with Cost Explorer's forecast for the rest of the
+    /// month (one forecast query per account)
+    #[arg(long, requires = "prorate")]
+    blend_forecast: bool,
+
+    /// Service counted as overhead (repeatable; replaces the default support/tax list)
+    #[arg(long = "overhead-service", requires = "split_overhead")]
+    overhead_services: Vec<String>,
+
+    /// Flag the month-over-month change as low confidence below this many days of data
+    #[arg(long, default_value_t = 3)]
+    min_comparison_days: i64,
+
+    /// Only list services whose change is at least this many dollars (or meets --min-delta-pct)
+    #[arg(long)]
+    min_delta_abs: Option<f64>,
+
+    /// Only list services whose change is at least this percentage (or meets --min-delta-abs)
+    #[arg(long)]
+    min_delta_pct: Option<f64>,
+
+    /// Give up on accounts still running after this many seconds and report the rest
+    #[arg(long)]
+    deadline_secs: Option<u64>,
+
+    /// Most Cost Explorer calls in flight at once while building the report
+    #[arg(long)]
+    max_concurrency: Option<usize>,
+
+    /// Page size for paginated AWS calls that accept one (capped at each API's maximum);
+    /// bigger pages mean fewer round-trips in large organizations
+    #[arg(long)]
+    page_size: Option<u32>,
+
+    /// Exit with an error if any account fails or times out. By default the report covers
+    /// the accounts that succeeded and lists the failures as warnings
+    #[arg(long)]
+    fail_on_error: bool,
+
+    /// Exit with an error if an account's services do not add up to its total, or all
+    /// services to the overall total, instead of printing a warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Use Cost Explorer's own comparison and cost drivers (falls back to the computed change).
+    /// Cost Explorer only compares whole months, so this needs --month
+    #[arg(long, conflicts_with = "consolidated", requires = "month")]
+    native_comparison: bool,
+
+    /// Skip the previous-period fetch and the month-to-month comparison; halves the
+    /// Cost Explorer calls for a plain spend snapshot
+    #[arg(long, conflicts_with_all = ["native_comparison", "min_delta_abs", "min_delta_pct"])]
+    no_comparison: bool,
+
+    /// Show short service names (EC2, S3, ...) using the built-in alias map
+    #[arg(long)]
+    alias_services: bool,
+
+    /// JSON object of service name -> alias, applied on top of the built-in map
+    #[arg(long, requires = "alias_services")]
+    service_aliases_file: Option<PathBuf>,
+
+    /// JSON list of {"pattern", "group"} rules folding matching services into one group,
+    /// after aliases; the first matching rule wins and JSON keeps the raw breakdown
+    #[arg(long)]
+    service_groups_file: Option<PathBuf>,
+
+    /// Only report on accounts under this organizational unit (searched recursively)
+    #[arg(long)]
+    ou: Option<String>,
+
+    /// Only report on accounts whose reference, id or name matches this regex (matched in
+    /// full, so a plain account id matches just that account); repeatable
+    #[arg(long, value_parser = parse_account_pattern)]
+    include_account: Vec<Regex>,
+
+    /// Leave out accounts whose reference, id or name matches this regex, applied after
+    /// --include-account; repeatable
+    #[arg(long, value_parser = parse_account_pattern)]
+    exclude_account: Vec<Regex>,
+
+    /// Profile with Organizations access used to enumerate --ou and for
+    /// --prefetch-account-names (defaults to the default chain)
+    #[arg(long)]
+    org_profile: Option<String>,
+
+    /// Look up every account name with one Organizations ListAccounts call up front instead
+    /// of one DescribeAccount per account
+    #[arg(long)]
+    prefetch_account_names: bool,
+
+    /// Print month-to-date spend grouped by up to two keys instead of the report, e.g.
+    /// `service,region` or `service,tag:team`
+    #[arg(long)]
+    group_by: Option<String>,
+
+    /// Report on this whole past calendar month (YYYY-MM) against the month before it,
+    /// instead of month-to-date
+    #[arg(long)]
+    month: Option<String>,
+
+    /// Order of accounts in every output format
+    #[arg(long, value_enum, default_value_t = AccountOrderArg::Input)]
+    sort_accounts: AccountOrderArg,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Print only the total as a bare number (e.g. `COST=$(cloud-cost-cli --quiet)`);
+    /// --store, the *-out files and --webhook still get the full report
+    #[arg(long, conflicts_with_all = ["format", "group_by"])]
+    quiet: bool,
+
+    /// After the report, print to stderr the windows, metric, granularity and every Cost
+    /// Explorer call behind it, with the number of calls made
+    #[arg(long)]
+    explain: bool,
+
+    /// Print a warning (exit code unchanged) when the total is above this many dollars; must
+    /// not be above --budget-fail
+    #[arg(long)]
+    budget_warn: Option<f64>,
+
+    /// Exit with --budget-fail-code when the total is above this many dollars; checked
+    /// before --budget-warn, so a total above both fails
+    #[arg(long)]
+    budget_fail: Option<f64>,
+
+    /// Exit code for a --budget-fail breach
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(1..))]
+    budget_fail_code: u8,
+
+    /// Write the --format output to this file instead of stdout, e.g.
+    /// `--format html --output-file report.html`
+    #[arg(long, conflicts_with_all = ["quiet", "group_by"])]
+    output_file: Option<PathBuf>,
+
+    /// Also write the report as JSON to this file
+    #[arg(long)]
+    json_out: Option<PathBuf>,
+
+    /// Also write the report as CSV to this file
+    #[arg(long)]
+    csv_out: Option<PathBuf>,
+
+    /// Also write the report as Markdown to this file
+    #[arg(long)]
+    markdown_out: Option<PathBuf>,
+
+    /// POST the JSON report to this URL (any endpoint; amounts in cents with --cents)
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// Extra header sent with --webhook, e.g. "Authorization: Bearer TOKEN" (repeatable)
+    #[arg(long, requires = "webhook", value_parser = webhook::parse_header)]
+    webhook_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Retries for --webhook after connection errors, 429 or 5xx responses
+    #[arg(long, default_value_t = 3)]
+    webhook_retries: u32,
+
+    /// Write amounts in JSON and CSV output as integer cents (text and Markdown stay in dollars)
     #[arg(long)]
-    accounts_file: Option<PathBuf>,
+    cents: bool,
+
+    /// Round dollar amounts in JSON and CSV output to this many decimal places instead of
+    /// to the cent (amounts keep full precision until then)
+    #[arg(long, conflicts_with = "cents", value_parser = clap::value_parser!(u32).range(0..=6))]
+    money_decimals: Option<u32>,
+
+    /// Rank accounts by month-to-date spend with each account's share of the total
+    #[arg(long)]
+    compare_accounts: bool,
+
+    /// Show only the top N accounts in the ranking (requires --compare-accounts)
+    #[arg(long, requires = "compare_accounts")]
+    limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
-struct AccountsFileEntry {
-    access_key_id: String,
-    secret_access_key: String,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show total spend over time from the --store history
+    History {
+        /// Number of most recent reports to show
+        #[arg(long, default_value_t = 30)]
+        limit: usize,
+    },
+    /// List cost allocation tag keys (or one key's values) seen in the month-to-date window
+    Tags {
+        /// List the values of this tag key instead of the keys
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// List the cost category definitions (names and effective dates), for --by-cost-category
+    CostCategories {
+        /// List the versions in effect on this day (YYYY-MM-DD) instead of the current ones
+        #[arg(long)]
+        effective_on: Option<NaiveDate>,
+    },
+    /// Sparklines of month-end totals, overall and per account, from the --store history
+    Trend {
+        /// Number of most recent months to include
+        #[arg(long, default_value_t = 12)]
+        months: usize,
+    },
+    /// Per-service spend for each of the last N months, straight from Cost Explorer (one
+    /// monthly query per account)
+    ServiceTrend {
+        /// Months to include, counting the current one (at most 14, what Cost Explorer keeps)
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..=MAX_TREND_MONTHS as i64))]
+        months: u32,
+    },
+    /// Split each top service's month-to-date spend by record type (usage, tax, credits,
+    /// refunds, ...), from one SERVICE x RECORD_TYPE query per account
+    RecordTypes {
+        /// Number of services to show, by spend
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Cost Explorer's usage forecast (GetUsageForecast) for one usage type, per account and
+    /// month, for capacity planning
+    ForecastUsage {
+        /// Usage metric to project
+        #[arg(long, default_value = "UsageQuantity", value_parser = USAGE_FORECAST_METRICS.to_vec())]
+        metric: String,
+        /// The single usage type to forecast: usage-type=<type> or usage-type-group=<group>
+        #[arg(long)]
+        filter: UsageFilter,
+        /// Calendar months to forecast, counting the rest of the current one
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=12))]
+        months: u32,
+    },
+    /// Compare two accounts' month-to-date spend per service, largest difference first
+    Diff {
+        /// Account reference shown on the left
+        left: String,
+        /// Account reference shown on the right
+        right: String,
+    },
+    /// Write a calendar heatmap (SVG) of this month's daily spend across all accounts
+    Heatmap {
+        /// Output SVG path
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Check each account's credentials and Cost Explorer access without building a report;
+    /// exits non-zero if any account fails
+    Doctor,
+    /// Print the JSON Schema of the `--format json` report
+    #[cfg(feature = "schema")]
+    Schema,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GranularityArg {
+    Monthly,
+    Daily,
+    Hourly,
+}
+
+impl From<GranularityArg> for Granularity {
+    fn from(value: GranularityArg) -> Self {
+        match value {
+            GranularityArg::Monthly => Granularity::Monthly,
+            GranularityArg::Daily => Granularity::Daily,
+            GranularityArg::Hourly => Granularity::Hourly,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AccountOrderArg {
+    /// Highest spend first
+    Spend,
+    /// Alphabetical by account name
+    Name,
+    /// As the accounts were given
+    Input,
+}
+
+impl From<AccountOrderArg> for AccountOrder {
+    fn from(value: AccountOrderArg) -> Self {
+        match value {
+            AccountOrderArg::Spend => AccountOrder::Spend,
+            AccountOrderArg::Name => AccountOrder::Name,
+            AccountOrderArg::Input => AccountOrder::Input,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Cloud {
+    /// AWS Cost Explorer
+    Aws,
+    /// Cost and Usage Report files, read locally without Cost Explorer calls
+    Cur,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// One row per account and service
+    Csv,
+    /// GitHub-flavored Markdown tables
+    Markdown,
+    /// Self-contained HTML page with inline CSS, e.g. for email
+    Html,
+    /// FOCUS (FinOps Open Cost and Usage Specification) CSV, one row per account and service
+    Focus,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let (provider, accounts) = if let Some(path) = args.accounts_file {
-        let contents = fs::read_to_string(&path)?;
-        let entries: Vec<AccountsFileEntry> = serde_json::from_str(&contents)?;
-        let mut creds_map = HashMap::new();
-        let mut labels = Vec::with_capacity(entries.len());
-        for (idx, entry) in entries.into_iter().enumerate() {
-            let label = format!("credential-{}", idx + 1);
-            labels.push(label.clone());
-            creds_map.insert(
-                label,
-                StaticCredentials {
-                    access_key_id: entry.access_key_id,
-                    secret_access_key: entry.secret_access_key,
-                    session_token: None,
-                },
+    check_columns(&args)?;
+    check_budget_limits(&args)?;
+
+    match &args.command {
+        Some(Command::History { limit }) => return print_history(&open_store(&args)?, *limit),
+        Some(Command::Trend { months }) => return print_trend(&open_store(&args)?, *months),
+        #[cfg(feature = "schema")]
+        Some(Command::Schema) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&cloud_cost_core::report_schema())?
             );
+            return Ok(());
         }
-        (
-            AwsCostProvider::with_static_credentials(args.region, creds_map),
-            labels,
-        )
-    } else {
-        let profiles = if args.profiles.is_empty() {
-            vec!["default".to_string()]
+        _ => {}
+    }
+
+    if args.cloud == Cloud::Cur {
+        return cur_report(&args).await;
+    }
+
+    let (mut provider, mut accounts) = build_provider(&AccountSources {
+        assume_roles_files: &args.assume_roles_file,
+        base_profile: args.base_profile.as_deref(),
+        files: &args.accounts_file,
+        ssm_parameter: args.accounts_from_ssm.as_deref(),
+        secret: args.accounts_from_secret.as_deref(),
+        region: &args.region,
+        bootstrap_profile: args.bootstrap_profile.as_deref(),
+        profiles: &args.profiles,
+        profile_accounts_files: &args.profile_accounts_file,
+    })
+    .await?;
+    provider.mfa_token_code = match (&args.mfa_code, mfa_serial_number(&provider, &accounts)) {
+        (Some(code), _) => Some(code.clone()),
+        (None, Some(serial)) => Some(prompt_mfa_code(&serial)?),
+        (None, None) => None,
+    };
+
+    provider.region_from_profile = args.region_from_profile;
+    if let Some(app_id) = &args.app_id {
+        provider.app_name = app_id.clone();
+    }
+    if let Some(page_size) = args.page_size {
+        provider.set_page_size(page_size)?;
+    }
+    provider.apply_partition(args.partition, args.ce_endpoint_url.clone())?;
+    provider.granularity = args.granularity.into();
+    provider.strict_amounts = args.strict_amounts;
+    provider.separate_tax = args.separate_tax;
+    provider.blended_split = args.blended_savings;
+    provider.savings_rate = args.savings_rate;
+    provider.amortized_services = args.amortized_services;
+    provider.skip_unassumable = args.skip_unassumable;
+    provider.only_services = args.only_services.clone();
+    provider.metric = args.primary_metric.clone();
+    provider.extra_metrics = args.metrics.clone();
+    if args.explain {
+        provider.audit = Some(AuditLog::default());
+    }
+    if args.by_purchase_type {
+        provider.groupings.push(Grouping::PurchaseType);
+    }
+    if let Some(category) = &args.by_cost_category {
+        provider
+            .groupings
+            .push(Grouping::CostCategory(category.clone()));
+    }
+    if let Some(key) = &args.by_tag {
+        provider.groupings.push(Grouping::Tag(key.clone()));
+    }
+    if args.by_usage_type {
+        provider
+            .groupings
+            .push(Grouping::UsageType(args.usage_type_filter.clone()));
+    }
+    if args.by_az {
+        provider.groupings.push(Grouping::AvailabilityZone);
+    }
+
+    let today = today_in(args.timezone);
+
+    if matches!(args.command, Some(Command::Doctor)) {
+        return doctor(&provider, &accounts, today).await;
+    }
+
+    if args.consolidated {
+        let [payer] = accounts.as_slice() else {
+            bail!("--consolidated needs exactly one profile or credential (the payer account)");
+        };
+        provider.consolidated_payer = Some(payer.clone());
+        let (current, previous) = report_windows(today, &args)?;
+        let windows = if args.no_comparison {
+            vec![current]
         } else {
-            args.profiles
+            vec![current, previous]
         };
-        (AwsCostProvider::new(args.region), profiles)
+        accounts = provider.consolidated_account_ids(&windows).await?;
+    }
+
+    if args.prefetch_account_names {
+        provider.org_account_names = provider
+            .list_account_names(args.org_profile.as_deref())
+            .await
+            .context("--prefetch-account-names needs Organizations access")?;
+    }
+
+    if !args.include_account.is_empty() || !args.exclude_account.is_empty() {
+        accounts = filter_accounts(&provider, accounts, &args).await;
+        if accounts.is_empty() {
+            bail!("No accounts left after --include-account / --exclude-account");
+        }
+    }
+
+    let (mut accounts, skipped) = provider.resolve_accounts(accounts).await?;
+    if accounts.is_empty() && !skipped.is_empty() {
+        let reasons: Vec<String> = skipped
+            .iter()
+            .map(|e| format!("{}: {}", e.account_ref, e.error))
+            .collect();
+        bail!("Every account was skipped ({})", reasons.join("; "));
+    }
+
+    if let Some(ou_id) = &args.ou {
+        let ou_accounts = provider
+            .list_ou_accounts(args.org_profile.as_deref(), ou_id)
+            .await?;
+        let mut in_ou = Vec::with_capacity(accounts.len());
+        for account_ref in accounts {
+            let account_id = provider.account_id(&account_ref).await?;
+            if ou_accounts.contains(&account_id) {
+                in_ou.push(account_ref);
+            }
+        }
+        if in_ou.is_empty() {
+            bail!("None of the configured accounts belong to organizational unit {ou_id}");
+        }
+        accounts = in_ou;
+    }
+
+    if accounts.is_empty() {
+        bail!(NO_ACCOUNTS);
+    }
+
+    match &args.command {
+        Some(Command::Tags { key }) => {
+            return print_tags(&provider, &accounts, today, key.as_deref()).await;
+        }
+        Some(Command::CostCategories { effective_on }) => {
+            return print_cost_categories(&provider, &accounts, *effective_on).await;
+        }
+        Some(Command::ServiceTrend { months }) => {
+            return print_service_trend(&provider, &accounts, today, *months, args.format).await;
+        }
+        Some(Command::RecordTypes { top }) => {
+            return print_record_types(&provider, &accounts, today, *top, args.format).await;
+        }
+        Some(Command::ForecastUsage {
+            metric,
+            filter,
+            months,
+        }) => {
+            return print_usage_forecast(
+                &provider,
+                &accounts,
+                today,
+                metric,
+                filter,
+                *months,
+                args.format,
+            )
+            .await;
+        }
+        Some(Command::Heatmap { out }) => {
+            return write_heatmap(&provider, &accounts, today, out).await;
+        }
+        Some(Command::Diff { left, right }) => {
+            return print_diff(&provider, left, right, today).await;
+        }
+        _ => {}
+    }
+
+    if let Some(spec) = &args.group_by {
+        let keys = parse_group_by(spec)?;
+        return print_grouped(&provider, &accounts, today, &keys, args.format).await;
+    }
+
+    let (mut report, report_date) = build_report(&provider, &accounts, today, &args).await?;
+    report.errors.extend(skipped);
+    let explanation = provider
+        .audit
+        .as_ref()
+        .map(|audit| explain::render(&report, &aws_method(&provider), &audit.calls()));
+    let (total, currency) = (report.total_all, report.currency.clone());
+    emit_report(report, report_date, &args).await?;
+    if let Some(explanation) = explanation {
+        eprint!("\n{explanation}");
+    }
+    check_budget(total, currency.as_deref(), &args);
+    Ok(())
+}
+
+/// `--explain` description of what the Cost Explorer queries asked for.
+fn aws_method(provider: &AwsCostProvider) -> Vec<String> {
+    let mut method = vec![format!(
+        "Metric: {} for services, totals and both periods",
+        provider.metric
+    )];
+    if !provider.extra_metrics.is_empty() {
+        method.push(format!(
+            "Additional metrics: {}, from the same queries",
+            provider.extra_metrics.join(", ")
+        ));
+    }
+    method.push(format!(
+        "Granularity: {}, buckets summed per service",
+        provider.granularity.as_str()
+    ));
+    let overrides: BTreeMap<&String, &Granularity> = provider.account_granularity.iter().collect();
+    for (account_ref, granularity) in overrides {
+        method.push(format!(
+            "Granularity for {account_ref}: {}",
+            granularity.as_str()
+        ));
+    }
+    if !provider.only_services.is_empty() {
+        method.push(format!(
+            "Only services: {}",
+            provider.only_services.join(", ")
+        ));
+    }
+    if provider.separate_tax {
+        method.push("Tax records left out of services and totals, fetched separately".to_string());
+    }
+    if let Some(payer) = &provider.consolidated_payer {
+        method.push(format!(
+            "Consolidated: one LINKED_ACCOUNT x SERVICE query per period through {payer}"
+        ));
+    }
+    method
+}
+
+/// Report from CUR files; only the options that do not need Cost Explorer apply.
+async fn cur_report(args: &Args) -> Result<()> {
+    if args.command.is_some() || args.group_by.is_some() {
+        bail!(
+            "--cloud cur only produces the report; subcommands and --group-by need Cost Explorer"
+        );
+    }
+    let provider = CurCostProvider::load(&args.cur_paths).await?;
+    let accounts = provider.account_ids();
+    if accounts.is_empty() {
+        bail!(
+            "No line items with a usage account id in {}",
+            args.cur_paths.join(", ")
+        );
+    }
+
+    let (mut report, report_date) =
+        build_report(&provider, &accounts, today_in(args.timezone), args).await?;
+    report.warnings.extend(provider.warnings());
+    let explanation = args.explain.then(|| {
+        let method = [format!(
+            "Source: lineItem/UnblendedCost from {} CUR file(s), by the day of \
+             lineItem/UsageStartDate",
+            args.cur_paths.len()
+        )];
+        explain::render(&report, &method, &[])
+    });
+    let (total, currency) = (report.total_all, report.currency.clone());
+    emit_report(report, report_date, args).await?;
+    if let Some(explanation) = explanation {
+        eprint!("\n{explanation}");
+    }
+    check_budget(total, currency.as_deref(), args);
+    Ok(())
+}
+
+/// Month-to-date report, or the whole `--month` against the month before. Also returns the
+/// date the report is stored under: today, or the last day of `--month`.
+async fn build_report<P: CostProvider>(
+    provider: &P,
+    accounts: &[String],
+    today: NaiveDate,
+    args: &Args,
+) -> Result<(Report, NaiveDate)> {
+    let options = report_options(args)?;
+    let (current, previous) = report_windows(today, args)?;
+    let report =
+        generate_report_for_windows(provider, accounts, current, previous, &options).await?;
+    let report_date = match &args.month {
+        Some(_) => current.1.pred_opt().unwrap_or(current.1),
+        None => today,
     };
+    Ok((report, report_date))
+}
 
-    let today = Utc::now().date_naive();
-    let report = generate_report(&provider, &accounts, today).await?;
+/// The (current, previous) windows of the report: month-to-date against the same point of
+/// last month, or the whole `--month` against the month before.
+fn report_windows(
+    today: NaiveDate,
+    args: &Args,
+) -> Result<((NaiveDate, NaiveDate), (NaiveDate, NaiveDate))> {
+    let Some(month) = &args.month else {
+        return Ok((month_to_date(today), previous_month_same_point(today)?));
+    };
 
-    println!("Cloud Cost Manager\n");
+    let month_start = parse_month(month)?;
+    if month_start >= month_to_date(today).0 {
+        bail!("--month {month} is not over yet; omit --month for the month-to-date report");
+    }
+    Ok((
+        month_window(month_start)?,
+        month_window(month_start - Months::new(1))?,
+    ))
+}
 
+fn report_options(args: &Args) -> Result<ReportOptions> {
+    Ok(ReportOptions {
+        min_comparison_days: args.min_comparison_days,
+        overhead_services: args.split_overhead.then(|| {
+            if args.overhead_services.is_empty() {
+                DEFAULT_OVERHEAD_SERVICES
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect()
+            } else {
+                args.overhead_services.clone()
+            }
+        }),
+        deadline: args.deadline_secs.map(Duration::from_secs),
+        cancel: None,
+        max_concurrency: args.max_concurrency,
+        fail_on_error: args.fail_on_error,
+        compare_previous: !args.no_comparison,
+        baseline_periods: args.baseline_periods as usize,
+        compare_breakdowns: args.compare_breakdowns,
+        native_comparison: args.native_comparison,
+        service_aliases: load_service_aliases(args)?,
+        service_groups: load_service_groups(args)?,
+        other_threshold: args.other_threshold,
+        top_credits: args.top_credits,
+        // Only for a person watching: not in pipes, CI logs or under --quiet
+        progress: (!args.quiet && std::io::stderr().is_terminal()).then(|| {
+            Progress::new(|done, total| {
+                if done < total {
+                    eprint!("\rFetched {done}/{total} accounts...");
+                } else {
+                    // Clear the line so the report starts on a clean one
+                    eprint!("\r\x1b[2K");
+                }
+            })
+        }),
+        strict: args.strict,
+        min_account_spend: args.min_account_spend,
+        count_small_accounts: args.count_small_accounts,
+        prorate: args.prorate,
+        blend_forecast: args.blend_forecast,
+    })
+}
+
+/// Stores the report (with --store), prints it, writes the extra output files and posts it
+/// to --webhook.
+async fn emit_report(mut report: Report, today: NaiveDate, args: &Args) -> Result<()> {
+    report.sort_accounts(args.sort_accounts.into());
+    if let Some(path) = &args.store {
+        if report.total_all.is_some() {
+            HistoryStore::open(path)?.save(today, &report)?;
+        } else {
+            eprintln!(
+                "Warning: report not stored; the accounts are billed in different currencies"
+            );
+        }
+    }
+
+    if args.quiet {
+        match report.total_all {
+            Some(total) => println!("{total:.2}"),
+            None => {
+                for subtotal in &report.currency_subtotals {
+                    println!("{:.2} {}", subtotal.total, subtotal.currency);
+                }
+            }
+        }
+    } else if let Some(path) = &args.output_file {
+        fs::write(path, render_output(&report, args.format, args)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    } else {
+        print!("{}", render_output(&report, args.format, args)?);
+    }
+
+    for (path, format) in [
+        (&args.json_out, OutputFormat::Json),
+        (&args.csv_out, OutputFormat::Csv),
+        (&args.markdown_out, OutputFormat::Markdown),
+    ] {
+        if let Some(path) = path {
+            fs::write(path, render_output(&report, format, args)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+
+    if let Some(url) = &args.webhook {
+        let body = render_output(&report, OutputFormat::Json, args)?;
+        webhook::post_json(url, &args.webhook_header, body, args.webhook_retries).await?;
+    }
+
+    Ok(())
+}
+
+/// Anchors an --include-account / --exclude-account pattern so it must match a whole
+/// reference or id.
+fn parse_account_pattern(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("^(?:{pattern})$"))
+        .with_context(|| format!("Invalid account pattern {pattern:?}"))
+}
+
+/// Keeps the discovered accounts that match an --include-account pattern (all of them when
+/// there is none), then drops those matching an --exclude-account pattern, so exclusion wins.
+/// Patterns are tried against the account reference, its account id and its name. The id and
+/// name are looked up once per account and reused by the report; an account whose id cannot
+/// be looked up is matched on its reference only, and its error surfaces when it is resolved.
+async fn filter_accounts(
+    provider: &AwsCostProvider,
+    accounts: Vec<String>,
+    args: &Args,
+) -> Vec<String> {
+    let identities = join_all(
+        accounts
+            .iter()
+            .map(|account_ref| provider.account_identity(account_ref)),
+    )
+    .await;
+    accounts
+        .into_iter()
+        .zip(identities)
+        .filter(|(account_ref, identity)| {
+            account_selected(args, account_ref, identity.as_ref().ok())
+        })
+        .map(|(account_ref, _)| account_ref)
+        .collect()
+}
+
+/// Whether --include-account / --exclude-account keep an account, given its reference and,
+/// when it could be looked up, its (account id, account name).
+fn account_selected(args: &Args, account_ref: &str, identity: Option<&(String, String)>) -> bool {
+    let matches = |patterns: &[Regex]| {
+        patterns.iter().any(|pattern| {
+            pattern.is_match(account_ref)
+                || identity.is_some_and(|(id, name)| pattern.is_match(id) || pattern.is_match(name))
+        })
+    };
+    (args.include_account.is_empty() || matches(&args.include_account))
+        && !matches(&args.exclude_account)
+}
+
+/// Two-tier budget gate, run once the report is out. A breach of --budget-fail exits with
+/// --budget-fail-code (and takes precedence when both are breached); --budget-warn only warns.
+fn check_budget(total: Option<f64>, currency: Option<&str>, args: &Args) {
+    let Some(total) = total else {
+        if args.budget_fail.is_some() || args.budget_warn.is_some() {
+            eprintln!(
+                "Warning: budgets not checked; the accounts are billed in different currencies"
+            );
+        }
+        return;
+    };
+    let money = |amount| render::money(amount, currency);
+    if let Some(limit) = args.budget_fail.filter(|limit| total > *limit) {
+        eprintln!(
+            "Error: total {} is over the --budget-fail limit of {}",
+            money(total),
+            money(limit)
+        );
+        std::process::exit(args.budget_fail_code.into());
+    }
+    if let Some(limit) = args.budget_warn.filter(|limit| total > *limit) {
+        eprintln!(
+            "Warning: total {} is over the --budget-warn limit of {}",
+            money(total),
+            money(limit)
+        );
+    }
+}
+
+fn load_service_aliases(args: &Args) -> Result<Option<HashMap<String, String>>> {
+    if !args.alias_services {
+        return Ok(None);
+    }
+    let mut aliases = default_service_aliases();
+    if let Some(path) = &args.service_aliases_file {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let overrides: HashMap<String, String> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid service alias map in {}", path.display()))?;
+        aliases.extend(overrides);
+    }
+    Ok(Some(aliases))
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceGroupEntry {
+    pattern: String,
+    group: String,
+}
+
+/// Reads --service-groups-file. Patterns are unanchored regexes tried against the service
+/// name after aliasing, in file order.
+fn load_service_groups(args: &Args) -> Result<Vec<ServiceGroupRule>> {
+    let Some(path) = &args.service_groups_file else {
+        return Ok(Vec::new());
+    };
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let entries: Vec<ServiceGroupEntry> = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid service grouping rules in {}", path.display()))?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let pattern = Regex::new(&entry.pattern).with_context(|| {
+                format!(
+                    "Invalid service group pattern {:?} in {}",
+                    entry.pattern,
+                    path.display()
+                )
+            })?;
+            Ok(ServiceGroupRule {
+                pattern,
+                group: entry.group,
+            })
+        })
+        .collect()
+}
+
+fn money_format(args: &Args) -> MoneyFormat {
+    if args.cents {
+        MoneyFormat::Cents
+    } else if let Some(places) = args.money_decimals {
+        MoneyFormat::Decimals(places)
+    } else {
+        MoneyFormat::Dollars
+    }
+}
+
+/// Metric names `--columns` takes besides the Cost Explorer spelling.
+const METRIC_COLUMN_NAMES: &[(&str, &str)] = &[
+    ("unblended", "UnblendedCost"),
+    ("blended", "BlendedCost"),
+    ("amortized", "AmortizedCost"),
+    ("net-unblended", "NetUnblendedCost"),
+    ("net-amortized", "NetAmortizedCost"),
+    ("usage", "UsageQuantity"),
+    ("normalized-usage", "NormalizedUsageAmount"),
+];
+
+fn parse_metric_column(name: &str) -> Result<String> {
+    let name = name.trim();
+    METRIC_COLUMN_NAMES
+        .iter()
+        .find(|(short, metric)| {
+            name.eq_ignore_ascii_case(short) || name.eq_ignore_ascii_case(metric)
+        })
+        .map(|(_, metric)| metric.to_string())
+        .ok_or_else(|| {
+            let names: Vec<&str> = METRIC_COLUMN_NAMES
+                .iter()
+                .map(|(short, _)| *short)
+                .collect();
+            anyhow!(
+                "Unknown metric column {name:?}; expected one of {}",
+                names.join(", ")
+            )
+        })
+}
+
+/// Rejects a --budget-warn above --budget-fail, which could never warn, before any query is
+/// made.
+fn check_budget_limits(args: &Args) -> Result<()> {
+    if let (Some(warn), Some(fail)) = (args.budget_warn, args.budget_fail)
+        && warn > fail
+    {
+        bail!("--budget-warn {warn} is above --budget-fail {fail}");
+    }
+    Ok(())
+}
+
+/// Rejects --columns naming a metric that is not fetched, before any query is made.
+fn check_columns(args: &Args) -> Result<()> {
+    for column in &args.columns {
+        if *column != args.primary_metric && !args.metrics.contains(column) {
+            bail!("--columns {column} is not fetched; add --metric {column}");
+        }
+    }
+    Ok(())
+}
+
+/// The report with only the --columns metrics, in that order, as additional columns. The
+/// primary metric is the main amount either way.
+fn with_columns(report: &Report, columns: &[String]) -> Report {
+    let pick = |metrics: &[MetricBreakdown]| -> Vec<MetricBreakdown> {
+        columns
+            .iter()
+            .filter_map(|column| metrics.iter().find(|m| m.metric == *column).cloned())
+            .collect()
+    };
+    let mut shown = report.clone();
+    shown.metrics = pick(&report.metrics);
+    for summary in &mut shown.summaries {
+        summary.metrics = pick(&summary.metrics);
+    }
+    shown
+}
+
+fn render_output(report: &Report, format: OutputFormat, args: &Args) -> Result<String> {
+    let selected;
+    let report =
+        if !args.columns.is_empty() && matches!(format, OutputFormat::Text | OutputFormat::Csv) {
+            selected = with_columns(report, &args.columns);
+            &selected
+        } else {
+            report
+        };
+    Ok(match format {
+        OutputFormat::Text => render::text(
+            report,
+            &TextOptions {
+                primary_metric: args.primary_metric.clone(),
+                rank_accounts: args.compare_accounts,
+                rank_limit: args.limit,
+                min_delta_abs: args.min_delta_abs,
+                min_delta_pct: args.min_delta_pct,
+            },
+        ),
+        OutputFormat::Json => render::json(report, money_format(args))?,
+        OutputFormat::Csv if !args.columns.is_empty() => {
+            render::csv_with_metrics(report, money_format(args))
+        }
+        OutputFormat::Csv => render::csv(report, money_format(args)),
+        OutputFormat::Markdown => render::markdown(report),
+        OutputFormat::Html => render::html(report),
+        OutputFormat::Focus => render::focus(report),
+    })
+}
+
+fn open_store(args: &Args) -> Result<HistoryStore> {
+    let path = args
+        .store
+        .as_deref()
+        .ok_or_else(|| anyhow!("This subcommand needs --store <PATH>"))?;
+    HistoryStore::open(path)
+}
+
+fn print_history(store: &HistoryStore, limit: usize) -> Result<()> {
+    let entries = store.entries(limit)?;
+    if entries.is_empty() {
+        println!("No reports stored yet.");
+        return Ok(());
+    }
+
+    println!("Stored reports (month-to-date total per report date):");
+    for e in &entries {
+        match (e.prev_total, e.delta, e.delta_pct) {
+            (Some(prev_total), Some(delta), Some(delta_pct)) => println!(
+                "- {}: ${:.2} (previous month same point ${:.2}, change ${:.2} / {:.2}%)",
+                e.report_date, e.total_all, prev_total, delta, delta_pct
+            ),
+            _ => println!("- {}: ${:.2} (no comparison)", e.report_date, e.total_all),
+        }
+    }
+    Ok(())
+}
+
+async fn print_tags(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    key: Option<&str>,
+) -> Result<()> {
+    let (start, end_exclusive) = month_to_date(today);
+    let mut tags = BTreeSet::new();
+    for account_ref in accounts {
+        tags.extend(
+            provider
+                .list_tags(account_ref, start, end_exclusive, key)
+                .await?,
+        );
+    }
+
+    let window = render::date_range_label(start, end_exclusive);
+    match key {
+        Some(key) => println!("Values of tag {key} ({window}):"),
+        None => println!("Cost allocation tag keys ({window}):"),
+    }
+    if tags.is_empty() {
+        println!("- none (activate cost allocation tags in the Billing console)");
+    }
+    for tag in tags {
+        println!("- {tag}");
+    }
+    Ok(())
+}
+
+async fn print_cost_categories(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    effective_on: Option<NaiveDate>,
+) -> Result<()> {
+    let mut categories = BTreeSet::new();
+    for account_ref in accounts {
+        categories.extend(
+            provider
+                .list_cost_categories(account_ref, effective_on)
+                .await?,
+        );
+    }
+
+    match effective_on {
+        Some(day) => println!("Cost categories in effect on {day}:"),
+        None => println!("Cost categories:"),
+    }
+    if categories.is_empty() {
+        println!("- none (define cost categories in the Billing console of the payer account)");
+    }
+    for category in categories {
+        match &category.effective_end {
+            Some(end) => println!(
+                "- {} (effective {} to {end})",
+                category.name, category.effective_start
+            ),
+            None => println!(
+                "- {} (effective since {})",
+                category.name, category.effective_start
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Checks every account concurrently and prints one OK/FAIL line each. In consolidated
+/// mode only the payer is checked, since it makes every query.
+async fn doctor(provider: &AwsCostProvider, accounts: &[String], today: NaiveDate) -> Result<()> {
+    if accounts.is_empty() {
+        bail!(NO_ACCOUNTS);
+    }
+
+    let results = join_all(
+        accounts
+            .iter()
+            .map(|account_ref| provider.check_access(account_ref, today)),
+    )
+    .await;
+
+    let mut failed = 0;
+    for (account_ref, result) in accounts.iter().zip(results) {
+        match result {
+            Ok(account_id) => println!("OK   {account_ref} ({account_id})"),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL {account_ref}: {err:#}");
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of {} account(s) failed the check", accounts.len());
+    }
+    Ok(())
+}
+
+async fn print_service_trend(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    months: u32,
+    format: OutputFormat,
+) -> Result<()> {
+    let (trend, currency) = provider.service_trend(accounts, today, months).await?;
+    let currency = currency.as_deref();
+    if matches!(format, OutputFormat::Json) {
+        let months: Vec<serde_json::Value> = trend
+            .iter()
+            .map(|(month, services)| serde_json::json!({ "month": month, "services": services }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&months)?);
+        return Ok(());
+    }
+
+    let mut totals: HashMap<&String, f64> = HashMap::new();
+    for (_, services) in &trend {
+        for (svc, amt) in services {
+            *totals.entry(svc).or_insert(0.0) += amt;
+        }
+    }
+    let mut services: Vec<(&String, f64)> = totals.into_iter().collect();
+    services.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(b.0))
+    });
+
+    println!("Monthly spend by service (the last month is month-to-date):");
+    print!("{:<50}", "Service");
+    for (month, _) in &trend {
+        print!(" {:>12}", month.format("%Y-%m").to_string());
+    }
+    println!();
+    for (svc, _) in &services {
+        print!("{svc:<50}");
+        for (_, month) in &trend {
+            print!(
+                " {:>12}",
+                render::money(month.get(*svc).copied().unwrap_or(0.0), currency)
+            );
+        }
+        println!();
+    }
+    print!("{:<50}", "Total");
+    for (_, month) in &trend {
+        print!(
+            " {:>12}",
+            render::money(month.values().sum::<f64>(), currency)
+        );
+    }
+    println!();
+    Ok(())
+}
+
+async fn print_diff(
+    provider: &AwsCostProvider,
+    left: &str,
+    right: &str,
+    today: NaiveDate,
+) -> Result<()> {
+    let (start, end_exclusive) = month_to_date(today);
+    let (left, right) = tokio::try_join!(
+        provider.fetch_account_summary(left, start, end_exclusive),
+        provider.fetch_account_summary(right, start, end_exclusive),
+    )?;
+    if let (Some(l), Some(r)) = (&left.currency, &right.currency)
+        && l != r
+    {
+        bail!(
+            "Cannot diff {} ({l}) against {} ({r}): they are billed in different currencies",
+            left.account_ref,
+            right.account_ref
+        );
+    }
+    let currency = left.currency.as_deref().or(right.currency.as_deref());
+
+    let services: BTreeSet<&String> = left.services.keys().chain(right.services.keys()).collect();
+    let mut rows: Vec<(&String, Option<f64>, Option<f64>)> = services
+        .into_iter()
+        .map(|svc| {
+            (
+                svc,
+                left.services.get(svc).copied(),
+                right.services.get(svc).copied(),
+            )
+        })
+        .collect();
+    let difference = |l: Option<f64>, r: Option<f64>| r.unwrap_or(0.0) - l.unwrap_or(0.0);
+    rows.sort_by(|a, b| {
+        difference(b.1, b.2)
+            .abs()
+            .partial_cmp(&difference(a.1, a.2).abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(b.0))
+    });
+
+    let amount =
+        |value: Option<f64>| value.map_or_else(|| "-".to_string(), |v| render::money(v, currency));
+    println!(
+        "Month-to-date window: {}",
+        render::date_range_label(start, end_exclusive)
+    );
+    println!(
+        "Left:  {} ({}) via {}\nRight: {} ({}) via {}\n",
+        left.account_name,
+        left.account_id,
+        left.account_ref,
+        right.account_name,
+        right.account_id,
+        right.account_ref
+    );
     println!(
-        "Month-to-date window: {} to {} (exclusive)",
-        report.month_start, report.month_end_exclusive
+        "{:<50} {:>14} {:>14} {:>14}",
+        "Service", "Left", "Right", "Difference"
     );
+    for (svc, l, r) in &rows {
+        println!(
+            "{:<50} {:>14} {:>14} {:>14}",
+            svc,
+            amount(*l),
+            amount(*r),
+            render::signed_money(difference(*l, *r), currency)
+        );
+    }
+    println!(
+        "{:<50} {:>14} {:>14} {:>14}",
+        "Total",
+        amount(Some(left.total)),
+        amount(Some(right.total)),
+        render::signed_money(right.total - left.total, currency)
+    );
+    Ok(())
+}
+
+async fn print_grouped(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    keys: &[GroupKey],
+    format: OutputFormat,
+) -> Result<()> {
+    let (start, end_exclusive) = month_to_date(today);
+    let (groups, currency) =
+        grouped_across_accounts(provider, accounts, start, end_exclusive, keys).await?;
+    let currency = currency.as_deref();
+
+    if matches!(format, OutputFormat::Json) {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+
+    let labels: Vec<String> = keys.iter().map(GroupKey::label).collect();
     println!(
-        "Previous month window: {} to {} (exclusive)\n",
-        report.prev_start, report.prev_end_exclusive
+        "Spend by {} ({}):",
+        labels.join(" and "),
+        render::date_range_label(start, end_exclusive)
     );
+    let mut rows: Vec<(&String, f64, &BTreeMap<String, f64>)> = groups
+        .iter()
+        .map(|(name, inner)| (name, inner.values().sum(), inner))
+        .collect();
+    rows.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    for (name, total, inner) in rows {
+        println!("- {name}: {}", render::money(total, currency));
+        if keys.len() < 2 {
+            continue;
+        }
+        let mut inner: Vec<(&String, &f64)> = inner.iter().collect();
+        inner.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal));
+        for (value, amount) in inner {
+            println!("  - {value}: {}", render::money(*amount, currency));
+        }
+    }
+    Ok(())
+}
+
+/// `grouped_costs` for every account, summed into one nested map, with the currency they
+/// share. Accounts billed in different currencies cannot be summed and are rejected.
+async fn grouped_across_accounts(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    start: NaiveDate,
+    end_exclusive: NaiveDate,
+    keys: &[GroupKey],
+) -> Result<(BTreeMap<String, BTreeMap<String, f64>>, Option<String>)> {
+    let per_account = try_join_all(
+        accounts
+            .iter()
+            .map(|account_ref| provider.grouped_costs(account_ref, start, end_exclusive, keys)),
+    )
+    .await?;
+    let currency = shared_currency(per_account.iter().map(|(_, currency)| currency.as_deref()))?;
 
-    println!("Breakdown by account:");
-    for s in &report.summaries {
+    let mut groups: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+    for (account, _) in per_account {
+        for (outer, inner) in account {
+            let entry = groups.entry(outer).or_default();
+            for (name, amount) in inner {
+                *entry.entry(name).or_insert(0.0) += amount;
+            }
+        }
+    }
+    Ok((groups, currency))
+}
+
+async fn print_record_types(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    top: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    let (start, end_exclusive) = month_to_date(today);
+    let keys = [
+        GroupKey::Dimension("SERVICE".to_string()),
+        GroupKey::Dimension("RECORD_TYPE".to_string()),
+    ];
+    let (groups, currency) =
+        grouped_across_accounts(provider, accounts, start, end_exclusive, &keys).await?;
+    let currency = currency.as_deref();
+    let services = top_services_by_record_type(groups, top);
+
+    if matches!(format, OutputFormat::Json) {
+        let shown: BTreeMap<&String, &BTreeMap<String, f64>> = services
+            .iter()
+            .map(|(service, _, record_types)| (service, record_types))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&shown)?);
+        return Ok(());
+    }
+
+    println!(
+        "Spend by record type, top {top} services ({}):",
+        render::date_range_label(start, end_exclusive)
+    );
+    for (service, total, record_types) in &services {
         println!(
-            "- {} ({}) via profile {}: ${:.2}",
-            s.account_name, s.account_id, s.account_ref, s.total
+            "- {service}: {} ({})",
+            render::money(*total, currency),
+            record_type_split(record_types, currency)
         );
     }
+    Ok(())
+}
+
+/// The `top` services by net spend (the sum over their record types), largest first, each
+/// with that net total. Credits count against their service, so a fully credited service
+/// drops down the list.
+fn top_services_by_record_type(
+    groups: BTreeMap<String, BTreeMap<String, f64>>,
+    top: usize,
+) -> Vec<(String, f64, BTreeMap<String, f64>)> {
+    let mut services: Vec<(String, f64, BTreeMap<String, f64>)> = groups
+        .into_iter()
+        .map(|(service, record_types)| {
+            let total = record_types.values().sum();
+            (service, total, record_types)
+        })
+        .collect();
+    services.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    services.truncate(top);
+    services
+}
 
-    println!("\nTotal across all accounts: ${:.2}", report.total_all);
+/// One service's record types, largest charges first, then credits and refunds.
+fn record_type_split(record_types: &BTreeMap<String, f64>, currency: Option<&str>) -> String {
+    let mut split: Vec<(&String, &f64)> = record_types.iter().collect();
+    split.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let split: Vec<String> = split
+        .into_iter()
+        .map(|(record_type, amount)| format!("{record_type} {}", render::money(*amount, currency)))
+        .collect();
+    split.join(", ")
+}
 
-    println!("\nTop 5 services across all accounts:");
-    for (svc, amt) in &report.top_services {
-        println!("- {}: ${:.2}", svc, amt);
+async fn print_usage_forecast(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    metric: &str,
+    filter: &UsageFilter,
+    months: u32,
+    format: OutputFormat,
+) -> Result<()> {
+    let (start, _) = month_to_date(today);
+    let end_exclusive = start
+        .checked_add_months(Months::new(months))
+        .ok_or_else(|| anyhow!("{months} months after {start} is out of range"))?;
+    let forecasts = try_join_all(accounts.iter().map(|account_ref| {
+        provider.usage_forecast(account_ref, filter, metric, today, end_exclusive)
+    }))
+    .await?;
+    let unit = forecasts
+        .iter()
+        .find_map(|forecast| forecast.unit.clone())
+        .unwrap_or_default();
+    let total: f64 = forecasts.iter().map(|forecast| forecast.total).sum();
+
+    if matches!(format, OutputFormat::Json) {
+        let per_account: Vec<serde_json::Value> = accounts
+            .iter()
+            .zip(&forecasts)
+            .map(|(account_ref, forecast)| {
+                serde_json::json!({
+                    "account_ref": account_ref,
+                    "total": forecast.total,
+                    "months": forecast.months,
+                })
+            })
+            .collect();
+        let out = serde_json::json!({
+            "metric": metric,
+            "unit": unit,
+            "total": total,
+            "accounts": per_account,
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
     }
 
-    println!("\nMonth-to-month comparison:");
-    println!("- Current MTD: ${:.2}", report.total_all);
-    println!("- Previous month same point: ${:.2}", report.prev_total);
-    println!("- Change: ${:.2} ({:.2}%)", report.delta, report.delta_pct);
+    println!(
+        "Forecast {metric} for {filter}, {}:",
+        render::date_range_label(today, end_exclusive)
+    );
+    for (account_ref, forecast) in accounts.iter().zip(&forecasts) {
+        let split: Vec<String> = forecast
+            .months
+            .iter()
+            .map(|(month, quantity)| format!("{} {quantity:.2}", month.format("%Y-%m")))
+            .collect();
+        println!(
+            "- {account_ref}: {:.2} {unit} ({})",
+            forecast.total,
+            split.join(", ")
+        );
+    }
+    println!("Total: {total:.2} {unit}");
+    Ok(())
+}
+
+async fn write_heatmap(
+    provider: &AwsCostProvider,
+    accounts: &[String],
+    today: NaiveDate,
+    out: &Path,
+) -> Result<()> {
+    let (start, end_exclusive) = month_to_date(today);
+    let per_account = try_join_all(
+        accounts
+            .iter()
+            .map(|account_ref| provider.daily_totals(account_ref, start, end_exclusive)),
+    )
+    .await?;
+
+    let mut daily: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for days in per_account {
+        for (day, amount) in days {
+            *daily.entry(day).or_insert(0.0) += amount;
+        }
+    }
+
+    fs::write(out, heatmap::render_svg(start, &daily))
+        .with_context(|| format!("Failed to write {}", out.display()))?;
+    println!(
+        "Wrote daily spend heatmap for {} ({} day(s) with data) to {}",
+        start.format("%B %Y"),
+        daily.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+fn print_trend(store: &HistoryStore, months: usize) -> Result<()> {
+    let trend = store.monthly_trend(months)?;
+    if trend.months.len() < 2 {
+        println!("Spend trend: insufficient history (need at least two months of stored reports)");
+        return Ok(());
+    }
 
+    println!(
+        "Spend trend {} to {} (month-end totals):",
+        trend.months[0].format("%Y-%m"),
+        trend.months[trend.months.len() - 1].format("%Y-%m")
+    );
+    println!(
+        "- All accounts: {} ${:.2}",
+        sparkline(&trend.totals),
+        trend.totals.last().copied().unwrap_or(0.0)
+    );
+    for (name, totals) in &trend.accounts {
+        println!(
+            "- {}: {} ${:.2}",
+            name,
+            sparkline(totals),
+            totals.last().copied().unwrap_or(0.0)
+        );
+    }
     Ok(())
 }
+
+fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+
+    values
+        .iter()
+        .map(|v| {
+            if range.abs() < f64::EPSILON {
+                BARS[BARS.len() / 2]
+            } else {
+                let idx = ((v - min) / range * (BARS.len() - 1) as f64).round() as usize;
+                BARS[idx.min(BARS.len() - 1)]
+            }
+        })
+        .collect()
+}
+
+/// Reads one MFA code from the terminal; the same code is used for every role that needs it.
+fn prompt_mfa_code(serial_number: &str) -> Result<String> {
+    eprint!("MFA code for {serial_number}: ");
+    std::io::stderr().flush()?;
+    let mut code = String::new();
+    std::io::stdin()
+        .read_line(&mut code)
+        .context("Failed to read MFA code")?;
+    let code = code.trim();
+    if code.len() != 6 || !code.chars().all(|c| c.is_ascii_digit()) {
+        bail!("MFA code must be 6 digits");
+    }
+    Ok(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloud_cost_core::AccountSummary;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Two accounts over August 1–14, 2024, without a comparison.
+    pub(crate) fn fixed_report() -> Report {
+        Report::for_test(vec![
+            AccountSummary {
+                account_id: "111111111111".to_string(),
+                ..AccountSummary::for_test("prod", [("Amazon EC2", 100.25), ("Amazon S3", 20.0)])
+            },
+            AccountSummary {
+                account_id: "222222222222".to_string(),
+                ..AccountSummary::for_test("dev", [("Amazon EC2", 9.5)])
+            },
+        ])
+    }
+
+    /// A path under the temp dir unique to this test process.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ccm-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn json_and_csv_files_are_written_with_the_same_totals() {
+        let (json_path, csv_path) = (temp_path("report.json"), temp_path("report.csv"));
+        let args = Args::try_parse_from([
+            "cloud-cost-manager",
+            "--quiet",
+            "--json-out",
+            json_path.to_str().unwrap(),
+            "--csv-out",
+            csv_path.to_str().unwrap(),
+        ])
+        .unwrap();
+
+        emit_report(fixed_report(), day(2024, 8, 15), &args)
+            .await
+            .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        fs::remove_file(&json_path).unwrap();
+        fs::remove_file(&csv_path).unwrap();
+
+        assert_eq!(json["total_all"], 129.75);
+        let csv_total: f64 = csv
+            .lines()
+            .skip(2)
+            .map(|line| line.rsplit(',').next().unwrap().parse::<f64>().unwrap())
+            .sum();
+        assert_eq!(csv_total, 129.75);
+        assert_eq!(csv.lines().count(), 2 + 3);
+    }
+
+    #[test]
+    fn budget_fail_code_defaults_to_3_and_warn_must_not_exceed_fail() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["cloud-cost-manager"];
+            argv.extend_from_slice(extra);
+            Args::try_parse_from(argv).unwrap()
+        };
+
+        assert_eq!(parse(&[]).budget_fail_code, 3);
+        assert_eq!(parse(&["--budget-fail-code", "7"]).budget_fail_code, 7);
+        assert!(
+            check_budget_limits(&parse(&["--budget-warn", "80", "--budget-fail", "100"])).is_ok()
+        );
+        assert!(
+            check_budget_limits(&parse(&["--budget-warn", "100", "--budget-fail", "100"])).is_ok()
+        );
+        assert!(check_budget_limits(&parse(&["--budget-warn", "500"])).is_ok());
+        let err = check_budget_limits(&parse(&["--budget-warn", "120", "--budget-fail", "100"]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "--budget-warn 120 is above --budget-fail 100");
+    }
+
+    #[test]
+    fn account_patterns_match_references_ids_and_names_in_full() {
+        let args = Args::try_parse_from([
+            "cloud-cost-manager",
+            "--include-account",
+            "1111.*",
+            "--include-account",
+            "Prod.*",
+            "--exclude-account",
+            ".*[Ss]andbox.*",
+        ])
+        .unwrap();
+        let identity = |id: &str, name: &str| Some((id.to_string(), name.to_string()));
+
+        // By id, by name and by reference
+        assert!(account_selected(
+            &args,
+            "a",
+            identity("111122223333", "Billing").as_ref()
+        ));
+        assert!(account_selected(
+            &args,
+            "b",
+            identity("222233334444", "Production").as_ref()
+        ));
+        assert!(account_selected(&args, "Prod-eu", None));
+        // Patterns match in full: "Prod.*" does not match inside "Preprod"
+        assert!(!account_selected(
+            &args,
+            "c",
+            identity("222233334444", "Preprod").as_ref()
+        ));
+        // Exclusion wins, whichever field it matches
+        assert!(!account_selected(
+            &args,
+            "d",
+            identity("111122223333", "Sandbox").as_ref()
+        ));
+        assert!(!account_selected(
+            &args,
+            "prod-sandbox",
+            identity("111122223333", "Billing").as_ref()
+        ));
+        // Without an identity only the reference is matched
+        assert!(!account_selected(&args, "e", None));
+
+        let exclude_only =
+            Args::try_parse_from(["cloud-cost-manager", "--exclude-account", "222233334444"])
+                .unwrap();
+        assert!(account_selected(
+            &exclude_only,
+            "a",
+            identity("111122223333", "Billing").as_ref()
+        ));
+        assert!(!account_selected(
+            &exclude_only,
+            "b",
+            identity("222233334444", "Data").as_ref()
+        ));
+    }
+
+    #[test]
+    fn record_types_rank_services_by_net_spend() {
+        let record_types = |split: &[(&str, f64)]| -> BTreeMap<String, f64> {
+            split
+                .iter()
+                .map(|(record_type, amount)| (record_type.to_string(), *amount))
+                .collect()
+        };
+        let groups = BTreeMap::from([
+            (
+                "Amazon EC2".to_string(),
+                record_types(&[("Usage", 100.0), ("Credit", -30.0), ("Tax", 5.0)]),
+            ),
+            ("Amazon S3".to_string(), record_types(&[("Usage", 90.0)])),
+            (
+                "AWS Lambda".to_string(),
+                record_types(&[("Usage", 20.0), ("Refund", -20.0)]),
+            ),
+            (
+                "Amazon RDS".to_string(),
+                record_types(&[("Usage", 40.0), ("SavingsPlanCoveredUsage", 10.0)]),
+            ),
+        ]);
+
+        let top = top_services_by_record_type(groups, 3);
+
+        let ranked: Vec<(&str, f64)> = top
+            .iter()
+            .map(|(service, total, _)| (service.as_str(), *total))
+            .collect();
+        // EC2's credit puts it below S3; Lambda nets to 0 and is cut by the limit
+        assert_eq!(
+            ranked,
+            [
+                ("Amazon S3", 90.0),
+                ("Amazon EC2", 75.0),
+                ("Amazon RDS", 50.0)
+            ]
+        );
+        assert_eq!(
+            record_type_split(&top[1].2, Some("USD")),
+            "Usage $100.00, Tax $5.00, Credit -$30.00"
+        );
+        assert_eq!(
+            record_type_split(&top[1].2, Some("EUR")),
+            "Usage €100.00, Tax €5.00, Credit -€30.00"
+        );
+    }
+
+    #[test]
+    fn columns_keep_only_the_chosen_metrics_in_their_order() {
+        let args = Args::try_parse_from([
+            "cloud-cost-manager",
+            "--metric",
+            "AmortizedCost",
+            "--metric",
+            "UsageQuantity",
+            "--columns",
+            "usage,amortized",
+        ])
+        .unwrap();
+        assert_eq!(args.columns, ["UsageQuantity", "AmortizedCost"]);
+        check_columns(&args).unwrap();
+
+        let metric = |name: &str, total: f64| MetricBreakdown {
+            metric: name.to_string(),
+            total,
+            services: HashMap::new(),
+        };
+        let mut report = fixed_report();
+        report.metrics = vec![
+            metric("AmortizedCost", 120.0),
+            metric("BlendedCost", 125.0),
+            metric("UsageQuantity", 42.0),
+        ];
+        report.summaries[0].metrics = report.metrics.clone();
+        let shown = with_columns(&report, &args.columns);
+        let names = |metrics: &[MetricBreakdown]| -> Vec<String> {
+            metrics.iter().map(|m| m.metric.clone()).collect()
+        };
+        assert_eq!(names(&shown.metrics), ["UsageQuantity", "AmortizedCost"]);
+        assert_eq!(
+            names(&shown.summaries[0].metrics),
+            ["UsageQuantity", "AmortizedCost"]
+        );
+        assert!(shown.summaries[1].metrics.is_empty());
+        assert_eq!(report.metrics.len(), 3);
+
+        let unfetched = Args::try_parse_from([
+            "cloud-cost-manager",
+            "--metric",
+            "AmortizedCost",
+            "--columns",
+            "usage",
+        ])
+        .unwrap();
+        let err = check_columns(&unfetched).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "--columns UsageQuantity is not fetched; add --metric UsageQuantity"
+        );
+        assert!(Args::try_parse_from(["cloud-cost-manager", "--columns", "bogus"]).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Doubled after every failed attempt.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Parses `--webhook-header NAME:VALUE`; the value may itself contain colons.
+pub fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue)> {
+    let (name, header_value) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid header {value:?}; expected NAME:VALUE"))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("Invalid header name in {value:?}"))?;
+    let header_value = HeaderValue::from_str(header_value.trim())
+        .with_context(|| format!("Invalid header value for {name}"))?;
+    Ok((name, header_value))
+}
+
+/// POSTs `body` (the JSON report) to `url`. Connection errors, 429 and 5xx responses are
+/// retried up to `retries` times with exponential backoff; other non-2xx responses fail
+/// straight away, since sending the same request again will not change the answer.
+pub async fn post_json(
+    url: &str,
+    headers: &[(HeaderName, HeaderValue)],
+    body: String,
+    retries: u32,
+) -> Result<()> {
+    let mut header_map = HeaderMap::with_capacity(headers.len() + 1);
+    header_map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    for (name, value) in headers {
+        header_map.insert(name.clone(), value.clone());
+    }
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .context("Failed to build the webhook HTTP client")?;
+
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let error = match client
+            .post(url)
+            .headers(header_map.clone())
+            .body(body.clone())
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => {
+                let status = resp.status();
+                let text = resp.text().await.unwrap_or_default();
+                let error = anyhow!(
+                    "Webhook {url} returned {status}: {}",
+                    text.chars().take(200).collect::<String>()
+                );
+                if !(status.is_server_error() || status.as_u16() == 429) {
+                    return Err(error);
+                }
+                error
+            }
+            Err(err) => anyhow!(err).context(format!("Webhook POST to {url} failed")),
+        };
+
+        if attempt > retries {
+            bail!("{error:#} (after {attempt} attempt(s))");
+        }
+        eprintln!("Warning: {error:#}; retrying in {}s", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
@@ -1,32 +1,430 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use async_trait::async_trait;
-use chrono::{Datelike, Duration, NaiveDate};
-use futures::future::try_join_all;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use futures::future::join_all;
+use regex::Regex;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration as StdDuration;
+use tokio::sync::Semaphore;
+use tokio::time::{Instant, timeout_at};
+use tokio_util::sync::CancellationToken;
 
+pub use chrono_tz::Tz;
+
+pub mod render;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricBreakdown {
+    /// Cost Explorer metric name; see `is_usage_metric` for the ones that are not money
+    pub metric: String,
+    pub total: f64,
+    pub services: HashMap<String, f64>,
+}
+
+/// `DimensionBreakdown::dimension` prefix for cost allocation tag breakdowns ("TAG team").
+pub const TAG_DIMENSION_PREFIX: &str = "TAG ";
+
+/// Bucket in a tag breakdown for spend with no value for the tag.
+pub const UNTAGGED: &str = "Untagged";
+
+/// Spend grouped by a dimension other than SERVICE (e.g. purchase type).
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DimensionBreakdown {
+    pub dimension: String,
+    pub values: HashMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AccountSummary {
     pub account_ref: String,
     pub account_id: String,
     pub account_name: String,
     pub total: f64,
     pub services: HashMap<String, f64>,
+    /// `services` as the provider reported them, before aliases and grouping rules. Only
+    /// when `ReportOptions::service_groups` regrouped them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_services: Option<HashMap<String, f64>>,
+    /// Tax reported apart from `services` and `total` (0 unless the provider separates it)
+    pub tax: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blended: Option<BlendedSplit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub savings_rate: Option<SavingsRate>,
+    /// Per-service `NetAmortizedCost`: RI and Savings Plan fees and discounts spread across
+    /// the services that used them, for unit economics. Only when the provider was asked for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amortized_services: Option<HashMap<String, f64>>,
+    /// Same window measured with additional Cost Explorer metrics, in the requested order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<MetricBreakdown>,
+    /// The first of `metrics`, under the key JSON consumers read before a report could
+    /// carry several. Deprecated in favour of `metrics`; filled in by the report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secondary: Option<MetricBreakdown>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakdowns: Vec<DimensionBreakdown>,
+    /// Spend per availability zone, when the provider was asked for zones; regional and
+    /// global charges are under the provider's no-zone bucket
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub azs: HashMap<String, f64>,
+    /// Amounts the provider could not parse and counted as zero, in this window or the
+    /// previous periods it is compared against
+    pub unparseable_amounts: usize,
+    /// Currency of every amount above (e.g. `USD`); `None` when the provider does not say
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+/// Period-over-period change for one slice of spend.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CostDelta {
+    pub baseline: f64,
+    pub current: f64,
+    pub difference: f64,
+}
+
+/// A usage type, purchase option or similar that explains part of a service's change.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CostDriver {
+    pub service: String,
+    pub driver_type: String,
+    pub name: String,
+    pub difference: f64,
+}
+
+/// Comparison computed by the provider rather than by subtracting our own totals.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NativeComparison {
+    pub total: CostDelta,
+    pub services: HashMap<String, CostDelta>,
+    /// Largest absolute change first
+    pub drivers: Vec<CostDriver>,
+}
+
+/// One service's month-over-month change across all accounts.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServiceDelta {
+    pub service: String,
+    pub previous: f64,
+    pub current: f64,
+    pub delta: f64,
+    /// `None` when the service had no spend in the previous period
+    pub delta_pct: Option<f64>,
+}
+
+/// One group's change against the previous period within a `DimensionDelta`.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GroupDelta {
+    pub group: String,
+    pub previous: f64,
+    pub current: f64,
+    pub delta: f64,
+    /// `None` when the group had no spend in the previous period
+    pub delta_pct: Option<f64>,
+}
+
+/// Per-group change for one breakdown dimension, largest absolute change first.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DimensionDelta {
+    pub dimension: String,
+    pub groups: Vec<GroupDelta>,
+}
+
+/// Blended against unblended cost for the same window. `savings` (blended - unblended)
+/// is how much RI / Savings Plans sharing across the organization moved.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BlendedSplit {
+    pub blended: f64,
+    pub unblended: f64,
+    pub savings: f64,
+}
+
+/// Effective savings rate of commitments (RI, Savings Plans) and discounts: how far net
+/// amortized cost is below an on-demand equivalent. Cost Explorer has no on-demand
+/// equivalent per account, so blended cost stands in for it.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SavingsRate {
+    pub on_demand_equivalent: f64,
+    pub net_amortized: f64,
+    /// `on_demand_equivalent - net_amortized`
+    pub savings: f64,
+    /// `savings` as a percentage of `on_demand_equivalent`; `None` when that is zero
+    pub rate_pct: Option<f64>,
+}
+
+impl SavingsRate {
+    pub fn new(on_demand_equivalent: f64, net_amortized: f64) -> Self {
+        let savings = on_demand_equivalent - net_amortized;
+        Self {
+            on_demand_equivalent,
+            net_amortized,
+            savings,
+            rate_pct: (on_demand_equivalent.abs() >= f64::EPSILON)
+                .then(|| savings / on_demand_equivalent * 100.0),
+        }
+    }
 }
 
+/// An account left out of the report, with the reason.
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AccountError {
+    pub account_ref: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Report {
+    /// When the report was computed; a cached report can be older than its window suggests
+    pub generated_at: DateTime<Utc>,
+    /// Version of the tool that produced the report
+    pub tool_version: &'static str,
     pub month_start: NaiveDate,
     pub month_end_exclusive: NaiveDate,
     pub prev_start: NaiveDate,
     pub prev_end_exclusive: NaiveDate,
     pub summaries: Vec<AccountSummary>,
-    pub total_all: f64,
+    /// Sum over `summaries`; `None` when they are billed in different currencies, which are
+    /// totalled separately in `currency_subtotals`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_all: Option<f64>,
+    /// Currency of every total, when the provider reports one and it is the same for all
+    /// accounts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// One total per currency when accounts are billed in more than one, by currency code
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub currency_subtotals: Vec<CurrencySubtotal>,
     pub services_total: HashMap<String, f64>,
+    /// Sum of `AccountSummary::tax`, not included in `total_all`
+    pub tax_total: f64,
+    /// Summed over accounts; only reported when every account has a split
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blended: Option<BlendedSplit>,
+    /// Summed over accounts, so the rate is weighted by spend; only reported when every
+    /// account has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub savings_rate: Option<SavingsRate>,
     pub top_services: Vec<(String, f64)>,
-    pub prev_total: f64,
-    pub delta: f64,
-    pub delta_pct: f64,
+    /// Largest credits and refunds, most negative first: per service, the sum of the negative
+    /// per-account amounts. Only with `ReportOptions::top_credits`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub top_credits: Vec<(String, f64)>,
+    /// Number of periods `prev_total` averages, the latest being `prev_start` to
+    /// `prev_end_exclusive`; `None` for a plain comparison with the previous period
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_periods: Option<usize>,
+    /// Comparison fields are `None` when `ReportOptions::compare_previous` is off
+    pub prev_total: Option<f64>,
+    pub delta: Option<f64>,
+    pub delta_pct: Option<f64>,
+    /// Services billed this month but not in the previous period
+    pub new_services: Vec<String>,
+    /// Services billed in the previous period but not this month
+    pub removed_services: Vec<String>,
+    /// Per-service change against the previous period, largest absolute change first
+    pub service_deltas: Vec<ServiceDelta>,
+    /// Days of current-month data behind the comparison
+    pub days_elapsed: i64,
+    /// `total_all / days_elapsed`; `None` when no days have elapsed
+    pub daily_run_rate: Option<f64>,
+    /// Naive month-end estimate: `daily_run_rate` times the days in the month
+    pub projected_month_total: Option<f64>,
+    /// Month-end estimate from complete days, with `ReportOptions::prorate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prorated_projection: Option<ProratedProjection>,
+    /// Set when comparing and `days_elapsed` is below `ReportOptions::min_comparison_days`
+    pub low_confidence: bool,
+    /// Per-metric totals for the additional metrics every account reported
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<MetricBreakdown>,
+    /// The first of `metrics`, kept for JSON consumers of the single-metric report.
+    /// Deprecated in favour of `metrics`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secondary: Option<MetricBreakdown>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakdowns: Vec<DimensionBreakdown>,
+    /// The `UNTAGGED` bucket of the tag breakdown, when there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untagged_spend: Option<f64>,
+    /// `untagged_spend` as a percentage of the tag breakdown's total
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untagged_pct: Option<f64>,
+    /// `breakdowns` against the previous period, when `ReportOptions::compare_breakdowns`
+    /// is on and every account's previous breakdowns were fetched
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakdown_deltas: Vec<DimensionDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overhead: Option<OverheadSplit>,
+    /// Accounts below `ReportOptions::min_account_spend`, left out of `summaries`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_accounts: Option<SmallAccounts>,
+    /// Accounts missing from `summaries` and every total
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<AccountError>,
+    /// Totals that do not add up to the sum of their parts (an error instead under
+    /// `ReportOptions::strict`) and NaN or infinite amounts that were counted as 0
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Provider-computed comparison, when requested and available for every account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub native_comparison: Option<NativeComparison>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl AccountSummary {
+    /// An account named after `account_ref`, spending `services` and nothing else.
+    pub fn for_test<S: Into<String>>(
+        account_ref: &str,
+        services: impl IntoIterator<Item = (S, f64)>,
+    ) -> Self {
+        let services: HashMap<String, f64> = services
+            .into_iter()
+            .map(|(svc, amt)| (svc.into(), amt))
+            .collect();
+        Self {
+            account_ref: account_ref.to_string(),
+            account_id: account_ref.to_string(),
+            account_name: account_ref.to_string(),
+            total: services.values().sum(),
+            services,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Report {
+    /// A report on `summaries` over August 1–14, 2024, generated the morning after, with the
+    /// totals summed from them and no comparison.
+    pub fn for_test(summaries: Vec<AccountSummary>) -> Self {
+        use chrono::TimeZone;
+
+        let day = |d| NaiveDate::from_ymd_opt(2024, 8, d).unwrap();
+        let mut services_total: HashMap<String, f64> = HashMap::new();
+        for s in &summaries {
+            for (svc, amt) in &s.services {
+                *services_total.entry(svc.clone()).or_insert(0.0) += amt;
+            }
+        }
+        let mut top_services: Vec<(String, f64)> = services_total
+            .iter()
+            .map(|(svc, amt)| (svc.clone(), *amt))
+            .collect();
+        top_services.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let (currency, currency_subtotals) = split_currencies(&summaries);
+        Self {
+            generated_at: Utc.with_ymd_and_hms(2024, 8, 15, 6, 0, 0).unwrap(),
+            tool_version: "1.2.3",
+            month_start: day(1),
+            month_end_exclusive: day(15),
+            prev_start: day(1) - Duration::days(31),
+            prev_end_exclusive: day(15) - Duration::days(31),
+            total_all: currency_subtotals
+                .is_empty()
+                .then(|| summaries.iter().map(|s| s.total).sum()),
+            currency,
+            currency_subtotals,
+            services_total,
+            top_services,
+            days_elapsed: 14,
+            summaries,
+            ..Self::default()
+        }
+    }
+}
+
+/// Month-end estimate that leaves out the last day of the window, which is still accruing
+/// in a month-to-date report.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProratedProjection {
+    /// Days of the window before the last one
+    pub complete_days: i64,
+    /// Spend on the complete days
+    pub complete_total: f64,
+    /// `complete_total / complete_days`
+    pub complete_daily_rate: f64,
+    /// `complete_total` plus `complete_daily_rate` for every remaining day of the month
+    pub prorated_total: f64,
+    /// `complete_total` plus the provider's forecast for the rest of the month, with
+    /// `ReportOptions::blend_forecast`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forecast_total: Option<f64>,
+    /// The average of `prorated_total` and `forecast_total`, or `prorated_total` alone
+    pub estimate: f64,
+}
+
+/// Spend of the accounts billed in one currency, in a report that mixes currencies.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CurrencySubtotal {
+    pub currency: String,
+    pub total: f64,
+    pub account_refs: Vec<String>,
+}
+
+/// Accounts left out of `Report::summaries` for spending less than
+/// `ReportOptions::min_account_spend`.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SmallAccounts {
+    pub account_refs: Vec<String>,
+    /// Their combined spend
+    pub total: f64,
+    /// Whether they are still in `total_all` and the other report totals
+    pub counted_in_total: bool,
+}
+
+/// Order of `Report::summaries`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountOrder {
+    /// As the accounts were given
+    #[default]
+    Input,
+    /// Highest spend first
+    Spend,
+    /// Alphabetical by account name
+    Name,
+}
+
+impl Report {
+    /// Reorders `summaries` for display. Ties fall back to account name, then account
+    /// reference, so the order is the same on every run.
+    pub fn sort_accounts(&mut self, order: AccountOrder) {
+        let by_name = |a: &AccountSummary, b: &AccountSummary| {
+            a.account_name
+                .cmp(&b.account_name)
+                .then_with(|| a.account_ref.cmp(&b.account_ref))
+        };
+        match order {
+            AccountOrder::Input => {}
+            AccountOrder::Spend => self.summaries.sort_by(|a, b| {
+                b.total
+                    .partial_cmp(&a.total)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| by_name(a, b))
+            }),
+            AccountOrder::Name => self.summaries.sort_by(by_name),
+        }
+    }
 }
 
 #[async_trait]
@@ -38,12 +436,293 @@ pub trait CostProvider: Send + Sync {
         end_exclusive: NaiveDate,
     ) -> Result<AccountSummary>;
 
-    async fn total_cost(
+    /// Summaries of several accounts over one window, one outcome per account in `accounts`
+    /// order; `Ok(None)` means the report's deadline passed first. Reports always fetch
+    /// through here. The default fetches each account on its own under `limiter`; providers
+    /// with a batch API (one query covering every account) override it and run that query
+    /// through `SummaryLimiter::batch` instead.
+    async fn fetch_all_summaries(
+        &self,
+        accounts: &[String],
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+        limiter: &SummaryLimiter<'_>,
+    ) -> Vec<Result<Option<AccountSummary>>> {
+        limiter
+            .each(accounts, |account_ref| {
+                self.fetch_account_summary(account_ref, start, end_exclusive)
+            })
+            .await
+    }
+
+    async fn service_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<HashMap<String, f64>>;
+
+    /// `service_costs` along with how many amounts the provider could not parse and counted
+    /// as zero. Reports use it for previous periods, so those amounts show up in
+    /// `AccountSummary::unparseable_amounts` too.
+    async fn checked_service_costs(
         &self,
         account_ref: &str,
         start: NaiveDate,
         end_exclusive: NaiveDate,
-    ) -> Result<f64>;
+    ) -> Result<(HashMap<String, f64>, usize)> {
+        let services = self
+            .service_costs(account_ref, start, end_exclusive)
+            .await?;
+        Ok((services, 0))
+    }
+
+    /// The extra groupings `fetch_account_summary` reports in `AccountSummary::breakdowns`,
+    /// for another window. Providers without groupings return none.
+    async fn breakdowns(
+        &self,
+        _account_ref: &str,
+        _start: NaiveDate,
+        _end_exclusive: NaiveDate,
+    ) -> Result<Vec<DimensionBreakdown>> {
+        Ok(Vec::new())
+    }
+
+    /// Provider-computed comparison of `baseline` against `current` (start, end-exclusive).
+    /// `None` means the provider cannot compute it and callers fall back to subtracting totals.
+    async fn native_comparison(
+        &self,
+        _account_ref: &str,
+        _baseline: (NaiveDate, NaiveDate),
+        _current: (NaiveDate, NaiveDate),
+    ) -> Result<Option<NativeComparison>> {
+        Ok(None)
+    }
+
+    /// Spend per day in the window, for `ReportOptions::prorate`. `None` means the provider
+    /// has no daily data.
+    async fn daily_costs(
+        &self,
+        _account_ref: &str,
+        _start: NaiveDate,
+        _end_exclusive: NaiveDate,
+    ) -> Result<Option<BTreeMap<NaiveDate, f64>>> {
+        Ok(None)
+    }
+
+    /// The provider's own forecast of spend in the (future) window, for
+    /// `ReportOptions::blend_forecast`; the window starts on the report's day in progress.
+    /// `None` means the provider cannot forecast.
+    async fn forecast(
+        &self,
+        _account_ref: &str,
+        _start: NaiveDate,
+        _end_exclusive: NaiveDate,
+    ) -> Result<Option<f64>> {
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    /// Month-over-month comparisons backed by fewer days are flagged as low confidence
+    pub min_comparison_days: i64,
+    /// When set, these services are reported as overhead and left out of `top_services`
+    pub overhead_services: Option<Vec<String>>,
+    /// Overall time budget; accounts still running when it passes are reported as timed out
+    pub deadline: Option<StdDuration>,
+    /// Stops the report's provider calls when cancelled (e.g. its requester went away); the
+    /// report then fails instead of being partial
+    pub cancel: Option<CancellationToken>,
+    /// Most provider calls in flight at once, across both windows; unlimited when `None`
+    pub max_concurrency: Option<usize>,
+    /// Abort on the first failed or timed-out account instead of reporting on the rest
+    pub fail_on_error: bool,
+    /// Fetch the previous period and compare against it; off halves the Cost Explorer calls
+    pub compare_previous: bool,
+    /// Compare against the average of this many previous periods (the previous window and
+    /// the same window 1..N-1 months before it) instead of the previous period alone; each
+    /// extra period costs one more per-account query
+    pub baseline_periods: usize,
+    /// Also fetch the previous period's breakdowns and report per-group deltas
+    pub compare_breakdowns: bool,
+    /// Ask the provider for its own comparison and cost drivers
+    pub native_comparison: bool,
+    /// Provider service name -> short name; names mapping to the same alias are summed
+    pub service_aliases: Option<HashMap<String, String>>,
+    /// Rules folding services into custom groups after aliasing; the first match wins
+    pub service_groups: Vec<ServiceGroupRule>,
+    /// Services below this are summed into one `OTHER_SERVICES` entry
+    pub other_threshold: Option<OtherThreshold>,
+    /// Also report the largest credits and refunds as `Report::top_credits`
+    pub top_credits: bool,
+    /// Called as each account's current-period summary completes (successfully or not)
+    pub progress: Option<Progress>,
+    /// Fail when the totals do not add up instead of reporting it in `Report::warnings`
+    pub strict: bool,
+    /// Accounts spending less than this are left out of `summaries` (see `Report::small_accounts`)
+    pub min_account_spend: Option<f64>,
+    /// Keep accounts below `min_account_spend` in every total; otherwise they are dropped
+    /// before the previous period is fetched and count nowhere
+    pub count_small_accounts: bool,
+    /// Also project the month from complete days only (`Report::prorated_projection`); one
+    /// daily query per account
+    pub prorate: bool,
+    /// Blend the prorated projection with the provider's forecast; one forecast query per
+    /// account
+    pub blend_forecast: bool,
+}
+
+/// Progress callback for `ReportOptions::progress`, given (accounts done, accounts in total).
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(usize, usize) + Send + Sync>);
+
+impl Progress {
+    pub fn new(callback: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Name of the entry that collects services below `ReportOptions::other_threshold`.
+pub const OTHER_SERVICES: &str = "Other";
+
+/// Cut-off below which a service is folded into `OTHER_SERVICES`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtherThreshold {
+    /// Dollars of spend across all accounts
+    Amount(f64),
+    /// Percentage of `Report::total_all`
+    Percent(f64),
+}
+
+impl OtherThreshold {
+    fn limit(self, total_all: f64) -> f64 {
+        match self {
+            OtherThreshold::Amount(amount) => amount,
+            OtherThreshold::Percent(pct) => total_all.abs() * pct / 100.0,
+        }
+    }
+}
+
+/// Parses `--other-threshold`: dollars (`25`, `$25`) or a percentage of the total (`2%`).
+pub fn parse_other_threshold(value: &str) -> Result<OtherThreshold> {
+    let value = value.trim();
+    let (number, percent) = match value.strip_suffix('%') {
+        Some(number) => (number, true),
+        None => (value.strip_prefix('$').unwrap_or(value), false),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite() && *n >= 0.0)
+        .ok_or_else(|| {
+            anyhow!("Invalid threshold {value:?}; expected dollars (25) or a percentage (2%)")
+        })?;
+    Ok(if percent {
+        OtherThreshold::Percent(number)
+    } else {
+        OtherThreshold::Amount(number)
+    })
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self {
+            min_comparison_days: 3,
+            overhead_services: None,
+            deadline: None,
+            cancel: None,
+            max_concurrency: None,
+            fail_on_error: false,
+            compare_previous: true,
+            baseline_periods: 1,
+            compare_breakdowns: false,
+            native_comparison: false,
+            service_aliases: None,
+            service_groups: Vec::new(),
+            other_threshold: None,
+            top_credits: false,
+            progress: None,
+            strict: false,
+            min_account_spend: None,
+            count_small_accounts: false,
+            prorate: false,
+            blend_forecast: false,
+        }
+    }
+}
+
+/// Short names for common AWS services, as shown in the console.
+pub const DEFAULT_SERVICE_ALIASES: &[(&str, &str)] = &[
+    ("Amazon Elastic Compute Cloud - Compute", "EC2"),
+    ("EC2 - Other", "EC2-Other"),
+    ("Amazon Simple Storage Service", "S3"),
+    ("Amazon Relational Database Service", "RDS"),
+    ("Amazon DynamoDB", "DynamoDB"),
+    ("AWS Lambda", "Lambda"),
+    ("Amazon CloudFront", "CloudFront"),
+    ("Amazon Virtual Private Cloud", "VPC"),
+    ("Amazon Elastic Load Balancing", "ELB"),
+    ("Amazon Elastic Container Service", "ECS"),
+    ("Amazon Elastic Container Service for Kubernetes", "EKS"),
+    ("Amazon Elastic Kubernetes Service", "EKS"),
+    ("Amazon EC2 Container Registry (ECR)", "ECR"),
+    ("Amazon ElastiCache", "ElastiCache"),
+    ("Amazon Elastic File System", "EFS"),
+    ("Amazon OpenSearch Service", "OpenSearch"),
+    ("Amazon Redshift", "Redshift"),
+    ("AmazonCloudWatch", "CloudWatch"),
+    ("Amazon Route 53", "Route 53"),
+    ("Amazon Simple Queue Service", "SQS"),
+    ("Amazon Simple Notification Service", "SNS"),
+    ("AWS Key Management Service", "KMS"),
+    ("AWS Secrets Manager", "Secrets Manager"),
+];
+
+/// Folds every service whose (aliased) name matches `pattern` into `group`.
+#[derive(Debug, Clone)]
+pub struct ServiceGroupRule {
+    pub pattern: Regex,
+    pub group: String,
+}
+
+pub fn default_service_aliases() -> HashMap<String, String> {
+    DEFAULT_SERVICE_ALIASES
+        .iter()
+        .map(|(name, alias)| (name.to_string(), alias.to_string()))
+        .collect()
+}
+
+/// Support plans and tax: charges that are not driven by resource usage.
+pub const DEFAULT_OVERHEAD_SERVICES: &[&str] = &[
+    "AWS Support (Basic)",
+    "AWS Support (Developer)",
+    "AWS Support (Business)",
+    "AWS Support (Enterprise On-Ramp)",
+    "AWS Support (Enterprise)",
+    "Tax",
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OverheadSplit {
+    pub usage_total: f64,
+    pub overhead_total: f64,
+    pub overhead_services: HashMap<String, f64>,
+}
+
+/// JSON Schema of `Report` (and the `AccountSummary` entries inside it) as serialized by
+/// `render::json` with dollar amounts.
+#[cfg(feature = "schema")]
+pub fn report_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(Report)
 }
 
 pub async fn generate_report<P: CostProvider>(
@@ -51,88 +730,2421 @@ pub async fn generate_report<P: CostProvider>(
     accounts: &[String],
     today: NaiveDate,
 ) -> Result<Report> {
-    let (month_start, month_end_exclusive) = month_to_date(today);
-    let (prev_start, prev_end_exclusive) = previous_month_same_point(today)?;
+    generate_report_with_options(provider, accounts, today, &ReportOptions::default()).await
+}
 
-    let summaries = try_join_all(accounts.iter().map(|account_ref| async move {
-        provider
-            .fetch_account_summary(account_ref, month_start, month_end_exclusive)
-            .await
-    }))
-    .await?;
+pub async fn generate_report_with_options<P: CostProvider>(
+    provider: &P,
+    accounts: &[String],
+    today: NaiveDate,
+    options: &ReportOptions,
+) -> Result<Report> {
+    let current = month_to_date(today);
+    let previous = previous_month_same_point(today)?;
+    generate_report_for_windows(provider, accounts, current, previous, options).await
+}
+
+/// Report on explicit (start, end-exclusive) windows, e.g. a whole past month against the
+/// month before it.
+pub async fn generate_report_for_windows<P: CostProvider>(
+    provider: &P,
+    accounts: &[String],
+    current: (NaiveDate, NaiveDate),
+    previous: (NaiveDate, NaiveDate),
+    options: &ReportOptions,
+) -> Result<Report> {
+    let report = report_for_windows(provider, accounts, current, previous, options);
+    let Some(cancel) = &options.cancel else {
+        return report.await;
+    };
+    // Dropping the report future drops every provider call still in flight
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => bail!("Report cancelled"),
+        report = report => report,
+    }
+}
+
+async fn report_for_windows<P: CostProvider>(
+    provider: &P,
+    accounts: &[String],
+    current: (NaiveDate, NaiveDate),
+    previous: (NaiveDate, NaiveDate),
+    options: &ReportOptions,
+) -> Result<Report> {
+    if accounts.is_empty() {
+        bail!("No accounts configured; nothing to report on");
+    }
+
+    let (month_start, month_end_exclusive) = current;
+    let (prev_start, prev_end_exclusive) = previous;
+    validate_window(month_start, month_end_exclusive)?;
+    validate_window(prev_start, prev_end_exclusive)?;
+    let baseline = baseline_windows(previous, options.baseline_periods)?;
+
+    let limits = FetchLimits::new(options);
+
+    let limiter = SummaryLimiter {
+        limits: Some(&limits),
+        progress: options.progress.as_ref(),
+        done: AtomicUsize::new(0),
+    };
+    let current = provider
+        .fetch_all_summaries(accounts, month_start, month_end_exclusive, &limiter)
+        .await;
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut completed = Vec::with_capacity(current.len());
+    for (account_ref, outcome) in accounts.iter().zip(current) {
+        if let Some(summary) = collect_outcome(account_ref, outcome, options, &mut errors)? {
+            completed.push(summary);
+        }
+    }
+
+    let mut small: Vec<AccountSummary> = Vec::new();
+    if let Some(min) = options.min_account_spend {
+        // NaN totals are kept and replaced by the sum of their services with a warning
+        let (kept, below): (Vec<_>, Vec<_>) = completed
+            .into_iter()
+            .partition(|s| s.total.is_nan() || s.total >= min);
+        // Counted small accounts still make a report: its totals, with no account listed
+        if kept.is_empty() && !below.is_empty() && !options.count_small_accounts {
+            let threshold = match split_currencies(&below) {
+                (currency, subtotals) if subtotals.is_empty() => {
+                    render::money(min, currency.as_deref())
+                }
+                _ => format!("{min:.2}"),
+            };
+            warnings.push(format!(
+                "No account spent at least {threshold}; all {} are left out as small accounts",
+                below.len()
+            ));
+        }
+        completed = if options.count_small_accounts {
+            // Reported on like any other account, then left out of `summaries` at the end
+            kept.into_iter().chain(below.iter().cloned()).collect()
+        } else {
+            kept
+        };
+        small = below;
+    }
+
+    // Previous-period totals only cover accounts that made it into this period.
+    let mut summaries = Vec::with_capacity(completed.len());
+    let mut prev_services: Option<HashMap<String, f64>> = None;
+    if options.compare_previous {
+        let previous = join_all(completed.iter().map(|summary| {
+            let periods: Vec<_> = baseline
+                .iter()
+                .map(|&(start, end_exclusive)| {
+                    limits.run(provider.checked_service_costs(
+                        &summary.account_ref,
+                        start,
+                        end_exclusive,
+                    ))
+                })
+                .collect();
+            async move { average_periods(join_all(periods).await) }
+        }))
+        .await;
+
+        let prev = prev_services.insert(HashMap::new());
+        for (mut summary, outcome) in completed.into_iter().zip(previous) {
+            let Some((services, unparseable)) =
+                collect_outcome(&summary.account_ref, outcome, options, &mut errors)?
+            else {
+                continue;
+            };
+            summary.unparseable_amounts += unparseable;
+            for (svc, amt) in services {
+                let amt = finite_or_zero(
+                    amt,
+                    || format!("{} previous-period {svc}", summary.account_ref),
+                    &mut warnings,
+                );
+                *prev.entry(service_alias(options, svc)).or_insert(0.0) += amt;
+            }
+            summaries.push(alias_summary(summary, options));
+        }
+    } else {
+        summaries.extend(
+            completed
+                .into_iter()
+                .map(|summary| alias_summary(summary, options)),
+        );
+    }
+
+    // Accounts left out as small still make a report, with the warning that says so
+    if summaries.is_empty() && small.is_empty() {
+        let reasons: Vec<String> = errors
+            .iter()
+            .map(|e| format!("{}: {}", e.account_ref, e.error))
+            .collect();
+        bail!("No account could be reported on ({})", reasons.join("; "));
+    }
+
+    for s in &mut summaries {
+        s.tax = finite_or_zero(s.tax, || format!("{} tax", s.account_ref), &mut warnings);
+        for (svc, amt) in &mut s.services {
+            *amt = finite_or_zero(*amt, || format!("{} {svc}", s.account_ref), &mut warnings);
+        }
+        // Usually NaN because one of the services was; their sum keeps the two consistent
+        if !s.total.is_finite() {
+            warnings.push(format!(
+                "{} total was {}; counted as the sum of its services",
+                s.account_ref, s.total
+            ));
+            s.total = s.services.values().sum();
+        }
+    }
+
+    let (currency, currency_subtotals) = split_currencies(&summaries);
+    // Amounts in different currencies cannot be added up, so nothing derived from the
+    // cross-account total is reported
+    let mixed_currencies = !currency_subtotals.is_empty();
+    if mixed_currencies {
+        let codes: Vec<&str> = currency_subtotals
+            .iter()
+            .map(|c| c.currency.as_str())
+            .collect();
+        warnings.push(format!(
+            "Accounts are billed in different currencies ({}): totals are given per currency, \
+             with no comparison or projection, and per-service totals add up amounts in \
+             different currencies",
+            codes.join(", ")
+        ));
+    }
 
     let mut total_all = 0.0_f64;
     let mut services_total: HashMap<String, f64> = HashMap::new();
+    // Negative amounts alone, so a refund is not hidden by the same service's spend elsewhere
+    let mut credits_total: HashMap<String, f64> = HashMap::new();
+    let mut tax_total = 0.0_f64;
 
     for s in &summaries {
         total_all += s.total;
+        tax_total += s.tax;
         for (svc, amt) in &s.services {
             *services_total.entry(svc.clone()).or_insert(0.0) += *amt;
+            if *amt < 0.0 {
+                *credits_total.entry(svc.clone()).or_insert(0.0) += *amt;
+            }
+        }
+    }
+
+    let overhead = options
+        .overhead_services
+        .as_ref()
+        .map(|names| split_overhead(&services_total, names));
+
+    let metrics = aggregate_metrics(&summaries);
+    let blended = summaries
+        .iter()
+        .map(|s| s.blended)
+        .sum::<Option<BlendedSplit>>();
+    let savings_rate = summaries
+        .iter()
+        .map(|s| s.savings_rate)
+        .sum::<Option<SavingsRate>>();
+    let breakdowns = aggregate_breakdowns(summaries.iter().flat_map(|s| &s.breakdowns));
+    let untagged = breakdowns
+        .iter()
+        .find(|b| b.dimension.starts_with(TAG_DIMENSION_PREFIX))
+        .map(|b| {
+            let untagged = b.values.get(UNTAGGED).copied().unwrap_or(0.0);
+            let tagged_total: f64 = b.values.values().sum();
+            let pct = if tagged_total.abs() < f64::EPSILON {
+                0.0
+            } else {
+                untagged / tagged_total * 100.0
+            };
+            (untagged, pct)
+        });
+
+    let prev_total: Option<f64> = prev_services
+        .as_ref()
+        .filter(|_| !mixed_currencies)
+        .map(|prev| prev.values().sum());
+
+    let mut new_services: Vec<String> = Vec::new();
+    let mut removed_services: Vec<String> = Vec::new();
+    let mut changes = Vec::new();
+    if let Some(prev_services) = &prev_services {
+        new_services = services_total
+            .keys()
+            .filter(|svc| !prev_services.contains_key(*svc))
+            .cloned()
+            .collect();
+        new_services.sort();
+        removed_services = prev_services
+            .keys()
+            .filter(|svc| !services_total.contains_key(*svc))
+            .cloned()
+            .collect();
+        removed_services.sort();
+        changes = service_deltas(&services_total, prev_services);
+    }
+
+    // Collapsed after the comparison so new, removed and changed services keep their names.
+    if let Some(threshold) = options.other_threshold {
+        let small = small_services(&services_total, threshold.limit(total_all), &overhead);
+        collapse_services(&mut services_total, &small);
+        for s in &mut summaries {
+            collapse_services(&mut s.services, &small);
         }
     }
 
+    // Checked on the services as reported: after aliasing, grouping and the "Other" collapse
+    let mismatches = totals_mismatches(&summaries, &services_total, total_all);
+    if options.strict && !mismatches.is_empty() {
+        bail!("Report totals do not add up: {}", mismatches.join("; "));
+    }
+    warnings.extend(mismatches);
+
     let mut top_services: Vec<(String, f64)> = services_total
         .iter()
+        .filter(|(k, _)| {
+            overhead
+                .as_ref()
+                .is_none_or(|o| !o.overhead_services.contains_key(*k))
+        })
         .map(|(k, v)| (k.clone(), *v))
         .collect();
     top_services.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
     top_services.truncate(5);
 
-    let prev_total =
-        total_for_all_accounts(provider, accounts, prev_start, prev_end_exclusive).await?;
+    let mut top_credits: Vec<(String, f64)> = if options.top_credits {
+        credits_total.into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    top_credits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    top_credits.truncate(5);
+
+    let delta = prev_total
+        .map(|prev| finite_or_zero(total_all - prev, || "delta".to_string(), &mut warnings));
+    let delta_pct = prev_total.map(|prev| {
+        if prev.abs() < f64::EPSILON {
+            0.0
+        } else {
+            finite_or_zero(
+                ((total_all - prev) / prev) * 100.0,
+                || "delta_pct".to_string(),
+                &mut warnings,
+            )
+        }
+    });
+
+    let native_comparison =
+        if options.native_comparison && options.compare_previous && !mixed_currencies {
+            native_comparison_for_all(
+                provider,
+                &summaries,
+                (prev_start, prev_end_exclusive),
+                (month_start, month_end_exclusive),
+                &limits,
+                options,
+                &mut warnings,
+            )
+            .await?
+        } else {
+            None
+        }
+        .map(|comparison| alias_comparison(comparison, options));
+
+    let breakdown_deltas =
+        if options.compare_breakdowns && options.compare_previous && !breakdowns.is_empty() {
+            previous_breakdowns(
+                provider,
+                &summaries,
+                (prev_start, prev_end_exclusive),
+                &limits,
+                options,
+                &mut warnings,
+            )
+            .await?
+            .map(|previous| breakdown_deltas(&breakdowns, &previous))
+            .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
 
-    let delta = total_all - prev_total;
-    let delta_pct = if prev_total.abs() < f64::EPSILON {
-        0.0
+    let days_elapsed = (month_end_exclusive - month_start).num_days();
+    let days_in_month = (month_start + chrono::Months::new(1) - month_start).num_days();
+    let daily_run_rate =
+        (days_elapsed > 0 && !mixed_currencies).then(|| total_all / days_elapsed as f64);
+    let projected_month_total = daily_run_rate.map(|rate| rate * days_in_month as f64);
+    let low_confidence = options.compare_previous && days_elapsed < options.min_comparison_days;
+    let prorated_projection = if options.prorate && !mixed_currencies {
+        prorated_projection(
+            provider,
+            &summaries,
+            (month_start, month_end_exclusive),
+            options.blend_forecast,
+            &limits,
+            &mut warnings,
+        )
+        .await
     } else {
-        (delta / prev_total) * 100.0
+        None
     };
 
+    let small_accounts = (!small.is_empty()).then(|| {
+        if options.count_small_accounts {
+            // Counted accounts keep the amounts the totals were built from; any that failed
+            // in the previous period are already in `errors`
+            let small_refs: HashSet<&str> = small.iter().map(|s| s.account_ref.as_str()).collect();
+            let (below, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut summaries)
+                .into_iter()
+                .partition(|s| small_refs.contains(s.account_ref.as_str()));
+            summaries = kept;
+            small = below;
+        }
+        SmallAccounts {
+            account_refs: small.iter().map(|s| s.account_ref.clone()).collect(),
+            total: small.iter().map(|s| s.total).sum(),
+            counted_in_total: options.count_small_accounts,
+        }
+    });
+
     Ok(Report {
+        generated_at: Utc::now(),
+        tool_version: env!("CARGO_PKG_VERSION"),
         month_start,
         month_end_exclusive,
         prev_start,
         prev_end_exclusive,
         summaries,
-        total_all,
+        total_all: (!mixed_currencies).then_some(total_all),
+        currency,
+        currency_subtotals,
         services_total,
+        tax_total,
+        blended,
+        savings_rate,
         top_services,
+        top_credits,
+        baseline_periods: (options.compare_previous && baseline.len() > 1)
+            .then_some(baseline.len()),
         prev_total,
         delta,
         delta_pct,
+        new_services,
+        removed_services,
+        service_deltas: changes,
+        days_elapsed,
+        daily_run_rate,
+        projected_month_total,
+        prorated_projection,
+        low_confidence,
+        secondary: metrics.first().cloned(),
+        metrics,
+        breakdowns,
+        untagged_spend: untagged.map(|(amount, _)| amount),
+        untagged_pct: untagged.map(|(_, pct)| pct),
+        breakdown_deltas,
+        overhead,
+        small_accounts,
+        errors,
+        native_comparison,
+        warnings,
     })
 }
 
-async fn total_for_all_accounts<P: CostProvider>(
+/// Projects the month from the complete days of `window` (all but its last day), summed over
+/// `summaries`. `None`, with a warning when data is missing, if there is nothing to project:
+/// no complete day yet, no days left in the month, or a provider without daily data.
+async fn prorated_projection<P: CostProvider>(
     provider: &P,
-    accounts: &[String],
-    start: NaiveDate,
-    end_exclusive: NaiveDate,
-) -> Result<f64> {
-    let totals = try_join_all(accounts.iter().map(|account_ref| async move {
-        provider.total_cost(account_ref, start, end_exclusive).await
-    }))
-    .await?;
+    summaries: &[AccountSummary],
+    window: (NaiveDate, NaiveDate),
+    blend_forecast: bool,
+    limits: &FetchLimits,
+    warnings: &mut Vec<String>,
+) -> Option<ProratedProjection> {
+    let (start, end_exclusive) = window;
+    let last_day = end_exclusive - Duration::days(1);
+    let month_end_exclusive = start + chrono::Months::new(1);
+    let days_in_month = (month_end_exclusive - start).num_days();
+    let complete_days = (last_day - start).num_days();
+    if complete_days < 1 || end_exclusive >= month_end_exclusive {
+        return None;
+    }
+
+    let daily = join_all(
+        summaries
+            .iter()
+            .map(|s| limits.run(provider.daily_costs(&s.account_ref, start, end_exclusive))),
+    )
+    .await;
+    let mut complete_total = 0.0;
+    for (s, outcome) in summaries.iter().zip(daily) {
+        match outcome {
+            Ok(Some(Some(days))) => {
+                complete_total += days
+                    .range(..last_day)
+                    .map(|(_, amount)| amount)
+                    .filter(|amount| amount.is_finite())
+                    .sum::<f64>();
+            }
+            Ok(Some(None)) => return None,
+            Ok(None) => {
+                warnings.push(format!(
+                    "prorated projection skipped: {} timed out",
+                    s.account_ref
+                ));
+                return None;
+            }
+            Err(err) => {
+                warnings.push(format!(
+                    "prorated projection skipped: {}: {err:#}",
+                    s.account_ref
+                ));
+                return None;
+            }
+        }
+    }
 
-    Ok(totals.into_iter().sum())
+    let complete_daily_rate = complete_total / complete_days as f64;
+    let prorated_total =
+        complete_total + complete_daily_rate * (days_in_month - complete_days) as f64;
+
+    let forecast_total = if blend_forecast {
+        forecast_remaining(
+            provider,
+            summaries,
+            (last_day, month_end_exclusive),
+            limits,
+            warnings,
+        )
+        .await
+        .map(|remaining| complete_total + remaining)
+    } else {
+        None
+    };
+
+    Some(ProratedProjection {
+        complete_days,
+        complete_total,
+        complete_daily_rate,
+        prorated_total,
+        forecast_total,
+        estimate: forecast_total
+            .map_or(prorated_total, |forecast| (prorated_total + forecast) / 2.0),
+    })
 }
 
-fn month_to_date(today: NaiveDate) -> (NaiveDate, NaiveDate) {
-    let start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
-    let end_exclusive = today + Duration::days(1);
-    (start, end_exclusive)
+/// The provider's forecast for `window` summed over `summaries`; `None` unless every account
+/// has one.
+async fn forecast_remaining<P: CostProvider>(
+    provider: &P,
+    summaries: &[AccountSummary],
+    window: (NaiveDate, NaiveDate),
+    limits: &FetchLimits,
+    warnings: &mut Vec<String>,
+) -> Option<f64> {
+    let (start, end_exclusive) = window;
+    let forecasts = join_all(
+        summaries
+            .iter()
+            .map(|s| limits.run(provider.forecast(&s.account_ref, start, end_exclusive))),
+    )
+    .await;
+    let mut remaining = 0.0;
+    for (s, outcome) in summaries.iter().zip(forecasts) {
+        match outcome {
+            Ok(Some(Some(amount))) if amount.is_finite() => remaining += amount,
+            Ok(Some(_)) => return None,
+            Ok(None) => {
+                warnings.push(format!(
+                    "forecast left out of the projection: {} timed out",
+                    s.account_ref
+                ));
+                return None;
+            }
+            Err(err) => {
+                warnings.push(format!(
+                    "forecast left out of the projection: {}: {err:#}",
+                    s.account_ref
+                ));
+                return None;
+            }
+        }
+    }
+    Some(remaining)
 }
 
-fn previous_month_same_point(today: NaiveDate) -> Result<(NaiveDate, NaiveDate)> {
-    let first_of_this_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
-        .ok_or_else(|| anyhow!("Invalid current month date"))?;
-    let last_of_prev_month = first_of_this_month - Duration::days(1);
-    let prev_start =
-        NaiveDate::from_ymd_opt(last_of_prev_month.year(), last_of_prev_month.month(), 1)
-            .ok_or_else(|| anyhow!("Invalid previous month date"))?;
+/// The previous window followed by the same window 1..`periods` months earlier.
+fn baseline_windows(
+    previous: (NaiveDate, NaiveDate),
+    periods: usize,
+) -> Result<Vec<(NaiveDate, NaiveDate)>> {
+    if periods == 0 {
+        bail!("The baseline needs at least one previous period");
+    }
+    let (start, end_exclusive) = previous;
+    (0..periods as u32)
+        .map(|back| {
+            let months = chrono::Months::new(back);
+            match (
+                start.checked_sub_months(months),
+                end_exclusive.checked_sub_months(months),
+            ) {
+                (Some(start), Some(end_exclusive)) => Ok((start, end_exclusive)),
+                _ => bail!("Baseline period {back} month(s) before {start} is out of range"),
+            }
+        })
+        .collect()
+}
 
-    let day = today.day();
-    let prev_end_exclusive = prev_start + Duration::days(day as i64);
+/// Per-service costs of one period and how many of its amounts could not be parsed.
+type CheckedServices = (HashMap<String, f64>, usize);
 
-    Ok((prev_start, prev_end_exclusive))
+/// One account's per-service costs averaged over the baseline periods, with the unparseable
+/// amounts of every period; a service missing from a period counts as 0 there. Fails or
+/// times out with the first period that does.
+fn average_periods(
+    periods: Vec<Result<Option<CheckedServices>>>,
+) -> Result<Option<CheckedServices>> {
+    let count = periods.len() as f64;
+    let mut sums: HashMap<String, f64> = HashMap::new();
+    let mut unparseable = 0;
+    for period in periods {
+        let Some((services, invalid)) = period? else {
+            return Ok(None);
+        };
+        unparseable += invalid;
+        for (svc, amt) in services {
+            *sums.entry(svc).or_insert(0.0) += amt;
+        }
+    }
+    let averaged = sums
+        .into_iter()
+        .map(|(svc, amt)| (svc, amt / count))
+        .collect();
+    Ok(Some((averaged, unparseable)))
+}
+
+/// Merges per-account native comparisons; `None` unless every account produced one.
+async fn native_comparison_for_all<P: CostProvider>(
+    provider: &P,
+    summaries: &[AccountSummary],
+    baseline: (NaiveDate, NaiveDate),
+    current: (NaiveDate, NaiveDate),
+    limits: &FetchLimits,
+    options: &ReportOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Option<NativeComparison>> {
+    let per_account = join_all(
+        summaries
+            .iter()
+            .map(|s| limits.run(provider.native_comparison(&s.account_ref, baseline, current))),
+    )
+    .await;
+    let Some(per_account) = collect_all(
+        summaries,
+        per_account,
+        "native comparison",
+        options,
+        warnings,
+    )?
+    else {
+        return Ok(None);
+    };
+
+    let mut merged = NativeComparison::default();
+    for comparison in per_account {
+        let Some(comparison) = comparison else {
+            return Ok(None);
+        };
+        merged.total.baseline += comparison.total.baseline;
+        merged.total.current += comparison.total.current;
+        merged.total.difference += comparison.total.difference;
+        for (svc, delta) in comparison.services {
+            let entry = merged.services.entry(svc).or_default();
+            entry.baseline += delta.baseline;
+            entry.current += delta.current;
+            entry.difference += delta.difference;
+        }
+        merged.drivers.extend(comparison.drivers);
+    }
+    merged.drivers.sort_by(|a, b| {
+        b.difference
+            .abs()
+            .partial_cmp(&a.difference.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    Ok(Some(merged))
+}
+
+/// Merged previous-period breakdowns; `None` unless every account's arrived in time.
+async fn previous_breakdowns<P: CostProvider>(
+    provider: &P,
+    summaries: &[AccountSummary],
+    previous: (NaiveDate, NaiveDate),
+    limits: &FetchLimits,
+    options: &ReportOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Vec<DimensionBreakdown>>> {
+    let (start, end_exclusive) = previous;
+    let per_account = join_all(
+        summaries
+            .iter()
+            .map(|s| limits.run(provider.breakdowns(&s.account_ref, start, end_exclusive))),
+    )
+    .await;
+
+    let Some(per_account) = collect_all(
+        summaries,
+        per_account,
+        "previous-period breakdowns",
+        options,
+        warnings,
+    )?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(aggregate_breakdowns(per_account.iter().flatten())))
+}
+
+/// Runs each account's outcome of an extra fetch through `collect_outcome`. An account that
+/// failed or timed out stays in the report: only `what` is left out, with a warning.
+fn collect_all<T>(
+    summaries: &[AccountSummary],
+    outcomes: Vec<Result<Option<T>>>,
+    what: &str,
+    options: &ReportOptions,
+    warnings: &mut Vec<String>,
+) -> Result<Option<Vec<T>>> {
+    let mut failed = Vec::new();
+    let mut collected = Vec::with_capacity(outcomes.len());
+    for (s, outcome) in summaries.iter().zip(outcomes) {
+        if let Some(value) = collect_outcome(&s.account_ref, outcome, options, &mut failed)? {
+            collected.push(value);
+        }
+    }
+    if failed.is_empty() {
+        return Ok(Some(collected));
+    }
+    warnings.extend(
+        failed
+            .into_iter()
+            .map(|e| format!("{what} left out: {}: {}", e.account_ref, e.error)),
+    );
+    Ok(None)
+}
+
+/// Pairs each current breakdown with the previous period's values for the same dimension.
+fn breakdown_deltas(
+    current: &[DimensionBreakdown],
+    previous: &[DimensionBreakdown],
+) -> Vec<DimensionDelta> {
+    let empty = HashMap::new();
+    current
+        .iter()
+        .map(|breakdown| {
+            let prev = previous
+                .iter()
+                .find(|p| p.dimension == breakdown.dimension)
+                .map_or(&empty, |p| &p.values);
+            DimensionDelta {
+                dimension: breakdown.dimension.clone(),
+                groups: group_deltas(&breakdown.values, prev),
+            }
+        })
+        .collect()
+}
+
+fn service_alias(options: &ReportOptions, name: String) -> String {
+    let name = options
+        .service_aliases
+        .as_ref()
+        .and_then(|aliases| aliases.get(&name))
+        .cloned()
+        .unwrap_or(name);
+    options
+        .service_groups
+        .iter()
+        .find(|rule| rule.pattern.is_match(&name))
+        .map_or(name, |rule| rule.group.clone())
+}
+
+fn renames_services(options: &ReportOptions) -> bool {
+    options.service_aliases.is_some() || !options.service_groups.is_empty()
+}
+
+/// Renames services by alias and grouping rule, summing services that end up sharing a name.
+fn alias_services(services: HashMap<String, f64>, options: &ReportOptions) -> HashMap<String, f64> {
+    if !renames_services(options) {
+        return services;
+    }
+    let mut aliased: HashMap<String, f64> = HashMap::with_capacity(services.len());
+    for (svc, amt) in services {
+        *aliased.entry(service_alias(options, svc)).or_insert(0.0) += amt;
+    }
+    aliased
+}
+
+fn alias_summary(mut summary: AccountSummary, options: &ReportOptions) -> AccountSummary {
+    if !options.service_groups.is_empty() {
+        summary.raw_services = Some(summary.services.clone());
+    }
+    summary.services = alias_services(summary.services, options);
+    summary.amortized_services = summary
+        .amortized_services
+        .map(|services| alias_services(services, options));
+    for metric in &mut summary.metrics {
+        metric.services = alias_services(std::mem::take(&mut metric.services), options);
+    }
+    summary.secondary = summary.metrics.first().cloned();
+    summary
+}
+
+fn alias_comparison(mut comparison: NativeComparison, options: &ReportOptions) -> NativeComparison {
+    if !renames_services(options) {
+        return comparison;
+    }
+    let mut services: HashMap<String, CostDelta> = HashMap::new();
+    for (svc, delta) in comparison.services {
+        let entry = services.entry(service_alias(options, svc)).or_default();
+        entry.baseline += delta.baseline;
+        entry.current += delta.current;
+        entry.difference += delta.difference;
+    }
+    comparison.services = services;
+    for driver in &mut comparison.drivers {
+        driver.service = service_alias(options, std::mem::take(&mut driver.service));
+    }
+    comparison
+}
+
+/// Unwraps one account's fetch, or records why it is missing from the report. With
+/// `fail_on_error` a failure or missed deadline aborts the whole report instead.
+fn collect_outcome<T>(
+    account_ref: &str,
+    outcome: Result<Option<T>>,
+    options: &ReportOptions,
+    errors: &mut Vec<AccountError>,
+) -> Result<Option<T>> {
+    let error = match outcome {
+        Ok(Some(value)) => return Ok(Some(value)),
+        Ok(None) => format!(
+            "timed out after {}s report deadline",
+            options.deadline.unwrap_or_default().as_secs()
+        ),
+        Err(err) if options.fail_on_error => {
+            return Err(err.context(format!("Account {account_ref} failed")));
+        }
+        Err(err) => format!("{err:#}"),
+    };
+    if options.fail_on_error {
+        bail!("Account {account_ref} failed: {error}");
+    }
+    errors.push(AccountError {
+        account_ref: account_ref.to_string(),
+        error,
+    });
+    Ok(None)
+}
+
+/// Deadline and in-flight cap shared by every provider call of one report, across the
+/// current window, the previous window and native comparisons.
+struct FetchLimits {
+    deadline: Option<Instant>,
+    permits: Option<Semaphore>,
+}
+
+impl FetchLimits {
+    fn new(options: &ReportOptions) -> Self {
+        Self {
+            deadline: options.deadline.map(|budget| Instant::now() + budget),
+            permits: options.max_concurrency.map(|n| Semaphore::new(n.max(1))),
+        }
+    }
+
+    /// Runs `fut` once a permit is free; `None` means the deadline passed first
+    /// (time spent waiting for a permit counts against it).
+    async fn run<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<Option<T>> {
+        let limited = async {
+            let _permit = match &self.permits {
+                Some(permits) => Some(permits.acquire().await?),
+                None => None,
+            };
+            fut.await
+        };
+        match self.deadline {
+            Some(deadline) => match timeout_at(deadline, limited).await {
+                Ok(result) => result.map(Some),
+                Err(_elapsed) => Ok(None),
+            },
+            None => limited.await.map(Some),
+        }
+    }
+}
+
+/// The report's deadline, in-flight cap and progress callback, handed to
+/// `CostProvider::fetch_all_summaries`. `SummaryLimiter::default()` has none of them.
+#[derive(Default)]
+pub struct SummaryLimiter<'a> {
+    limits: Option<&'a FetchLimits>,
+    progress: Option<&'a Progress>,
+    done: AtomicUsize,
+}
+
+impl SummaryLimiter<'_> {
+    /// Fetches every account concurrently with `fetch`, each under the limits, reporting
+    /// progress as each one finishes.
+    pub async fn each<'b, F, Fut>(
+        &self,
+        accounts: &'b [String],
+        fetch: F,
+    ) -> Vec<Result<Option<AccountSummary>>>
+    where
+        F: Fn(&'b str) -> Fut,
+        Fut: Future<Output = Result<AccountSummary>>,
+    {
+        let fetch = &fetch;
+        join_all(accounts.iter().map(|account_ref| async move {
+            let outcome = self.run(fetch(account_ref.as_str())).await;
+            self.advance(1, accounts.len());
+            outcome
+        }))
+        .await
+    }
+
+    /// Runs one query answering for every account in `accounts`, so the deadline and
+    /// in-flight cap apply to it once; `fetch` returns one outcome per account, in order.
+    pub async fn batch(
+        &self,
+        accounts: &[String],
+        fetch: impl Future<Output = Vec<Result<AccountSummary>>>,
+    ) -> Vec<Result<Option<AccountSummary>>> {
+        let batch = self.run(async { Ok(fetch.await) }).await;
+        self.advance(accounts.len(), accounts.len());
+        match batch {
+            Ok(Some(outcomes)) => outcomes
+                .into_iter()
+                .map(|outcome| outcome.map(Some))
+                .collect(),
+            Ok(None) => accounts.iter().map(|_| Ok(None)).collect(),
+            Err(err) => {
+                let message = format!("{err:#}");
+                accounts.iter().map(|_| Err(anyhow!("{message}"))).collect()
+            }
+        }
+    }
+
+    async fn run<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<Option<T>> {
+        match self.limits {
+            Some(limits) => limits.run(fut).await,
+            None => fut.await.map(Some),
+        }
+    }
+
+    fn advance(&self, by: usize, total: usize) {
+        let done = self.done.fetch_add(by, Ordering::Relaxed) + by;
+        if let Some(Progress(progress)) = self.progress {
+            progress(done, total);
+        }
+    }
+}
+
+fn service_deltas(
+    current: &HashMap<String, f64>,
+    previous: &HashMap<String, f64>,
+) -> Vec<ServiceDelta> {
+    group_deltas(current, previous)
+        .into_iter()
+        .map(|d| ServiceDelta {
+            service: d.group,
+            previous: d.previous,
+            current: d.current,
+            delta: d.delta,
+            delta_pct: d.delta_pct,
+        })
+        .collect()
+}
+
+/// Change per key across both periods, largest absolute change first.
+fn group_deltas(
+    current: &HashMap<String, f64>,
+    previous: &HashMap<String, f64>,
+) -> Vec<GroupDelta> {
+    let mut groups: Vec<&String> = current.keys().chain(previous.keys()).collect();
+    groups.sort();
+    groups.dedup();
+
+    let mut deltas: Vec<GroupDelta> = groups
+        .into_iter()
+        .map(|group| {
+            let current = current.get(group).copied().unwrap_or(0.0);
+            let previous = previous.get(group).copied().unwrap_or(0.0);
+            let delta = current - previous;
+            GroupDelta {
+                group: group.clone(),
+                previous,
+                current,
+                delta,
+                delta_pct: (previous.abs() >= f64::EPSILON).then(|| (delta / previous) * 100.0),
+            }
+        })
+        .collect();
+    deltas.sort_by(|a, b| {
+        b.delta
+            .abs()
+            .partial_cmp(&a.delta.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    deltas
+}
+
+/// Services whose spend (either sign) is below `limit`, leaving overhead services alone.
+/// Folding a single service would only rename it, so fewer than two yields none.
+fn small_services(
+    services_total: &HashMap<String, f64>,
+    limit: f64,
+    overhead: &Option<OverheadSplit>,
+) -> Vec<String> {
+    let small: Vec<String> = services_total
+        .iter()
+        .filter(|(svc, amt)| {
+            amt.abs() < limit
+                && svc.as_str() != OTHER_SERVICES
+                && overhead
+                    .as_ref()
+                    .is_none_or(|o| !o.overhead_services.contains_key(*svc))
+        })
+        .map(|(svc, _)| svc.clone())
+        .collect();
+    if small.len() < 2 { Vec::new() } else { small }
+}
+
+/// Moves `names` into one `OTHER_SERVICES` entry; the sum over `services` is unchanged.
+fn collapse_services(services: &mut HashMap<String, f64>, names: &[String]) {
+    let mut other = 0.0_f64;
+    let mut any = false;
+    for name in names {
+        if let Some(amt) = services.remove(name) {
+            other += amt;
+            any = true;
+        }
+    }
+    if any {
+        *services.entry(OTHER_SERVICES.to_string()).or_insert(0.0) += other;
+    }
+}
+
+/// The one currency the accounts are billed in, or else each currency's subtotal. Accounts
+/// whose provider does not report a currency are assumed to match the rest.
+fn split_currencies(summaries: &[AccountSummary]) -> (Option<String>, Vec<CurrencySubtotal>) {
+    let mut subtotals: BTreeMap<&str, CurrencySubtotal> = BTreeMap::new();
+    for s in summaries {
+        if let Some(currency) = &s.currency {
+            let subtotal = subtotals
+                .entry(currency)
+                .or_insert_with(|| CurrencySubtotal {
+                    currency: currency.clone(),
+                    total: 0.0,
+                    account_refs: Vec::new(),
+                });
+            subtotal.total += s.total;
+            subtotal.account_refs.push(s.account_ref.clone());
+        }
+    }
+    if subtotals.len() > 1 {
+        return (None, subtotals.into_values().collect());
+    }
+    (subtotals.into_keys().next().map(String::from), Vec::new())
+}
+
+/// The one currency all of `currencies` are in, for amounts about to be summed; amounts of
+/// unknown currency go with any. Different currencies cannot be added up and are rejected.
+pub fn shared_currency<'a>(
+    currencies: impl IntoIterator<Item = Option<&'a str>>,
+) -> Result<Option<String>> {
+    let known: BTreeSet<&str> = currencies.into_iter().flatten().collect();
+    if known.len() > 1 {
+        let known: Vec<&str> = known.into_iter().collect();
+        bail!(
+            "Accounts are billed in different currencies ({}); their costs cannot be added up",
+            known.join(", ")
+        );
+    }
+    Ok(known.into_iter().next().map(String::from))
+}
+
+/// NaN and infinity are not valid JSON numbers and would break strict parsers, so one coming
+/// from the provider (or out of the arithmetic) is counted as 0 and noted in `warnings`.
+fn finite_or_zero(value: f64, what: impl FnOnce() -> String, warnings: &mut Vec<String>) -> f64 {
+    if value.is_finite() {
+        return value;
+    }
+    warnings.push(format!("{} was {value}; counted as 0", what()));
+    0.0
+}
+
+/// Largest difference, in dollars, allowed between a total and the sum of its parts.
+const TOTALS_TOLERANCE: f64 = 0.01;
+
+/// Checks that every account's services add up to its total and that `services_total` adds
+/// up to `total_all`, describing each mismatch.
+fn totals_mismatches(
+    summaries: &[AccountSummary],
+    services_total: &HashMap<String, f64>,
+    total_all: f64,
+) -> Vec<String> {
+    let mut mismatches: Vec<String> = summaries
+        .iter()
+        .filter_map(|s| {
+            let services: f64 = s.services.values().sum();
+            ((services - s.total).abs() > TOTALS_TOLERANCE).then(|| {
+                format!(
+                    "{}: services sum to ${services:.2} but the total is ${:.2}",
+                    s.account_ref, s.total
+                )
+            })
+        })
+        .collect();
+    let services: f64 = services_total.values().sum();
+    if (services - total_all).abs() > TOTALS_TOLERANCE {
+        mismatches.push(format!(
+            "services sum to ${services:.2} across all accounts but the total is ${total_all:.2}"
+        ));
+    }
+    mismatches
+}
+
+fn split_overhead(services_total: &HashMap<String, f64>, names: &[String]) -> OverheadSplit {
+    let mut usage_total = 0.0_f64;
+    let mut overhead_total = 0.0_f64;
+    let mut overhead_services: HashMap<String, f64> = HashMap::new();
+
+    for (svc, amt) in services_total {
+        if names.iter().any(|name| name == svc) {
+            overhead_total += *amt;
+            overhead_services.insert(svc.clone(), *amt);
+        } else {
+            usage_total += *amt;
+        }
+    }
+
+    OverheadSplit {
+        usage_total,
+        overhead_total,
+        overhead_services,
+    }
+}
+
+fn aggregate_breakdowns<'a>(
+    breakdowns: impl IntoIterator<Item = &'a DimensionBreakdown>,
+) -> Vec<DimensionBreakdown> {
+    let mut merged: Vec<DimensionBreakdown> = Vec::new();
+
+    for b in breakdowns {
+        let idx = match merged.iter().position(|m| m.dimension == b.dimension) {
+            Some(idx) => idx,
+            None => {
+                merged.push(DimensionBreakdown {
+                    dimension: b.dimension.clone(),
+                    values: HashMap::new(),
+                });
+                merged.len() - 1
+            }
+        };
+        for (key, amt) in &b.values {
+            *merged[idx].values.entry(key.clone()).or_insert(0.0) += *amt;
+        }
+    }
+
+    merged
+}
+
+impl std::iter::Sum for BlendedSplit {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |acc, s| Self {
+            blended: acc.blended + s.blended,
+            unblended: acc.unblended + s.unblended,
+            savings: acc.savings + s.savings,
+        })
+    }
+}
+
+impl std::iter::Sum for SavingsRate {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        let (on_demand_equivalent, net_amortized) = iter.fold((0.0, 0.0), |acc, s| {
+            (acc.0 + s.on_demand_equivalent, acc.1 + s.net_amortized)
+        });
+        Self::new(on_demand_equivalent, net_amortized)
+    }
+}
+
+/// Sums each additional metric across accounts; a metric is only reported when every
+/// account has it.
+fn aggregate_metrics(summaries: &[AccountSummary]) -> Vec<MetricBreakdown> {
+    let Some(first) = summaries.first() else {
+        return Vec::new();
+    };
+
+    first
+        .metrics
+        .iter()
+        .filter_map(|m| aggregate_metric(summaries, &m.metric))
+        .collect()
+}
+
+fn aggregate_metric(summaries: &[AccountSummary], metric: &str) -> Option<MetricBreakdown> {
+    let mut total = 0.0_f64;
+    let mut services: HashMap<String, f64> = HashMap::new();
+
+    for s in summaries {
+        let breakdown = s.metrics.iter().find(|m| m.metric == metric)?;
+        total += breakdown.total;
+        for (svc, amt) in &breakdown.services {
+            *services.entry(svc.clone()).or_insert(0.0) += *amt;
+        }
+    }
+
+    Some(MetricBreakdown {
+        metric: metric.to_string(),
+        total,
+        services,
+    })
+}
+
+/// Metrics measured in usage units rather than money (summed across services as-is).
+pub fn is_usage_metric(metric: &str) -> bool {
+    matches!(metric, "UsageQuantity" | "NormalizedUsageAmount")
+}
+
+/// Window from the 1st of `today`'s month through `today`. On the 1st this is the single,
+/// still partial day `[1st, 2nd)`, never an empty range.
+pub fn month_to_date(today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
+    let end_exclusive = today + Duration::days(1);
+    (start, end_exclusive)
+}
+
+/// The calendar date of `instant` in `timezone`, or in UTC when `None`. Report windows are
+/// derived from this "today", so it decides when the billing day rolls over.
+pub fn date_in(instant: DateTime<Utc>, timezone: Option<Tz>) -> NaiveDate {
+    match timezone {
+        Some(tz) => instant.with_timezone(&tz).date_naive(),
+        None => instant.date_naive(),
+    }
+}
+
+/// Today's date in `timezone` (UTC when `None`).
+pub fn today_in(timezone: Option<Tz>) -> NaiveDate {
+    date_in(Utc::now(), timezone)
+}
+
+/// Parses an IANA timezone name such as `America/Los_Angeles`.
+pub fn parse_timezone(name: &str) -> Result<Tz> {
+    name.trim().parse::<Tz>().map_err(|_| {
+        anyhow!("Unknown timezone {name:?}; expected an IANA name such as America/New_York")
+    })
+}
+
+/// Parses `YYYY-MM` into the first day of that month.
+pub fn parse_month(value: &str) -> Result<NaiveDate> {
+    let value = value.trim();
+    if value.len() != 7 {
+        bail!("Invalid month {value:?}; expected YYYY-MM, e.g. 2024-03");
+    }
+    NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid month {value:?}; expected YYYY-MM, e.g. 2024-03"))
+}
+
+/// The whole calendar month starting at `month_start`: 1st to the 1st of the next month
+/// (January of the next year for December).
+pub fn month_window(month_start: NaiveDate) -> Result<(NaiveDate, NaiveDate)> {
+    let end_exclusive = month_start
+        .checked_add_months(chrono::Months::new(1))
+        .ok_or_else(|| anyhow!("Month after {month_start} is out of range"))?;
+    Ok((month_start, end_exclusive))
+}
+
+/// Rejects empty or reversed windows, which Cost Explorer answers with an opaque
+/// validation error.
+pub fn validate_window(start: NaiveDate, end_exclusive: NaiveDate) -> Result<()> {
+    if start >= end_exclusive {
+        bail!(
+            "Invalid date range {start} to {end_exclusive} (exclusive): the start must be before the end"
+        );
+    }
+    Ok(())
+}
+
+/// The previous month from its 1st up to the same day of the month as `today`: the window
+/// a month-to-date report compares against. Days past the end of a shorter previous month
+/// are dropped, so the window never runs into `today`'s month.
+pub fn previous_month_same_point(today: NaiveDate) -> Result<(NaiveDate, NaiveDate)> {
+    let first_of_this_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
+        .ok_or_else(|| anyhow!("Invalid current month date"))?;
+    let last_of_prev_month = first_of_this_month - Duration::days(1);
+    let prev_start =
+        NaiveDate::from_ymd_opt(last_of_prev_month.year(), last_of_prev_month.month(), 1)
+            .ok_or_else(|| anyhow!("Invalid previous month date"))?;
+
+    let day = today.day();
+    let prev_end_exclusive = (prev_start + Duration::days(day as i64)).min(first_of_this_month);
+
+    Ok((prev_start, prev_end_exclusive))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Provider answering from fixed per-account service costs, keyed by window start.
+    #[derive(Default)]
+    struct MockProvider {
+        /// (account_ref, window start) -> service -> amount
+        costs: HashMap<(String, NaiveDate), HashMap<String, f64>>,
+        /// account_ref -> how long each of its calls takes
+        delays: HashMap<String, StdDuration>,
+        /// account_ref -> total difference of its native comparison; others fail to compare
+        native: HashMap<String, f64>,
+        /// account_ref -> total reported regardless of its services
+        totals: HashMap<String, f64>,
+        /// account_ref -> currency it is billed in; others report none
+        currencies: HashMap<String, String>,
+        calls: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl MockProvider {
+        fn with(mut self, account_ref: &str, start: NaiveDate, services: &[(&str, f64)]) -> Self {
+            let services = services
+                .iter()
+                .map(|(svc, amt)| (svc.to_string(), *amt))
+                .collect();
+            self.costs
+                .insert((account_ref.to_string(), start), services);
+            self
+        }
+
+        fn slow(mut self, account_ref: &str, delay: StdDuration) -> Self {
+            self.delays.insert(account_ref.to_string(), delay);
+            self
+        }
+
+        fn native(mut self, account_ref: &str, difference: f64) -> Self {
+            self.native.insert(account_ref.to_string(), difference);
+            self
+        }
+
+        fn total(mut self, account_ref: &str, total: f64) -> Self {
+            self.totals.insert(account_ref.to_string(), total);
+            self
+        }
+
+        fn billed_in(mut self, account_ref: &str, currency: &str) -> Self {
+            self.currencies
+                .insert(account_ref.to_string(), currency.to_string());
+            self
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl CostProvider for MockProvider {
+        async fn fetch_account_summary(
+            &self,
+            account_ref: &str,
+            start: NaiveDate,
+            end_exclusive: NaiveDate,
+        ) -> Result<AccountSummary> {
+            let services = self
+                .service_costs(account_ref, start, end_exclusive)
+                .await?;
+            let mut summary = AccountSummary::for_test(account_ref, services);
+            if let Some(total) = self.totals.get(account_ref) {
+                summary.total = *total;
+            }
+            summary.currency = self.currencies.get(account_ref).cloned();
+            Ok(summary)
+        }
+
+        async fn service_costs(
+            &self,
+            account_ref: &str,
+            start: NaiveDate,
+            _end_exclusive: NaiveDate,
+        ) -> Result<HashMap<String, f64>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            if let Some(delay) = self.delays.get(account_ref) {
+                tokio::time::sleep(*delay).await;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.costs
+                .get(&(account_ref.to_string(), start))
+                .cloned()
+                .ok_or_else(|| anyhow!("No costs for {account_ref} from {start}"))
+        }
+
+        async fn native_comparison(
+            &self,
+            account_ref: &str,
+            _baseline: (NaiveDate, NaiveDate),
+            _current: (NaiveDate, NaiveDate),
+        ) -> Result<Option<NativeComparison>> {
+            let difference = self
+                .native
+                .get(account_ref)
+                .ok_or_else(|| anyhow!("No comparison for {account_ref}"))?;
+            Ok(Some(NativeComparison {
+                total: CostDelta {
+                    difference: *difference,
+                    ..CostDelta::default()
+                },
+                ..NativeComparison::default()
+            }))
+        }
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn only_amounts_in_one_currency_are_summed() {
+        let shared = shared_currency([Some("EUR"), None, Some("EUR")]).unwrap();
+        assert_eq!(shared.as_deref(), Some("EUR"));
+        assert_eq!(shared_currency([None, None]).unwrap(), None);
+        let err = shared_currency([Some("USD"), Some("EUR")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Accounts are billed in different currencies (EUR, USD); their costs cannot be added up"
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_accounts_are_rejected_before_any_provider_call() {
+        let provider = MockProvider::default().with("prod", day(2024, 8, 1), &[("EC2", 1.0)]);
+
+        let err = generate_report(&provider, &[], day(2024, 8, 15))
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("No accounts configured"),
+            "{err:#}"
+        );
+        assert_eq!(provider.calls(), 0);
+    }
+
+    #[test]
+    fn overhead_split_uses_the_configured_names() {
+        let services = HashMap::from([
+            ("Amazon EC2".to_string(), 80.0),
+            ("AWS Support (Business)".to_string(), 15.0),
+            ("Tax".to_string(), 5.0),
+        ]);
+        let defaults: Vec<String> = DEFAULT_OVERHEAD_SERVICES
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let split = split_overhead(&services, &defaults);
+        assert_eq!(split.usage_total, 80.0);
+        assert_eq!(split.overhead_total, 20.0);
+        assert_eq!(split.overhead_services.len(), 2);
+
+        // An overridden list replaces the defaults rather than adding to them
+        let split = split_overhead(&services, &["Tax".to_string()]);
+        assert_eq!(split.usage_total, 95.0);
+        assert_eq!(split.overhead_total, 5.0);
+        assert_eq!(
+            split.overhead_services,
+            HashMap::from([("Tax".to_string(), 5.0)])
+        );
+    }
+
+    #[tokio::test]
+    async fn overhead_services_are_left_out_of_top_services() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with(
+                "prod",
+                day(2024, 8, 1),
+                &[("Amazon EC2", 80.0), ("AWS Support (Business)", 150.0)],
+            )
+            .with("prod", day(2024, 7, 1), &[("Amazon EC2", 70.0)]);
+        let options = ReportOptions {
+            overhead_services: Some(vec!["AWS Support (Business)".to_string()]),
+            ..ReportOptions::default()
+        };
+
+        let report =
+            generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+                .await
+                .unwrap();
+
+        assert_eq!(report.top_services, [("Amazon EC2".to_string(), 80.0)]);
+        let overhead = report.overhead.as_ref().unwrap();
+        assert_eq!(overhead.usage_total, 80.0);
+        assert_eq!(overhead.overhead_total, 150.0);
+        // Overhead still counts towards the total
+        assert_eq!(report.total_all, Some(230.0));
+        let options = render::TextOptions {
+            primary_metric: "UnblendedCost".to_string(),
+            rank_accounts: false,
+            rank_limit: None,
+            min_delta_abs: None,
+            min_delta_pct: None,
+        };
+        let text = render::text(&report, &options);
+        assert!(text.contains("- Overhead: $150.00"), "{text}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accounts_past_the_deadline_are_reported_as_timed_out() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("Amazon EC2", 50.0)])
+            .with("prod", day(2024, 7, 1), &[("Amazon EC2", 40.0)])
+            .with("slow", day(2024, 8, 1), &[("Amazon EC2", 1000.0)])
+            .with("slow", day(2024, 7, 1), &[("Amazon EC2", 1000.0)])
+            .slow("slow", StdDuration::from_secs(60));
+        let options = ReportOptions {
+            deadline: Some(StdDuration::from_secs(5)),
+            ..ReportOptions::default()
+        };
+        let accounts = ["prod".to_string(), "slow".to_string()];
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.summaries.len(), 1);
+        assert_eq!(report.total_all, Some(50.0));
+        assert_eq!(report.prev_total, Some(40.0));
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].account_ref, "slow");
+        assert_eq!(report.errors[0].error, "timed out after 5s report deadline");
+
+        let options = ReportOptions {
+            fail_on_error: true,
+            ..options
+        };
+        let err = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Account slow failed"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn services_sharing_an_alias_are_summed_in_both_windows() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with(
+                "prod",
+                day(2024, 8, 1),
+                &[
+                    ("Amazon Elastic Container Service for Kubernetes", 30.0),
+                    ("Amazon Elastic Kubernetes Service", 12.0),
+                    ("Some New Service", 1.0),
+                ],
+            )
+            .with(
+                "prod",
+                day(2024, 7, 1),
+                &[("Amazon Elastic Kubernetes Service", 20.0)],
+            );
+        let options = ReportOptions {
+            service_aliases: Some(default_service_aliases()),
+            ..ReportOptions::default()
+        };
+
+        let report =
+            generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+                .await
+                .unwrap();
+
+        let services = &report.summaries[0].services;
+        assert_eq!(services.len(), 2);
+        assert_eq!(services["EKS"], 42.0);
+        // Names without an alias are kept as they are
+        assert_eq!(services["Some New Service"], 1.0);
+        assert_eq!(report.services_total["EKS"], 42.0);
+        assert_eq!(report.top_services[0], ("EKS".to_string(), 42.0));
+        let eks = report
+            .service_deltas
+            .iter()
+            .find(|d| d.service == "EKS")
+            .unwrap();
+        assert_eq!((eks.previous, eks.current, eks.delta), (20.0, 42.0, 22.0));
+    }
+
+    #[test]
+    fn user_aliases_override_the_defaults() {
+        let mut aliases = default_service_aliases();
+        aliases.insert(
+            "Amazon Elastic Kubernetes Service".to_string(),
+            "Kubernetes".to_string(),
+        );
+        let options = ReportOptions {
+            service_aliases: Some(aliases),
+            ..ReportOptions::default()
+        };
+        let services = HashMap::from([
+            ("Amazon Elastic Kubernetes Service".to_string(), 5.0),
+            (
+                "Amazon Elastic Container Service for Kubernetes".to_string(),
+                3.0,
+            ),
+        ]);
+
+        let aliased = alias_services(services, &options);
+
+        assert_eq!(
+            aliased,
+            HashMap::from([("Kubernetes".to_string(), 5.0), ("EKS".to_string(), 3.0)])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn in_flight_calls_stay_within_the_limit_across_both_windows() {
+        let today = day(2024, 8, 15);
+        let accounts: Vec<String> = (1..=6).map(|n| format!("account-{n}")).collect();
+        let mut provider = MockProvider::default();
+        for account_ref in &accounts {
+            provider = provider
+                .with(account_ref, day(2024, 8, 1), &[("Amazon EC2", 10.0)])
+                .with(account_ref, day(2024, 7, 1), &[("Amazon EC2", 8.0)])
+                .slow(account_ref, StdDuration::from_secs(1));
+        }
+        let options = ReportOptions {
+            max_concurrency: Some(2),
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.total_all, Some(60.0));
+        assert_eq!(report.prev_total, Some(48.0));
+        assert_eq!(provider.calls(), 12);
+        assert_eq!(provider.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn first_metric_is_also_serialized_as_secondary() {
+        let mut prod = AccountSummary::for_test("prod", [("Amazon EC2", 10.0)]);
+        for (metric, total) in [("AmortizedCost", 9.0), ("UsageQuantity", 720.0)] {
+            prod.metrics.push(MetricBreakdown {
+                metric: metric.to_string(),
+                total,
+                services: HashMap::from([("Amazon EC2".to_string(), total)]),
+            });
+        }
+
+        let json = serde_json::to_value(alias_summary(prod, &ReportOptions::default())).unwrap();
+
+        assert_eq!(json["metrics"].as_array().unwrap().len(), 2);
+        assert_eq!(json["secondary"]["metric"], "AmortizedCost");
+        assert_eq!(json["secondary"]["total"], 9.0);
+        assert_eq!(json["secondary"], json["metrics"][0]);
+    }
+
+    #[test]
+    fn month_to_date_covers_boundary_days() {
+        // The 1st is one partial day, never an empty window
+        assert_eq!(
+            month_to_date(day(2024, 8, 1)),
+            (day(2024, 8, 1), day(2024, 8, 2))
+        );
+        assert_eq!(
+            month_to_date(day(2024, 1, 31)),
+            (day(2024, 1, 1), day(2024, 2, 1))
+        );
+        assert_eq!(
+            month_to_date(day(2024, 2, 29)),
+            (day(2024, 2, 1), day(2024, 3, 1))
+        );
+        assert_eq!(
+            month_to_date(day(2024, 12, 31)),
+            (day(2024, 12, 1), day(2025, 1, 1))
+        );
+    }
+
+    #[test]
+    fn previous_window_stays_in_the_previous_month() {
+        assert_eq!(
+            previous_month_same_point(day(2024, 8, 1)).unwrap(),
+            (day(2024, 7, 1), day(2024, 7, 2))
+        );
+        assert_eq!(
+            previous_month_same_point(day(2025, 1, 15)).unwrap(),
+            (day(2024, 12, 1), day(2024, 12, 16))
+        );
+        // February has no 30th or 31st
+        assert_eq!(
+            previous_month_same_point(day(2024, 3, 31)).unwrap(),
+            (day(2024, 2, 1), day(2024, 3, 1))
+        );
+        assert_eq!(
+            previous_month_same_point(day(2023, 3, 30)).unwrap(),
+            (day(2023, 2, 1), day(2023, 3, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_and_reversed_windows_fail_before_any_provider_call() {
+        let provider = MockProvider::default();
+        let accounts = ["prod".to_string()];
+        let july = (day(2024, 7, 1), day(2024, 8, 1));
+
+        for window in [
+            (day(2024, 8, 1), day(2024, 8, 1)),
+            (day(2024, 8, 2), day(2024, 8, 1)),
+        ] {
+            let err = generate_report_for_windows(
+                &provider,
+                &accounts,
+                window,
+                july,
+                &ReportOptions::default(),
+            )
+            .await
+            .unwrap_err();
+            assert!(
+                err.to_string().contains("the start must be before the end"),
+                "{err:#}"
+            );
+        }
+        assert_eq!(provider.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_native_comparison_keeps_the_account_in_the_report() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", 10.0)])
+            .with("prod", day(2024, 7, 1), &[("EC2", 8.0)])
+            .with("dev", day(2024, 8, 1), &[("EC2", 3.0)])
+            .with("dev", day(2024, 7, 1), &[("EC2", 2.0)])
+            .native("prod", 2.0);
+        let accounts = ["prod".to_string(), "dev".to_string()];
+        let options = ReportOptions {
+            native_comparison: true,
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.summaries.len(), 2);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.total_all, Some(13.0));
+        // Not every account compared, so the report falls back to subtracting totals
+        assert!(report.native_comparison.is_none());
+        assert_eq!(report.delta, Some(3.0));
+        assert!(
+            report
+                .warnings
+                .iter()
+                .any(|w| w.contains("native comparison left out: dev")),
+            "{:?}",
+            report.warnings
+        );
+
+        let provider = provider.native("dev", 1.0);
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+        assert_eq!(report.native_comparison.unwrap().total.difference, 3.0);
+    }
+
+    #[tokio::test]
+    async fn failed_native_comparison_fails_the_report_with_fail_on_error() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", 10.0)])
+            .with("prod", day(2024, 7, 1), &[("EC2", 8.0)]);
+        let options = ReportOptions {
+            native_comparison: true,
+            fail_on_error: true,
+            ..ReportOptions::default()
+        };
+
+        let err = generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+            .await
+            .unwrap_err();
+
+        assert!(
+            format!("{err:#}").contains("No comparison for prod"),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn other_bucket_sums_the_collapsed_services_and_keeps_the_total() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with(
+                "prod",
+                day(2024, 8, 1),
+                &[("EC2", 90.0), ("S3", 3.0), ("SNS", 0.5)],
+            )
+            .with("dev", day(2024, 8, 1), &[("EC2", 4.0), ("SQS", 1.5)]);
+        let accounts = ["prod".to_string(), "dev".to_string()];
+        let options = ReportOptions {
+            compare_previous: false,
+            other_threshold: Some(OtherThreshold::Amount(5.0)),
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.total_all, Some(99.0));
+        assert_eq!(
+            report.services_total,
+            HashMap::from([("EC2".to_string(), 94.0), (OTHER_SERVICES.to_string(), 5.0)])
+        );
+        assert_eq!(
+            Some(report.services_total.values().sum::<f64>()),
+            report.total_all
+        );
+        assert_eq!(
+            report.top_services,
+            [("EC2".to_string(), 94.0), (OTHER_SERVICES.to_string(), 5.0)]
+        );
+        let prod = report
+            .summaries
+            .iter()
+            .find(|s| s.account_ref == "prod")
+            .unwrap();
+        assert_eq!(prod.services[OTHER_SERVICES], 3.5);
+        assert_eq!(prod.services.values().sum::<f64>(), prod.total);
+    }
+
+    #[test]
+    fn other_threshold_as_a_percentage_scales_with_the_total() {
+        assert_eq!(
+            parse_other_threshold("2%").unwrap(),
+            OtherThreshold::Percent(2.0)
+        );
+        assert_eq!(
+            parse_other_threshold("$25").unwrap(),
+            OtherThreshold::Amount(25.0)
+        );
+        assert!(parse_other_threshold("-1").is_err());
+        assert_eq!(OtherThreshold::Percent(2.0).limit(-500.0), 10.0);
+    }
+
+    #[test]
+    fn report_day_follows_the_timezone() {
+        // 2024-09-01 03:30 UTC is still August 31st on the US west coast
+        let instant = DateTime::parse_from_rfc3339("2024-09-01T03:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let los_angeles = parse_timezone("America/Los_Angeles").unwrap();
+
+        assert_eq!(date_in(instant, None), day(2024, 9, 1));
+        assert_eq!(date_in(instant, Some(los_angeles)), day(2024, 8, 31));
+        assert_eq!(
+            date_in(instant, Some(parse_timezone("Asia/Tokyo").unwrap())),
+            day(2024, 9, 1)
+        );
+        // So the windows land in different months
+        assert_eq!(
+            month_to_date(date_in(instant, None)),
+            (day(2024, 9, 1), day(2024, 9, 2))
+        );
+        assert_eq!(
+            month_to_date(date_in(instant, Some(los_angeles))),
+            (day(2024, 8, 1), day(2024, 9, 1))
+        );
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+
+    #[tokio::test]
+    async fn totals_that_do_not_add_up_are_reported() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", 10.0), ("S3", 2.0)])
+            .with("dev", day(2024, 8, 1), &[("EC2", 3.0)])
+            .total("prod", 15.0);
+        let accounts = ["prod".to_string(), "dev".to_string()];
+        let options = ReportOptions {
+            compare_previous: false,
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.total_all, Some(18.0));
+        assert_eq!(
+            report.warnings,
+            [
+                "prod: services sum to $12.00 but the total is $15.00",
+                "services sum to $15.00 across all accounts but the total is $18.00",
+            ]
+        );
+
+        let strict = ReportOptions {
+            strict: true,
+            ..options
+        };
+        let err = generate_report_with_options(&provider, &accounts, today, &strict)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Report totals do not add up"),
+            "{err:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn new_service_folded_into_other_keeps_its_amount() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with(
+                "prod",
+                day(2024, 8, 1),
+                &[("EC2", 90.0), ("SNS", 0.5), ("SQS", 1.0)],
+            )
+            .with("prod", day(2024, 7, 1), &[("EC2", 80.0), ("SQS", 1.0)]);
+        let options = ReportOptions {
+            other_threshold: Some(OtherThreshold::Amount(5.0)),
+            ..ReportOptions::default()
+        };
+
+        let report =
+            generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+                .await
+                .unwrap();
+
+        assert_eq!(report.new_services, ["SNS"]);
+        assert!(!report.services_total.contains_key("SNS"));
+        let options = render::TextOptions {
+            primary_metric: "UnblendedCost".to_string(),
+            rank_accounts: false,
+            rank_limit: None,
+            min_delta_abs: None,
+            min_delta_pct: None,
+        };
+        let text = render::text(&report, &options);
+        assert!(text.contains("- NEW SNS: $0.50"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn renamed_and_collapsed_services_still_add_up() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default().with(
+            "prod",
+            day(2024, 8, 1),
+            &[
+                ("Amazon Elastic Compute Cloud - Compute", 80.0),
+                ("EC2 - Other", 10.0),
+                ("Amazon SNS", 0.5),
+                ("Amazon SQS", 0.25),
+            ],
+        );
+        let options = ReportOptions {
+            compare_previous: false,
+            strict: true,
+            service_groups: vec![ServiceGroupRule {
+                pattern: Regex::new("EC2|Elastic Compute").unwrap(),
+                group: "Compute".to_string(),
+            }],
+            other_threshold: Some(OtherThreshold::Amount(1.0)),
+            ..ReportOptions::default()
+        };
+
+        let report =
+            generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+                .await
+                .unwrap();
+
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(
+            report.services_total,
+            HashMap::from([
+                ("Compute".to_string(), 90.0),
+                (OTHER_SERVICES.to_string(), 0.75)
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn mixed_currencies_are_totalled_separately() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("us", day(2024, 8, 1), &[("EC2", 100.0)])
+            .with("us", day(2024, 7, 1), &[("EC2", 90.0)])
+            .with("eu", day(2024, 8, 1), &[("EC2", 40.0), ("S3", 2.0)])
+            .with("eu", day(2024, 7, 1), &[("EC2", 30.0)])
+            .with("eu-dev", day(2024, 8, 1), &[("EC2", 8.0)])
+            .with("eu-dev", day(2024, 7, 1), &[("EC2", 7.0)])
+            .billed_in("us", "USD")
+            .billed_in("eu", "EUR")
+            .billed_in("eu-dev", "EUR");
+        let accounts = ["us".to_string(), "eu".to_string(), "eu-dev".to_string()];
+
+        let report = generate_report(&provider, &accounts, today).await.unwrap();
+
+        assert_eq!(report.summaries.len(), 3);
+        assert_eq!(report.total_all, None);
+        assert_eq!(report.currency, None);
+        let subtotals: Vec<(&str, f64, &[String])> = report
+            .currency_subtotals
+            .iter()
+            .map(|c| (c.currency.as_str(), c.total, c.account_refs.as_slice()))
+            .collect();
+        assert_eq!(
+            subtotals,
+            [
+                ("EUR", 50.0, &["eu".to_string(), "eu-dev".to_string()][..]),
+                ("USD", 100.0, &["us".to_string()][..]),
+            ]
+        );
+        // Nothing derived from a cross-currency sum
+        assert_eq!(report.prev_total, None);
+        assert_eq!(report.delta, None);
+        assert_eq!(report.daily_run_rate, None);
+        assert!(
+            report.warnings[0].contains("different currencies (EUR, USD)"),
+            "{:?}",
+            report.warnings
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json.get("total_all").is_none());
+        assert_eq!(json["currency_subtotals"][1]["currency"], "USD");
+    }
+
+    #[tokio::test]
+    async fn one_currency_keeps_the_total() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("eu", day(2024, 8, 1), &[("EC2", 40.0)])
+            .with("eu", day(2024, 7, 1), &[("EC2", 30.0)])
+            .with("legacy", day(2024, 8, 1), &[("EC2", 2.0)])
+            .with("legacy", day(2024, 7, 1), &[("EC2", 2.0)])
+            .billed_in("eu", "EUR");
+        let accounts = ["eu".to_string(), "legacy".to_string()];
+
+        let report = generate_report(&provider, &accounts, today).await.unwrap();
+
+        // An account without a reported currency is assumed to match
+        assert_eq!(report.total_all, Some(42.0));
+        assert_eq!(report.currency.as_deref(), Some("EUR"));
+        assert!(report.currency_subtotals.is_empty());
+        assert_eq!(report.delta, Some(10.0));
+    }
+
+    #[tokio::test]
+    async fn non_finite_amounts_are_counted_as_zero_with_a_warning() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", f64::NAN), ("S3", 10.0)])
+            .with(
+                "prod",
+                day(2024, 7, 1),
+                &[("EC2", f64::INFINITY), ("S3", 5.0)],
+            );
+
+        let report = generate_report(&provider, &["prod".to_string()], today)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.warnings,
+            [
+                "prod previous-period EC2 was inf; counted as 0",
+                "prod EC2 was NaN; counted as 0",
+                "prod total was NaN; counted as the sum of its services",
+            ]
+        );
+        assert_eq!(report.summaries[0].services["EC2"], 0.0);
+        assert_eq!(report.summaries[0].total, 10.0);
+        assert_eq!(report.total_all, Some(10.0));
+        assert_eq!(report.prev_total, Some(5.0));
+        assert_eq!(report.delta, Some(5.0));
+        assert_eq!(report.delta_pct, Some(100.0));
+        assert!(report.services_total.values().all(|amt| amt.is_finite()));
+    }
+
+    fn small_accounts_provider() -> MockProvider {
+        MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", 100.0)])
+            .with("prod", day(2024, 7, 1), &[("EC2", 80.0)])
+            .with("dev", day(2024, 8, 1), &[("EC2", 3.0)])
+            .with("dev", day(2024, 7, 1), &[("EC2", 4.0)])
+            .with("sandbox", day(2024, 8, 1), &[("S3", 2.0)])
+            .with("sandbox", day(2024, 7, 1), &[("S3", 1.0)])
+    }
+
+    #[tokio::test]
+    async fn small_accounts_are_left_out_of_every_total_by_default() {
+        let provider = small_accounts_provider();
+        let accounts = ["prod", "dev", "sandbox"].map(String::from);
+        let options = ReportOptions {
+            min_account_spend: Some(5.0),
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, day(2024, 8, 15), &options)
+            .await
+            .unwrap();
+
+        let refs: Vec<&str> = report
+            .summaries
+            .iter()
+            .map(|s| s.account_ref.as_str())
+            .collect();
+        assert_eq!(refs, ["prod"]);
+        assert_eq!(report.total_all, Some(100.0));
+        assert_eq!(report.prev_total, Some(80.0));
+        assert_eq!(
+            report.services_total,
+            HashMap::from([("EC2".to_string(), 100.0)])
+        );
+        let small = report.small_accounts.unwrap();
+        assert_eq!(small.account_refs, ["dev", "sandbox"]);
+        assert_eq!(small.total, 5.0);
+        assert!(!small.counted_in_total);
+
+        // Nothing left to report on: an empty report that says why
+        let options = ReportOptions {
+            min_account_spend: Some(1000.0),
+            ..ReportOptions::default()
+        };
+        let report = generate_report_with_options(
+            &provider
+                .billed_in("prod", "EUR")
+                .billed_in("dev", "EUR")
+                .billed_in("sandbox", "EUR"),
+            &accounts,
+            day(2024, 8, 15),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(report.summaries.is_empty());
+        assert_eq!(report.total_all, Some(0.0));
+        assert_eq!(report.small_accounts.unwrap().account_refs.len(), 3);
+        assert_eq!(
+            report.warnings,
+            ["No account spent at least €1000.00; all 3 are left out as small accounts"]
+        );
+    }
+
+    #[tokio::test]
+    async fn counted_small_accounts_stay_in_every_total() {
+        let provider = small_accounts_provider();
+        let accounts = ["prod", "dev", "sandbox"].map(String::from);
+        let options = ReportOptions {
+            min_account_spend: Some(5.0),
+            count_small_accounts: true,
+            ..ReportOptions::default()
+        };
+
+        let report = generate_report_with_options(&provider, &accounts, day(2024, 8, 15), &options)
+            .await
+            .unwrap();
+
+        let refs: Vec<&str> = report
+            .summaries
+            .iter()
+            .map(|s| s.account_ref.as_str())
+            .collect();
+        assert_eq!(refs, ["prod"]);
+        assert_eq!(report.total_all, Some(105.0));
+        assert_eq!(report.prev_total, Some(85.0));
+        assert_eq!(
+            report.services_total,
+            HashMap::from([("EC2".to_string(), 103.0), ("S3".to_string(), 2.0)])
+        );
+        let small = report.small_accounts.unwrap();
+        assert_eq!(small.account_refs, ["dev", "sandbox"]);
+        assert_eq!(small.total, 5.0);
+        assert!(small.counted_in_total);
+
+        // Every account small: still a report, with the totals and no account listed
+        let options = ReportOptions {
+            min_account_spend: Some(1000.0),
+            count_small_accounts: true,
+            ..ReportOptions::default()
+        };
+        let report = generate_report_with_options(&provider, &accounts, day(2024, 8, 15), &options)
+            .await
+            .unwrap();
+        assert!(report.summaries.is_empty());
+        assert_eq!(report.total_all, Some(105.0));
+        assert_eq!(report.small_accounts.unwrap().account_refs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn baseline_averages_each_service_over_the_previous_periods() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("EC2", 120.0)])
+            .with("prod", day(2024, 7, 1), &[("EC2", 90.0), ("S3", 30.0)])
+            .with("prod", day(2024, 6, 1), &[("EC2", 60.0)])
+            .with("prod", day(2024, 5, 1), &[("EC2", 30.0)]);
+        let options = ReportOptions {
+            baseline_periods: 3,
+            ..ReportOptions::default()
+        };
+
+        let report =
+            generate_report_with_options(&provider, &["prod".to_string()], today, &options)
+                .await
+                .unwrap();
+
+        assert_eq!(report.baseline_periods, Some(3));
+        // EC2: (90 + 60 + 30) / 3; S3 only ran in July, so it counts as 0 in June and May
+        assert_eq!(report.prev_total, Some(60.0 + 10.0));
+        assert_eq!(report.delta, Some(50.0));
+        assert!((report.delta_pct.unwrap() - 50.0 / 70.0 * 100.0).abs() < 1e-9);
+        let ec2 = report
+            .service_deltas
+            .iter()
+            .find(|d| d.service == "EC2")
+            .unwrap();
+        assert_eq!((ec2.previous, ec2.current), (60.0, 120.0));
+        assert_eq!(report.removed_services, ["S3"]);
+        // One call for the current window and one per baseline period
+        assert_eq!(provider.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn the_first_matching_grouping_rule_wins() {
+        let provider = MockProvider::default().with(
+            "prod",
+            day(2024, 8, 1),
+            &[
+                ("Amazon EC2", 10.0),
+                ("AWS Lambda", 5.0),
+                ("Amazon S3", 3.0),
+                ("Amazon RDS", 2.0),
+            ],
+        );
+        let rule = |pattern: &str, group: &str| ServiceGroupRule {
+            pattern: Regex::new(pattern).unwrap(),
+            group: group.to_string(),
+        };
+        let grouped = |rules: Vec<ServiceGroupRule>| {
+            let options = ReportOptions {
+                compare_previous: false,
+                service_groups: rules,
+                ..ReportOptions::default()
+            };
+            let provider = &provider;
+            async move {
+                generate_report_with_options(
+                    provider,
+                    &["prod".to_string()],
+                    day(2024, 8, 15),
+                    &options,
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        let report = grouped(vec![
+            rule("EC2|Lambda", "Compute"),
+            rule("EC2", "Virtual machines"),
+            rule("S3", "Storage"),
+        ])
+        .await;
+        assert_eq!(
+            report.services_total,
+            HashMap::from([
+                ("Compute".to_string(), 15.0),
+                ("Storage".to_string(), 3.0),
+                // No rule matches: kept as is
+                ("Amazon RDS".to_string(), 2.0),
+            ])
+        );
+        let raw = report.summaries[0].raw_services.as_ref().unwrap();
+        assert_eq!(raw["Amazon EC2"], 10.0);
+        assert_eq!(raw.len(), 4);
+
+        // Same rules, EC2's own first
+        let report = grouped(vec![
+            rule("EC2", "Virtual machines"),
+            rule("EC2|Lambda", "Compute"),
+            rule("S3", "Storage"),
+        ])
+        .await;
+        assert_eq!(report.services_total["Virtual machines"], 10.0);
+        assert_eq!(report.services_total["Compute"], 5.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_the_report_stops_its_provider_calls() {
+        let accounts = ["a", "b", "c"].map(String::from);
+        let provider = accounts
+            .iter()
+            .fold(MockProvider::default(), |provider, account| {
+                provider
+                    .with(account, day(2024, 8, 1), &[("EC2", 1.0)])
+                    .with(account, day(2024, 7, 1), &[("EC2", 1.0)])
+                    .slow(account, StdDuration::from_secs(60))
+            });
+        let cancel = CancellationToken::new();
+        let options = ReportOptions {
+            cancel: Some(cancel.clone()),
+            max_concurrency: Some(1),
+            ..ReportOptions::default()
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(StdDuration::from_secs(30)).await;
+            cancel.cancel();
+        });
+
+        let err = generate_report_with_options(&provider, &accounts, day(2024, 8, 15), &options)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "Report cancelled");
+        // Long after the other accounts would have been fetched, only the first call was made
+        tokio::time::sleep(StdDuration::from_secs(600)).await;
+        assert_eq!(provider.calls(), 1);
+    }
+
+    #[test]
+    fn savings_rate_is_net_amortized_below_blended_summed_before_dividing() {
+        let prod = SavingsRate::new(200.0, 150.0);
+        assert_eq!(prod.savings, 50.0);
+        assert_eq!(prod.rate_pct, Some(25.0));
+
+        // Net amortized above blended (e.g. unused commitments) is a negative rate
+        let dev = SavingsRate::new(100.0, 110.0);
+        assert_eq!(dev.savings, -10.0);
+        assert_eq!(dev.rate_pct, Some(-10.0));
+
+        assert_eq!(SavingsRate::new(0.0, 5.0).rate_pct, None);
+
+        // The total rate comes from the summed amounts, not an average of account rates
+        let total: SavingsRate = [prod, dev].into_iter().sum();
+        assert_eq!(total.on_demand_equivalent, 300.0);
+        assert_eq!(total.net_amortized, 260.0);
+        assert_eq!(total.savings, 40.0);
+        assert!((total.rate_pct.unwrap() - 40.0 / 3.0).abs() < 1e-9);
+
+        let partial: Option<SavingsRate> = [Some(prod), None].into_iter().sum();
+        assert!(partial.is_none());
+    }
+
+    /// `MockProvider` answering every account's summary from one batch call.
+    struct BatchProvider {
+        inner: MockProvider,
+        batches: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CostProvider for BatchProvider {
+        async fn fetch_account_summary(
+            &self,
+            _account_ref: &str,
+            _start: NaiveDate,
+            _end_exclusive: NaiveDate,
+        ) -> Result<AccountSummary> {
+            bail!("Summaries are only fetched in a batch")
+        }
+
+        async fn fetch_all_summaries(
+            &self,
+            accounts: &[String],
+            start: NaiveDate,
+            end_exclusive: NaiveDate,
+            limiter: &SummaryLimiter<'_>,
+        ) -> Vec<Result<Option<AccountSummary>>> {
+            self.batches.fetch_add(1, Ordering::SeqCst);
+            let batch = join_all(accounts.iter().map(|account_ref| {
+                self.inner
+                    .fetch_account_summary(account_ref, start, end_exclusive)
+            }));
+            limiter.batch(accounts, batch).await
+        }
+
+        async fn service_costs(
+            &self,
+            account_ref: &str,
+            start: NaiveDate,
+            end_exclusive: NaiveDate,
+        ) -> Result<HashMap<String, f64>> {
+            self.inner
+                .service_costs(account_ref, start, end_exclusive)
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_fetch_summaries_in_one_batch_when_the_provider_has_one() {
+        let today = day(2024, 8, 15);
+        let provider = BatchProvider {
+            inner: MockProvider::default()
+                .with("prod", day(2024, 8, 1), &[("Amazon EC2", 50.0)])
+                .with("prod", day(2024, 7, 1), &[("Amazon EC2", 40.0)])
+                .with("dev", day(2024, 8, 1), &[("Amazon EC2", 5.0)])
+                .with("dev", day(2024, 7, 1), &[("Amazon EC2", 8.0)]),
+            batches: AtomicUsize::new(0),
+        };
+        let ticks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let options = ReportOptions {
+            progress: Some(Progress::new({
+                let ticks = ticks.clone();
+                move |done, total| ticks.lock().unwrap().push((done, total))
+            })),
+            ..ReportOptions::default()
+        };
+        let accounts = ["prod".to_string(), "dev".to_string()];
+
+        let report = generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.total_all, Some(55.0));
+        assert_eq!(report.prev_total, Some(48.0));
+        assert_eq!(provider.batches.load(Ordering::SeqCst), 1);
+        // The whole batch counts as done at once
+        assert_eq!(*ticks.lock().unwrap(), [(2, 2)]);
+    }
+
+    #[tokio::test]
+    async fn progress_counts_each_account_fetched_on_its_own() {
+        let today = day(2024, 8, 15);
+        let provider = MockProvider::default()
+            .with("prod", day(2024, 8, 1), &[("Amazon EC2", 50.0)])
+            .with("prod", day(2024, 7, 1), &[("Amazon EC2", 40.0)])
+            .with("dev", day(2024, 8, 1), &[("Amazon EC2", 5.0)])
+            .with("dev", day(2024, 7, 1), &[("Amazon EC2", 8.0)]);
+        let ticks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let options = ReportOptions {
+            progress: Some(Progress::new({
+                let ticks = ticks.clone();
+                move |done, total| ticks.lock().unwrap().push((done, total))
+            })),
+            ..ReportOptions::default()
+        };
+        let accounts = ["prod".to_string(), "dev".to_string()];
+
+        generate_report_with_options(&provider, &accounts, today, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(*ticks.lock().unwrap(), [(1, 2), (2, 2)]);
+    }
 }
@@ -0,0 +1,1722 @@
+//! Report formatters shared by the CLI and the API.
+
+use crate::{
+    AccountSummary, DimensionBreakdown, DimensionDelta, MetricBreakdown, ProratedProjection,
+    Report, SavingsRate, ServiceDelta, SmallAccounts, is_usage_metric,
+};
+use chrono::{Datelike, Months, NaiveDate};
+use serde_json::Value;
+use std::fmt::Write;
+
+/// How amounts appear in machine-readable output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MoneyFormat {
+    /// Dollars as decimal numbers, rounded to the cent
+    #[default]
+    Dollars,
+    /// Dollars rounded to this many decimal places, e.g. 4 for sub-cent unit costs
+    Decimals(u32),
+    /// Whole cents as integers, for consumers that reject floating-point money
+    Cents,
+}
+
+impl MoneyFormat {
+    /// Decimal places dollar amounts are rounded to; `None` for cents.
+    fn decimals(self) -> Option<u32> {
+        match self {
+            MoneyFormat::Dollars => Some(2),
+            MoneyFormat::Decimals(places) => Some(places),
+            MoneyFormat::Cents => None,
+        }
+    }
+}
+
+/// Keys whose numeric value is an amount of money.
+const MONEY_FIELDS: &[&str] = &[
+    "previous",
+    "total",
+    "total_all",
+    "tax",
+    "tax_total",
+    "blended",
+    "unblended",
+    "on_demand_equivalent",
+    "net_amortized",
+    "savings",
+    "prev_total",
+    "delta",
+    "usage_total",
+    "daily_run_rate",
+    "projected_month_total",
+    "complete_total",
+    "complete_daily_rate",
+    "prorated_total",
+    "forecast_total",
+    "estimate",
+    "untagged_spend",
+    "overhead_total",
+    "baseline",
+    "current",
+    "difference",
+];
+
+/// Keys whose object values are keyed by name and hold amounts.
+const MONEY_MAPS: &[&str] = &[
+    "services",
+    "amortized_services",
+    "raw_services",
+    "services_total",
+    "values",
+    "overhead_services",
+    "azs",
+];
+
+/// Knobs for the human-readable text rendering.
+#[derive(Debug, Clone)]
+pub struct TextOptions {
+    /// Name of the metric behind `total` columns, shown when additional metrics are present
+    pub primary_metric: String,
+    /// Include the accounts-ranked-by-spend section
+    pub rank_accounts: bool,
+    /// Only rank the top N accounts
+    pub rank_limit: Option<usize>,
+    /// Hide per-service and per-group changes smaller than this many dollars (unless
+    /// `min_delta_pct` is met)
+    pub min_delta_abs: Option<f64>,
+    /// Hide per-service and per-group changes smaller than this percentage (unless
+    /// `min_delta_abs` is met)
+    pub min_delta_pct: Option<f64>,
+}
+
+impl TextOptions {
+    /// With no thresholds every change is shown; otherwise exceeding either one is enough.
+    fn shows_delta(&self, delta: f64, delta_pct: Option<f64>) -> bool {
+        if delta.abs() < f64::EPSILON {
+            return false;
+        }
+        if self.min_delta_abs.is_none() && self.min_delta_pct.is_none() {
+            return true;
+        }
+        let by_abs = self.min_delta_abs.is_some_and(|min| delta.abs() >= min);
+        // A service new this period has no percentage; treat it as an unbounded change
+        let by_pct = self
+            .min_delta_pct
+            .is_some_and(|min| delta_pct.is_none_or(|pct| pct.abs() >= min));
+        by_abs || by_pct
+    }
+}
+
+/// The human-readable report the CLI prints by default.
+pub fn text(report: &Report, options: &TextOptions) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "Cloud Cost Manager\n").unwrap();
+    writeln!(out, "{}\n", comparison_label(report)).unwrap();
+
+    if !report.metrics.is_empty() {
+        let names: Vec<&str> = report.metrics.iter().map(|m| m.metric.as_str()).collect();
+        writeln!(
+            out,
+            "Columns: {} | {}\n",
+            options.primary_metric,
+            names.join(" | ")
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "Breakdown by account:").unwrap();
+    for s in &report.summaries {
+        let currency = account_currency(report, s);
+        writeln!(
+            out,
+            "- {} ({}) via profile {}: {}{}",
+            s.account_name,
+            s.account_id,
+            s.account_ref,
+            money(s.total, currency),
+            metric_columns(&s.metrics, |m| m.total, |amt| money(amt, currency))
+        )
+        .unwrap();
+    }
+    if let Some(small) = &report.small_accounts {
+        writeln!(out, "- {}", small_accounts_note(report, small)).unwrap();
+    }
+
+    if !report.errors.is_empty() {
+        writeln!(
+            out,
+            "\nWarning: accounts missing from this report (not in any total):"
+        )
+        .unwrap();
+        for e in &report.errors {
+            writeln!(out, "- {}: {}", e.account_ref, e.error).unwrap();
+        }
+    }
+    for warning in &report.warnings {
+        writeln!(out, "\nWarning: {warning}").unwrap();
+    }
+
+    match report.total_all {
+        Some(total) => writeln!(
+            out,
+            "\nTotal across all accounts: {}{}",
+            report_money(report, total),
+            metric_columns(
+                &report.metrics,
+                |m| m.total,
+                |amt| report_money(report, amt)
+            )
+        )
+        .unwrap(),
+        None => {
+            writeln!(out, "\nTotals by currency (not summed across currencies):").unwrap();
+            for subtotal in &report.currency_subtotals {
+                writeln!(
+                    out,
+                    "- {}: {} ({})",
+                    subtotal.currency,
+                    money(subtotal.total, Some(&subtotal.currency)),
+                    subtotal.account_refs.join(", ")
+                )
+                .unwrap();
+            }
+        }
+    }
+    if report.tax_total.abs() >= f64::EPSILON {
+        writeln!(
+            out,
+            "Tax (not in totals): {}",
+            report_money(report, report.tax_total)
+        )
+        .unwrap();
+    }
+    if let (Some(rate), Some(projection)) = (report.daily_run_rate, report.projected_month_total) {
+        writeln!(
+            out,
+            "Run rate: {}/day, naive projection: {}",
+            report_money(report, rate),
+            report_money(report, projection)
+        )
+        .unwrap();
+    }
+    if let Some(projection) = &report.prorated_projection {
+        writeln!(
+            out,
+            "Prorated projection: {}",
+            prorated_note(report, projection)
+        )
+        .unwrap();
+    }
+
+    if options.rank_accounts {
+        account_ranking(&mut out, report, options.rank_limit);
+    }
+
+    writeln!(out, "\nTop 5 services across all accounts:").unwrap();
+    for (svc, amt) in &report.top_services {
+        let columns = metric_columns(
+            &report.metrics,
+            |m| m.services.get(svc).copied().unwrap_or(0.0),
+            |amt| report_money(report, amt),
+        );
+        writeln!(out, "- {}: {}{}", svc, report_money(report, *amt), columns).unwrap();
+    }
+
+    if !report.top_credits.is_empty() {
+        writeln!(out, "\nTop credits and refunds:").unwrap();
+        for (svc, amt) in &report.top_credits {
+            writeln!(out, "- {svc}: {}", report_money(report, *amt)).unwrap();
+        }
+    }
+
+    if let Some(total) = &report.blended {
+        writeln!(
+            out,
+            "\nBlended vs unblended (savings = blended - unblended):"
+        )
+        .unwrap();
+        for s in &report.summaries {
+            if let Some(split) = &s.blended {
+                let currency = account_currency(report, s);
+                writeln!(
+                    out,
+                    "- {}: blended {}, unblended {}, savings {}",
+                    s.account_name,
+                    money(split.blended, currency),
+                    money(split.unblended, currency),
+                    money(split.savings, currency)
+                )
+                .unwrap();
+            }
+        }
+        writeln!(
+            out,
+            "- Total: blended {}, unblended {}, savings {}",
+            report_money(report, total.blended),
+            report_money(report, total.unblended),
+            report_money(report, total.savings)
+        )
+        .unwrap();
+    }
+
+    if let Some(total) = &report.savings_rate {
+        writeln!(
+            out,
+            "\nEffective savings rate (blended as the on-demand equivalent vs net amortized):"
+        )
+        .unwrap();
+        for s in &report.summaries {
+            if let Some(rate) = &s.savings_rate {
+                let note = savings_rate_note(rate, |amt| money(amt, account_currency(report, s)));
+                writeln!(out, "- {}: {note}", s.account_name).unwrap();
+            }
+        }
+        let note = savings_rate_note(total, |amt| report_money(report, amt));
+        writeln!(out, "- Total: {note}").unwrap();
+    }
+
+    let amortized: Vec<(&AccountSummary, f64)> = report
+        .summaries
+        .iter()
+        .filter_map(|s| Some((s, s.amortized_services.as_ref()?.values().sum())))
+        .collect();
+    if !amortized.is_empty() {
+        writeln!(
+            out,
+            "\nNet amortized cost (RI / Savings Plans spread over the services that used them):"
+        )
+        .unwrap();
+        for (s, total) in amortized {
+            let currency = account_currency(report, s);
+            writeln!(
+                out,
+                "- {}: {} (vs {})",
+                s.account_name,
+                money(total, currency),
+                money(s.total, currency)
+            )
+            .unwrap();
+        }
+    }
+
+    if let Some(overhead) = &report.overhead {
+        writeln!(out, "\nUsage vs overhead:").unwrap();
+        writeln!(
+            out,
+            "- Usage: {}",
+            report_money(report, overhead.usage_total)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "- Overhead: {}",
+            report_money(report, overhead.overhead_total)
+        )
+        .unwrap();
+        for (svc, amt) in &overhead.overhead_services {
+            writeln!(out, "  - {}: {}", svc, report_money(report, *amt)).unwrap();
+        }
+    }
+
+    if !report.new_services.is_empty() {
+        writeln!(
+            out,
+            "\nNew services this month (not billed in the previous period):"
+        )
+        .unwrap();
+        // From the deltas, which keep services later folded into "Other" under their name
+        for svc in &report.new_services {
+            let amt = report
+                .service_deltas
+                .iter()
+                .find(|d| &d.service == svc)
+                .map_or(0.0, |d| d.current);
+            writeln!(out, "- NEW {}: {}", svc, report_money(report, amt)).unwrap();
+        }
+    }
+    if !report.removed_services.is_empty() {
+        writeln!(out, "\nServices billed last period but not this month:").unwrap();
+        for svc in &report.removed_services {
+            writeln!(out, "- {}", svc).unwrap();
+        }
+    }
+
+    for breakdown in &report.breakdowns {
+        dimension_breakdown(&mut out, report, breakdown);
+    }
+    if let (Some(amount), Some(pct)) = (report.untagged_spend, report.untagged_pct) {
+        writeln!(
+            out,
+            "Untagged: {} ({:.2}%)",
+            report_money(report, amount),
+            pct
+        )
+        .unwrap();
+    }
+    for deltas in &report.breakdown_deltas {
+        dimension_delta(&mut out, report, deltas, options);
+    }
+
+    let unparseable: usize = report.summaries.iter().map(|s| s.unparseable_amounts).sum();
+    if unparseable > 0 {
+        writeln!(
+            out,
+            "\nWarning: {} Cost Explorer amount(s) could not be parsed and were counted as {}",
+            unparseable,
+            report_money(report, 0.0)
+        )
+        .unwrap();
+    }
+
+    if let (Some(total), Some(prev_total), Some(delta), Some(delta_pct)) = (
+        report.total_all,
+        report.prev_total,
+        report.delta,
+        report.delta_pct,
+    ) {
+        writeln!(out, "\nMonth-to-month comparison:").unwrap();
+        let (current, point) = if is_whole_month(report) {
+            ("Current month", "")
+        } else {
+            ("Current MTD", " same point")
+        };
+        writeln!(out, "- {current}: {}", report_money(report, total)).unwrap();
+        let prev_total = report_money(report, prev_total);
+        match report.baseline_periods {
+            Some(periods) => writeln!(
+                out,
+                "- Average of the previous {periods} months,{point}: {prev_total}"
+            ),
+            None => writeln!(out, "- Previous month{point}: {prev_total}"),
+        }
+        .unwrap();
+        writeln!(
+            out,
+            "- Change: {} ({:.2}%)",
+            report_money(report, delta),
+            delta_pct
+        )
+        .unwrap();
+        if report.low_confidence {
+            writeln!(
+                out,
+                "  Note: only {} day(s) of current-month data; treat the change as low confidence.",
+                report.days_elapsed
+            )
+            .unwrap();
+        }
+    }
+
+    let shown: Vec<&ServiceDelta> = report
+        .service_deltas
+        .iter()
+        .filter(|d| options.shows_delta(d.delta, d.delta_pct))
+        .collect();
+    if !shown.is_empty() {
+        writeln!(out, "\nChange by service:").unwrap();
+        for d in shown {
+            let pct = d
+                .delta_pct
+                .map_or_else(|| "new".to_string(), |pct| format!("{:+.2}%", pct));
+            writeln!(
+                out,
+                "- {}: {} -> {} ({:+.2}, {})",
+                d.service,
+                report_money(report, d.previous),
+                report_money(report, d.current),
+                d.delta,
+                pct
+            )
+            .unwrap();
+        }
+    }
+
+    if let Some(native) = &report.native_comparison {
+        writeln!(
+            out,
+            "\nProvider-computed comparison: {} -> {} (change {})",
+            report_money(report, native.total.baseline),
+            report_money(report, native.total.current),
+            report_money(report, native.total.difference)
+        )
+        .unwrap();
+        if !native.drivers.is_empty() {
+            writeln!(out, "Largest cost drivers:").unwrap();
+            for d in native.drivers.iter().take(10) {
+                writeln!(
+                    out,
+                    "- {} / {} {}: {}",
+                    d.service,
+                    d.driver_type,
+                    d.name,
+                    signed_money(d.difference, report.currency.as_deref())
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(out, "\n{}", generated_line(report)).unwrap();
+
+    out
+}
+
+/// A (start, end-exclusive) window for readers: `Aug 1`, `Aug 1–14`, `Jul 30–Aug 2`, or
+/// `Dec 30, 2024–Jan 2, 2025` when it spans two years. The year is otherwise left out.
+pub fn date_range_label(start: NaiveDate, end_exclusive: NaiveDate) -> String {
+    let last = end_exclusive.pred_opt().unwrap_or(start).max(start);
+    if last == start {
+        start.format("%b %-d").to_string()
+    } else if last.year() != start.year() {
+        format!(
+            "{}–{}",
+            start.format("%b %-d, %Y"),
+            last.format("%b %-d, %Y")
+        )
+    } else if last.month() != start.month() {
+        format!("{}–{}", start.format("%b %-d"), last.format("%b %-d"))
+    } else {
+        format!("{}–{}", start.format("%b %-d"), last.day())
+    }
+}
+
+/// `date_range_label` with the year, unless the label already carries it.
+fn date_range_label_with_year(start: NaiveDate, end_exclusive: NaiveDate) -> String {
+    let label = date_range_label(start, end_exclusive);
+    let last = end_exclusive.pred_opt().unwrap_or(start).max(start);
+    if last.year() == start.year() {
+        format!("{label}, {}", start.year())
+    } else {
+        label
+    }
+}
+
+/// The report's window against its comparison window, e.g. `Aug 1–14 vs Jul 1–14, 2025`;
+/// the year is given once when both windows fall in the same one. Without a comparison
+/// only the report's window is given, e.g. `Aug 1–14, 2025`.
+pub fn comparison_label(report: &Report) -> String {
+    if report.prev_total.is_none() {
+        return date_range_label_with_year(report.month_start, report.month_end_exclusive);
+    }
+    let years = [
+        report.month_start,
+        report
+            .month_end_exclusive
+            .pred_opt()
+            .unwrap_or(report.month_start),
+        report.prev_start,
+        report
+            .prev_end_exclusive
+            .pred_opt()
+            .unwrap_or(report.prev_start),
+    ]
+    .map(|date| date.year());
+    if years.iter().all(|year| *year == years[0]) {
+        format!(
+            "{} vs {}, {}",
+            date_range_label(report.month_start, report.month_end_exclusive),
+            date_range_label(report.prev_start, report.prev_end_exclusive),
+            years[0]
+        )
+    } else {
+        format!(
+            "{} vs {}",
+            date_range_label_with_year(report.month_start, report.month_end_exclusive),
+            date_range_label_with_year(report.prev_start, report.prev_end_exclusive)
+        )
+    }
+}
+
+/// Whether the report covers a whole calendar month, as with `--month`, rather than the
+/// running month so far.
+fn is_whole_month(report: &Report) -> bool {
+    report.month_start.day() == 1
+        && report.month_start.checked_add_months(Months::new(1)) == Some(report.month_end_exclusive)
+}
+
+/// `comparison_label`, introduced as month-to-date unless the report covers a whole month.
+fn window_heading(report: &Report) -> String {
+    if is_whole_month(report) {
+        comparison_label(report)
+    } else {
+        format!("Month-to-date: {}", comparison_label(report))
+    }
+}
+
+/// When and by which version the report was produced, for archived output.
+fn generated_line(report: &Report) -> String {
+    format!(
+        "Generated {} by Cloud Cost Manager {}",
+        report.generated_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        report.tool_version
+    )
+}
+
+/// Pretty-printed JSON of the whole report. Amounts are rounded (or turned into cents) only
+/// here, so binary noise such as `123.4500000001` never reaches the output while the report
+/// keeps full precision.
+pub fn json(report: &Report, money: MoneyFormat) -> serde_json::Result<String> {
+    let mut value = serde_json::to_value(report)?;
+    match money.decimals() {
+        Some(places) => map_amounts(&mut value, None, &|amount| {
+            Value::from(round_to(amount, places))
+        }),
+        None => map_amounts(&mut value, None, &|amount| Value::from(to_cents(amount))),
+    }
+    Ok(serde_json::to_string_pretty(&value)? + "\n")
+}
+
+/// One row per account and service, for spreadsheets.
+pub fn csv(report: &Report, money: MoneyFormat) -> String {
+    csv_rows(report, money, &[])
+}
+
+/// `csv` plus one column per additional metric in `Report::metrics`, in that order and
+/// headed by the metric name. Usage quantities are never turned into cents.
+pub fn csv_with_metrics(report: &Report, money: MoneyFormat) -> String {
+    let metrics: Vec<&str> = report.metrics.iter().map(|m| m.metric.as_str()).collect();
+    csv_rows(report, money, &metrics)
+}
+
+fn csv_rows(report: &Report, money: MoneyFormat, metrics: &[&str]) -> String {
+    let mut out = format!("# {}\n", generated_line(report));
+    out.push_str(match money {
+        MoneyFormat::Dollars | MoneyFormat::Decimals(_) => {
+            "account_ref,account_id,account_name,service,amount"
+        }
+        MoneyFormat::Cents => "account_ref,account_id,account_name,service,amount_cents",
+    });
+    for metric in metrics {
+        if money == MoneyFormat::Cents && !is_usage_metric(metric) {
+            write!(out, ",{metric}_cents").unwrap();
+        } else {
+            write!(out, ",{metric}").unwrap();
+        }
+    }
+    out.push('\n');
+
+    let format_amount = |amt: f64| match money.decimals() {
+        Some(places) => format!("{:.*}", places as usize, amt),
+        None => to_cents(amt).to_string(),
+    };
+    for s in &report.summaries {
+        let mut services: Vec<(&String, &f64)> = s.services.iter().collect();
+        services.sort_by(|a, b| a.0.cmp(b.0));
+        for (svc, amt) in services {
+            write!(
+                out,
+                "{},{},{},{},{}",
+                csv_field(&s.account_ref),
+                csv_field(&s.account_id),
+                csv_field(&s.account_name),
+                csv_field(svc),
+                format_amount(*amt)
+            )
+            .unwrap();
+            for metric in metrics {
+                let amount = s
+                    .metrics
+                    .iter()
+                    .find(|m| m.metric == *metric)
+                    .and_then(|m| m.services.get(svc))
+                    .copied()
+                    .unwrap_or(0.0);
+                if is_usage_metric(metric) {
+                    write!(out, ",{amount:.2}").unwrap();
+                } else {
+                    write!(out, ",{}", format_amount(amount)).unwrap();
+                }
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// Columns of `focus`, in FOCUS 1.0 spelling and order.
+const FOCUS_COLUMNS: &[&str] = &[
+    "BilledCost",
+    "BillingAccountId",
+    "BillingAccountName",
+    "BillingCurrency",
+    "BillingPeriodEnd",
+    "BillingPeriodStart",
+    "ChargeCategory",
+    "ChargePeriodEnd",
+    "ChargePeriodStart",
+    "ContractedCost",
+    "EffectiveCost",
+    "InvoiceIssuerName",
+    "ListCost",
+    "ProviderName",
+    "PublisherName",
+    "RegionId",
+    "ResourceId",
+    "ServiceCategory",
+    "ServiceName",
+    "SubAccountId",
+    "SubAccountName",
+];
+
+/// One row per account and service in the FOCUS (FinOps Open Cost and Usage Specification)
+/// column schema, for FinOps platforms. Every row covers the whole report window.
+///
+/// Only the columns the report can fill are set: `BilledCost` is the report's metric
+/// (unblended cost by default), and the reported account is both the billing account and the
+/// sub account, since the payer is not known. `ChargeCategory` is `Tax` for tax and `Credit`
+/// for negative amounts, `Usage` otherwise. `ContractedCost`, `EffectiveCost`, `ListCost`,
+/// `RegionId`, `ResourceId` and `ServiceCategory` need line-item data and are left empty.
+pub fn focus(report: &Report) -> String {
+    let mut out = FOCUS_COLUMNS.join(",");
+    out.push('\n');
+
+    let billing_start = report.month_start.with_day(1).unwrap_or(report.month_start);
+    let billing_end = billing_start
+        .checked_add_months(Months::new(1))
+        .unwrap_or(report.month_end_exclusive);
+    let timestamp = |date: NaiveDate| format!("{date}T00:00:00Z");
+
+    for s in &report.summaries {
+        let currency = s
+            .currency
+            .as_deref()
+            .or(report.currency.as_deref())
+            .unwrap_or("");
+        let mut services: Vec<(&str, f64)> = s
+            .services
+            .iter()
+            .map(|(svc, amt)| (svc.as_str(), *amt))
+            .collect();
+        services.sort_by(|a, b| a.0.cmp(b.0));
+        // Tax split out with `--separate-tax` is still billed
+        if s.tax.abs() >= f64::EPSILON {
+            services.push(("Tax", s.tax));
+        }
+        for (svc, amt) in services {
+            let category = if svc == "Tax" {
+                "Tax"
+            } else if amt < 0.0 {
+                "Credit"
+            } else {
+                "Usage"
+            };
+            writeln!(
+                out,
+                "{:.2},{},{},{},{},{},{},{},{},,,AWS,,AWS,AWS,,,,{},{},{}",
+                amt,
+                csv_field(&s.account_id),
+                csv_field(&s.account_name),
+                csv_field(currency),
+                timestamp(billing_end),
+                timestamp(billing_start),
+                category,
+                timestamp(report.month_end_exclusive),
+                timestamp(report.month_start),
+                csv_field(svc),
+                csv_field(&s.account_id),
+                csv_field(&s.account_name),
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+/// The estimate and how it was reached, e.g. "$1234.00 ($40.00/day over 12 complete day(s))".
+fn prorated_note(report: &Report, projection: &ProratedProjection) -> String {
+    let mut note = format!(
+        "{} ({}/day over {} complete day(s))",
+        report_money(report, projection.estimate),
+        report_money(report, projection.complete_daily_rate),
+        projection.complete_days
+    );
+    if let Some(forecast) = projection.forecast_total {
+        write!(
+            note,
+            ", the average of {} prorated and {} forecast",
+            report_money(report, projection.prorated_total),
+            report_money(report, forecast)
+        )
+        .unwrap();
+    }
+    note
+}
+
+/// One line on the accounts hidden by `ReportOptions::min_account_spend`.
+fn small_accounts_note(report: &Report, small: &SmallAccounts) -> String {
+    format!(
+        "{} smaller account(s) not listed: {}, {} the total",
+        small.account_refs.len(),
+        report_money(report, small.total),
+        if small.counted_in_total {
+            "included in"
+        } else {
+            "not in"
+        }
+    )
+}
+
+/// GitHub-flavored Markdown tables for pasting into PRs and wikis.
+pub fn markdown(report: &Report) -> String {
+    let mut out = String::new();
+
+    out.push_str("## Cloud Cost Manager\n\n");
+    out.push_str(&format!("{}\n\n", window_heading(report)));
+
+    out.push_str("| Account | Account ID | Reference | Spend |\n");
+    out.push_str("| --- | --- | --- | ---: |\n");
+    for s in &report.summaries {
+        out.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            escape_markdown_cell(&s.account_name),
+            s.account_id,
+            escape_markdown_cell(&s.account_ref),
+            money(s.total, account_currency(report, s))
+        ));
+    }
+    match report.total_all {
+        Some(total) => out.push_str(&format!(
+            "| **Total** | | | **{}** |\n",
+            report_money(report, total)
+        )),
+        None => {
+            for subtotal in &report.currency_subtotals {
+                out.push_str(&format!(
+                    "| **Total {}** | | | **{}** |\n",
+                    subtotal.currency,
+                    money(subtotal.total, Some(&subtotal.currency))
+                ));
+            }
+        }
+    }
+    out.push('\n');
+    if let Some(small) = &report.small_accounts {
+        out.push_str(&format!("_{}_\n\n", small_accounts_note(report, small)));
+    }
+    if let (Some(rate), Some(projection)) = (report.daily_run_rate, report.projected_month_total) {
+        out.push_str(&format!(
+            "**Run rate:** {}/day, naive projection {}\n\n",
+            report_money(report, rate),
+            report_money(report, projection)
+        ));
+    }
+    if let Some(projection) = &report.prorated_projection {
+        out.push_str(&format!(
+            "**Prorated projection:** {}\n\n",
+            prorated_note(report, projection)
+        ));
+    }
+
+    if !report.errors.is_empty() {
+        let missing: Vec<String> = report
+            .errors
+            .iter()
+            .map(|e| format!("{} ({})", escape_markdown_cell(&e.account_ref), e.error))
+            .collect();
+        out.push_str(&format!("**Missing accounts:** {}\n\n", missing.join(", ")));
+    }
+    for warning in &report.warnings {
+        out.push_str(&format!(
+            "**Warning:** {}\n\n",
+            escape_markdown_cell(warning)
+        ));
+    }
+
+    out.push_str("| Service | Spend |\n");
+    out.push_str("| --- | ---: |\n");
+    for (svc, amt) in &report.top_services {
+        out.push_str(&format!(
+            "| {} | {} |\n",
+            escape_markdown_cell(svc),
+            report_money(report, *amt)
+        ));
+    }
+
+    if !report.top_credits.is_empty() {
+        out.push_str("\n| Credit / refund | Amount |\n");
+        out.push_str("| --- | ---: |\n");
+        for (svc, amt) in &report.top_credits {
+            out.push_str(&format!(
+                "| {} | {} |\n",
+                escape_markdown_cell(svc),
+                report_money(report, *amt)
+            ));
+        }
+    }
+
+    if !report.new_services.is_empty() {
+        let names: Vec<String> = report
+            .new_services
+            .iter()
+            .map(|svc| escape_markdown_cell(svc))
+            .collect();
+        out.push_str(&format!("\n**New services:** {}\n", names.join(", ")));
+    }
+
+    if let (Some(total), Some(prev_total), Some(delta), Some(delta_pct)) = (
+        report.total_all,
+        report.prev_total,
+        report.delta,
+        report.delta_pct,
+    ) {
+        let baseline = match report.baseline_periods {
+            Some(periods) => format!("average of the previous {periods} months, same point"),
+            None => "previous month same point".to_string(),
+        };
+        out.push_str(&format!(
+            "\n**Month-over-month:** {} vs {} {baseline}, change {} ({:.2}%)",
+            report_money(report, total),
+            report_money(report, prev_total),
+            report_money(report, delta),
+            delta_pct
+        ));
+        if report.low_confidence {
+            out.push_str(&format!(
+                " _(low confidence: {} day(s) of data)_",
+                report.days_elapsed
+            ));
+        }
+        out.push('\n');
+    }
+
+    out.push_str(&format!("\n_{}_\n", generated_line(report)));
+
+    out
+}
+
+/// Rounds dollars to whole cents, half away from zero. The product is first snapped to
+/// 1e-6 cents so binary noise (1.005 * 100 = 100.4999...) does not decide the rounding.
+pub fn to_cents(amount: f64) -> i64 {
+    let cents = ((amount * 100.0) * 1e6).round() / 1e6;
+    cents.round() as i64
+}
+
+/// `amount` rounded half away from zero to `places` decimals, with the same noise allowance
+/// as `to_cents`.
+fn round_to(amount: f64, places: u32) -> f64 {
+    let scale = 10_f64.powi(places as i32);
+    let scaled = ((amount * scale) * 1e6).round() / 1e6;
+    scaled.round() / scale
+}
+
+/// Replaces every money amount in the serialized report with `convert(amount)`.
+fn map_amounts(value: &mut Value, key: Option<&str>, convert: &impl Fn(f64) -> Value) {
+    match value {
+        // Usage metrics (e.g. UsageQuantity) are not money
+        Value::Object(fields)
+            if fields
+                .get("metric")
+                .and_then(Value::as_str)
+                .is_some_and(is_usage_metric) => {}
+        Value::Object(fields) => {
+            for (field, inner) in fields.iter_mut() {
+                if MONEY_MAPS.contains(&field.as_str())
+                    && let Value::Object(entries) = inner
+                {
+                    for amount in entries.values_mut() {
+                        if amount.is_number() {
+                            convert_amount(amount, convert);
+                        } else {
+                            map_amounts(amount, None, convert);
+                        }
+                    }
+                } else {
+                    map_amounts(inner, Some(field.as_str()), convert);
+                }
+            }
+        }
+        // `top_services` and `top_credits` are lists of [name, amount] pairs
+        Value::Array(items) if matches!(key, Some("top_services" | "top_credits")) => {
+            for pair in items {
+                if let Some(amount) = pair.get_mut(1) {
+                    convert_amount(amount, convert);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                map_amounts(item, None, convert);
+            }
+        }
+        Value::Number(_) if key.is_some_and(|k| MONEY_FIELDS.contains(&k)) => {
+            convert_amount(value, convert);
+        }
+        _ => {}
+    }
+}
+
+fn convert_amount(value: &mut Value, convert: &impl Fn(f64) -> Value) {
+    if let Some(amount) = value.as_f64() {
+        *value = convert(amount);
+    }
+}
+
+/// Inline stylesheet for `html`, so the page renders the same when emailed.
+const HTML_STYLE: &str = "\
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; color: #24292f; max-width: 760px; margin: 24px auto; padding: 0 16px; }
+h1 { font-size: 22px; margin-bottom: 4px; }
+h2 { font-size: 17px; margin-top: 28px; border-bottom: 1px solid #d0d7de; padding-bottom: 4px; }
+p.window { color: #57606a; margin-top: 0; }
+table { border-collapse: collapse; width: 100%; font-size: 14px; }
+th, td { text-align: left; padding: 6px 8px; border-bottom: 1px solid #eaeef2; }
+td.amount, th.amount { text-align: right; font-variant-numeric: tabular-nums; white-space: nowrap; }
+tr.total td { font-weight: 600; border-top: 2px solid #d0d7de; }
+td.bar { width: 40%; }
+div.bar { background: #2f81f7; height: 10px; border-radius: 2px; }
+.up { color: #cf222e; }
+.down { color: #1a7f37; }
+.warning { background: #fff8c5; border: 1px solid #d4a72c; padding: 8px 12px; border-radius: 4px; }
+";
+
+/// A self-contained HTML page (inline CSS, no external assets) for emailing: accounts, top
+/// services with bars sized by their share of the total, and the month-over-month summary.
+pub fn html(report: &Report) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>Cloud Cost Manager</title>\n");
+    writeln!(out, "<style>\n{HTML_STYLE}</style>\n</head>\n<body>").unwrap();
+    out.push_str("<h1>Cloud Cost Manager</h1>\n");
+    writeln!(
+        out,
+        "<p class=\"window\">{}</p>",
+        escape_html(&window_heading(report))
+    )
+    .unwrap();
+
+    if !report.errors.is_empty() {
+        out.push_str("<p class=\"warning\">Accounts missing from this report (not in any total): ");
+        let missing: Vec<String> = report
+            .errors
+            .iter()
+            .map(|e| {
+                format!(
+                    "{} ({})",
+                    escape_html(&e.account_ref),
+                    escape_html(&e.error)
+                )
+            })
+            .collect();
+        out.push_str(&missing.join(", "));
+        out.push_str("</p>\n");
+    }
+    for warning in &report.warnings {
+        writeln!(out, "<p class=\"warning\">{}</p>", escape_html(warning)).unwrap();
+    }
+
+    out.push_str("<h2>Accounts</h2>\n<table>\n");
+    out.push_str("<tr><th>Account</th><th>Account ID</th><th class=\"amount\">Spend</th></tr>\n");
+    for s in &report.summaries {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td class=\"amount\">{}</td></tr>",
+            escape_html(&s.account_name),
+            escape_html(&s.account_id),
+            escape_html(&money(s.total, account_currency(report, s)))
+        )
+        .unwrap();
+    }
+    match report.total_all {
+        Some(total) => writeln!(
+            out,
+            "<tr class=\"total\"><td>Total</td><td></td><td class=\"amount\">{}</td></tr>",
+            escape_html(&report_money(report, total))
+        )
+        .unwrap(),
+        None => {
+            for subtotal in &report.currency_subtotals {
+                writeln!(
+                    out,
+                    "<tr class=\"total\"><td>Total {}</td><td></td><td class=\"amount\">{}</td></tr>",
+                    escape_html(&subtotal.currency),
+                    escape_html(&money(subtotal.total, Some(&subtotal.currency)))
+                )
+                .unwrap();
+            }
+        }
+    }
+    out.push_str("</table>\n");
+    if let Some(small) = &report.small_accounts {
+        writeln!(
+            out,
+            "<p>{}</p>",
+            escape_html(&small_accounts_note(report, small))
+        )
+        .unwrap();
+    }
+    if let (Some(rate), Some(projection)) = (report.daily_run_rate, report.projected_month_total) {
+        writeln!(
+            out,
+            "<p>Run rate {}/day, naive month-end projection {}</p>",
+            escape_html(&report_money(report, rate)),
+            escape_html(&report_money(report, projection))
+        )
+        .unwrap();
+    }
+    if let Some(projection) = &report.prorated_projection {
+        writeln!(
+            out,
+            "<p>Prorated month-end projection: {}</p>",
+            escape_html(&prorated_note(report, projection))
+        )
+        .unwrap();
+    }
+
+    if let (Some(total), Some(prev_total), Some(delta), Some(delta_pct)) = (
+        report.total_all,
+        report.prev_total,
+        report.delta,
+        report.delta_pct,
+    ) {
+        out.push_str("<h2>Month over month</h2>\n");
+        writeln!(
+            out,
+            "<p>{} vs {} {}: <span class=\"{}\">{:+.2} ({:+.2}%)</span>{}</p>",
+            escape_html(&report_money(report, total)),
+            escape_html(&report_money(report, prev_total)),
+            match report.baseline_periods {
+                Some(periods) =>
+                    format!("on average at the same point of the previous {periods} months"),
+                None => "at the same point last month".to_string(),
+            },
+            change_class(delta),
+            delta,
+            delta_pct,
+            if report.low_confidence {
+                format!(
+                    " <em>(low confidence: {} day(s) of data)</em>",
+                    report.days_elapsed
+                )
+            } else {
+                String::new()
+            }
+        )
+        .unwrap();
+    }
+
+    out.push_str("<h2>Top services</h2>\n<table>\n");
+    out.push_str("<tr><th>Service</th><th class=\"amount\">Spend</th><th>Share</th></tr>\n");
+    for (svc, amt) in &report.top_services {
+        // Without a total (mixed currencies) the bars are left empty
+        let share = match report.total_all {
+            Some(total) if total.abs() >= f64::EPSILON => (amt / total * 100.0).clamp(0.0, 100.0),
+            _ => 0.0,
+        };
+        writeln!(
+            out,
+            "<tr><td>{}</td><td class=\"amount\">{}</td><td class=\"bar\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>",
+            escape_html(svc),
+            escape_html(&report_money(report, *amt)),
+            share
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+
+    if !report.top_credits.is_empty() {
+        out.push_str("<h2>Top credits and refunds</h2>\n<table>\n");
+        out.push_str("<tr><th>Service</th><th class=\"amount\">Amount</th></tr>\n");
+        for (svc, amt) in &report.top_credits {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td class=\"amount\">{}</td></tr>",
+                escape_html(svc),
+                escape_html(&report_money(report, *amt))
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+    }
+
+    let changes: Vec<_> = report
+        .service_deltas
+        .iter()
+        .filter(|d| d.delta.abs() >= 0.005)
+        .take(10)
+        .collect();
+    if !changes.is_empty() {
+        out.push_str("<h2>Largest changes by service</h2>\n<table>\n");
+        out.push_str(
+            "<tr><th>Service</th><th class=\"amount\">Previous</th><th class=\"amount\">Current</th><th class=\"amount\">Change</th></tr>\n",
+        );
+        for d in changes {
+            let pct = d
+                .delta_pct
+                .map_or_else(|| "new".to_string(), |pct| format!("{:+.2}%", pct));
+            writeln!(
+                out,
+                "<tr><td>{}</td><td class=\"amount\">{}</td><td class=\"amount\">{}</td><td class=\"amount {}\">{:+.2} ({})</td></tr>",
+                escape_html(&d.service),
+                escape_html(&report_money(report, d.previous)),
+                escape_html(&report_money(report, d.current)),
+                change_class(d.delta),
+                d.delta,
+                pct
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+    }
+
+    writeln!(
+        out,
+        "<p class=\"window\">{}</p>",
+        escape_html(&generated_line(report))
+    )
+    .unwrap();
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Spend going up is shown in red, down in green.
+fn change_class(delta: f64) -> &'static str {
+    if delta > 0.0 { "up" } else { "down" }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn escape_markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// `amount` in `currency`: a symbol for the common ones (`$12.50`, `-€12.50`), the ISO code
+/// otherwise (`12.50 CHF`). Amounts of unknown currency are shown in dollars.
+pub fn money(amount: f64, currency: Option<&str>) -> String {
+    with_currency(format!("{amount:.2}"), currency)
+}
+
+/// `money` with the sign always shown, for changes.
+pub fn signed_money(amount: f64, currency: Option<&str>) -> String {
+    with_currency(format!("{amount:+.2}"), currency)
+}
+
+/// The sign goes in front of a symbol, so a credit reads `-$30.00`.
+fn with_currency(number: String, currency: Option<&str>) -> String {
+    let symbol = match currency {
+        None | Some("USD") => "$",
+        Some("EUR") => "€",
+        Some("GBP") => "£",
+        Some("JPY") => "¥",
+        Some(code) => return format!("{number} {code}"),
+    };
+    match number.strip_prefix(['-', '+']) {
+        Some(digits) => format!("{}{symbol}{digits}", &number[..1]),
+        None => format!("{symbol}{number}"),
+    }
+}
+
+/// An amount summed across the report's accounts. When they are billed in different
+/// currencies it has none to show, so it is printed as a bare number.
+fn report_money(report: &Report, amount: f64) -> String {
+    if report.currency_subtotals.is_empty() {
+        money(amount, report.currency.as_deref())
+    } else {
+        format!("{amount:.2}")
+    }
+}
+
+/// The currency `s` is billed in, falling back to the report's.
+fn account_currency<'a>(report: &'a Report, s: &'a AccountSummary) -> Option<&'a str> {
+    s.currency.as_deref().or(report.currency.as_deref())
+}
+
+/// "rate% (on-demand equivalent …, net amortized …, savings …)"; the rate is "n/a" when
+/// there is no on-demand spend to save on.
+fn savings_rate_note(rate: &SavingsRate, money: impl Fn(f64) -> String) -> String {
+    let pct = rate
+        .rate_pct
+        .map_or_else(|| "n/a".to_string(), |pct| format!("{pct:.1}%"));
+    format!(
+        "{pct} (on-demand equivalent {}, net amortized {}, savings {})",
+        money(rate.on_demand_equivalent),
+        money(rate.net_amortized),
+        money(rate.savings)
+    )
+}
+
+/// One ` | amount` cell per additional metric; usage metrics are printed without `$`.
+fn metric_columns(
+    metrics: &[MetricBreakdown],
+    amount: impl Fn(&MetricBreakdown) -> f64,
+    money: impl Fn(f64) -> String,
+) -> String {
+    metrics
+        .iter()
+        .map(|m| {
+            if is_usage_metric(&m.metric) {
+                format!(" | {:.2}", amount(m))
+            } else {
+                format!(" | {}", money(amount(m)))
+            }
+        })
+        .collect()
+}
+
+fn dimension_breakdown(out: &mut String, report: &Report, breakdown: &DimensionBreakdown) {
+    let mut values: Vec<(&String, &f64)> = breakdown.values.iter().collect();
+    values.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    writeln!(out, "\nSpend by {}:", breakdown.dimension).unwrap();
+    for (key, amt) in values {
+        writeln!(out, "- {}: {}", key, report_money(report, *amt)).unwrap();
+    }
+}
+
+fn dimension_delta(
+    out: &mut String,
+    report: &Report,
+    deltas: &DimensionDelta,
+    options: &TextOptions,
+) {
+    let shown: Vec<_> = deltas
+        .groups
+        .iter()
+        .filter(|d| options.shows_delta(d.delta, d.delta_pct))
+        .collect();
+    if shown.is_empty() {
+        return;
+    }
+
+    writeln!(out, "\nChange by {}:", deltas.dimension).unwrap();
+    for d in shown {
+        let pct = d
+            .delta_pct
+            .map_or_else(|| "new".to_string(), |pct| format!("{:+.2}%", pct));
+        writeln!(
+            out,
+            "- {}: {} -> {} ({:+.2}, {})",
+            d.group,
+            report_money(report, d.previous),
+            report_money(report, d.current),
+            d.delta,
+            pct
+        )
+        .unwrap();
+    }
+}
+
+/// Accounts by spend, each with its share of the total (of its own currency's subtotal when
+/// the report mixes currencies).
+fn account_ranking(out: &mut String, report: &Report, limit: Option<usize>) {
+    let mut ranked: Vec<&AccountSummary> = report.summaries.iter().collect();
+    ranked.sort_by(|a, b| {
+        b.total
+            .partial_cmp(&a.total)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.account_name.cmp(&b.account_name))
+    });
+    if let Some(limit) = limit {
+        ranked.truncate(limit);
+    }
+
+    writeln!(out, "\nAccounts ranked by spend:").unwrap();
+    for (idx, s) in ranked.iter().enumerate() {
+        let total = report.total_all.unwrap_or_else(|| {
+            report
+                .currency_subtotals
+                .iter()
+                .find(|subtotal| s.currency.as_deref() == Some(subtotal.currency.as_str()))
+                .map_or(0.0, |subtotal| subtotal.total)
+        });
+        let share = if total.abs() < f64::EPSILON {
+            0.0
+        } else {
+            (s.total / total) * 100.0
+        };
+        writeln!(
+            out,
+            "{:>3}. {} ({}): {} ({:.2}%)",
+            idx + 1,
+            s.account_name,
+            s.account_id,
+            money(s.total, account_currency(report, s)),
+            share
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CurrencySubtotal;
+    use std::collections::HashMap;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn text_options() -> TextOptions {
+        TextOptions {
+            primary_metric: "UnblendedCost".to_string(),
+            rank_accounts: false,
+            rank_limit: None,
+            min_delta_abs: None,
+            min_delta_pct: None,
+        }
+    }
+
+    /// One account with two services, August 1–14 against July 1–14, 2024.
+    fn fixed_report() -> Report {
+        let prod = AccountSummary {
+            account_id: "111111111111".to_string(),
+            account_name: "Production".to_string(),
+            currency: Some("USD".to_string()),
+            ..AccountSummary::for_test("prod", [("Amazon EC2", 100.5), ("Amazon S3", 20.0)])
+        };
+        Report {
+            prev_total: Some(100.0),
+            delta: Some(20.5),
+            delta_pct: Some(20.5),
+            service_deltas: vec![ServiceDelta {
+                service: "Amazon EC2".to_string(),
+                previous: 82.0,
+                current: 100.5,
+                delta: 18.5,
+                delta_pct: Some(18.5 / 82.0 * 100.0),
+            }],
+            daily_run_rate: Some(120.5 / 14.0),
+            projected_month_total: Some(120.5 / 14.0 * 31.0),
+            ..Report::for_test(vec![prod])
+        }
+    }
+
+    #[test]
+    fn text_renders_a_fixed_report() {
+        let expected = "\
+Cloud Cost Manager
+
+Aug 1–14 vs Jul 1–14, 2024
+
+Breakdown by account:
+- Production (111111111111) via profile prod: $120.50
+
+Total across all accounts: $120.50
+Run rate: $8.61/day, naive projection: $266.82
+
+Top 5 services across all accounts:
+- Amazon EC2: $100.50
+- Amazon S3: $20.00
+
+Month-to-month comparison:
+- Current MTD: $120.50
+- Previous month same point: $100.00
+- Change: $20.50 (20.50%)
+
+Change by service:
+- Amazon EC2: $82.00 -> $100.50 (+18.50, +22.56%)
+
+Generated 2024-08-15 06:00:00 UTC by Cloud Cost Manager 1.2.3
+";
+
+        assert_eq!(text(&fixed_report(), &text_options()), expected);
+    }
+
+    #[test]
+    fn text_lists_missing_accounts_and_warnings() {
+        let mut report = fixed_report();
+        report.errors.push(crate::AccountError {
+            account_ref: "staging".to_string(),
+            error: "AccessDenied".to_string(),
+        });
+        report
+            .warnings
+            .push("prod total is NaN; counted as 0".to_string());
+
+        let text = text(&report, &text_options());
+
+        assert!(
+            text.contains(
+                "\nWarning: accounts missing from this report (not in any total):\n\
+                 - staging: AccessDenied\n"
+            ),
+            "{text}"
+        );
+        assert!(
+            text.contains("\nWarning: prod total is NaN; counted as 0\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn half_cents_round_away_from_zero() {
+        assert_eq!(to_cents(1234.56), 123456);
+        // 1.005 * 100 is 100.49999999999999 in binary
+        assert_eq!(to_cents(1.005), 101);
+        assert_eq!(to_cents(0.125), 13);
+        assert_eq!(to_cents(2.675), 268);
+        assert_eq!(to_cents(-1.005), -101);
+        assert_eq!(to_cents(-0.125), -13);
+        assert_eq!(to_cents(0.124999), 12);
+        assert_eq!(to_cents(0.0), 0);
+    }
+
+    #[test]
+    fn cents_apply_to_json_and_csv_only() {
+        let mut report = fixed_report();
+        report.summaries[0]
+            .services
+            .insert("AWS Lambda".to_string(), 0.005);
+        report.summaries[0]
+            .azs
+            .insert("us-east-1a".to_string(), 0.125);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&json(&report, MoneyFormat::Cents).unwrap()).unwrap();
+        assert_eq!(json["total_all"], 12050);
+        assert_eq!(json["summaries"][0]["services"]["AWS Lambda"], 1);
+        assert_eq!(json["summaries"][0]["azs"]["us-east-1a"], 13);
+
+        let csv = csv(&report, MoneyFormat::Cents);
+        assert!(
+            csv.contains("\nprod,111111111111,Production,AWS Lambda,1\n"),
+            "{csv}"
+        );
+        assert!(csv.contains(",amount_cents\n"), "{csv}");
+
+        // Text stays in dollars
+        assert!(text(&report, &text_options()).contains("Total across all accounts: $120.50"));
+    }
+
+    #[test]
+    fn amounts_use_the_billing_currency() {
+        let mut report = fixed_report();
+        report.currency = Some("EUR".to_string());
+        report.summaries[0].currency = Some("EUR".to_string());
+
+        let text = text(&report, &text_options());
+        assert!(text.contains("via profile prod: €120.50\n"), "{text}");
+        assert!(
+            text.contains("Total across all accounts: €120.50\n"),
+            "{text}"
+        );
+        assert!(text.contains("- Amazon EC2: €82.00 -> €100.50"), "{text}");
+        assert!(!text.contains('$'), "{text}");
+        assert!(markdown(&report).contains("| **Total** | | | **€120.50** |"));
+        assert!(html(&report).contains("<td class=\"amount\">€120.50</td>"));
+
+        assert_eq!(money(-3.5, Some("GBP")), "-£3.50");
+        assert_eq!(signed_money(3.5, None), "+$3.50");
+        assert_eq!(money(3.5, Some("CHF")), "3.50 CHF");
+        assert_eq!(money(3.5, None), "$3.50");
+    }
+
+    #[test]
+    fn mixed_currencies_are_shown_per_currency() {
+        let mut report = fixed_report();
+        let mut eu = report.summaries[0].clone();
+        eu.account_ref = "eu".to_string();
+        eu.account_name = "Europe".to_string();
+        eu.total = 30.0;
+        eu.currency = Some("EUR".to_string());
+        report.summaries.push(eu);
+        report.total_all = None;
+        report.currency = None;
+        report.currency_subtotals = vec![
+            CurrencySubtotal {
+                currency: "EUR".to_string(),
+                total: 30.0,
+                account_refs: vec!["eu".to_string()],
+            },
+            CurrencySubtotal {
+                currency: "USD".to_string(),
+                total: 120.5,
+                account_refs: vec!["prod".to_string()],
+            },
+        ];
+        (report.prev_total, report.delta, report.delta_pct) = (None, None, None);
+        (report.daily_run_rate, report.projected_month_total) = (None, None);
+        report.service_deltas.clear();
+
+        let text = text(
+            &report,
+            &TextOptions {
+                rank_accounts: true,
+                ..text_options()
+            },
+        );
+        assert!(
+            text.contains(
+                "\nTotals by currency (not summed across currencies):\n\
+                 - EUR: €30.00 (eu)\n\
+                 - USD: $120.50 (prod)\n"
+            ),
+            "{text}"
+        );
+        assert!(!text.contains("Total across all accounts"), "{text}");
+        assert!(text.contains("via profile eu: €30.00\n"), "{text}");
+        // Each account's share is of its own currency's subtotal
+        assert!(
+            text.contains("Europe (111111111111): €30.00 (100.00%)"),
+            "{text}"
+        );
+        // Cross-account amounts get no symbol
+        assert!(text.contains("- Amazon EC2: 100.50\n"), "{text}");
+
+        let markdown = markdown(&report);
+        assert!(
+            markdown.contains("| **Total EUR** | | | **€30.00** |"),
+            "{markdown}"
+        );
+        assert!(
+            markdown.contains("| **Total USD** | | | **$120.50** |"),
+            "{markdown}"
+        );
+        assert!(!markdown.contains("| **Total** |"), "{markdown}");
+    }
+
+    #[test]
+    fn json_money_has_at_most_two_decimal_places() {
+        let mut report = fixed_report();
+        let services = HashMap::from([
+            ("Amazon EC2".to_string(), 0.1 + 0.2),
+            ("Amazon S3".to_string(), 1234.5600000000002),
+        ]);
+        let total = services.values().sum::<f64>();
+        report.summaries[0].services = services.clone();
+        report.summaries[0].total = total;
+        report.summaries[0].azs = HashMap::from([("us-east-1a".to_string(), 0.1 + 0.2)]);
+        report.services_total = services;
+        report.total_all = Some(total);
+        report.top_services = vec![("Amazon EC2".to_string(), 0.1 + 0.2)];
+        report.delta = Some(total - 100.0);
+
+        let mut value: serde_json::Value =
+            serde_json::from_str(&json(&report, MoneyFormat::Dollars).unwrap()).unwrap();
+
+        let checked = std::cell::Cell::new(0);
+        map_amounts(&mut value, None, &|amount| {
+            let shown = amount.to_string();
+            let decimals = shown
+                .split_once('.')
+                .map_or(0, |(_, fraction)| fraction.len());
+            assert!(decimals <= 2, "{shown}");
+            checked.set(checked.get() + 1);
+            Value::from(amount)
+        });
+        // Every money field, map entry and [name, amount] pair above, and the fixed ones
+        assert!(checked.get() >= 10, "{}", checked.get());
+        assert_eq!(value["total_all"], 1234.86);
+        assert_eq!(value["summaries"][0]["services"]["Amazon EC2"], 0.3);
+        assert_eq!(value["summaries"][0]["azs"]["us-east-1a"], 0.3);
+        assert_eq!(value["top_services"][0][1], 0.3);
+        // Rates such as the daily run rate (120.5 / 14) are money too
+        assert_eq!(value["daily_run_rate"], 8.61);
+    }
+
+    #[test]
+    fn text_lists_the_savings_rate_per_account_and_in_total() {
+        let mut report = fixed_report();
+        report.summaries[0].savings_rate = Some(SavingsRate::new(160.0, 120.0));
+        report.savings_rate = Some(SavingsRate::new(160.0, 120.0));
+        let out = text(&report, &text_options());
+        assert!(
+            out.contains(
+                "\nEffective savings rate (blended as the on-demand equivalent vs net amortized):\n\
+                 - Production: 25.0% (on-demand equivalent $160.00, net amortized $120.00, savings $40.00)\n\
+                 - Total: 25.0% (on-demand equivalent $160.00, net amortized $120.00, savings $40.00)\n"
+            ),
+            "{out}"
+        );
+
+        report.savings_rate = Some(SavingsRate::new(0.0, 0.0));
+        assert!(text(&report, &text_options()).contains(
+            "- Total: n/a (on-demand equivalent $0.00, net amortized $0.00, savings $0.00)\n"
+        ));
+    }
+
+    #[test]
+    fn date_ranges_name_the_month_again_only_when_it_changes() {
+        assert_eq!(
+            date_range_label(day(2024, 8, 1), day(2024, 8, 15)),
+            "Aug 1–14"
+        );
+        assert_eq!(date_range_label(day(2024, 8, 1), day(2024, 8, 2)), "Aug 1");
+        assert_eq!(
+            date_range_label(day(2024, 7, 30), day(2024, 8, 3)),
+            "Jul 30–Aug 2"
+        );
+        assert_eq!(
+            date_range_label(day(2024, 12, 30), day(2025, 1, 3)),
+            "Dec 30, 2024–Jan 2, 2025"
+        );
+        // An exclusive end on the 1st is the last day of the previous month
+        assert_eq!(
+            date_range_label(day(2024, 2, 1), day(2024, 3, 1)),
+            "Feb 1–29"
+        );
+    }
+
+    #[test]
+    fn whole_month_reports_are_not_called_month_to_date() {
+        let mut report = fixed_report();
+        assert!(markdown(&report).contains("\nMonth-to-date: Aug 1–14 vs Jul 1–14, 2024\n"));
+
+        report.month_start = day(2024, 6, 1);
+        report.month_end_exclusive = day(2024, 7, 1);
+        report.prev_start = day(2024, 5, 1);
+        report.prev_end_exclusive = day(2024, 6, 1);
+        let markdown = markdown(&report);
+        assert!(
+            markdown.contains("\nJun 1–30 vs May 1–31, 2024\n"),
+            "{markdown}"
+        );
+        assert!(!markdown.contains("Month-to-date"), "{markdown}");
+        let html = html(&report);
+        assert!(
+            html.contains("<p class=\"window\">Jun 1–30 vs May 1–31, 2024</p>"),
+            "{html}"
+        );
+        let text = text(&report, &text_options());
+        assert!(
+            text.contains("- Current month: $120.50\n- Previous month: $100.00\n"),
+            "{text}"
+        );
+        assert!(!text.contains("MTD"), "{text}");
+    }
+
+    #[test]
+    fn comparison_label_gives_the_year_once_or_per_window() {
+        let mut report = fixed_report();
+        assert_eq!(comparison_label(&report), "Aug 1–14 vs Jul 1–14, 2024");
+
+        report.month_start = day(2025, 1, 1);
+        report.month_end_exclusive = day(2025, 1, 15);
+        report.prev_start = day(2024, 12, 1);
+        report.prev_end_exclusive = day(2024, 12, 15);
+        assert_eq!(
+            comparison_label(&report),
+            "Jan 1–14, 2025 vs Dec 1–14, 2024"
+        );
+
+        report.prev_total = None;
+        assert_eq!(comparison_label(&report), "Jan 1–14, 2025");
+        let out = text(&report, &text_options());
+        assert!(
+            out.starts_with("Cloud Cost Manager\n\nJan 1–14, 2025\n\n"),
+            "{out}"
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::Client as S3Client;
+use bytes::Bytes;
+use chrono::NaiveDate;
+use cloud_cost_core::{AccountSummary, CostProvider, validate_window};
+use flate2::read::GzDecoder;
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+
+// CUR columns, normalized by `normalize_column` so legacy CSV headers
+// (`lineItem/UnblendedCost`) and Parquet / CUR 2.0 names (`line_item_unblended_cost`) match.
+const ACCOUNT_ID: &str = "line_item_usage_account_id";
+const ACCOUNT_NAME: &str = "line_item_usage_account_name";
+const PRODUCT_NAME: &str = "product_product_name";
+const PRODUCT_CODE: &str = "line_item_product_code";
+const USAGE_START: &str = "line_item_usage_start_date";
+const UNBLENDED_COST: &str = "line_item_unblended_cost";
+const CURRENCY: &str = "line_item_currency_code";
+
+/// One CUR line item, reduced to what the report needs.
+#[derive(Debug, Clone)]
+struct LineItem {
+    account_id: String,
+    service: String,
+    usage_date: NaiveDate,
+    cost: f64,
+}
+
+/// `CostProvider` backed by exported Cost and Usage Report files instead of Cost Explorer.
+/// Account references are the usage account ids found in the files; services are
+/// `product/ProductName` and amounts are `lineItem/UnblendedCost`, bucketed by the day
+/// of `lineItem/UsageStartDate`.
+#[derive(Debug, Default)]
+pub struct CurCostProvider {
+    lines: Vec<LineItem>,
+    account_names: HashMap<String, String>,
+    /// Per account: `lineItem/CurrencyCode` of its first line item that has one
+    currencies: HashMap<String, String>,
+    /// Per account: usage days of costs that could not be parsed and were counted as zero
+    unparseable: HashMap<String, Vec<NaiveDate>>,
+    /// Per file: line items left out because their usage start date could not be parsed
+    undated: Vec<(String, usize)>,
+}
+
+impl CurCostProvider {
+    /// Reads every CUR file in `paths`: local paths or `s3://bucket/key` URLs, as `.csv`,
+    /// `.csv.gz` or (with the `parquet` feature) `.parquet`. A report spanning two months
+    /// needs both months' files.
+    pub async fn load(paths: &[String]) -> Result<Self> {
+        if paths.is_empty() {
+            bail!("No CUR files given");
+        }
+
+        let mut provider = Self::default();
+        for path in paths {
+            let data = read_source(path).await?;
+            provider
+                .ingest(path, data)
+                .with_context(|| format!("Reading CUR file {path}"))?;
+        }
+        Ok(provider)
+    }
+
+    /// One warning per file with line items that belong to no day, and so to no window.
+    pub fn warnings(&self) -> Vec<String> {
+        self.undated
+            .iter()
+            .map(|(path, rows)| {
+                format!(
+                    "{path}: {rows} line item(s) without a readable lineItem/UsageStartDate \
+                     left out"
+                )
+            })
+            .collect()
+    }
+
+    /// Usage account ids present in the loaded files, sorted.
+    pub fn account_ids(&self) -> Vec<String> {
+        let ids: BTreeSet<&String> = self.lines.iter().map(|l| &l.account_id).collect();
+        ids.into_iter().cloned().collect()
+    }
+
+    fn ingest(&mut self, path: &str, data: Bytes) -> Result<()> {
+        let name = path.to_ascii_lowercase();
+        let undated = if name.ends_with(".parquet") {
+            self.ingest_parquet(data)?
+        } else if name.ends_with(".zip") {
+            bail!("Zipped CUR files are not supported; unzip it or export as .csv.gz or Parquet");
+        } else if name.ends_with(".gz") {
+            let mut csv = Vec::new();
+            GzDecoder::new(data.as_ref())
+                .read_to_end(&mut csv)
+                .context("Decompressing gzip")?;
+            self.ingest_csv(&csv)?
+        } else {
+            self.ingest_csv(&data)?
+        };
+        if undated > 0 {
+            self.undated.push((path.to_string(), undated));
+        }
+        Ok(())
+    }
+
+    /// Reads every row; returns how many had no readable usage date.
+    fn ingest_csv(&mut self, data: &[u8]) -> Result<usize> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+        let columns: HashMap<String, usize> = reader
+            .headers()
+            .context("Missing CSV header")?
+            .iter()
+            .enumerate()
+            .map(|(idx, header)| (normalize_column(header), idx))
+            .collect();
+        for required in [ACCOUNT_ID, USAGE_START, UNBLENDED_COST] {
+            if !columns.contains_key(required) {
+                bail!("CUR file has no {required} column");
+            }
+        }
+
+        let mut undated = 0;
+        for record in reader.records() {
+            let record = record?;
+            let dated = self.push_line(|column| {
+                columns
+                    .get(column)
+                    .and_then(|idx| record.get(*idx))
+                    .map(str::to_string)
+            });
+            undated += usize::from(!dated);
+        }
+        Ok(undated)
+    }
+
+    #[cfg(feature = "parquet")]
+    fn ingest_parquet(&mut self, data: Bytes) -> Result<usize> {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let reader = SerializedFileReader::new(data).context("Opening Parquet file")?;
+        let mut undated = 0;
+        for row in reader.get_row_iter(None)? {
+            let row = row?;
+            let cells: HashMap<String, String> = row
+                .get_column_iter()
+                .filter_map(|(name, field)| Some((normalize_column(name), parquet_cell(field)?)))
+                .collect();
+            undated += usize::from(!self.push_line(|column| cells.get(column).cloned()));
+        }
+        Ok(undated)
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn ingest_parquet(&mut self, _data: Bytes) -> Result<usize> {
+        bail!("Parquet CUR files need the `parquet` feature (or export the CUR as CSV)")
+    }
+
+    /// Adds one row; `false` when it has an account but no readable usage date, so it
+    /// belongs to no window.
+    fn push_line(&mut self, cell: impl Fn(&str) -> Option<String>) -> bool {
+        let Some(account_id) = cell(ACCOUNT_ID).filter(|id| !id.is_empty()) else {
+            return true;
+        };
+        // Dates look like 2024-05-01T00:00:00Z (or with milliseconds); the day is enough
+        let Some(usage_date) = cell(USAGE_START)
+            .and_then(|start| NaiveDate::parse_from_str(start.get(..10)?, "%Y-%m-%d").ok())
+        else {
+            return false;
+        };
+        let cost = match cell(UNBLENDED_COST).and_then(|cost| cost.trim().parse::<f64>().ok()) {
+            Some(cost) if cost.is_finite() => cost,
+            _ => {
+                tracing::warn!(account = %account_id, %usage_date, "unparseable CUR cost counted as 0");
+                self.unparseable
+                    .entry(account_id)
+                    .or_default()
+                    .push(usage_date);
+                return true;
+            }
+        };
+        let service = cell(PRODUCT_NAME)
+            .filter(|name| !name.is_empty())
+            .or_else(|| cell(PRODUCT_CODE).filter(|code| !code.is_empty()))
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        if let Some(name) = cell(ACCOUNT_NAME).filter(|name| !name.is_empty()) {
+            self.account_names.entry(account_id.clone()).or_insert(name);
+        }
+        if let Some(currency) = cell(CURRENCY).filter(|code| !code.is_empty()) {
+            self.currencies
+                .entry(account_id.clone())
+                .or_insert(currency);
+        }
+        self.lines.push(LineItem {
+            account_id,
+            service,
+            usage_date,
+            cost,
+        });
+        true
+    }
+
+    fn services_in(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<HashMap<String, f64>> {
+        validate_window(start, end_exclusive)?;
+        if !self.lines.iter().any(|l| l.account_id == account_ref) {
+            bail!("Account {account_ref} does not appear in the CUR data");
+        }
+
+        let mut services: HashMap<String, f64> = HashMap::new();
+        for line in self.lines.iter().filter(|l| {
+            l.account_id == account_ref && l.usage_date >= start && l.usage_date < end_exclusive
+        }) {
+            *services.entry(line.service.clone()).or_insert(0.0) += line.cost;
+        }
+        Ok(services)
+    }
+
+    /// Costs of `account_ref` in the window that could not be parsed.
+    fn unparseable_in(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> usize {
+        self.unparseable.get(account_ref).map_or(0, |days| {
+            days.iter()
+                .filter(|day| **day >= start && **day < end_exclusive)
+                .count()
+        })
+    }
+}
+
+#[async_trait]
+impl CostProvider for CurCostProvider {
+    async fn fetch_account_summary(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<AccountSummary> {
+        let services = self.services_in(account_ref, start, end_exclusive)?;
+        Ok(AccountSummary {
+            account_ref: account_ref.to_string(),
+            account_id: account_ref.to_string(),
+            account_name: self
+                .account_names
+                .get(account_ref)
+                .cloned()
+                .unwrap_or_else(|| account_ref.to_string()),
+            total: services.values().sum(),
+            services,
+            raw_services: None,
+            tax: 0.0,
+            blended: None,
+            savings_rate: None,
+            amortized_services: None,
+            metrics: Vec::new(),
+            secondary: None,
+            breakdowns: Vec::new(),
+            azs: HashMap::new(),
+            unparseable_amounts: self.unparseable_in(account_ref, start, end_exclusive),
+            currency: self.currencies.get(account_ref).cloned(),
+        })
+    }
+
+    async fn service_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<HashMap<String, f64>> {
+        self.services_in(account_ref, start, end_exclusive)
+    }
+
+    async fn checked_service_costs(
+        &self,
+        account_ref: &str,
+        start: NaiveDate,
+        end_exclusive: NaiveDate,
+    ) -> Result<(HashMap<String, f64>, usize)> {
+        let services = self.services_in(account_ref, start, end_exclusive)?;
+        Ok((
+            services,
+            self.unparseable_in(account_ref, start, end_exclusive),
+        ))
+    }
+}
+
+async fn read_source(path: &str) -> Result<Bytes> {
+    let Some(location) = path.strip_prefix("s3://") else {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {path}"))?;
+        return Ok(Bytes::from(data));
+    };
+
+    let (bucket, key) = location
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| anyhow!("Expected s3://bucket/key, got {path}"))?;
+    let config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+    let object = S3Client::new(&config)
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("GetObject {path} failed"))?;
+    let body = object
+        .body
+        .collect()
+        .await
+        .with_context(|| format!("Downloading {path} failed"))?;
+    Ok(body.into_bytes())
+}
+
+/// `lineItem/UnblendedCost` -> `line_item_unblended_cost`; snake_case names pass through.
+fn normalize_column(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 8);
+    let mut prev_lower = false;
+    for ch in name.trim().chars() {
+        if ch == '/' || ch == '-' || ch == ' ' {
+            out.push('_');
+            prev_lower = false;
+        } else if ch.is_ascii_uppercase() {
+            if prev_lower {
+                out.push('_');
+            }
+            out.push(ch.to_ascii_lowercase());
+            prev_lower = false;
+        } else {
+            out.push(ch);
+            prev_lower = ch.is_ascii_lowercase() || ch.is_ascii_digit();
+        }
+    }
+    out
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_cell(field: &parquet::record::Field) -> Option<String> {
+    use chrono::DateTime;
+    use parquet::record::Field;
+
+    match field {
+        Field::Str(value) => Some(value.clone()),
+        Field::Double(value) => Some(value.to_string()),
+        Field::Float(value) => Some(value.to_string()),
+        Field::Long(value) => Some(value.to_string()),
+        Field::Int(value) => Some(value.to_string()),
+        Field::TimestampMillis(ms) => DateTime::from_timestamp_millis(*ms).map(|t| t.to_rfc3339()),
+        Field::TimestampMicros(us) => DateTime::from_timestamp_micros(*us).map(|t| t.to_rfc3339()),
+        Field::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const LEGACY_CSV: &str = "\
+identity/LineItemId,lineItem/UsageAccountId,lineItem/UsageStartDate,lineItem/ProductCode,product/ProductName,lineItem/UnblendedCost,lineItem/CurrencyCode
+a,111111111111,2024-05-01T00:00:00Z,AmazonEC2,Amazon Elastic Compute Cloud,10.5,USD
+b,111111111111,2024-05-02T00:00:00Z,AmazonS3,,2.25,USD
+c,111111111111,2024-05-02T13:00:00.000Z,AmazonEC2,Amazon Elastic Compute Cloud,1.5,USD
+d,222222222222,2024-05-03T00:00:00Z,AmazonEC2,Amazon Elastic Compute Cloud,7,EUR
+";
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn load(path: &str, data: &[u8]) -> CurCostProvider {
+        let mut provider = CurCostProvider::default();
+        provider.ingest(path, Bytes::copy_from_slice(data)).unwrap();
+        provider
+    }
+
+    #[test]
+    fn columns_normalize_to_snake_case() {
+        assert_eq!(normalize_column("lineItem/UnblendedCost"), UNBLENDED_COST);
+        assert_eq!(normalize_column("product/ProductName"), PRODUCT_NAME);
+        assert_eq!(normalize_column(" lineItem/UsageAccountId "), ACCOUNT_ID);
+        assert_eq!(normalize_column(UNBLENDED_COST), UNBLENDED_COST);
+    }
+
+    #[test]
+    fn legacy_headers_are_summed_per_service() {
+        let provider = load("cur.csv", LEGACY_CSV.as_bytes());
+
+        assert_eq!(provider.account_ids(), ["111111111111", "222222222222"]);
+        let services = provider
+            .services_in("111111111111", day(2024, 5, 1), day(2024, 6, 1))
+            .unwrap();
+        // Without a product name the product code names the service
+        assert_eq!(
+            services,
+            HashMap::from([
+                ("Amazon Elastic Compute Cloud".to_string(), 12.0),
+                ("AmazonS3".to_string(), 2.25),
+            ])
+        );
+        assert_eq!(provider.currencies["222222222222"], "EUR");
+        assert!(provider.unparseable.is_empty());
+        assert!(provider.warnings().is_empty());
+    }
+
+    #[test]
+    fn cur2_snake_case_headers_are_read_from_gzip() {
+        let csv = "\
+line_item_usage_account_id,line_item_usage_account_name,line_item_usage_start_date,product_product_name,line_item_unblended_cost
+111111111111,Production,2024-05-01T00:00:00Z,Amazon S3,4
+111111111111,Production,2024-05-01T00:00:00Z,Amazon S3,0.5
+";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(csv.as_bytes()).unwrap();
+        let provider = load("cur/2024-05.CSV.GZ", &gzip.finish().unwrap());
+
+        let services = provider
+            .services_in("111111111111", day(2024, 5, 1), day(2024, 6, 1))
+            .unwrap();
+        assert_eq!(services, HashMap::from([("Amazon S3".to_string(), 4.5)]));
+        assert_eq!(provider.account_names["111111111111"], "Production");
+    }
+
+    #[test]
+    fn rows_outside_the_window_are_left_out() {
+        let provider = load("cur.csv", LEGACY_CSV.as_bytes());
+
+        // The second of May only, including the line item that started mid-day
+        let services = provider
+            .services_in("111111111111", day(2024, 5, 2), day(2024, 5, 3))
+            .unwrap();
+        assert_eq!(
+            services,
+            HashMap::from([
+                ("Amazon Elastic Compute Cloud".to_string(), 1.5),
+                ("AmazonS3".to_string(), 2.25),
+            ])
+        );
+        let services = provider
+            .services_in("111111111111", day(2024, 6, 1), day(2024, 7, 1))
+            .unwrap();
+        assert!(services.is_empty());
+        assert!(
+            provider
+                .services_in("333333333333", day(2024, 5, 1), day(2024, 6, 1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn malformed_cells_are_counted_per_account_and_window() {
+        let csv = "\
+lineItem/UsageAccountId,lineItem/UsageStartDate,product/ProductName,lineItem/UnblendedCost
+111111111111,2024-05-01T00:00:00Z,Amazon EC2,3
+111111111111,2024-05-01T00:00:00Z,Amazon EC2,n/a
+111111111111,2024-05-01T00:00:00Z,Amazon EC2,NaN
+111111111111,yesterday,Amazon EC2,1
+111111111111,2024-05-01T00:00:00Z,Amazon EC2
+111111111111,2024-04-30T00:00:00Z,Amazon EC2,n/a
+,2024-05-01T00:00:00Z,Amazon EC2,1
+";
+        let provider = load("cur.csv", csv.as_bytes());
+
+        let services = provider
+            .services_in("111111111111", day(2024, 5, 1), day(2024, 6, 1))
+            .unwrap();
+        assert_eq!(services, HashMap::from([("Amazon EC2".to_string(), 3.0)]));
+        // Bad cost, non-finite cost and missing cost in May; April's bad cost counts for
+        // April only. Rows without an account are skipped without counting
+        assert_eq!(
+            provider.unparseable_in("111111111111", day(2024, 5, 1), day(2024, 6, 1)),
+            3
+        );
+        assert_eq!(
+            provider.unparseable_in("111111111111", day(2024, 4, 1), day(2024, 5, 1)),
+            1
+        );
+        // The row without a date belongs to no account's window
+        assert_eq!(
+            provider.warnings(),
+            ["cur.csv: 1 line item(s) without a readable lineItem/UsageStartDate left out"]
+        );
+    }
+
+    #[test]
+    fn files_without_the_required_columns_are_rejected() {
+        let mut provider = CurCostProvider::default();
+        let err = provider
+            .ingest(
+                "cur.csv",
+                Bytes::from_static(b"lineItem/UsageAccountId,cost\n1,2\n"),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains(USAGE_START), "{err}");
+        assert!(provider.ingest("cur.zip", Bytes::from_static(b"")).is_err());
+    }
+}